@@ -1,6 +1,7 @@
 extern crate cc;
 use std::env;
 use std::path::Path;
+use std::process::Command;
 
 fn main() {
     let root_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
@@ -27,6 +28,8 @@ fn main() {
     println!("cargo:rerun-if-changed=vendor/minhook/src");
     println!("cargo:rustc-link-search=native={}", env::var("OUT_DIR").unwrap());
 
+    emit_git_hash(&root_dir);
+
     #[cfg(feature = "opengl3")]
     {
         use std::fs::File;
@@ -41,3 +44,24 @@ fn main() {
             .unwrap();
     }
 }
+
+// Expose the commit hudhook is built from as `HUDHOOK_GIT_HASH`, when building
+// from a git checkout rather than from a published crate.
+fn emit_git_hash(root_dir: &str) {
+    let git_dir = Path::new(root_dir).join(".git");
+    if !git_dir.exists() {
+        return;
+    }
+
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    println!("cargo:rerun-if-changed={}", git_dir.join("refs").display());
+
+    let output =
+        Command::new("git").args(["rev-parse", "--short", "HEAD"]).current_dir(root_dir).output();
+    if let Ok(output) = output {
+        if output.status.success() {
+            let hash = String::from_utf8_lossy(&output.stdout);
+            println!("cargo:rustc-env=HUDHOOK_GIT_HASH={}", hash.trim());
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Introspection of the hooks installed by [`hudhook`](crate) and of its
+//! build, for debugging.
+
+use std::ffi::{c_void, OsString};
+use std::os::windows::ffi::OsStringExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{fmt, mem};
+
+use parking_lot::Mutex;
+use tracing::{error, warn};
+use windows::core::{HSTRING, PCSTR};
+use windows::Win32::Foundation::{
+    CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, HANDLE, HMODULE, MAX_PATH,
+};
+use windows::Win32::System::LibraryLoader::{
+    GetModuleFileNameW, GetModuleHandleExA, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+    GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+};
+use windows::Win32::System::ProcessStatus::{EnumProcessModules, GetModuleBaseNameW};
+use windows::Win32::System::Threading::{CreateMutexExW, GetCurrentProcess};
+
+use crate::mh::MhHook;
+use crate::HUDHOOK;
+
+/// File names of the modules of overlays known to conflict with
+/// [`hudhook`](crate): RivaTuner Statistics Server (also drawing the MSI
+/// Afterburner overlay), Discord and Steam.
+pub(crate) const DEFAULT_KNOWN_CONFLICTS: &[&str] = &[
+    "RTSSHooks.dll",
+    "RTSSHooks64.dll",
+    "DiscordHook.dll",
+    "DiscordHook64.dll",
+    "GameOverlayRenderer.dll",
+    "GameOverlayRenderer64.dll",
+];
+
+// Whether the hooks have been enabled and not disabled since.
+static HOOKS_ENABLED: AtomicBool = AtomicBool::new(false);
+// Conflicting overlays found when the hooks were last applied.
+static CONFLICTS: Mutex<Vec<OverlayConflict>> = Mutex::new(Vec::new());
+// Named mutex marking the process as hooked by a copy of hudhook, held while
+// the hooks are applied.
+static INSTANCE_MARKER: Mutex<Option<HANDLE>> = Mutex::new(None);
+
+/// Description of a hook installed by [`hudhook`](crate).
+///
+/// Retrieve them with [`installed_hooks`](crate::installed_hooks).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookInfo {
+    /// Name of the hooked function, e.g. `IDXGISwapChain::Present`.
+    pub name: &'static str,
+    /// Address of the hooked function.
+    pub target: usize,
+    /// Address of the function called instead of the hooked function.
+    pub detour: usize,
+    /// Address of the trampoline used to call the original function.
+    pub trampoline: usize,
+    /// File name of the module containing the hooked function, if it could be
+    /// determined.
+    pub module: Option<String>,
+    /// Whether the hook is currently enabled.
+    pub enabled: bool,
+}
+
+impl HookInfo {
+    fn new(hook: &MhHook, enabled: bool) -> Self {
+        Self {
+            name: hook.name(),
+            target: hook.target() as usize,
+            detour: hook.detour() as usize,
+            trampoline: hook.trampoline() as usize,
+            module: module_name(hook.target()),
+            enabled,
+        }
+    }
+}
+
+/// Overlay found in the process when the hooks were applied, which may compete
+/// with [`hudhook`](crate) for the hooked functions or the input.
+///
+/// Retrieve them with [`detected_conflicts`](crate::detected_conflicts).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverlayConflict {
+    /// A loaded module from the known conflicts, by file name, e.g.
+    /// `RTSSHooks64.dll`. See
+    /// [`HudhookBuilder::with_known_conflicts`](crate::HudhookBuilder::with_known_conflicts).
+    Module(String),
+    /// Another copy of [`hudhook`](crate), e.g. in another DLL, applied its
+    /// hooks in the process.
+    Hudhook,
+}
+
+impl fmt::Display for OverlayConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverlayConflict::Module(module) => write!(f, "{module}"),
+            OverlayConflict::Hudhook => write!(f, "another hudhook instance"),
+        }
+    }
+}
+
+/// Description of the [`hudhook`](crate) build in use, to include in bug
+/// reports.
+///
+/// Retrieve it with [`build_info`](crate::build_info).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Version of [`hudhook`](crate), see [`version`](crate::version).
+    pub version: &'static str,
+    /// Short hash of the commit [`hudhook`](crate) was built from, if it was
+    /// built from a git checkout.
+    pub git_hash: Option<&'static str>,
+    /// Architecture of the target, e.g. `x86_64`.
+    pub target_arch: &'static str,
+    /// Backend and optional features enabled in the build.
+    pub features: &'static [&'static str],
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "hudhook {}", self.version)?;
+        if let Some(git_hash) = self.git_hash {
+            write!(f, " ({git_hash})")?;
+        }
+        write!(f, " {} [{}]", self.target_arch, self.features.join(", "))
+    }
+}
+
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "dx9")]
+    "dx9",
+    #[cfg(feature = "dx11")]
+    "dx11",
+    #[cfg(feature = "dx12")]
+    "dx12",
+    #[cfg(feature = "opengl3")]
+    "opengl3",
+    #[cfg(feature = "inject")]
+    "inject",
+    #[cfg(feature = "offscreen")]
+    "offscreen",
+    #[cfg(feature = "image-loader")]
+    "image-loader",
+    #[cfg(feature = "ipc")]
+    "ipc",
+    #[cfg(feature = "serde")]
+    "serde",
+    #[cfg(feature = "imgui-freetype")]
+    "imgui-freetype",
+    #[cfg(feature = "imgui-docking")]
+    "imgui-docking",
+    #[cfg(feature = "imgui-tables-api")]
+    "imgui-tables-api",
+];
+
+/// Version of [`hudhook`](crate) the program was built against.
+pub fn version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// Describe the [`hudhook`](crate) build in use: version, commit, target
+/// architecture and enabled features.
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: version(),
+        git_hash: option_env!("HUDHOOK_GIT_HASH"),
+        target_arch: std::env::consts::ARCH,
+        features: FEATURES,
+    }
+}
+
+/// Record whether the hooks are enabled.
+pub(crate) fn set_hooks_enabled(enabled: bool) {
+    HOOKS_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// List the hooks currently installed by [`hudhook`](crate).
+///
+/// Returns an empty list if the hooks haven't been applied yet, or have been
+/// removed by ejecting.
+pub fn installed_hooks() -> Vec<HookInfo> {
+    let enabled = HOOKS_ENABLED.load(Ordering::SeqCst);
+
+    match unsafe { HUDHOOK.get() } {
+        Some(hudhook) => {
+            hudhook.hooks().into_iter().map(|hook| HookInfo::new(hook, enabled)).collect()
+        },
+        None => Vec::new(),
+    }
+}
+
+/// Look for conflicting overlays in the process, and warn about them. Modules
+/// are matched against `known_conflicts` by file name, ignoring case.
+pub(crate) fn detect_conflicts(known_conflicts: &[String]) {
+    let mut conflicts: Vec<_> = loaded_modules()
+        .into_iter()
+        .filter(|module| known_conflicts.iter().any(|known| known.eq_ignore_ascii_case(module)))
+        .map(OverlayConflict::Module)
+        .collect();
+    if !mark_instance() {
+        conflicts.push(OverlayConflict::Hudhook);
+    }
+
+    if !conflicts.is_empty() {
+        let names: Vec<_> = conflicts.iter().map(ToString::to_string).collect();
+        warn!(
+            conflicts = ?names,
+            "Other overlays are loaded: if input breaks or the overlay flickers, try without them"
+        );
+    }
+
+    *CONFLICTS.lock() = conflicts;
+}
+
+/// List the overlays known to conflict with [`hudhook`](crate) that were
+/// loaded in the process when the hooks were applied.
+///
+/// Returns an empty list if the hooks haven't been applied yet.
+pub fn detected_conflicts() -> Vec<OverlayConflict> {
+    CONFLICTS.lock().clone()
+}
+
+// Mark the process as hooked by this copy of hudhook. Returns `false` if
+// another copy marked it already.
+fn mark_instance() -> bool {
+    let mut marker = INSTANCE_MARKER.lock();
+    if marker.is_some() {
+        return true;
+    }
+
+    let name = HSTRING::from(format!("Local\\hudhook-{}", std::process::id()));
+    // SYNCHRONIZE: the mutex is only ever held open, never waited for.
+    match unsafe { CreateMutexExW(None, &name, 0, 0x0010_0000) } {
+        Ok(handle) => {
+            let already_exists = unsafe { GetLastError() } == ERROR_ALREADY_EXISTS;
+            *marker = Some(handle);
+            !already_exists
+        },
+        Err(e) => {
+            error!("Could not create instance marker: {e:?}");
+            true
+        },
+    }
+}
+
+/// Release the mark of this copy of hudhook once the hooks are removed.
+pub(crate) fn release_instance_marker() {
+    if let Some(handle) = INSTANCE_MARKER.lock().take() {
+        unsafe { CloseHandle(handle) }.ok();
+    }
+}
+
+// File names of the modules loaded in the process.
+fn loaded_modules() -> Vec<String> {
+    let process = unsafe { GetCurrentProcess() };
+
+    // Modules can be loaded while enumerating: grow the list until it fits.
+    let mut modules = Vec::new();
+    loop {
+        let mut needed = 0u32;
+        let size = (modules.len() * mem::size_of::<HMODULE>()) as u32;
+        if let Err(e) =
+            unsafe { EnumProcessModules(process, modules.as_mut_ptr(), size, &mut needed) }
+        {
+            error!("Could not enumerate modules: {e:?}");
+            return Vec::new();
+        }
+
+        let count = needed as usize / mem::size_of::<HMODULE>();
+        if count <= modules.len() {
+            modules.truncate(count);
+            break;
+        }
+        modules.resize(count, HMODULE(0));
+    }
+
+    modules
+        .into_iter()
+        .filter_map(|module| {
+            let mut name = [0u16; MAX_PATH as usize];
+            let len = unsafe { GetModuleBaseNameW(process, module, &mut name) } as usize;
+            (len > 0).then(|| String::from_utf16_lossy(&name[..len]))
+        })
+        .collect()
+}
+
+// File name of the module containing `addr`.
+fn module_name(addr: *mut c_void) -> Option<String> {
+    let mut hmodule = HMODULE(0);
+    unsafe {
+        GetModuleHandleExA(
+            GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT | GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+            PCSTR(addr as *const u8),
+            &mut hmodule,
+        )
+    }
+    .ok()?;
+
+    let mut sz_filename = [0u16; MAX_PATH as usize];
+    let len = unsafe { GetModuleFileNameW(hmodule, &mut sz_filename) } as usize;
+    if len == 0 {
+        return None;
+    }
+
+    let path = OsString::from_wide(&sz_filename[..len]);
+    Path::new(&path).file_name().map(|name| name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_conflicts() {
+        detect_conflicts(&["NTDLL.DLL".to_owned(), "not-loaded.dll".to_owned()]);
+        assert_eq!(detected_conflicts(), vec![OverlayConflict::Module("ntdll.dll".to_owned())]);
+
+        // Detecting again from the same copy of hudhook doesn't report itself.
+        detect_conflicts(&[]);
+        assert!(detected_conflicts().is_empty());
+        release_instance_marker();
+    }
+}
@@ -0,0 +1,79 @@
+//! Minimal overlays that only draw shapes, text and images, without building
+//! imgui windows.
+//!
+//! Implement [`DrawListRenderLoop`] and wrap it in a [`DrawListOverlay`],
+//! which can be passed to [`HudhookBuilder::with`](crate::HudhookBuilder::with)
+//! like any [`ImguiRenderLoop`].
+
+use imgui::{Context, DrawListMut, Ui};
+
+use crate::{ImguiRenderLoop, RenderContext};
+
+/// Render loop of an overlay that only draws on the screen, e.g. a crosshair
+/// or a few lines of text.
+///
+/// It draws on the foreground draw list of imgui, through the same renderers
+/// as [`ImguiRenderLoop`], but without windows or widgets, so there is no ID
+/// stack to keep balanced and nothing to lay out. The limitations follow:
+///
+/// - The overlay never captures input: the game receives every message.
+/// - Text is drawn with the default imgui font.
+/// - Everything is drawn on top of the game, in the order it is added.
+pub trait DrawListRenderLoop {
+    /// Called once at the first occurrence of the hook, e.g. to load the
+    /// textures to draw with [`DrawListMut::add_image`].
+    fn initialize(&mut self, _render_context: &mut dyn RenderContext) {}
+
+    /// Called every frame to draw on `draw_list`. Coordinates are in pixels,
+    /// from the top left corner of the client area, which is `display_size`
+    /// large.
+    fn draw(&mut self, draw_list: &DrawListMut, display_size: [f32; 2]);
+}
+
+/// Adapter running a [`DrawListRenderLoop`] as an [`ImguiRenderLoop`].
+pub struct DrawListOverlay<T>(pub T);
+
+impl<T: DrawListRenderLoop> ImguiRenderLoop for DrawListOverlay<T> {
+    fn initialize<'a>(&'a mut self, _ctx: &mut Context, render_context: &'a mut dyn RenderContext) {
+        self.0.initialize(render_context);
+    }
+
+    fn render(&mut self, ui: &mut Ui) {
+        let display_size = ui.io().display_size;
+        let draw_list = ui.get_foreground_draw_list();
+        self.0.draw(&draw_list, display_size);
+    }
+
+    // Nothing is drawn besides the draw list: frames without shapes are
+    // skipped.
+    fn render_ui(&mut self, ui: &mut Ui) -> bool {
+        self.render(ui);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Crosshair;
+
+    impl DrawListRenderLoop for Crosshair {
+        fn draw(&mut self, draw_list: &DrawListMut, [width, height]: [f32; 2]) {
+            let center = [width / 2., height / 2.];
+            draw_list.add_circle(center, 4., [1., 0., 0., 1.]).filled(true).build();
+        }
+    }
+
+    #[test]
+    fn test_draw_list_overlay() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+        let mut ctx = crate::renderer::test_context();
+
+        let mut overlay = DrawListOverlay(Crosshair);
+        overlay.render(ctx.frame());
+        let draw_data = ctx.render();
+
+        assert!(draw_data.total_vtx_count > 0);
+    }
+}
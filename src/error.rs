@@ -0,0 +1,105 @@
+//! Errors reported when applying the hooks, and when loading textures.
+
+use std::fmt;
+
+use windows::core::HRESULT;
+use windows::Win32::Foundation::E_INVALIDARG;
+
+use crate::mh::MH_STATUS;
+
+/// Reason why [`Hudhook::apply`](crate::Hudhook::apply) or
+/// [`Hooks::from_render_loop`](crate::Hooks::from_render_loop) failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyError {
+    /// No hooks were added to the builder.
+    NoBackendDetected,
+    /// A module required by the hooks is not loaded in the process, e.g.
+    /// `opengl32.dll`. Retrying later, or with
+    /// [`HudhookBuilder::with_install_timeout`](crate::HudhookBuilder::with_install_timeout),
+    /// may succeed.
+    ModuleNotLoaded(String),
+    /// The addresses of the functions to hook couldn't be retrieved, e.g.
+    /// because the dummy device or swap chain couldn't be created.
+    PresentAddrFailed(HRESULT),
+    /// minhook couldn't create or enable a hook.
+    HookCreationFailed(MH_STATUS),
+    /// Hooks were already applied in this process.
+    AlreadyApplied,
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::NoBackendDetected => write!(f, "no hooks to apply"),
+            ApplyError::ModuleNotLoaded(module) => write!(f, "{module} is not loaded"),
+            ApplyError::PresentAddrFailed(hresult) => {
+                write!(f, "couldn't find the functions to hook (HRESULT {:#010x})", hresult.0)
+            },
+            ApplyError::HookCreationFailed(status) => {
+                write!(f, "couldn't create or enable a hook ({status:?})")
+            },
+            ApplyError::AlreadyApplied => write!(f, "hooks were already applied"),
+        }
+    }
+}
+
+impl std::error::Error for ApplyError {}
+
+impl From<MH_STATUS> for ApplyError {
+    fn from(status: MH_STATUS) -> Self {
+        ApplyError::HookCreationFailed(status)
+    }
+}
+
+impl From<windows::core::Error> for ApplyError {
+    fn from(error: windows::core::Error) -> Self {
+        ApplyError::PresentAddrFailed(error.code())
+    }
+}
+
+/// Reason why a texture couldn't be loaded or replaced via
+/// [`RenderContext`](crate::RenderContext).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextureError {
+    /// The texture is wider or taller than `max`, the largest width and height
+    /// supported by the device, or set via
+    /// [`HudhookBuilder::with_max_texture_size`](crate::HudhookBuilder::with_max_texture_size).
+    TooLarge {
+        /// Largest width and height of a texture.
+        max: [u32; 2],
+    },
+    /// The texture has a width or height of 0.
+    Empty,
+    /// The renderer couldn't create or upload the texture, or the image file
+    /// couldn't be read.
+    Device(windows::core::Error),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::TooLarge { max: [max_width, max_height] } => {
+                write!(f, "textures are at most {max_width}x{max_height}")
+            },
+            TextureError::Empty => write!(f, "textures are at least 1x1"),
+            TextureError::Device(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+impl From<windows::core::Error> for TextureError {
+    fn from(error: windows::core::Error) -> Self {
+        TextureError::Device(error)
+    }
+}
+
+impl From<TextureError> for windows::core::Error {
+    fn from(error: TextureError) -> Self {
+        match error {
+            TextureError::Device(error) => error,
+            error => windows::core::Error::new(E_INVALIDARG, error.to_string()),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Session-wide frame clock.
+//!
+//! The clock is anchored when the first renderer is created and lives outside
+//! of the renderers, so that it keeps counting when they are rebuilt (e.g.
+//! after a device reset).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::OnceCell;
+
+static START: OnceCell<Instant> = OnceCell::new();
+// Time of the current frame since `START`, in nanoseconds.
+static FRAME_TIME: AtomicU64 = AtomicU64::new(0);
+static FRAME_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Anchor the clock, if it wasn't already.
+pub(crate) fn start() {
+    START.get_or_init(Instant::now);
+}
+
+/// Capture the time of the frame about to be rendered.
+pub(crate) fn begin_frame() {
+    let elapsed = START.get_or_init(Instant::now).elapsed();
+    let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+    FRAME_TIME.store(nanos, Ordering::SeqCst);
+}
+
+/// Count a rendered frame.
+pub(crate) fn end_frame() {
+    FRAME_COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Time of the current frame since the clock was anchored.
+pub(crate) fn time() -> Duration {
+    Duration::from_nanos(FRAME_TIME.load(Ordering::SeqCst))
+}
+
+/// Number of frames rendered so far.
+pub(crate) fn frame_count() -> u64 {
+    FRAME_COUNT.load(Ordering::SeqCst)
+}
@@ -0,0 +1,201 @@
+//! Hooks for games that may render with either DirectX 11 or DirectX 12.
+
+use std::ffi::c_void;
+use std::mem;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use once_cell::sync::OnceCell;
+use tracing::{info, trace};
+use windows::core::{Interface, HRESULT};
+use windows::Win32::Graphics::Direct3D11::ID3D11Device;
+use windows::Win32::Graphics::Direct3D12::ID3D12Device;
+use windows::Win32::Graphics::Dxgi::{IDXGISwapChain, IDXGISwapChain3};
+
+use super::{dx11, dx12};
+use crate::mh::MhHook;
+use crate::{ApplyError, Hooks, ImguiRenderLoop, RenderBackend};
+
+type DXGISwapChainPresentType =
+    unsafe extern "system" fn(this: IDXGISwapChain, sync_interval: u32, flags: u32) -> HRESULT;
+
+const BACKEND_NONE: u8 = 0;
+const BACKEND_DX11: u8 = 1;
+const BACKEND_DX12: u8 = 2;
+
+// The backend of the first swap chain presented. The other one is ignored from
+// then on.
+static ACTIVE_BACKEND: AtomicU8 = AtomicU8::new(BACKEND_NONE);
+// Address of the `Present` method of DirectX 11 swap chains.
+static DX11_PRESENT_ADDR: AtomicUsize = AtomicUsize::new(0);
+static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
+
+// Which backend a swap chain was created for, according to its device.
+unsafe fn swap_chain_backend(swap_chain: &IDXGISwapChain) -> u8 {
+    if swap_chain.GetDevice::<ID3D12Device>().is_ok() {
+        BACKEND_DX12
+    } else if swap_chain.GetDevice::<ID3D11Device>().is_ok() {
+        BACKEND_DX11
+    } else {
+        BACKEND_NONE
+    }
+}
+
+// Make `backend` the active backend if there is none yet, handing it the
+// render loop. Return whether `backend` is the active backend.
+unsafe fn claim_backend(backend: u8) -> bool {
+    let claimed =
+        ACTIVE_BACKEND.compare_exchange(BACKEND_NONE, backend, Ordering::SeqCst, Ordering::SeqCst);
+    if let Err(active_backend) = claimed {
+        return active_backend == backend;
+    }
+
+    info!("Rendering with DirectX {}", if backend == BACKEND_DX11 { "11" } else { "12" });
+    if let Some(render_loop) = RENDER_LOOP.take() {
+        match backend {
+            BACKEND_DX11 => dx11::set_render_loop(render_loop),
+            _ => dx12::set_render_loop(render_loop),
+        }
+    }
+
+    true
+}
+
+unsafe extern "system" fn dxgi_swap_chain_present_impl(
+    swap_chain: IDXGISwapChain,
+    sync_interval: u32,
+    flags: u32,
+) -> HRESULT {
+    let present_addr = swap_chain.vtable().Present as usize;
+    let backend = match swap_chain_backend(&swap_chain) {
+        // DirectX 11 swap chains implementing `Present` at another address
+        // than the dummy one may share their trampoline with other classes.
+        BACKEND_DX11 if present_addr != DX11_PRESENT_ADDR.load(Ordering::SeqCst) => BACKEND_NONE,
+        backend => backend,
+    };
+
+    if backend != BACKEND_NONE && claim_backend(backend) {
+        return match backend {
+            BACKEND_DX11 => dx11::dxgi_swap_chain_present_impl(swap_chain, sync_interval, flags),
+            // `IDXGISwapChain3` extends `IDXGISwapChain`: same object, same
+            // vtable.
+            _ => dx12::dxgi_swap_chain_present_impl(
+                mem::transmute::<IDXGISwapChain, IDXGISwapChain3>(swap_chain),
+                sync_interval,
+                flags,
+            ),
+        };
+    }
+
+    // Without a trampoline, the hooks were removed while this present was in
+    // flight: they're disabled by now, so the vtable leads to the original
+    // function.
+    let dxgi_swap_chain_present =
+        dx12::swap_chain_trampoline(present_addr).unwrap_or(present_addr as *mut c_void);
+    let dxgi_swap_chain_present =
+        mem::transmute::<*mut c_void, DXGISwapChainPresentType>(dxgi_swap_chain_present);
+
+    trace!("Call IDXGISwapChain::Present trampoline");
+    dxgi_swap_chain_present(swap_chain, sync_interval, flags)
+}
+
+/// Hooks for games that may render with either DirectX 11 or DirectX 12.
+///
+/// Use these when you can't tell in advance which API the game uses, or when
+/// both are loaded, e.g. games running DirectX 11 on top of DirectX 12. The
+/// hooks of both [`ImguiDx11Hooks`](super::dx11::ImguiDx11Hooks) and
+/// [`ImguiDx12Hooks`](super::dx12::ImguiDx12Hooks) are installed, and the
+/// backend of the first swap chain presented wins: the render loop is handed
+/// to it, and presents of the other backend are left alone from then on.
+///
+/// Unlike picking the hooks based on which modules are loaded, this is not
+/// fooled by DirectX 11-on-12, where both `d3d11.dll` and `d3d12.dll` are
+/// loaded.
+pub struct ImguiDualDxHooks(Vec<MhHook>);
+
+impl ImguiDualDxHooks {
+    /// Construct a set of [`MhHook`]s that will render UI via the
+    /// provided [`ImguiRenderLoop`].
+    ///
+    /// The following functions are hooked:
+    /// - `IDXGISwapChain::Present`, for both DirectX 11 and DirectX 12 swap
+    ///   chains
+    /// - the other functions hooked by
+    ///   [`ImguiDx12Hooks`](super::dx12::ImguiDx12Hooks)
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn new<T>(t: T) -> Self
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Self::try_new(t).expect("couldn't create DirectX 11 and 12 hooks")
+    }
+
+    /// Like [`new`](Self::new), but returns an error instead of panicking if
+    /// the functions to hook can't be found or hooked.
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn try_new<T>(t: T) -> Result<Self, ApplyError>
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        ACTIVE_BACKEND.store(BACKEND_NONE, Ordering::SeqCst);
+        RENDER_LOOP.get_or_init(|| Box::new(t));
+
+        let (dx12_hooks, dx12_present_addr) =
+            dx12::install(dxgi_swap_chain_present_impl as *mut c_void)?;
+        let mut hooks = Vec::from(dx12_hooks);
+
+        // Both kinds of swap chains usually share the same `Present`: only hook
+        // the DirectX 11 one if it differs.
+        let dx11_present_addr = dx11::get_target_addrs()?.0 as usize;
+        DX11_PRESENT_ADDR.store(dx11_present_addr, Ordering::SeqCst);
+        if dx11_present_addr == dx12_present_addr {
+            dx11::set_trampolines(hooks[0].trampoline(), None);
+        } else {
+            trace!("IDXGISwapChain::Present (DirectX 11) = {:#x}", dx11_present_addr);
+            let hook_present =
+                MhHook::new(dx11_present_addr as *mut _, dxgi_swap_chain_present_impl as *mut _)?
+                    .with_name("IDXGISwapChain::Present");
+
+            dx11::set_trampolines(hook_present.trampoline(), None);
+            dx12::add_swap_chain_hook(dx11_present_addr, hook_present.trampoline());
+            hooks.push(hook_present);
+        }
+
+        Ok(Self(hooks))
+    }
+}
+
+impl Hooks for ImguiDualDxHooks {
+    fn from_render_loop<T>(t: T) -> Result<Box<Self>, ApplyError>
+    where
+        Self: Sized,
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        unsafe { Self::try_new(t) }.map(Box::new)
+    }
+
+    fn hooks(&self) -> &[MhHook] {
+        &self.0
+    }
+
+    fn backend(&self) -> Option<RenderBackend> {
+        match ACTIVE_BACKEND.load(Ordering::SeqCst) {
+            BACKEND_DX11 => Some(RenderBackend::Dx11),
+            BACKEND_DX12 => Some(RenderBackend::Dx12),
+            _ => None,
+        }
+    }
+
+    unsafe fn unhook(&mut self) {
+        dx11::unhook();
+        dx12::unhook();
+        RENDER_LOOP.take();
+        ACTIVE_BACKEND.store(BACKEND_NONE, Ordering::SeqCst);
+        DX11_PRESENT_ADDR.store(0, Ordering::SeqCst);
+    }
+}
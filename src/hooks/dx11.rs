@@ -2,14 +2,16 @@
 
 use std::ffi::c_void;
 use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
 use imgui::Context;
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
-use tracing::{error, trace};
+use tracing::{debug, error, trace};
 use windows::core::{Error, Interface, Result, HRESULT};
-use windows::Win32::Foundation::BOOL;
+use windows::Win32::Foundation::{BOOL, E_FAIL};
 use windows::Win32::Graphics::Direct3D::{
     D3D_DRIVER_TYPE_NULL, D3D_FEATURE_LEVEL_10_0, D3D_FEATURE_LEVEL_11_0,
 };
@@ -17,35 +19,66 @@ use windows::Win32::Graphics::Direct3D11::{
     D3D11CreateDeviceAndSwapChain, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D,
     D3D11_CREATE_DEVICE_FLAG, D3D11_SDK_VERSION,
 };
+use windows::Win32::Graphics::Direct3D12::ID3D12CommandQueue;
 use windows::Win32::Graphics::Dxgi::Common::{
-    DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_MODE_DESC, DXGI_MODE_SCALING_UNSPECIFIED,
+    DXGI_FORMAT, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_MODE_DESC, DXGI_MODE_SCALING_UNSPECIFIED,
     DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_SAMPLE_DESC,
 };
 use windows::Win32::Graphics::Dxgi::{
-    IDXGISwapChain, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_EFFECT_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
+    IDXGISwapChain, IDXGISwapChain1, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_EFFECT_DISCARD,
+    DXGI_USAGE_RENDER_TARGET_OUTPUT,
 };
 
-use super::DummyHwnd;
+use super::{
+    guard_pipeline_init, is_test_present, present_args, record_dxgi_back_buffer,
+    replace_render_loop, reset_unrelated_swap_chains, resize_to_dxgi_back_buffer,
+    skip_startup_present, skip_unrelated_swap_chain, DummyHwnd, ThreadDpiAwareness,
+};
 use crate::mh::MhHook;
-use crate::renderer::{D3D11RenderEngine, Pipeline};
-use crate::{util, Hooks, ImguiRenderLoop};
+use crate::renderer::{font_atlas, mirror, D3D11On12, D3D11RenderEngine, Pipeline};
+use crate::{
+    game_hwnd, options, perform_eject, set_game_hwnd, timings, util, ApplyError, Hooks,
+    ImguiRenderLoop, RenderBackend, EJECT_REQUESTED, HOOK_EJECTION_BARRIER,
+};
 
 type DXGISwapChainPresentType =
     unsafe extern "system" fn(this: IDXGISwapChain, sync_interval: u32, flags: u32) -> HRESULT;
 
+type DXGISwapChainResizeBuffersType = unsafe extern "system" fn(
+    this: IDXGISwapChain,
+    buffer_count: u32,
+    width: u32,
+    height: u32,
+    new_format: DXGI_FORMAT,
+    flags: u32,
+) -> HRESULT;
+
 struct Trampolines {
     dxgi_swap_chain_present: DXGISwapChainPresentType,
+    // Not hooked alongside the DirectX 12 hooks, which hook it themselves.
+    dxgi_swap_chain_resize_buffers: Option<DXGISwapChainResizeBuffersType>,
 }
 
+// Time after which the swap chain the pipeline renders into is considered
+// released if it stopped presenting, and the pipeline moves to another one.
+const SWAP_CHAIN_RELEASE_TIMEOUT: Duration = Duration::from_secs(2);
+
 static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
 static mut PIPELINE: OnceCell<Mutex<Pipeline<D3D11RenderEngine>>> = OnceCell::new();
+// Raw pointer of the swap chain the pipeline renders into, so that presents of
+// other swap chains in the same process are left untouched.
+static PIPELINE_SWAP_CHAIN: AtomicUsize = AtomicUsize::new(0);
+// When the swap chain the pipeline renders into last presented.
+static PIPELINE_SWAP_CHAIN_PRESENT: Mutex<Option<Instant>> = Mutex::new(None);
 static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
 
 unsafe fn init_pipeline(swap_chain: &IDXGISwapChain) -> Result<Mutex<Pipeline<D3D11RenderEngine>>> {
     let hwnd = util::try_out_param(|v| swap_chain.GetDesc(v)).map(|desc| desc.OutputWindow)?;
+    record_dxgi_back_buffer(swap_chain)?;
 
     let mut ctx = Context::create();
-    let engine = D3D11RenderEngine::new(&swap_chain.GetDevice()?, &mut ctx)?;
+    let mut engine = create_engine(swap_chain, &mut ctx)?;
+    engine.set_swap_chain(swap_chain.as_raw());
 
     let Some(render_loop) = RENDER_LOOP.take() else {
         error!("Render loop not yet initialized");
@@ -57,44 +90,277 @@ unsafe fn init_pipeline(swap_chain: &IDXGISwapChain) -> Result<Mutex<Pipeline<D3
         e
     })?;
 
+    PIPELINE_SWAP_CHAIN.store(swap_chain.as_raw() as usize, Ordering::SeqCst);
+    *PIPELINE_SWAP_CHAIN_PRESENT.lock() = Some(Instant::now());
+
     Ok(Mutex::new(pipeline))
 }
 
-fn render(swap_chain: &IDXGISwapChain) -> Result<()> {
+// Renderer drawing into the back buffers of `swap_chain`, with its device or,
+// for Direct3D 12 swap chains, through a D3D11On12 device over its command
+// queue.
+unsafe fn create_engine(
+    swap_chain: &IDXGISwapChain,
+    ctx: &mut Context,
+) -> Result<D3D11RenderEngine> {
+    match swap_chain.GetDevice::<ID3D12CommandQueue>() {
+        Ok(command_queue) if options::get().d3d11on12 => {
+            trace!("Rendering via D3D11On12");
+            D3D11RenderEngine::with_d3d11on12(D3D11On12::new(&command_queue)?, ctx)
+        },
+        _ => D3D11RenderEngine::new(&swap_chain.GetDevice()?, ctx),
+    }
+}
+
+// Whether the pipeline renders into `swap_chain`. The pipeline follows the
+// game to another swap chain when it presents to the window of the pipeline,
+// replacing the previous one, or once the previous one stopped presenting, as
+// it was likely released: its address may be reused from then on.
+unsafe fn track_swap_chain(
+    pipeline: &mut Pipeline<D3D11RenderEngine>,
+    swap_chain: &IDXGISwapChain,
+) -> Result<bool> {
+    let now = Instant::now();
+    let mut last_present = PIPELINE_SWAP_CHAIN_PRESENT.lock();
+    if swap_chain.as_raw() as usize == PIPELINE_SWAP_CHAIN.load(Ordering::SeqCst) {
+        *last_present = Some(now);
+        return Ok(true);
+    }
+
+    let hwnd = util::try_out_param(|v| swap_chain.GetDesc(v))?.OutputWindow;
+    let released = last_present
+        .is_some_and(|last_present| now.duration_since(last_present) >= SWAP_CHAIN_RELEASE_TIMEOUT);
+    if hwnd != pipeline.hwnd() && !released {
+        return Ok(false);
+    }
+
+    debug!("Moving the pipeline to swap chain {swap_chain:?}");
+    let same_device = swap_chain
+        .GetDevice::<ID3D11Device>()
+        .is_ok_and(|device| device == *pipeline.engine().device());
+    if !same_device {
+        pipeline.replace_engine(|ctx| create_engine(swap_chain, ctx))?;
+    }
+    pipeline.retarget(hwnd);
+    mirror::close();
+
+    PIPELINE_SWAP_CHAIN.store(swap_chain.as_raw() as usize, Ordering::SeqCst);
+    *last_present = Some(now);
+
+    Ok(true)
+}
+
+// Draw the overlay on the current back buffer of `swap_chain`, returning
+// whether it was drawn, i.e. the pipeline is pinned to the swap chain.
+fn render(swap_chain: &IDXGISwapChain) -> Result<bool> {
+    let _dpi_awareness = ThreadDpiAwareness::enter();
+
     unsafe {
-        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline(swap_chain))?;
+        let pipeline =
+            PIPELINE.get_or_try_init(|| guard_pipeline_init(|| init_pipeline(swap_chain)))?;
 
         let Some(mut pipeline) = pipeline.try_lock() else {
             error!("Could not lock pipeline");
             return Err(Error::from_hresult(HRESULT(-1)));
         };
 
+        if !track_swap_chain(&mut pipeline, swap_chain)? {
+            skip_unrelated_swap_chain(swap_chain);
+            return Ok(false);
+        }
+
+        replace_render_loop(&mut pipeline)?;
+        pipeline.engine().set_swap_chain(swap_chain.as_raw());
+
+        record_dxgi_back_buffer(swap_chain)?;
         pipeline.prepare_render()?;
 
+        // In exclusive fullscreen, the display mode may have been switched
+        // (`DXGI_SWAP_CHAIN_FLAG_ALLOW_MODE_SWITCH`) without the window being
+        // resized accordingly: the back buffer, not the client area, is what
+        // we are drawing to.
+        let mut fullscreen = BOOL(0);
+        swap_chain.GetFullscreenState(Some(&mut fullscreen), None)?;
+        if fullscreen.as_bool() {
+            let desc = util::try_out_param(|v| swap_chain.GetDesc(v))?;
+            pipeline.resize(desc.BufferDesc.Width, desc.BufferDesc.Height);
+        } else {
+            resize_to_dxgi_back_buffer(&mut pipeline, swap_chain)?;
+        }
+
+        if let Some(d3d11on12) = pipeline.engine().d3d11on12() {
+            let target = d3d11on12.acquire_back_buffer(&swap_chain.cast()?)?;
+            let result = pipeline.render(target.clone());
+            if let Some(d3d11on12) = pipeline.engine().d3d11on12() {
+                d3d11on12.release_back_buffer(&target)?;
+            }
+            return result.map(|()| true);
+        }
+
+        // Buffer 0 is always the current back buffer in DirectX 11, in both
+        // windowed and fullscreen mode. It must be retrieved every frame, as
+        // the buffers are recreated on mode switches.
         let target: ID3D11Texture2D = swap_chain.GetBuffer(0)?;
 
         pipeline.render(target)?;
+
+        if let Err(e) = render_mirror(&mut pipeline, swap_chain) {
+            error!("Mirror render error: {e:?}");
+        }
     }
-    Ok(())
+    Ok(true)
 }
 
-unsafe extern "system" fn dxgi_swap_chain_present_impl(
+// Draw the frame again on the mirror window, if one is configured, and
+// present it.
+unsafe fn render_mirror(
+    pipeline: &mut Pipeline<D3D11RenderEngine>,
+    swap_chain: &IDXGISwapChain,
+) -> Result<()> {
+    let desc = util::try_out_param(|v| swap_chain.GetDesc(v))?;
+    let size = [desc.BufferDesc.Width, desc.BufferDesc.Height];
+
+    mirror::with_mirror_window(size, |mirror| {
+        let device: ID3D11Device = swap_chain.GetDevice()?;
+        let mirror_swap_chain = mirror.swap_chain(swap_chain, size, |factory, hwnd, desc| {
+            factory.CreateSwapChainForHwnd(&device, hwnd, desc, None, None)
+        })?;
+
+        let target: ID3D11Texture2D = mirror_swap_chain.GetBuffer(0)?;
+        pipeline.engine().clear_next_target(mirror.background());
+        pipeline.render_mirror(target)?;
+
+        present_mirror(swap_chain, &mirror_swap_chain)
+    })
+}
+
+// Present the swap chain of the mirror window. If it shares its `Present` with
+// the hooked swap chain, go through the trampoline rather than the hook.
+unsafe fn present_mirror(
+    swap_chain: &IDXGISwapChain,
+    mirror_swap_chain: &IDXGISwapChain1,
+) -> Result<()> {
+    let mirror_swap_chain: IDXGISwapChain = mirror_swap_chain.cast()?;
+    let present_addr = mirror_swap_chain.vtable().Present as usize;
+
+    let present = match TRAMPOLINES.get() {
+        Some(&Trampolines { dxgi_swap_chain_present, .. })
+            if present_addr == swap_chain.vtable().Present as usize =>
+        {
+            dxgi_swap_chain_present
+        },
+        _ => mem::transmute::<usize, DXGISwapChainPresentType>(present_addr),
+    };
+
+    // Borrowed by `Present`, like the swap chain passed to the hook.
+    present(mem::transmute_copy(&mirror_swap_chain), 0, 0).ok()
+}
+
+// Drop the back buffers wrapped for D3D11On12, as any reference to them makes
+// `ResizeBuffers` fail.
+pub(super) unsafe fn release_back_buffers() {
+    if let Some(pipeline) = PIPELINE.get() {
+        if let Some(d3d11on12) = pipeline.lock().engine().d3d11on12() {
+            d3d11on12.release_back_buffers();
+        }
+    }
+}
+
+pub(super) unsafe extern "system" fn dxgi_swap_chain_present_impl(
     swap_chain: IDXGISwapChain,
     sync_interval: u32,
     flags: u32,
 ) -> HRESULT {
-    let Trampolines { dxgi_swap_chain_present } =
-        TRAMPOLINES.get().expect("DirectX 11 trampolines uninitialized");
+    let present_start = Instant::now();
+    let _hook_ejection_guard = HOOK_EJECTION_BARRIER.acquire_ejection_guard();
+
+    let Some(&Trampolines { dxgi_swap_chain_present, .. }) = TRAMPOLINES.get() else {
+        // The hooks were removed while this present was in flight: they're
+        // disabled by now, so the vtable leads to the original function.
+        trace!("DirectX 11 trampolines uninitialized, skipping render");
+        let present =
+            mem::transmute::<usize, DXGISwapChainPresentType>(swap_chain.vtable().Present as usize);
+        return present(swap_chain, sync_interval, flags);
+    };
+
+    if is_test_present(flags) {
+        trace!("Forwarding test present");
+        return dxgi_swap_chain_present(swap_chain, sync_interval, flags);
+    }
 
-    if let Err(e) = render(&swap_chain) {
-        error!("Render error: {e:?}");
+    let rendered = if skip_startup_present() {
+        trace!("Skipping startup present");
+        false
+    } else {
+        if game_hwnd().is_none() {
+            if let Ok(desc) = util::try_out_param(|v| swap_chain.GetDesc(v)) {
+                set_game_hwnd(desc.OutputWindow);
+            }
+        }
+
+        render(&swap_chain).unwrap_or_else(|e| {
+            error!("Render error: {e:?}");
+            false
+        })
+    };
+
+    timings::record(present_start.elapsed());
+
+    // Only the presents of the swap chain the overlay was drawn on are
+    // reported to the render loop.
+    let rendered =
+        rendered && swap_chain.as_raw() as usize == PIPELINE_SWAP_CHAIN.load(Ordering::SeqCst);
+    if rendered {
+        if let Some(mut pipeline) = PIPELINE.get().and_then(|pipeline| pipeline.try_lock()) {
+            pipeline.render_loop().before_present();
+        }
     }
 
+    let (sync_interval, flags) = present_args(sync_interval, flags);
     trace!("Call IDXGISwapChain::Present trampoline");
-    dxgi_swap_chain_present(swap_chain, sync_interval, flags)
+    let result = dxgi_swap_chain_present(swap_chain, sync_interval, flags);
+
+    if rendered {
+        if let Some(mut pipeline) = PIPELINE.get().and_then(|pipeline| pipeline.try_lock()) {
+            pipeline.render_loop().after_present(result);
+        }
+    }
+
+    if EJECT_REQUESTED.swap(false, Ordering::SeqCst) {
+        perform_eject();
+    }
+
+    result
 }
 
-fn get_target_addrs() -> DXGISwapChainPresentType {
+unsafe extern "system" fn dxgi_swap_chain_resize_buffers_impl(
+    swap_chain: IDXGISwapChain,
+    buffer_count: u32,
+    width: u32,
+    height: u32,
+    new_format: DXGI_FORMAT,
+    flags: u32,
+) -> HRESULT {
+    let _hook_ejection_guard = HOOK_EJECTION_BARRIER.acquire_ejection_guard();
+
+    let Some(&Trampolines { dxgi_swap_chain_resize_buffers: Some(resize_buffers), .. }) =
+        TRAMPOLINES.get()
+    else {
+        trace!("DirectX 11 trampolines uninitialized, calling ResizeBuffers");
+        let resize_buffers = mem::transmute::<usize, DXGISwapChainResizeBuffersType>(
+            swap_chain.vtable().ResizeBuffers as usize,
+        );
+        return resize_buffers(swap_chain, buffer_count, width, height, new_format, flags);
+    };
+
+    release_back_buffers();
+
+    trace!("Call IDXGISwapChain::ResizeBuffers trampoline");
+    resize_buffers(swap_chain, buffer_count, width, height, new_format, flags)
+}
+
+pub(super) fn get_target_addrs(
+) -> std::result::Result<(DXGISwapChainPresentType, DXGISwapChainResizeBuffersType), ApplyError> {
     let mut p_device: Option<ID3D11Device> = None;
     let mut p_context: Option<ID3D11DeviceContext> = None;
     let mut p_swap_chain: Option<IDXGISwapChain> = None;
@@ -128,10 +394,13 @@ fn get_target_addrs() -> DXGISwapChainPresentType {
             None,
             Some(&mut p_context),
         )
-        .expect("D3D11CreateDeviceAndSwapChain failed");
+        .map_err(|e| {
+            error!("D3D11CreateDeviceAndSwapChain failed: {e:?}");
+            e
+        })?;
     }
 
-    let swap_chain = p_swap_chain.unwrap();
+    let swap_chain = p_swap_chain.ok_or(ApplyError::PresentAddrFailed(E_FAIL))?;
 
     let present_ptr: DXGISwapChainPresentType = unsafe {
         mem::transmute::<
@@ -139,12 +408,29 @@ fn get_target_addrs() -> DXGISwapChainPresentType {
             DXGISwapChainPresentType,
         >(swap_chain.vtable().Present)
     };
+    let resize_buffers_ptr: DXGISwapChainResizeBuffersType =
+        unsafe { mem::transmute(swap_chain.vtable().ResizeBuffers) };
 
-    present_ptr
+    Ok((present_ptr, resize_buffers_ptr))
 }
 
 /// Hooks for DirectX 11.
-pub struct ImguiDx11Hooks([MhHook; 1]);
+///
+/// Exclusive fullscreen is supported, but some games handle display mode
+/// switches in ways that leave the overlay invisible or misplaced. If that
+/// happens, running the game in borderless windowed mode is the most reliable
+/// workaround.
+///
+/// With [`HudhookBuilder::with_d3d11on12`](crate::HudhookBuilder::with_d3d11on12),
+/// these hooks also draw on the swap chains of Direct3D 12 games, e.g. games
+/// that compose their UI with Direct3D 11 via D3D11On12.
+///
+/// The overlay is drawn into a single swap chain: the first one to present.
+/// It follows the game to a swap chain presenting to the same window, or to
+/// any other one after the current one stopped presenting. Other swap chains
+/// are left untouched, see
+/// [`RenderContext::swap_chain_raw`](crate::RenderContext::swap_chain_raw).
+pub struct ImguiDx11Hooks([MhHook; 2]);
 
 impl ImguiDx11Hooks {
     /// Construct a set of [`MhHook`]s that will render UI via the
@@ -152,6 +438,7 @@ impl ImguiDx11Hooks {
     ///
     /// The following functions are hooked:
     /// - `IDXGISwapChain::Present`
+    /// - `IDXGISwapChain::ResizeBuffers`
     ///
     /// # Safety
     ///
@@ -160,42 +447,87 @@ impl ImguiDx11Hooks {
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        let dxgi_swap_chain_present_addr = get_target_addrs();
+        Self::try_new(t).expect("couldn't create DirectX 11 hooks")
+    }
+
+    /// Like [`new`](Self::new), but returns an error instead of panicking if
+    /// the functions to hook can't be found or hooked.
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn try_new<T>(t: T) -> std::result::Result<Self, ApplyError>
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        let (dxgi_swap_chain_present_addr, dxgi_swap_chain_resize_buffers_addr) =
+            get_target_addrs()?;
 
         trace!("IDXGISwapChain::Present = {:p}", dxgi_swap_chain_present_addr as *const c_void);
         let hook_present = MhHook::new(
             dxgi_swap_chain_present_addr as *mut _,
             dxgi_swap_chain_present_impl as *mut _,
-        )
-        .expect("couldn't create IDXGISwapChain::Present hook");
+        )?
+        .with_name("IDXGISwapChain::Present");
+        trace!(
+            "IDXGISwapChain::ResizeBuffers = {:p}",
+            dxgi_swap_chain_resize_buffers_addr as *const c_void
+        );
+        let hook_resize_buffers = MhHook::new(
+            dxgi_swap_chain_resize_buffers_addr as *mut _,
+            dxgi_swap_chain_resize_buffers_impl as *mut _,
+        )?
+        .with_name("IDXGISwapChain::ResizeBuffers");
 
         RENDER_LOOP.get_or_init(|| Box::new(t));
-        TRAMPOLINES.get_or_init(|| Trampolines {
-            dxgi_swap_chain_present: mem::transmute::<*mut c_void, DXGISwapChainPresentType>(
-                hook_present.trampoline(),
-            ),
-        });
+        set_trampolines(hook_present.trampoline(), Some(hook_resize_buffers.trampoline()));
 
-        Self([hook_present])
+        Ok(Self([hook_present, hook_resize_buffers]))
     }
 }
 
+pub(super) unsafe fn set_trampolines(present: *mut c_void, resize_buffers: Option<*mut c_void>) {
+    TRAMPOLINES.get_or_init(|| Trampolines {
+        dxgi_swap_chain_present: mem::transmute::<*mut c_void, DXGISwapChainPresentType>(present),
+        dxgi_swap_chain_resize_buffers: resize_buffers.map(|resize_buffers| {
+            mem::transmute::<*mut c_void, DXGISwapChainResizeBuffersType>(resize_buffers)
+        }),
+    });
+}
+
+#[cfg(feature = "dx12")]
+pub(super) unsafe fn set_render_loop(render_loop: Box<dyn ImguiRenderLoop + Send + Sync>) {
+    RENDER_LOOP.get_or_init(move || render_loop);
+}
+
+pub(super) unsafe fn unhook() {
+    TRAMPOLINES.take();
+    PIPELINE.take().map(|p| p.into_inner().take());
+    font_atlas::reset();
+    mirror::close();
+    RENDER_LOOP.take(); // should already be null
+    PIPELINE_SWAP_CHAIN.store(0, Ordering::SeqCst);
+    reset_unrelated_swap_chains();
+}
+
 impl Hooks for ImguiDx11Hooks {
-    fn from_render_loop<T>(t: T) -> Box<Self>
+    fn from_render_loop<T>(t: T) -> std::result::Result<Box<Self>, ApplyError>
     where
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        Box::new(unsafe { Self::new(t) })
+        unsafe { Self::try_new(t) }.map(Box::new)
     }
 
     fn hooks(&self) -> &[MhHook] {
         &self.0
     }
 
+    fn backend(&self) -> Option<RenderBackend> {
+        Some(RenderBackend::Dx11)
+    }
+
     unsafe fn unhook(&mut self) {
-        TRAMPOLINES.take();
-        PIPELINE.take().map(|p| p.into_inner().take());
-        RENDER_LOOP.take(); // should already be null
+        unhook();
     }
 }
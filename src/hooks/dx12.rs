@@ -1,16 +1,17 @@
 //! Hooks for DirectX 12.
 
 use std::ffi::c_void;
-use std::mem;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::OnceLock;
+use std::time::Instant;
+use std::{mem, ptr};
 
 use imgui::Context;
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
-use tracing::{debug, error, trace, warn};
-use windows::core::{Error, Interface, Result, HRESULT};
-use windows::Win32::Foundation::BOOL;
+use tracing::{debug, error, info, trace, warn};
+use windows::core::{Error, IUnknown, Interface, Result, HRESULT};
+use windows::Win32::Foundation::{BOOL, HWND, S_OK};
 use windows::Win32::Graphics::Direct3D::D3D_FEATURE_LEVEL_11_0;
 use windows::Win32::Graphics::Direct3D12::{
     D3D12CreateDevice, ID3D12CommandList, ID3D12CommandQueue, ID3D12Device, ID3D12Resource,
@@ -21,15 +22,24 @@ use windows::Win32::Graphics::Dxgi::Common::{
     DXGI_MODE_SCANLINE_ORDER_UNSPECIFIED, DXGI_RATIONAL, DXGI_SAMPLE_DESC,
 };
 use windows::Win32::Graphics::Dxgi::{
-    CreateDXGIFactory2, IDXGIFactory2, IDXGISwapChain, IDXGISwapChain3, DXGI_SWAP_CHAIN_DESC,
-    DXGI_SWAP_CHAIN_FLAG_ALLOW_MODE_SWITCH, DXGI_SWAP_EFFECT_FLIP_DISCARD,
+    CreateDXGIFactory2, IDXGIFactory, IDXGIFactory2, IDXGISwapChain, IDXGISwapChain1,
+    IDXGISwapChain2, IDXGISwapChain3, DXGI_SWAP_CHAIN_DESC, DXGI_SWAP_CHAIN_DESC1,
+    DXGI_SWAP_CHAIN_FLAG_ALLOW_MODE_SWITCH, DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT,
+    DXGI_SWAP_CHAIN_FULLSCREEN_DESC, DXGI_SWAP_EFFECT_FLIP_DISCARD,
     DXGI_USAGE_RENDER_TARGET_OUTPUT,
 };
 
-use super::DummyHwnd;
-use crate::mh::MhHook;
-use crate::renderer::{D3D12RenderEngine, Pipeline};
-use crate::{perform_eject, util, Hooks, ImguiRenderLoop, EJECT_REQUESTED, HOOK_EJECTION_BARRIER};
+use super::{
+    guard_pipeline_init, is_init_skipped, is_test_present, present_args, record_dxgi_back_buffer,
+    replace_render_loop, reset_unrelated_swap_chains, resize_to_dxgi_back_buffer,
+    skip_startup_present, skip_unrelated_swap_chain, DummyHwnd, InitBackoff, ThreadDpiAwareness,
+};
+use crate::mh::{MH_ApplyQueued, MhHook};
+use crate::renderer::{font_atlas, mirror, pixel_sample, D3D12RenderEngine, Pipeline};
+use crate::{
+    game_hwnd, options, perform_eject, set_game_hwnd, timings, util, ApplyError, Hooks,
+    ImguiRenderLoop, RenderBackend, ResizeParams, EJECT_REQUESTED, HOOK_EJECTION_BARRIER,
+};
 
 type DXGISwapChainPresentType =
     unsafe extern "system" fn(this: IDXGISwapChain3, sync_interval: u32, flags: u32) -> HRESULT;
@@ -49,10 +59,29 @@ type D3D12CommandQueueExecuteCommandListsType = unsafe extern "system" fn(
     command_lists: *mut ID3D12CommandList,
 );
 
+type DXGIFactoryCreateSwapChainType = unsafe extern "system" fn(
+    this: IDXGIFactory,
+    device: *mut c_void,
+    desc: *const DXGI_SWAP_CHAIN_DESC,
+    swap_chain: *mut *mut c_void,
+) -> HRESULT;
+
+type DXGIFactoryCreateSwapChainForHwndType = unsafe extern "system" fn(
+    this: IDXGIFactory2,
+    device: *mut c_void,
+    hwnd: HWND,
+    desc: *const DXGI_SWAP_CHAIN_DESC1,
+    fullscreen_desc: *const DXGI_SWAP_CHAIN_FULLSCREEN_DESC,
+    restrict_to_output: *mut c_void,
+    swap_chain: *mut *mut c_void,
+) -> HRESULT;
+
 struct Trampolines {
     dxgi_swap_chain_present: DXGISwapChainPresentType,
     dxgi_swap_chain_resize_buffers: DXGISwapChainResizeBuffersType,
     d3d12_command_queue_execute_command_lists: D3D12CommandQueueExecuteCommandListsType,
+    dxgi_factory_create_swap_chain: DXGIFactoryCreateSwapChainType,
+    dxgi_factory_create_swap_chain_for_hwnd: DXGIFactoryCreateSwapChainForHwndType,
 }
 
 static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
@@ -114,6 +143,35 @@ impl InitializationContext {
         swap_chain: &IDXGISwapChain3,
         command_queue: &ID3D12CommandQueue,
     ) -> bool {
+        // Only direct queues can present. Copy and compute queues, as well as
+        // the command lists (e.g. bundles) submitted to them, are irrelevant.
+        let queue_type = command_queue.GetDesc().Type;
+        if queue_type != D3D12_COMMAND_LIST_TYPE_DIRECT {
+            trace!("Skipping command queue {command_queue:?} of type {queue_type:?}");
+            return false;
+        }
+
+        // In multi-device applications, a direct queue may belong to an
+        // unrelated device.
+        let swap_chain_device = swap_chain.GetDevice::<ID3D12Device>();
+        let command_queue_device: Result<ID3D12Device> =
+            util::try_out_ptr(|v| command_queue.GetDevice(v));
+        match (swap_chain_device, command_queue_device) {
+            (Ok(swap_chain_device), Ok(command_queue_device))
+                if swap_chain_device.as_raw() == command_queue_device.as_raw() => {},
+            (Ok(swap_chain_device), Ok(command_queue_device)) => {
+                debug!(
+                    "Skipping command queue {command_queue:?}: its device \
+                     {command_queue_device:?} doesn't match the swap chain device \
+                     {swap_chain_device:?}"
+                );
+                return false;
+            },
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Couldn't compare command queue and swap chain devices: {e:?}");
+            },
+        }
+
         let swap_chain_ptr = swap_chain.as_raw() as *mut *mut c_void;
         let readable_ptrs = util::readable_region(swap_chain_ptr, 512);
 
@@ -140,7 +198,23 @@ impl InitializationContext {
 static INITIALIZATION_CONTEXT: Mutex<InitializationContext> =
     Mutex::new(InitializationContext::Empty);
 static mut PIPELINE: OnceCell<Mutex<Pipeline<D3D12RenderEngine>>> = OnceCell::new();
+// Raw pointer of the swap chain the pipeline renders into. Applications may
+// present more than one swap chain (e.g. an editor and a game view): only the
+// one the pipeline was created for receives the overlay.
+static PIPELINE_SWAP_CHAIN: AtomicUsize = AtomicUsize::new(0);
+// Retries of the renderer creation after it failed.
+static ENGINE_INIT_BACKOFF: Mutex<InitBackoff> = Mutex::new(InitBackoff::new());
 static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
+// Addresses of the hooked swap chain methods, and of their trampolines. Swap
+// chains created after startup may implement `Present` and `ResizeBuffers` at
+// different addresses than the dummy swap chain did: these are hooked when
+// the swap chain is created.
+static SWAP_CHAIN_HOOKS: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+// Hooks created for those swap chains. They are leaked so that they can be
+// enabled, disabled and listed along with the hooks of `Hooks::hooks`.
+static REHOOKED_METHODS: Mutex<Vec<RehookedMethod>> = Mutex::new(Vec::new());
+// Detour of the hooked `Present` methods.
+static PRESENT_DETOUR: AtomicUsize = AtomicUsize::new(0);
 
 unsafe fn init_pipeline() -> Result<Mutex<Pipeline<D3D12RenderEngine>>> {
     let Some((swap_chain, command_queue)) = ({ INITIALIZATION_CONTEXT.lock().get() }) else {
@@ -149,9 +223,19 @@ unsafe fn init_pipeline() -> Result<Mutex<Pipeline<D3D12RenderEngine>>> {
     };
 
     let hwnd = util::try_out_param(|v| swap_chain.GetDesc(v)).map(|desc| desc.OutputWindow)?;
+    // The swap chain the game presents with, not the one created to find the
+    // functions to hook.
+    record_dxgi_back_buffer(&swap_chain)?;
 
-    let mut ctx = Context::create();
-    let engine = D3D12RenderEngine::new(&command_queue, &mut ctx)?;
+    // Creating the renderer allocates descriptor heaps, command allocators and
+    // lists, and buffers, which may fail when the device is out of memory:
+    // back off instead of trying again at every present.
+    let (ctx, mut engine) = ENGINE_INIT_BACKOFF.lock().attempt(Instant::now(), || {
+        let mut ctx = Context::create();
+        let engine = D3D12RenderEngine::new(&command_queue, &mut ctx)?;
+        Ok((ctx, engine))
+    })?;
+    engine.set_swap_chain(swap_chain.as_raw());
 
     let Some(render_loop) = RENDER_LOOP.take() else {
         error!("Render loop not yet initialized");
@@ -167,51 +251,322 @@ unsafe fn init_pipeline() -> Result<Mutex<Pipeline<D3D12RenderEngine>>> {
         INITIALIZATION_CONTEXT.lock().done();
     }
 
+    PIPELINE_SWAP_CHAIN.store(swap_chain.as_raw() as usize, Ordering::SeqCst);
+
     Ok(Mutex::new(pipeline))
 }
 
-fn render(swap_chain: &IDXGISwapChain3) -> Result<()> {
+// Whether `swap_chain` replaces the one of the pipeline after the game moved
+// to another adapter, e.g. from the integrated to the discrete GPU of a hybrid
+// laptop: it presents to the same window, from a device the renderer wasn't
+// created with. The resources of the renderer can't be used with it.
+unsafe fn is_adapter_switch(
+    pipeline: &mut Pipeline<D3D12RenderEngine>,
+    swap_chain: &IDXGISwapChain3,
+) -> bool {
+    if swap_chain.as_raw() as usize == PIPELINE_SWAP_CHAIN.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    let Ok(desc) = util::try_out_param(|v| swap_chain.GetDesc(v)) else {
+        return false;
+    };
+    if desc.OutputWindow != pipeline.hwnd() {
+        return false;
+    }
+
+    match swap_chain.GetDevice::<ID3D12Device>() {
+        Ok(device) => device.as_raw() != pipeline.engine().device().as_raw(),
+        Err(_) => false,
+    }
+}
+
+// Rebuild the renderer of the pipeline for `swap_chain` after the game moved
+// to another adapter, once the command queue presenting to it is found. The
+// pipeline stays in place, along with its render loop.
+unsafe fn rebuild_engine(
+    pipeline: &mut Pipeline<D3D12RenderEngine>,
+    swap_chain: &IDXGISwapChain3,
+) -> Result<()> {
+    let command_queue = {
+        let mut initialization_context = INITIALIZATION_CONTEXT.lock();
+        match initialization_context.get() {
+            Some((context_swap_chain, command_queue)) if context_swap_chain == *swap_chain => {
+                command_queue
+            },
+            _ => {
+                if !matches!(
+                    &*initialization_context,
+                    InitializationContext::WithSwapChain(context_swap_chain)
+                        if context_swap_chain == swap_chain
+                ) {
+                    info!("The game switched to another device, rebuilding the renderer");
+                    *ENGINE_INIT_BACKOFF.lock() = InitBackoff::new();
+                    *initialization_context =
+                        InitializationContext::WithSwapChain(swap_chain.clone());
+                }
+                return Ok(());
+            },
+        }
+    };
+
+    ENGINE_INIT_BACKOFF.lock().attempt(Instant::now(), || {
+        // The previous device may be gone already.
+        if let Err(e) = pipeline.engine().flush() {
+            warn!("Could not flush the previous device: {e:?}");
+        }
+        mirror::close();
+        pipeline.replace_engine(|ctx| D3D12RenderEngine::new(&command_queue, ctx))
+    })?;
+
+    {
+        INITIALIZATION_CONTEXT.lock().done();
+    }
+
+    PIPELINE_SWAP_CHAIN.store(swap_chain.as_raw() as usize, Ordering::SeqCst);
+
+    Ok(())
+}
+
+// Draw the overlay on the current back buffer of `swap_chain`, returning
+// whether it was drawn, i.e. the pipeline is pinned to the swap chain.
+fn render(swap_chain: &IDXGISwapChain3) -> Result<bool> {
+    let _dpi_awareness = ThreadDpiAwareness::enter();
+
     unsafe {
-        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline())?;
+        let pipeline = PIPELINE.get_or_try_init(|| guard_pipeline_init(|| init_pipeline()))?;
 
         let Some(mut pipeline) = pipeline.try_lock() else {
             error!("Could not lock pipeline");
             return Err(Error::from_hresult(HRESULT(-1)));
         };
 
+        if swap_chain.as_raw() as usize != PIPELINE_SWAP_CHAIN.load(Ordering::SeqCst) {
+            if is_adapter_switch(&mut pipeline, swap_chain) {
+                rebuild_engine(&mut pipeline, swap_chain)?;
+            }
+            if swap_chain.as_raw() as usize != PIPELINE_SWAP_CHAIN.load(Ordering::SeqCst) {
+                skip_unrelated_swap_chain(swap_chain);
+                return Ok(false);
+            }
+        }
+
+        replace_render_loop(&mut pipeline)?;
+        pipeline.engine().set_swap_chain(swap_chain.as_raw());
+
+        let (swapchain_waitable_object, shared_capture) = {
+            let options = options::get();
+            (options.swapchain_waitable_object, options.shared_capture)
+        };
+
+        if swapchain_waitable_object {
+            if let Some(frame_latency) = waitable_frame_latency(swap_chain) {
+                pipeline.engine().reserve_frame_contexts(frame_latency as usize)?;
+            }
+        }
+
+        record_dxgi_back_buffer(swap_chain)?;
         pipeline.prepare_render()?;
+        resize_to_dxgi_back_buffer(&mut pipeline, swap_chain)?;
 
         let target: ID3D12Resource =
             swap_chain.GetBuffer(swap_chain.GetCurrentBackBufferIndex())?;
 
+        if shared_capture {
+            pipeline.engine().capture_next_target();
+        }
+        if let Some(point) = pixel_sample::take_request() {
+            let desc = target.GetDesc();
+            let [x, y] = pipeline.client_mapping().map([point[0] as f32, point[1] as f32]);
+            if (0. ..desc.Width as f32).contains(&x) && (0. ..desc.Height as f32).contains(&y) {
+                pipeline.engine().sample_next_target(point, [x as u32, y as u32]);
+            }
+        }
         pipeline.render(target)?;
+
+        if let Err(e) = render_mirror(&mut pipeline, swap_chain) {
+            error!("Mirror render error: {e:?}");
+        }
     }
 
-    Ok(())
+    Ok(true)
+}
+
+// Draw the frame again on the mirror window, if one is configured, and
+// present it.
+unsafe fn render_mirror(
+    pipeline: &mut Pipeline<D3D12RenderEngine>,
+    swap_chain: &IDXGISwapChain3,
+) -> Result<()> {
+    let desc = util::try_out_param(|v| swap_chain.GetDesc(v))?;
+    let size = [desc.BufferDesc.Width, desc.BufferDesc.Height];
+
+    mirror::with_mirror_window(size, |mirror| {
+        // The swap chain of the window can't be replaced while the render
+        // engine holds references to its back buffers.
+        if mirror.needs_swap_chain(size) {
+            pipeline.engine().flush()?;
+        }
+
+        let command_queue = pipeline.engine().command_queue().clone();
+        let mirror_swap_chain = mirror.swap_chain(swap_chain, size, |factory, hwnd, desc| {
+            create_mirror_swap_chain(factory, &command_queue, hwnd, desc)
+        })?;
+        let mirror_swap_chain: IDXGISwapChain3 = mirror_swap_chain.cast()?;
+
+        let target: ID3D12Resource =
+            mirror_swap_chain.GetBuffer(mirror_swap_chain.GetCurrentBackBufferIndex())?;
+        pipeline.engine().clear_next_target(mirror.background());
+        pipeline.render_mirror(target)?;
+
+        present_mirror(swap_chain, &mirror_swap_chain)
+    })
+}
+
+// Create the swap chain of the mirror window, through the trampoline so that it
+// isn't mistaken for a swap chain of the game.
+unsafe fn create_mirror_swap_chain(
+    factory: &IDXGIFactory2,
+    command_queue: &ID3D12CommandQueue,
+    hwnd: HWND,
+    desc: &DXGI_SWAP_CHAIN_DESC1,
+) -> Result<IDXGISwapChain1> {
+    let Some(&Trampolines { dxgi_factory_create_swap_chain_for_hwnd, .. }) = TRAMPOLINES.get()
+    else {
+        return factory.CreateSwapChainForHwnd(command_queue, hwnd, desc, None, None);
+    };
+
+    let mut swap_chain = ptr::null_mut();
+    // Borrowed by `CreateSwapChainForHwnd`, like the factory passed to the hook.
+    dxgi_factory_create_swap_chain_for_hwnd(
+        mem::transmute_copy(factory),
+        command_queue.as_raw(),
+        hwnd,
+        desc,
+        ptr::null(),
+        ptr::null_mut(),
+        &mut swap_chain,
+    )
+    .ok()?;
+
+    Ok(IDXGISwapChain1::from_raw(swap_chain))
+}
+
+// Present the swap chain of the mirror window. If its `Present` is hooked, go
+// through the trampoline rather than the hook.
+unsafe fn present_mirror(
+    swap_chain: &IDXGISwapChain3,
+    mirror_swap_chain: &IDXGISwapChain3,
+) -> Result<()> {
+    let (present_addr, _) = swap_chain_methods(mirror_swap_chain);
+    let (game_present_addr, _) = swap_chain_methods(swap_chain);
+
+    let present = match (swap_chain_trampoline(present_addr), TRAMPOLINES.get()) {
+        (Some(trampoline), _) => {
+            mem::transmute::<*mut c_void, DXGISwapChainPresentType>(trampoline)
+        },
+        (None, Some(Trampolines { dxgi_swap_chain_present, .. }))
+            if present_addr == game_present_addr =>
+        {
+            *dxgi_swap_chain_present
+        },
+        _ => mem::transmute::<usize, DXGISwapChainPresentType>(present_addr),
+    };
+
+    // Borrowed by `Present`, like the swap chain passed to the hook.
+    present(mem::transmute_copy(mirror_swap_chain), 0, 0).ok()
+}
+
+// Maximum frame latency of the swap chain, if it was created with a frame
+// latency waitable object. When the game waits on it, every frame older than
+// that has been displayed, and so is done on the GPU.
+unsafe fn waitable_frame_latency(swap_chain: &IDXGISwapChain3) -> Option<u32> {
+    let desc = util::try_out_param(|v| swap_chain.GetDesc(v)).ok()?;
+    if desc.Flags & DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT.0 as u32 == 0 {
+        return None;
+    }
+
+    let swap_chain: IDXGISwapChain2 = swap_chain.cast().ok()?;
+    swap_chain.GetMaximumFrameLatency().ok()
 }
 
-unsafe extern "system" fn dxgi_swap_chain_present_impl(
+pub(super) unsafe extern "system" fn dxgi_swap_chain_present_impl(
     swap_chain: IDXGISwapChain3,
     sync_interval: u32,
     flags: u32,
 ) -> HRESULT {
+    let present_start = Instant::now();
     let _hook_ejection_guard = HOOK_EJECTION_BARRIER.acquire_ejection_guard();
-    {
-        INITIALIZATION_CONTEXT.lock().insert_swap_chain(&swap_chain);
+
+    let (present_addr, _) = swap_chain_methods(&swap_chain);
+    let dxgi_swap_chain_present = match (swap_chain_trampoline(present_addr), TRAMPOLINES.get()) {
+        (Some(trampoline), _) => {
+            mem::transmute::<*mut c_void, DXGISwapChainPresentType>(trampoline)
+        },
+        (None, Some(Trampolines { dxgi_swap_chain_present, .. })) => *dxgi_swap_chain_present,
+        (None, None) => {
+            // The hooks were removed while this present was in flight: they're
+            // disabled by now, so the vtable leads to the original function.
+            trace!("DirectX 12 trampolines uninitialized, skipping render");
+            let present = mem::transmute::<usize, DXGISwapChainPresentType>(present_addr);
+            return present(swap_chain, sync_interval, flags);
+        },
+    };
+
+    if is_test_present(flags) {
+        trace!("Forwarding test present");
+        return dxgi_swap_chain_present(swap_chain, sync_interval, flags);
     }
 
-    let Trampolines { dxgi_swap_chain_present, .. } =
-        TRAMPOLINES.get().expect("DirectX 12 trampolines uninitialized");
+    let rendered = if skip_startup_present() {
+        trace!("Skipping startup present");
+        false
+    } else {
+        {
+            INITIALIZATION_CONTEXT.lock().insert_swap_chain(&swap_chain);
+        }
+
+        if game_hwnd().is_none() {
+            if let Ok(desc) = util::try_out_param(|v| swap_chain.GetDesc(v)) {
+                set_game_hwnd(desc.OutputWindow);
+            }
+        }
+
+        match render(&swap_chain) {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                if !is_init_skipped(&e) {
+                    util::print_dxgi_debug_messages();
+                    error!("Render error: {e:?}");
+                }
+                false
+            },
+        }
+    };
+
+    timings::record(present_start.elapsed());
 
-    if let Err(e) = render(&swap_chain) {
-        util::print_dxgi_debug_messages();
-        error!("Render error: {e:?}");
+    // Only the presents of the swap chain the overlay was drawn on are
+    // reported to the render loop.
+    let rendered =
+        rendered && swap_chain.as_raw() as usize == PIPELINE_SWAP_CHAIN.load(Ordering::SeqCst);
+    if rendered {
+        if let Some(mut pipeline) = PIPELINE.get().and_then(|pipeline| pipeline.try_lock()) {
+            pipeline.render_loop().before_present();
+        }
     }
 
+    let (sync_interval, flags) = present_args(sync_interval, flags);
     trace!("Call IDXGISwapChain::Present trampoline");
     let result = dxgi_swap_chain_present(swap_chain, sync_interval, flags);
 
-    if EJECT_REQUESTED.load(Ordering::SeqCst) {
+    if rendered {
+        if let Some(mut pipeline) = PIPELINE.get().and_then(|pipeline| pipeline.try_lock()) {
+            pipeline.render_loop().after_present(result);
+        }
+    }
+
+    if EJECT_REQUESTED.swap(false, Ordering::SeqCst) {
         perform_eject();
     }
 
@@ -227,9 +582,57 @@ unsafe extern "system" fn dxgi_swap_chain_resize_buffers_impl(
     flags: u32,
 ) -> HRESULT {
     let _hook_ejection_guard = HOOK_EJECTION_BARRIER.acquire_ejection_guard();
-    let Trampolines { dxgi_swap_chain_resize_buffers, .. } =
-        TRAMPOLINES.get().expect("DirectX 12 trampolines uninitialized");
+    let (_, resize_buffers_addr) = swap_chain_methods(&p_this);
+    let dxgi_swap_chain_resize_buffers =
+        match (swap_chain_trampoline(resize_buffers_addr), TRAMPOLINES.get()) {
+            (Some(trampoline), _) => {
+                mem::transmute::<*mut c_void, DXGISwapChainResizeBuffersType>(trampoline)
+            },
+            (None, Some(Trampolines { dxgi_swap_chain_resize_buffers, .. })) => {
+                *dxgi_swap_chain_resize_buffers
+            },
+            (None, None) => {
+                trace!("DirectX 12 trampolines uninitialized, calling ResizeBuffers");
+                let resize_buffers =
+                    mem::transmute::<usize, DXGISwapChainResizeBuffersType>(resize_buffers_addr);
+                return resize_buffers(p_this, buffer_count, width, height, new_format, flags);
+            },
+        };
+
+    // The back buffers may be wrapped by the DirectX 11 renderer, when it
+    // draws on DirectX 12 swap chains alongside these hooks.
+    #[cfg(feature = "dx11")]
+    super::dx11::release_back_buffers();
+    // `buffer_count` is 0 when the game preserves the count. The renderer
+    // doesn't rely on it: the back buffer, and the frame latency the frame
+    // contexts are reserved for, are read from the swap chain at every present.
+    let mut params = ResizeParams { buffer_count, width, height, new_format, flags };
+
+    let proceed = if let Some(pipeline) = PIPELINE.get() {
+        let mut pipeline = pipeline.lock();
+        let proceed = pipeline.render_loop().on_resize_buffers(&mut params);
+
+        // Release our references to the back buffers once the GPU is done
+        // with them, otherwise the resize fails.
+        if proceed {
+            if let Err(e) = pipeline.engine().flush() {
+                error!("Could not flush render engine: {e:?}");
+            }
+        }
+
+        proceed
+    } else if let Some(render_loop) = RENDER_LOOP.get() {
+        render_loop.on_resize_buffers(&mut params)
+    } else {
+        true
+    };
 
+    if !proceed {
+        trace!("IDXGISwapChain::ResizeBuffers skipped by render loop");
+        return S_OK;
+    }
+
+    let ResizeParams { buffer_count, width, height, new_format, flags } = params;
     trace!("Call IDXGISwapChain::ResizeBuffers trampoline");
     dxgi_swap_chain_resize_buffers(p_this, buffer_count, width, height, new_format, flags)
 }
@@ -245,29 +648,195 @@ unsafe extern "system" fn d3d12_command_queue_execute_command_lists_impl(
          {command_lists:p}) invoked",
     );
 
+    let Some(&Trampolines { d3d12_command_queue_execute_command_lists, .. }) = TRAMPOLINES.get()
+    else {
+        trace!("DirectX 12 trampolines uninitialized, calling ExecuteCommandLists");
+        let execute_command_lists = mem::transmute::<usize, D3D12CommandQueueExecuteCommandListsType>(
+            command_queue.vtable().ExecuteCommandLists as usize,
+        );
+        return execute_command_lists(command_queue, num_command_lists, command_lists);
+    };
+
     {
         INITIALIZATION_CONTEXT.lock().insert_command_queue(&command_queue);
     }
 
-    let Trampolines { d3d12_command_queue_execute_command_lists, .. } =
-        TRAMPOLINES.get().expect("DirectX 12 trampolines uninitialized");
-
     d3d12_command_queue_execute_command_lists(command_queue, num_command_lists, command_lists);
 }
 
-fn get_target_addrs() -> (
+unsafe extern "system" fn dxgi_factory_create_swap_chain_impl(
+    factory: IDXGIFactory,
+    device: *mut c_void,
+    desc: *const DXGI_SWAP_CHAIN_DESC,
+    swap_chain: *mut *mut c_void,
+) -> HRESULT {
+    let _hook_ejection_guard = HOOK_EJECTION_BARRIER.acquire_ejection_guard();
+    let Some(&Trampolines { dxgi_factory_create_swap_chain, .. }) = TRAMPOLINES.get() else {
+        trace!("DirectX 12 trampolines uninitialized, calling CreateSwapChain");
+        let create_swap_chain = mem::transmute::<usize, DXGIFactoryCreateSwapChainType>(
+            factory.vtable().CreateSwapChain as usize,
+        );
+        return create_swap_chain(factory, device, desc, swap_chain);
+    };
+
+    trace!("Call IDXGIFactory::CreateSwapChain trampoline");
+    let result = dxgi_factory_create_swap_chain(factory, device, desc, swap_chain);
+    if result.is_ok() && !swap_chain.is_null() {
+        on_swap_chain_created(*swap_chain);
+    }
+
+    result
+}
+
+unsafe extern "system" fn dxgi_factory_create_swap_chain_for_hwnd_impl(
+    factory: IDXGIFactory2,
+    device: *mut c_void,
+    hwnd: HWND,
+    desc: *const DXGI_SWAP_CHAIN_DESC1,
+    fullscreen_desc: *const DXGI_SWAP_CHAIN_FULLSCREEN_DESC,
+    restrict_to_output: *mut c_void,
+    swap_chain: *mut *mut c_void,
+) -> HRESULT {
+    let _hook_ejection_guard = HOOK_EJECTION_BARRIER.acquire_ejection_guard();
+    let Some(&Trampolines { dxgi_factory_create_swap_chain_for_hwnd, .. }) = TRAMPOLINES.get()
+    else {
+        trace!("DirectX 12 trampolines uninitialized, calling CreateSwapChainForHwnd");
+        let create_swap_chain_for_hwnd = mem::transmute::<
+            usize,
+            DXGIFactoryCreateSwapChainForHwndType,
+        >(factory.vtable().CreateSwapChainForHwnd as usize);
+        return create_swap_chain_for_hwnd(
+            factory,
+            device,
+            hwnd,
+            desc,
+            fullscreen_desc,
+            restrict_to_output,
+            swap_chain,
+        );
+    };
+
+    trace!("Call IDXGIFactory2::CreateSwapChainForHwnd trampoline");
+    let result = dxgi_factory_create_swap_chain_for_hwnd(
+        factory,
+        device,
+        hwnd,
+        desc,
+        fullscreen_desc,
+        restrict_to_output,
+        swap_chain,
+    );
+    if result.is_ok() && !swap_chain.is_null() {
+        on_swap_chain_created(*swap_chain);
+    }
+
+    result
+}
+
+// Addresses of the `Present` and `ResizeBuffers` implementations of a swap
+// chain.
+fn swap_chain_methods(swap_chain: &IDXGISwapChain) -> (usize, usize) {
+    let vtable = swap_chain.vtable();
+    (vtable.Present as usize, vtable.ResizeBuffers as usize)
+}
+
+// Trampoline of the hooked swap chain method at `addr`, if any.
+pub(super) fn swap_chain_trampoline(addr: usize) -> Option<*mut c_void> {
+    SWAP_CHAIN_HOOKS
+        .lock()
+        .iter()
+        .find(|&&(target, _)| target == addr)
+        .map(|&(_, trampoline)| trampoline as *mut c_void)
+}
+
+struct RehookedMethod(&'static MhHook);
+unsafe impl Send for RehookedMethod {}
+
+// Swap chain methods hooked when swap chains were created after the hooks were
+// applied.
+pub(crate) fn rehooked_methods<'a>() -> Vec<&'a MhHook> {
+    REHOOKED_METHODS.lock().iter().map(|method| method.0).collect()
+}
+
+// Make sure the overlay survives the game recreating its swap chain: hook its
+// methods if they differ from the ones hooked so far, and move the pipeline
+// over to it if it presents to the same window.
+unsafe fn on_swap_chain_created(swap_chain: *mut c_void) {
+    let Some(swap_chain) = IUnknown::from_raw_borrowed(&swap_chain)
+        .and_then(|swap_chain| swap_chain.cast::<IDXGISwapChain3>().ok())
+    else {
+        return;
+    };
+
+    let (present_addr, resize_buffers_addr) = swap_chain_methods(&swap_chain);
+    {
+        let mut swap_chain_hooks = SWAP_CHAIN_HOOKS.lock();
+        for (name, addr, hook_impl) in [
+            (
+                "IDXGISwapChain::Present",
+                present_addr,
+                PRESENT_DETOUR.load(Ordering::SeqCst) as *mut c_void,
+            ),
+            (
+                "IDXGISwapChain::ResizeBuffers",
+                resize_buffers_addr,
+                dxgi_swap_chain_resize_buffers_impl as *mut _,
+            ),
+        ] {
+            if swap_chain_hooks.iter().any(|&(target, _)| target == addr) {
+                continue;
+            }
+
+            info!("Re-hooking {name} of swap chain {swap_chain:?} at {addr:#x}");
+            let hook = match MhHook::new(addr as *mut _, hook_impl) {
+                Ok(hook) => hook.with_name(name),
+                Err(e) => {
+                    error!("Couldn't create {name} hook: {e:?}");
+                    continue;
+                },
+            };
+            if let Err(e) = hook.queue_enable().and_then(|_| MH_ApplyQueued().ok()) {
+                error!("Couldn't enable {name} hook: {e:?}");
+                continue;
+            }
+
+            swap_chain_hooks.push((addr, hook.trampoline() as usize));
+            REHOOKED_METHODS.lock().push(RehookedMethod(Box::leak(Box::new(hook))));
+        }
+    }
+
+    let pipeline_swap_chain = PIPELINE_SWAP_CHAIN.load(Ordering::SeqCst);
+    if pipeline_swap_chain == 0 || pipeline_swap_chain == swap_chain.as_raw() as usize {
+        return;
+    }
+
+    let hwnd = util::try_out_param(|v| swap_chain.GetDesc(v)).map(|desc| desc.OutputWindow);
+    if hwnd.ok() == game_hwnd() {
+        info!("Moving the pipeline to the recreated swap chain {swap_chain:?}");
+        PIPELINE_SWAP_CHAIN.store(swap_chain.as_raw() as usize, Ordering::SeqCst);
+    }
+}
+
+type TargetAddrs = (
     DXGISwapChainPresentType,
     DXGISwapChainResizeBuffersType,
     D3D12CommandQueueExecuteCommandListsType,
-) {
+    DXGIFactoryCreateSwapChainType,
+    DXGIFactoryCreateSwapChainForHwndType,
+);
+
+fn get_target_addrs() -> std::result::Result<TargetAddrs, ApplyError> {
     let dummy_hwnd = DummyHwnd::new();
 
-    let factory: IDXGIFactory2 = unsafe { CreateDXGIFactory2(0) }.unwrap();
-    let adapter = unsafe { factory.EnumAdapters(0) }.unwrap();
+    let factory: IDXGIFactory2 = unsafe { CreateDXGIFactory2(0) }?;
+    let adapter = unsafe { factory.EnumAdapters(0) }?;
 
     let device: ID3D12Device =
         util::try_out_ptr(|v| unsafe { D3D12CreateDevice(&adapter, D3D_FEATURE_LEVEL_11_0, v) })
-            .expect("D3D12CreateDevice failed");
+            .map_err(|e| {
+                error!("D3D12CreateDevice failed: {e:?}");
+                e
+            })?;
 
     let command_queue: ID3D12CommandQueue = unsafe {
         device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
@@ -276,8 +845,7 @@ fn get_target_addrs() -> (
             Flags: D3D12_COMMAND_QUEUE_FLAG_NONE,
             NodeMask: 0,
         })
-    }
-    .unwrap();
+    }?;
 
     let swap_chain: IDXGISwapChain = match util::try_out_ptr(|v| unsafe {
         factory
@@ -307,7 +875,8 @@ fn get_target_addrs() -> (
         Ok(swap_chain) => swap_chain,
         Err(e) => {
             util::print_dxgi_debug_messages();
-            panic!("{e:?}");
+            error!("IDXGIFactory::CreateSwapChain failed: {e:?}");
+            return Err(e.into());
         },
     };
 
@@ -317,12 +886,27 @@ fn get_target_addrs() -> (
         unsafe { mem::transmute(swap_chain.vtable().ResizeBuffers) };
     let cqecl_ptr: D3D12CommandQueueExecuteCommandListsType =
         unsafe { mem::transmute(command_queue.vtable().ExecuteCommandLists) };
+    let create_swap_chain_ptr: DXGIFactoryCreateSwapChainType =
+        unsafe { mem::transmute(factory.cast::<IDXGIFactory>()?.vtable().CreateSwapChain) };
+    let create_swap_chain_for_hwnd_ptr: DXGIFactoryCreateSwapChainForHwndType =
+        unsafe { mem::transmute(factory.vtable().CreateSwapChainForHwnd) };
 
-    (present_ptr, resize_buffers_ptr, cqecl_ptr)
+    Ok((
+        present_ptr,
+        resize_buffers_ptr,
+        cqecl_ptr,
+        create_swap_chain_ptr,
+        create_swap_chain_for_hwnd_ptr,
+    ))
 }
 
 /// Hooks for DirectX 12.
-pub struct ImguiDx12Hooks([MhHook; 3]);
+///
+/// The overlay is drawn into a single swap chain: the first one to present
+/// once its command queue is found. It follows the game to a swap chain
+/// recreated for the same window. Other swap chains are left untouched, see
+/// [`RenderContext::swap_chain_raw`](crate::RenderContext::swap_chain_raw).
+pub struct ImguiDx12Hooks([MhHook; 5]);
 
 impl ImguiDx12Hooks {
     /// Construct a set of [`MhHook`]s that will render UI via the
@@ -332,6 +916,12 @@ impl ImguiDx12Hooks {
     /// - `IDXGISwapChain3::Present`
     /// - `IDXGISwapChain3::ResizeBuffers`
     /// - `ID3D12CommandQueue::ExecuteCommandLists`
+    /// - `IDXGIFactory::CreateSwapChain`
+    /// - `IDXGIFactory2::CreateSwapChainForHwnd`
+    ///
+    /// The swap chain creation hooks keep the overlay alive when the game
+    /// recreates its swap chain: if the new swap chain implements `Present`
+    /// or `ResizeBuffers` at a different address, those are hooked as well.
     ///
     /// # Safety
     ///
@@ -340,67 +930,150 @@ impl ImguiDx12Hooks {
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        let (
-            dxgi_swap_chain_present_addr,
-            dxgi_swap_chain_resize_buffers_addr,
-            d3d12_command_queue_execute_command_lists_addr,
-        ) = get_target_addrs();
-
-        trace!("IDXGISwapChain::Present = {:p}", dxgi_swap_chain_present_addr as *const c_void);
-        let hook_present = MhHook::new(
-            dxgi_swap_chain_present_addr as *mut _,
-            dxgi_swap_chain_present_impl as *mut _,
-        )
-        .expect("couldn't create IDXGISwapChain::Present hook");
-        let hook_resize_buffers = MhHook::new(
-            dxgi_swap_chain_resize_buffers_addr as *mut _,
-            dxgi_swap_chain_resize_buffers_impl as *mut _,
-        )
-        .expect("couldn't create IDXGISwapChain::ResizeBuffers hook");
-        let hook_cqecl = MhHook::new(
-            d3d12_command_queue_execute_command_lists_addr as *mut _,
-            d3d12_command_queue_execute_command_lists_impl as *mut _,
-        )
-        .expect("couldn't create ID3D12CommandQueue::ExecuteCommandLists hook");
+        Self::try_new(t).expect("couldn't create DirectX 12 hooks")
+    }
 
+    /// Like [`new`](Self::new), but returns an error instead of panicking if
+    /// the functions to hook can't be found or hooked.
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn try_new<T>(t: T) -> std::result::Result<Self, ApplyError>
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
         RENDER_LOOP.get_or_init(|| Box::new(t));
 
-        TRAMPOLINES.get_or_init(|| Trampolines {
-            dxgi_swap_chain_present: mem::transmute::<*mut c_void, DXGISwapChainPresentType>(
-                hook_present.trampoline(),
-            ),
-            dxgi_swap_chain_resize_buffers: mem::transmute::<
-                *mut c_void,
-                DXGISwapChainResizeBuffersType,
-            >(hook_resize_buffers.trampoline()),
-            d3d12_command_queue_execute_command_lists: mem::transmute::<
-                *mut c_void,
-                D3D12CommandQueueExecuteCommandListsType,
-            >(hook_cqecl.trampoline()),
-        });
-
-        Self([hook_present, hook_resize_buffers, hook_cqecl])
+        let (hooks, _) = install(dxgi_swap_chain_present_impl as *mut c_void)?;
+        Ok(Self(hooks))
     }
 }
 
+// Create the hooks, with `present_detour` as the detour of
+// `IDXGISwapChain::Present`. Also return the address of the hooked `Present`.
+pub(super) unsafe fn install(
+    present_detour: *mut c_void,
+) -> std::result::Result<([MhHook; 5], usize), ApplyError> {
+    let (
+        dxgi_swap_chain_present_addr,
+        dxgi_swap_chain_resize_buffers_addr,
+        d3d12_command_queue_execute_command_lists_addr,
+        dxgi_factory_create_swap_chain_addr,
+        dxgi_factory_create_swap_chain_for_hwnd_addr,
+    ) = get_target_addrs()?;
+
+    trace!("IDXGISwapChain::Present = {:p}", dxgi_swap_chain_present_addr as *const c_void);
+    let hook_present = MhHook::new(dxgi_swap_chain_present_addr as *mut _, present_detour)?
+        .with_name("IDXGISwapChain::Present");
+    let hook_resize_buffers = MhHook::new(
+        dxgi_swap_chain_resize_buffers_addr as *mut _,
+        dxgi_swap_chain_resize_buffers_impl as *mut _,
+    )?
+    .with_name("IDXGISwapChain::ResizeBuffers");
+    let hook_cqecl = MhHook::new(
+        d3d12_command_queue_execute_command_lists_addr as *mut _,
+        d3d12_command_queue_execute_command_lists_impl as *mut _,
+    )?
+    .with_name("ID3D12CommandQueue::ExecuteCommandLists");
+    let hook_create_swap_chain = MhHook::new(
+        dxgi_factory_create_swap_chain_addr as *mut _,
+        dxgi_factory_create_swap_chain_impl as *mut _,
+    )?
+    .with_name("IDXGIFactory::CreateSwapChain");
+    let hook_create_swap_chain_for_hwnd = MhHook::new(
+        dxgi_factory_create_swap_chain_for_hwnd_addr as *mut _,
+        dxgi_factory_create_swap_chain_for_hwnd_impl as *mut _,
+    )?
+    .with_name("IDXGIFactory2::CreateSwapChainForHwnd");
+
+    *SWAP_CHAIN_HOOKS.lock() = vec![
+        (dxgi_swap_chain_present_addr as usize, hook_present.trampoline() as usize),
+        (dxgi_swap_chain_resize_buffers_addr as usize, hook_resize_buffers.trampoline() as usize),
+    ];
+
+    PRESENT_DETOUR.store(present_detour as usize, Ordering::SeqCst);
+
+    TRAMPOLINES.get_or_init(|| Trampolines {
+        dxgi_swap_chain_present: mem::transmute::<*mut c_void, DXGISwapChainPresentType>(
+            hook_present.trampoline(),
+        ),
+        dxgi_swap_chain_resize_buffers: mem::transmute::<
+            *mut c_void,
+            DXGISwapChainResizeBuffersType,
+        >(hook_resize_buffers.trampoline()),
+        d3d12_command_queue_execute_command_lists: mem::transmute::<
+            *mut c_void,
+            D3D12CommandQueueExecuteCommandListsType,
+        >(hook_cqecl.trampoline()),
+        dxgi_factory_create_swap_chain: mem::transmute::<
+            *mut c_void,
+            DXGIFactoryCreateSwapChainType,
+        >(hook_create_swap_chain.trampoline()),
+        dxgi_factory_create_swap_chain_for_hwnd: mem::transmute::<
+            *mut c_void,
+            DXGIFactoryCreateSwapChainForHwndType,
+        >(hook_create_swap_chain_for_hwnd.trampoline()),
+    });
+
+    Ok((
+        [
+            hook_present,
+            hook_resize_buffers,
+            hook_cqecl,
+            hook_create_swap_chain,
+            hook_create_swap_chain_for_hwnd,
+        ],
+        dxgi_swap_chain_present_addr as usize,
+    ))
+}
+
+// Hand the render loop over to the hooks created via `install`.
+#[cfg(feature = "dx11")]
+pub(super) unsafe fn set_render_loop(render_loop: Box<dyn ImguiRenderLoop + Send + Sync>) {
+    RENDER_LOOP.get_or_init(move || render_loop);
+}
+
+// Register a swap chain `Present` method hooked outside of this module.
+#[cfg(feature = "dx11")]
+pub(super) fn add_swap_chain_hook(addr: usize, trampoline: *mut c_void) {
+    SWAP_CHAIN_HOOKS.lock().push((addr, trampoline as usize));
+}
+
+pub(super) unsafe fn unhook() {
+    TRAMPOLINES.take();
+    PIPELINE.take().map(|p| p.into_inner().take());
+    font_atlas::reset();
+    mirror::close();
+    RENDER_LOOP.take(); // should already be null
+    PIPELINE_SWAP_CHAIN.store(0, Ordering::SeqCst);
+    reset_unrelated_swap_chains();
+    SWAP_CHAIN_HOOKS.lock().clear();
+    REHOOKED_METHODS.lock().clear();
+    pixel_sample::reset();
+
+    *INITIALIZATION_CONTEXT.lock() = InitializationContext::Empty;
+    *ENGINE_INIT_BACKOFF.lock() = InitBackoff::new();
+}
+
 impl Hooks for ImguiDx12Hooks {
-    fn from_render_loop<T>(t: T) -> Box<Self>
+    fn from_render_loop<T>(t: T) -> std::result::Result<Box<Self>, ApplyError>
     where
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        Box::new(unsafe { Self::new(t) })
+        unsafe { Self::try_new(t) }.map(Box::new)
     }
 
     fn hooks(&self) -> &[MhHook] {
         &self.0
     }
 
-    unsafe fn unhook(&mut self) {
-        TRAMPOLINES.take();
-        PIPELINE.take().map(|p| p.into_inner().take());
-        RENDER_LOOP.take(); // should already be null
+    fn backend(&self) -> Option<RenderBackend> {
+        Some(RenderBackend::Dx12)
+    }
 
-        *INITIALIZATION_CONTEXT.lock() = InitializationContext::Empty;
+    unsafe fn unhook(&mut self) {
+        unhook();
     }
 }
@@ -3,24 +3,30 @@
 use std::ffi::c_void;
 use std::mem;
 use std::sync::OnceLock;
+use std::time::Instant;
 
 use imgui::Context;
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
 use tracing::{error, trace};
 use windows::core::{Error, Interface, Result, HRESULT};
-use windows::Win32::Foundation::{BOOL, HWND, RECT};
+use windows::Win32::Foundation::{BOOL, E_FAIL, HWND, RECT};
 use windows::Win32::Graphics::Direct3D9::{
-    Direct3DCreate9, IDirect3DDevice9, D3DADAPTER_DEFAULT, D3DBACKBUFFER_TYPE_MONO,
-    D3DCREATE_SOFTWARE_VERTEXPROCESSING, D3DDEVTYPE_NULLREF, D3DDISPLAYMODE, D3DFORMAT,
-    D3DPRESENT_PARAMETERS, D3DSWAPEFFECT_DISCARD, D3D_SDK_VERSION,
+    Direct3DCreate9, IDirect3DDevice9, IDirect3DSurface9, D3DADAPTER_DEFAULT,
+    D3DBACKBUFFER_TYPE_MONO, D3DCREATE_SOFTWARE_VERTEXPROCESSING, D3DDEVTYPE_NULLREF,
+    D3DDISPLAYMODE, D3DFORMAT, D3DPRESENT_PARAMETERS, D3DSWAPEFFECT_DISCARD, D3D_SDK_VERSION,
 };
 use windows::Win32::Graphics::Gdi::RGNDATA;
 
-use super::DummyHwnd;
+use super::{
+    guard_pipeline_init, replace_render_loop, skip_startup_present, DummyHwnd, ThreadDpiAwareness,
+};
 use crate::mh::MhHook;
-use crate::renderer::{D3D9RenderEngine, Pipeline};
-use crate::{util, Hooks, ImguiRenderLoop};
+use crate::renderer::{font_atlas, D3D9RenderEngine, Pipeline};
+use crate::{
+    game_hwnd, set_back_buffer, set_game_hwnd, timings, util, ApplyError, BackBufferFormat,
+    BackBufferInfo, Hooks, ImguiRenderLoop, RenderBackend,
+};
 
 type Dx9PresentType = unsafe extern "system" fn(
     this: IDirect3DDevice9,
@@ -48,6 +54,7 @@ unsafe fn init_pipeline(device: &IDirect3DDevice9) -> Result<Mutex<Pipeline<D3D9
     device.GetCreationParameters(&mut creation_parameters)?;
 
     let hwnd = creation_parameters.hFocusWindow;
+    record_back_buffer(&device.GetBackBuffer(0, 0, D3DBACKBUFFER_TYPE_MONO)?)?;
 
     let mut ctx = Context::create();
     trace!("creating engine");
@@ -67,16 +74,22 @@ unsafe fn init_pipeline(device: &IDirect3DDevice9) -> Result<Mutex<Pipeline<D3D9
 }
 
 fn render(device: &IDirect3DDevice9) -> Result<()> {
-    let pipeline = unsafe { PIPELINE.get_or_try_init(|| init_pipeline(device)) }?;
+    let _dpi_awareness = ThreadDpiAwareness::enter();
+
+    let pipeline =
+        unsafe { PIPELINE.get_or_try_init(|| guard_pipeline_init(|| init_pipeline(device))) }?;
 
     let Some(mut pipeline) = pipeline.try_lock() else {
         error!("Could not lock pipeline");
         return Err(Error::from_hresult(HRESULT(-1)));
     };
 
-    pipeline.prepare_render()?;
+    replace_render_loop(&mut pipeline)?;
 
     let surface = unsafe { device.GetBackBuffer(0, 0, D3DBACKBUFFER_TYPE_MONO)? };
+    unsafe { record_back_buffer(&surface) }?;
+
+    pipeline.prepare_render()?;
 
     unsafe { device.BeginScene() }?;
     let render_result = pipeline.render(surface);
@@ -85,6 +98,19 @@ fn render(device: &IDirect3DDevice9) -> Result<()> {
     render_result
 }
 
+// Record the size and format of the back buffer the overlay draws into.
+unsafe fn record_back_buffer(surface: &IDirect3DSurface9) -> Result<()> {
+    let mut desc = Default::default();
+    surface.GetDesc(&mut desc)?;
+    set_back_buffer(BackBufferInfo {
+        width: desc.Width,
+        height: desc.Height,
+        format: BackBufferFormat::D3d9(desc.Format),
+    });
+
+    Ok(())
+}
+
 unsafe extern "system" fn dx9_present_impl(
     device: IDirect3DDevice9,
     psourcerect: *const RECT,
@@ -92,22 +118,65 @@ unsafe extern "system" fn dx9_present_impl(
     hdestwindowoverride: HWND,
     pdirtyregion: *const RGNDATA,
 ) -> HRESULT {
-    let Trampolines { dx9_present, .. } =
-        TRAMPOLINES.get().expect("DirectX 9 trampolines uninitialized");
+    let present_start = Instant::now();
+    let Some(&Trampolines { dx9_present, .. }) = TRAMPOLINES.get() else {
+        // The hooks were removed while this present was in flight: they're
+        // disabled by now, so the vtable leads to the original function.
+        trace!("DirectX 9 trampolines uninitialized, skipping render");
+        let present = mem::transmute::<usize, Dx9PresentType>(device.vtable().Present as usize);
+        return present(device, psourcerect, pdestrect, hdestwindowoverride, pdirtyregion);
+    };
 
-    if let Err(e) = render(&device) {
-        error!("Render error: {e:?}");
+    // Only the presents the overlay was drawn for are reported to the render
+    // loop.
+    let rendered = if skip_startup_present() {
+        trace!("Skipping startup present");
+        false
+    } else {
+        if game_hwnd().is_none() {
+            let mut creation_parameters = Default::default();
+            if device.GetCreationParameters(&mut creation_parameters).is_ok() {
+                set_game_hwnd(creation_parameters.hFocusWindow);
+            }
+        }
+
+        match render(&device) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Render error: {e:?}");
+                false
+            },
+        }
+    };
+
+    timings::record(present_start.elapsed());
+
+    if rendered {
+        if let Some(mut pipeline) = PIPELINE.get().and_then(|pipeline| pipeline.try_lock()) {
+            pipeline.render_loop().before_present();
+        }
     }
 
     trace!("Call IDirect3DDevice9::Present trampoline");
-    dx9_present(device, psourcerect, pdestrect, hdestwindowoverride, pdirtyregion)
+    let result = dx9_present(device, psourcerect, pdestrect, hdestwindowoverride, pdirtyregion);
+
+    if rendered {
+        if let Some(mut pipeline) = PIPELINE.get().and_then(|pipeline| pipeline.try_lock()) {
+            pipeline.render_loop().after_present(result);
+        }
+    }
+
+    result
 }
 unsafe extern "system" fn dx9_reset_impl(
     this: IDirect3DDevice9,
     present_params: *const D3DPRESENT_PARAMETERS,
 ) -> HRESULT {
-    let Trampolines { dx9_reset, .. } =
-        TRAMPOLINES.get().expect("DirectX 9 trampolines uninitialized");
+    let Some(&Trampolines { dx9_reset, .. }) = TRAMPOLINES.get() else {
+        trace!("DirectX 9 trampolines uninitialized, calling Reset");
+        let reset = mem::transmute::<usize, Dx9ResetType>(this.vtable().Reset as usize);
+        return reset(this, present_params);
+    };
 
     trace!("Resetting pipeline");
     if let Some(pipeline) = PIPELINE.take() {
@@ -119,12 +188,15 @@ unsafe extern "system" fn dx9_reset_impl(
     dx9_reset(this, present_params)
 }
 
-fn get_target_addrs() -> (Dx9PresentType, Dx9ResetType) {
-    let d9 = unsafe { Direct3DCreate9(D3D_SDK_VERSION).unwrap() };
+fn get_target_addrs() -> std::result::Result<(Dx9PresentType, Dx9ResetType), ApplyError> {
+    let Some(d9) = (unsafe { Direct3DCreate9(D3D_SDK_VERSION) }) else {
+        error!("Direct3DCreate9 failed");
+        return Err(ApplyError::PresentAddrFailed(E_FAIL));
+    };
 
     let mut d3d_display_mode =
         D3DDISPLAYMODE { Width: 0, Height: 0, RefreshRate: 0, Format: D3DFORMAT(0) };
-    unsafe { d9.GetAdapterDisplayMode(D3DADAPTER_DEFAULT, &mut d3d_display_mode).unwrap() };
+    unsafe { d9.GetAdapterDisplayMode(D3DADAPTER_DEFAULT, &mut d3d_display_mode)? };
 
     let mut present_params = D3DPRESENT_PARAMETERS {
         Windowed: BOOL(1),
@@ -146,12 +218,15 @@ fn get_target_addrs() -> (Dx9PresentType, Dx9ResetType) {
             )
         }
     })
-    .expect("IDirect3DDevice9::CreateDevice: failed to create device");
+    .map_err(|e| {
+        error!("IDirect3DDevice9::CreateDevice: failed to create device: {e:?}");
+        e
+    })?;
 
     let present_ptr = device.vtable().Present;
     let reset_ptr = device.vtable().Reset;
 
-    unsafe {
+    Ok(unsafe {
         (
             mem::transmute::<
                 unsafe extern "system" fn(
@@ -168,7 +243,7 @@ fn get_target_addrs() -> (Dx9PresentType, Dx9ResetType) {
                 Dx9ResetType,
             >(reset_ptr),
         )
-    }
+    })
 }
 
 /// Hooks for DirectX 9.
@@ -188,14 +263,27 @@ impl ImguiDx9Hooks {
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        let (dx9_present_addr, dx9_reset_addr) = get_target_addrs();
+        Self::try_new(t).expect("couldn't create DirectX 9 hooks")
+    }
+
+    /// Like [`new`](Self::new), but returns an error instead of panicking if
+    /// the functions to hook can't be found or hooked.
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn try_new<T>(t: T) -> std::result::Result<Self, ApplyError>
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        let (dx9_present_addr, dx9_reset_addr) = get_target_addrs()?;
 
         trace!("IDirect3DDevice9::Present = {:p}", dx9_present_addr as *const c_void);
         let hook_present =
-            MhHook::new(dx9_present_addr as *mut c_void, dx9_present_impl as *mut c_void)
-                .expect("couldn't create IDirect3DDevice9::Present hook");
-        let hook_reset = MhHook::new(dx9_reset_addr as *mut c_void, dx9_reset_impl as *mut c_void)
-            .expect("couldn't create IDirect3DDevice9::Reset hook");
+            MhHook::new(dx9_present_addr as *mut c_void, dx9_present_impl as *mut c_void)?
+                .with_name("IDirect3DDevice9::Present");
+        let hook_reset = MhHook::new(dx9_reset_addr as *mut c_void, dx9_reset_impl as *mut c_void)?
+            .with_name("IDirect3DDevice9::Reset");
 
         RENDER_LOOP.get_or_init(|| Box::new(t));
         TRAMPOLINES.get_or_init(|| Trampolines {
@@ -203,26 +291,31 @@ impl ImguiDx9Hooks {
             dx9_reset: mem::transmute::<*mut c_void, Dx9ResetType>(hook_reset.trampoline()),
         });
 
-        Self([hook_present, hook_reset])
+        Ok(Self([hook_present, hook_reset]))
     }
 }
 
 impl Hooks for ImguiDx9Hooks {
-    fn from_render_loop<T>(t: T) -> Box<Self>
+    fn from_render_loop<T>(t: T) -> std::result::Result<Box<Self>, ApplyError>
     where
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        Box::new(unsafe { Self::new(t) })
+        unsafe { Self::try_new(t) }.map(Box::new)
     }
 
     fn hooks(&self) -> &[MhHook] {
         &self.0
     }
 
+    fn backend(&self) -> Option<RenderBackend> {
+        Some(RenderBackend::Dx9)
+    }
+
     unsafe fn unhook(&mut self) {
         TRAMPOLINES.take();
         PIPELINE.take().map(|p| p.into_inner().take());
+        font_atlas::reset();
         RENDER_LOOP.take();
     }
 }
@@ -1,19 +1,45 @@
 //! Implementations of render engine hooks.
 
-use std::mem;
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use std::{mem, thread};
 
-use tracing::{debug, error};
-use windows::core::w;
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
+use tracing::{debug, error, info, trace, warn};
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use windows::core::Interface;
+use windows::core::{w, Error, Result, HRESULT, HSTRING};
+#[cfg(feature = "dx12")]
+use windows::Win32::Foundation::E_PENDING;
 use windows::Win32::Foundation::{BOOL, HWND, LPARAM, LRESULT, WPARAM};
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use windows::Win32::Graphics::Dxgi::{
+    IDXGISwapChain, IDXGISwapChain1, DXGI_PRESENT_ALLOW_TEARING, DXGI_PRESENT_TEST,
+    DXGI_SCALING_ASPECT_RATIO_STRETCH, DXGI_SCALING_NONE, DXGI_SCALING_STRETCH,
+};
 use windows::Win32::System::LibraryLoader::GetModuleHandleW;
 use windows::Win32::System::Threading::GetCurrentProcessId;
+use windows::Win32::UI::HiDpi::{
+    SetThreadDpiAwarenessContext, DPI_AWARENESS_CONTEXT, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
+};
 use windows::Win32::UI::WindowsAndMessaging::{
     CreateWindowExW, DefWindowProcW, DestroyWindow, EnumWindows, GetWindowThreadProcessId,
     RegisterClassExW, UnregisterClassW, CS_HREDRAW, CS_VREDRAW, WNDCLASSEXW,
     WS_EX_OVERLAPPEDWINDOW, WS_OVERLAPPEDWINDOW,
 };
 
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use crate::renderer::ClientMapping;
+use crate::renderer::{Pipeline, RenderEngine};
+use crate::{options, ApplyError, ImguiRenderLoop};
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use crate::{set_back_buffer, util, BackBufferFormat, BackBufferInfo};
+
+#[cfg(all(feature = "dx11", feature = "dx12"))]
+pub mod dual_dx;
 #[cfg(feature = "dx11")]
 pub mod dx11;
 #[cfg(feature = "dx12")]
@@ -23,6 +49,301 @@ pub mod dx9;
 #[cfg(feature = "opengl3")]
 pub mod opengl3;
 
+// Longest pause between two checks for the modules required by hooks.
+const MAX_INSTALL_BACKOFF: Duration = Duration::from_millis(250);
+// Pause before creating a renderer again after it failed, doubled at each
+// consecutive failure.
+#[cfg(feature = "dx12")]
+const INIT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+// Consecutive failures to create a renderer after which it isn't attempted
+// anymore.
+#[cfg(feature = "dx12")]
+const MAX_INIT_FAILURES: u32 = 8;
+
+static STARTUP_PRESENTS_SEEN: AtomicUsize = AtomicUsize::new(0);
+// Held for reading while a pipeline is being created, and for writing while
+// the hooks are cleaned up, so that a half-constructed pipeline is never
+// observed or dropped by the cleanup. Set once the hooks are cleaned up, so
+// that presents still in flight don't create a pipeline afterwards.
+static PIPELINE_INIT_LOCK: RwLock<bool> = RwLock::new(false);
+
+// Swap chains that presented without receiving the overlay, reported once.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+static UNRELATED_SWAP_CHAINS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+// Render loop waiting to replace the current one at the next present.
+static RENDER_LOOP_REPLACEMENT: Mutex<Option<Box<dyn ImguiRenderLoop + Send + Sync>>> =
+    Mutex::new(None);
+
+/// Wait for `modules` to be loaded in the process, checking again with an
+/// exponential backoff for at most `timeout`.
+pub(crate) fn wait_for_modules(
+    modules: &[&str],
+    timeout: Duration,
+) -> std::result::Result<(), ApplyError> {
+    let start = Instant::now();
+    let mut backoff = Duration::from_millis(1);
+
+    loop {
+        let Some(missing) = modules.iter().find(|&&module| !is_module_loaded(module)) else {
+            return Ok(());
+        };
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            error!("{missing} is not loaded after {timeout:?}");
+            return Err(ApplyError::ModuleNotLoaded(missing.to_string()));
+        }
+
+        debug!("Waiting for {missing} to be loaded");
+        thread::sleep(backoff.min(timeout - elapsed));
+        backoff = (backoff * 2).min(MAX_INSTALL_BACKOFF);
+    }
+}
+
+fn is_module_loaded(module: &str) -> bool {
+    unsafe { GetModuleHandleW(&HSTRING::from(module)) }.is_ok()
+}
+
+/// Queue a render loop to replace the current one at the next present, see
+/// [`replace_render_loop`](crate::replace_render_loop).
+pub(crate) fn set_render_loop_replacement(render_loop: Box<dyn ImguiRenderLoop + Send + Sync>) {
+    *RENDER_LOOP_REPLACEMENT.lock() = Some(render_loop);
+}
+
+/// Take the render loop queued to replace the current one, if any.
+pub(crate) fn take_render_loop_replacement() -> Option<Box<dyn ImguiRenderLoop + Send + Sync>> {
+    RENDER_LOOP_REPLACEMENT.lock().take()
+}
+
+/// Hand `pipeline` to the render loop queued to replace the current one, if
+/// any. Called by the present hooks with the pipeline locked, so that the
+/// replacement never races a frame.
+pub(crate) fn replace_render_loop<T: RenderEngine>(pipeline: &mut Pipeline<T>) -> Result<()> {
+    if let Some(render_loop) = take_render_loop_replacement() {
+        trace!("Replacing render loop");
+        pipeline.replace_render_loop(render_loop)?;
+    }
+    Ok(())
+}
+
+/// Count a present call, returning whether it is one of the first presents
+/// that should be skipped as configured via
+/// [`HudhookBuilder::with_startup_present_skip`](crate::HudhookBuilder::with_startup_present_skip).
+pub(crate) fn skip_startup_present() -> bool {
+    let startup_present_skip = options::get().startup_present_skip;
+    startup_present_skip > 0
+        && STARTUP_PRESENTS_SEEN.load(Ordering::SeqCst) < startup_present_skip
+        && STARTUP_PRESENTS_SEEN.fetch_add(1, Ordering::SeqCst) < startup_present_skip
+}
+
+/// Reset the count of skipped startup presents.
+pub(crate) fn reset_startup_present_skip() {
+    STARTUP_PRESENTS_SEEN.store(0, Ordering::SeqCst);
+}
+
+/// Apply the sync interval override set via
+/// [`HudhookBuilder::with_sync_interval`](crate::HudhookBuilder::with_sync_interval)
+/// to the arguments of an `IDXGISwapChain::Present` call.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) fn present_args(sync_interval: u32, flags: u32) -> (u32, u32) {
+    static INVALID_OVERRIDE_LOGGED: AtomicBool = AtomicBool::new(false);
+
+    let Some(sync_interval_override) = options::get().sync_interval else {
+        return (sync_interval, flags);
+    };
+
+    if is_valid_sync_interval(sync_interval_override, flags) {
+        (sync_interval_override, flags)
+    } else {
+        if !INVALID_OVERRIDE_LOGGED.swap(true, Ordering::SeqCst) {
+            warn!(
+                "Ignoring sync interval override {sync_interval_override} for present flags \
+                 {flags:#x}"
+            );
+        }
+        (sync_interval, flags)
+    }
+}
+
+// `Present` fails with `DXGI_ERROR_INVALID_CALL` for sync intervals above 4,
+// and for non-zero sync intervals when tearing is allowed.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+fn is_valid_sync_interval(sync_interval: u32, flags: u32) -> bool {
+    sync_interval <= 4 && (sync_interval == 0 || flags & DXGI_PRESENT_ALLOW_TEARING == 0)
+}
+
+/// Whether an `IDXGISwapChain::Present` call with `flags` only checks whether
+/// the window is occluded, without presenting anything: the overlay must not
+/// be rendered for it.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) fn is_test_present(flags: u32) -> bool {
+    flags & DXGI_PRESENT_TEST != 0
+}
+
+/// Skip the render for `swap_chain`, which presented while the overlay is
+/// drawn into another swap chain: there is one imgui context and one render
+/// loop per process, so the overlay only ever goes to one of them. Logged
+/// once per swap chain, so that the overlay missing from a window of an
+/// application presenting several of them isn't a mystery.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) fn skip_unrelated_swap_chain(swap_chain: &IDXGISwapChain) {
+    let mut reported = UNRELATED_SWAP_CHAINS.lock();
+    let addr = swap_chain.as_raw() as usize;
+    if reported.contains(&addr) {
+        trace!("Skipping render for unrelated swap chain {swap_chain:?}");
+    } else {
+        info!(
+            "Swap chain {swap_chain:?} presented, but the overlay is drawn into another swap \
+             chain: skipping it"
+        );
+        reported.push(addr);
+    }
+}
+
+/// Forget the swap chains reported by [`skip_unrelated_swap_chain`], when the
+/// hooks are removed.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) fn reset_unrelated_swap_chains() {
+    UNRELATED_SWAP_CHAINS.lock().clear();
+}
+
+/// Record the size and format of the buffers of `swap_chain`, as the back
+/// buffer the overlay draws into.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) unsafe fn record_dxgi_back_buffer(swap_chain: &IDXGISwapChain) -> Result<()> {
+    let desc = util::try_out_param(|v| swap_chain.GetDesc(v))?;
+    set_back_buffer(BackBufferInfo {
+        width: desc.BufferDesc.Width,
+        height: desc.BufferDesc.Height,
+        format: BackBufferFormat::Dxgi(desc.BufferDesc.Format),
+    });
+
+    Ok(())
+}
+
+/// Size the display of `pipeline` to the back buffer of `swap_chain`, and map
+/// the mouse positions from the client area to it as the swap chain presents
+/// it: with `DXGI_SCALING_NONE` or `DXGI_SCALING_ASPECT_RATIO_STRETCH`, the
+/// back buffer no longer lines up with the client area once the window is
+/// resized, and the overlay would draw away from the cursor.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) unsafe fn resize_to_dxgi_back_buffer<T: RenderEngine>(
+    pipeline: &mut Pipeline<T>,
+    swap_chain: &IDXGISwapChain,
+) -> Result<()> {
+    let desc = util::try_out_param(|v| swap_chain.GetDesc(v))?;
+    let (width, height) = (desc.BufferDesc.Width, desc.BufferDesc.Height);
+
+    // Swap chains for composition have no window to map from.
+    let mapping = if desc.OutputWindow.0 == 0 {
+        ClientMapping::IDENTITY
+    } else {
+        let back_buffer = [width as f32, height as f32];
+        let (client_width, client_height) = util::win_size(desc.OutputWindow);
+        let client = [client_width as f32, client_height as f32];
+
+        // Swap chains created with `CreateSwapChain` are always stretched.
+        let scaling = swap_chain
+            .cast::<IDXGISwapChain1>()
+            .and_then(|swap_chain| swap_chain.GetDesc1())
+            .map_or(DXGI_SCALING_STRETCH, |desc| desc.Scaling);
+        match scaling {
+            DXGI_SCALING_NONE => ClientMapping::IDENTITY,
+            DXGI_SCALING_ASPECT_RATIO_STRETCH => {
+                ClientMapping::aspect_ratio_stretch(back_buffer, client)
+            },
+            _ => ClientMapping::stretch(back_buffer, client),
+        }
+    };
+    pipeline.resize_back_buffer(width, height, mapping);
+
+    Ok(())
+}
+
+/// Create a pipeline with `init`, unless the hooks are being or have been
+/// cleaned up.
+pub(crate) fn guard_pipeline_init<T>(init: impl FnOnce() -> Result<T>) -> Result<T> {
+    let unhooked = PIPELINE_INIT_LOCK.read();
+    if *unhooked {
+        error!("Not creating a pipeline after unhooking");
+        return Err(Error::from_hresult(HRESULT(-1)));
+    }
+
+    init()
+}
+
+/// Retry policy for creating a renderer: after a failure, e.g. because the
+/// device is out of memory, wait with an exponential backoff before trying
+/// again, and give up after a few failures in a row, rather than trying (and
+/// logging) at every present.
+#[cfg(feature = "dx12")]
+pub(crate) struct InitBackoff {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+#[cfg(feature = "dx12")]
+impl InitBackoff {
+    pub(crate) const fn new() -> Self {
+        Self { failures: 0, retry_at: None }
+    }
+
+    /// Run `init`, unless the last failure is too recent or there were too
+    /// many of them, in which case fail with `E_PENDING` without running it.
+    pub(crate) fn attempt<T>(
+        &mut self,
+        now: Instant,
+        init: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        if self.failures >= MAX_INIT_FAILURES
+            || self.retry_at.is_some_and(|retry_at| now < retry_at)
+        {
+            return Err(Error::from(E_PENDING));
+        }
+
+        match init() {
+            Ok(value) => {
+                *self = Self::new();
+                Ok(value)
+            },
+            Err(e) => {
+                self.failures += 1;
+                if self.failures == MAX_INIT_FAILURES {
+                    error!(
+                        "Could not create the renderer {MAX_INIT_FAILURES} times, giving up: {e:?}"
+                    );
+                } else {
+                    let backoff = INIT_RETRY_BACKOFF * 2u32.pow(self.failures - 1);
+                    warn!("Could not create the renderer, retrying in {backoff:?}: {e:?}");
+                    self.retry_at = Some(now + backoff);
+                }
+                Err(e)
+            },
+        }
+    }
+}
+
+/// Whether `e` comes from [`InitBackoff::attempt`] not running the
+/// initialization, which isn't worth logging at every present.
+#[cfg(feature = "dx12")]
+pub(crate) fn is_init_skipped(e: &Error) -> bool {
+    e.code() == E_PENDING
+}
+
+/// Wait for the pipeline being created, if any, and prevent further ones from
+/// being created until [`allow_pipeline_init`] is called.
+pub(crate) fn forbid_pipeline_init() -> RwLockWriteGuard<'static, bool> {
+    let mut unhooked = PIPELINE_INIT_LOCK.write();
+    *unhooked = true;
+    unhooked
+}
+
+/// Allow pipelines to be created again, e.g. when hooks are reapplied.
+pub(crate) fn allow_pipeline_init() {
+    *PIPELINE_INIT_LOCK.write() = false;
+}
+
 /// A utility function to retrieve the top level [`HWND`] belonging to this
 /// process.
 pub fn find_process_hwnd() -> Option<HWND> {
@@ -52,7 +373,7 @@ pub fn find_process_hwnd() -> Option<HWND> {
 ///
 /// Registers a class and creates a window on instantiation.
 /// Destroys the window and unregisters the class on drop.
-pub struct DummyHwnd(HWND, WNDCLASSEXW);
+pub struct DummyHwnd(HWND, WNDCLASSEXW, ThreadDpiAwareness);
 
 impl Default for DummyHwnd {
     fn default() -> Self {
@@ -63,6 +384,10 @@ impl Default for DummyHwnd {
 impl DummyHwnd {
     /// Construct the dummy [`HWND`].
     pub fn new() -> Self {
+        // Kept until the window is destroyed, so that the probe devices and
+        // swap chains created for it see the same DPI awareness.
+        let dpi_awareness = ThreadDpiAwareness::enter();
+
         // The window procedure for the class just calls `DefWindowProcW`.
         unsafe extern "system" fn wnd_proc(
             hwnd: HWND,
@@ -106,7 +431,7 @@ impl DummyHwnd {
         };
         debug!("{:?}", hwnd);
 
-        Self(hwnd, wndclass)
+        Self(hwnd, wndclass, dpi_awareness)
     }
 
     /// Retrieve the window handle.
@@ -128,3 +453,107 @@ impl Drop for DummyHwnd {
         }
     }
 }
+
+/// Makes the current thread per-monitor (v2) DPI aware while it lives, if
+/// enabled via
+/// [`HudhookBuilder::with_per_monitor_dpi_awareness`](crate::HudhookBuilder::with_per_monitor_dpi_awareness),
+/// and restores the previous DPI awareness of the thread when dropped.
+pub(crate) struct ThreadDpiAwareness(Option<DPI_AWARENESS_CONTEXT>);
+
+impl ThreadDpiAwareness {
+    pub(crate) fn enter() -> Self {
+        if !options::get().per_monitor_dpi_awareness {
+            return Self(None);
+        }
+
+        // A null context is returned on failure, e.g. before Windows 10 1703.
+        let previous =
+            unsafe { SetThreadDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) };
+        Self((previous.0 != 0).then_some(previous))
+    }
+}
+
+impl Drop for ThreadDpiAwareness {
+    fn drop(&mut self) {
+        if let Some(previous) = self.0 {
+            unsafe { SetThreadDpiAwarenessContext(previous) };
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "dx11", feature = "dx12")))]
+mod tests {
+    use windows::Win32::Graphics::Dxgi::DXGI_PRESENT_TEST;
+
+    use super::*;
+
+    #[test]
+    fn test_is_valid_sync_interval() {
+        assert!(is_valid_sync_interval(0, 0));
+        assert!(is_valid_sync_interval(1, 0));
+        assert!(is_valid_sync_interval(4, DXGI_PRESENT_TEST));
+        assert!(!is_valid_sync_interval(5, 0));
+
+        // Tearing requires a sync interval of zero.
+        assert!(is_valid_sync_interval(0, DXGI_PRESENT_ALLOW_TEARING));
+        assert!(!is_valid_sync_interval(1, DXGI_PRESENT_ALLOW_TEARING));
+    }
+
+    #[test]
+    fn test_wait_for_modules() {
+        assert_eq!(wait_for_modules(&[], Duration::ZERO), Ok(()));
+        assert_eq!(wait_for_modules(&["kernel32.dll"], Duration::ZERO), Ok(()));
+
+        let modules = ["kernel32.dll", "hudhook-missing.dll"];
+        let start = Instant::now();
+        assert_eq!(
+            wait_for_modules(&modules, Duration::from_millis(50)),
+            Err(ApplyError::ModuleNotLoaded(String::from("hudhook-missing.dll")))
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[cfg(feature = "dx12")]
+    #[test]
+    fn test_init_backoff() {
+        use windows::Win32::Foundation::E_OUTOFMEMORY;
+
+        let mut backoff = InitBackoff::new();
+        let mut attempts = 0;
+        let fail = |attempts: &mut u32| {
+            *attempts += 1;
+            Err::<(), _>(Error::from(E_OUTOFMEMORY))
+        };
+        let start = Instant::now();
+
+        assert_eq!(
+            backoff.attempt(start, || fail(&mut attempts)).unwrap_err().code(),
+            E_OUTOFMEMORY
+        );
+        // Not retried until the backoff elapses.
+        let e =
+            backoff.attempt(start + INIT_RETRY_BACKOFF / 2, || fail(&mut attempts)).unwrap_err();
+        assert!(is_init_skipped(&e));
+        assert_eq!(attempts, 1);
+
+        // Retried with a doubling backoff, until it gives up.
+        let mut now = start;
+        for failures in 1..MAX_INIT_FAILURES {
+            now += INIT_RETRY_BACKOFF * 2u32.pow(failures - 1);
+            assert!(!is_init_skipped(&backoff.attempt(now, || fail(&mut attempts)).unwrap_err()));
+        }
+        assert_eq!(attempts, MAX_INIT_FAILURES);
+        now += Duration::from_secs(3600);
+        assert!(is_init_skipped(&backoff.attempt(now, || fail(&mut attempts)).unwrap_err()));
+        assert_eq!(attempts, MAX_INIT_FAILURES);
+
+        // A success resets it.
+        let mut backoff = InitBackoff::new();
+        backoff.attempt(start, || fail(&mut attempts)).unwrap_err();
+        backoff.attempt(start + INIT_RETRY_BACKOFF, || Ok(())).unwrap();
+        backoff.attempt(start + INIT_RETRY_BACKOFF, || fail(&mut attempts)).unwrap_err();
+        let e =
+            backoff.attempt(start + INIT_RETRY_BACKOFF * 2, || fail(&mut attempts)).unwrap_err();
+        assert!(!is_init_skipped(&e));
+    }
+}
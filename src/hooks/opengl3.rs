@@ -2,21 +2,33 @@
 
 use std::ffi::{c_void, CString};
 use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
 use std::sync::OnceLock;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use imgui::Context;
 use once_cell::sync::OnceCell;
 use parking_lot::Mutex;
-use tracing::{error, trace};
+use tracing::{error, trace, warn};
 use windows::core::{Error, Result, HRESULT, PCSTR};
+use windows::Win32::Foundation::{BOOL, FALSE, HWND, S_OK};
 use windows::Win32::Graphics::Gdi::{WindowFromDC, HDC};
 use windows::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
 
+use super::{
+    guard_pipeline_init, is_module_loaded, replace_render_loop, skip_startup_present,
+    ThreadDpiAwareness,
+};
 use crate::mh::MhHook;
-use crate::renderer::{OpenGl3RenderEngine, Pipeline};
-use crate::{Hooks, ImguiRenderLoop};
+use crate::renderer::{font_atlas, OpenGl3RenderEngine, Pipeline};
+use crate::{
+    game_hwnd, set_back_buffer, set_game_hwnd, timings, util, ApplyError, BackBufferFormat,
+    BackBufferInfo, Hooks, ImguiRenderLoop, RenderBackend,
+};
 
-type OpenGl32wglSwapBuffersType = unsafe extern "system" fn(HDC) -> ();
+type OpenGl32wglSwapBuffersType = unsafe extern "system" fn(HDC) -> BOOL;
 
 struct Trampolines {
     opengl32_wgl_swap_buffers: OpenGl32wglSwapBuffersType,
@@ -25,9 +37,19 @@ struct Trampolines {
 static mut TRAMPOLINES: OnceLock<Trampolines> = OnceLock::new();
 static mut PIPELINE: OnceCell<Mutex<Pipeline<OpenGl3RenderEngine>>> = OnceCell::new();
 static mut RENDER_LOOP: OnceCell<Box<dyn ImguiRenderLoop + Send + Sync>> = OnceCell::new();
+// Whether the hooked `wglSwapBuffers` was called since the hooks were created.
+static SWAP_BUFFERS_CALLED: AtomicBool = AtomicBool::new(false);
+// Thread checking that `wglSwapBuffers` gets called, stopped by dropping the
+// sender when unhooking.
+static SWAP_BUFFERS_WATCHDOG: Mutex<Option<(Sender<()>, JoinHandle<()>)>> = Mutex::new(None);
+
+// How long `wglSwapBuffers` can go uncalled before warning that the
+// application doesn't present with it.
+const SWAP_BUFFERS_WATCHDOG_TIMEOUT: Duration = Duration::from_secs(10);
 
 unsafe fn init_pipeline(dc: HDC) -> Result<Mutex<Pipeline<OpenGl3RenderEngine>>> {
     let hwnd = WindowFromDC(dc);
+    record_back_buffer(hwnd);
 
     let mut ctx = Context::create();
     let engine = OpenGl3RenderEngine::new(&mut ctx)?;
@@ -45,53 +67,165 @@ unsafe fn init_pipeline(dc: HDC) -> Result<Mutex<Pipeline<OpenGl3RenderEngine>>>
     Ok(Mutex::new(pipeline))
 }
 
-fn render(dc: HDC) -> Result<()> {
+// Draw the overlay on the default framebuffer of `dc`, returning whether it
+// belongs to the window the pipeline was created for.
+fn render(dc: HDC) -> Result<bool> {
+    let _dpi_awareness = ThreadDpiAwareness::enter();
+
     unsafe {
-        let pipeline = PIPELINE.get_or_try_init(|| init_pipeline(dc))?;
+        let pipeline = PIPELINE.get_or_try_init(|| guard_pipeline_init(|| init_pipeline(dc)))?;
 
         let Some(mut pipeline) = pipeline.try_lock() else {
             error!("Could not lock pipeline");
             return Err(Error::from_hresult(HRESULT(-1)));
         };
 
+        replace_render_loop(&mut pipeline)?;
+
+        let hwnd = WindowFromDC(dc);
+        record_back_buffer(hwnd);
         pipeline.prepare_render()?;
 
         pipeline.render(())?;
+
+        Ok(hwnd == pipeline.hwnd())
+    }
+}
+
+// Record the size of the default framebuffer, which matches the client area of
+// the window.
+fn record_back_buffer(hwnd: HWND) {
+    let (width, height) = util::win_size(hwnd);
+    set_back_buffer(BackBufferInfo {
+        width: width as u32,
+        height: height as u32,
+        format: BackBufferFormat::Unknown,
+    });
+}
+
+// Warn if `wglSwapBuffers` isn't called for a while after hooking it. Some
+// applications that look like OpenGL ones, e.g. built on ANGLE, translate the
+// calls and present through DXGI instead, so the overlay never shows up.
+fn start_swap_buffers_watchdog() {
+    SWAP_BUFFERS_CALLED.store(false, Ordering::SeqCst);
+
+    let (tx, rx) = mpsc::channel::<()>();
+    let handle = thread::spawn(move || {
+        if rx.recv_timeout(SWAP_BUFFERS_WATCHDOG_TIMEOUT) != Err(RecvTimeoutError::Timeout)
+            || SWAP_BUFFERS_CALLED.load(Ordering::SeqCst)
+        {
+            return;
+        }
+
+        if is_module_loaded("libGLESv2.dll") {
+            warn!(
+                "wglSwapBuffers was not called in {SWAP_BUFFERS_WATCHDOG_TIMEOUT:?}: \
+                 libGLESv2.dll is loaded, so the application likely uses ANGLE and presents \
+                 through DXGI. Use the DirectX 11 hooks instead."
+            );
+        } else if is_module_loaded("dxgi.dll") {
+            warn!(
+                "wglSwapBuffers was not called in {SWAP_BUFFERS_WATCHDOG_TIMEOUT:?}: dxgi.dll is \
+                 loaded, so the application may present through DXGI. Try the DirectX 11 or 12 \
+                 hooks instead."
+            );
+        } else {
+            warn!("wglSwapBuffers was not called in {SWAP_BUFFERS_WATCHDOG_TIMEOUT:?}");
+        }
+    });
+
+    if let Some((tx, handle)) = SWAP_BUFFERS_WATCHDOG.lock().replace((tx, handle)) {
+        drop(tx);
+        handle.join().ok();
     }
+}
 
-    Ok(())
+// Stop the watchdog, so that it doesn't outlive the hooks, nor the module
+// when ejecting.
+fn stop_swap_buffers_watchdog() {
+    if let Some((tx, handle)) = SWAP_BUFFERS_WATCHDOG.lock().take() {
+        drop(tx);
+        handle.join().ok();
+    }
 }
 
-unsafe extern "system" fn opengl32_wgl_swap_buffers_impl(dc: HDC) {
-    let Trampolines { opengl32_wgl_swap_buffers } =
-        TRAMPOLINES.get().expect("OpenGL3 trampolines uninitialized");
+unsafe extern "system" fn opengl32_wgl_swap_buffers_impl(dc: HDC) -> BOOL {
+    let present_start = Instant::now();
+    SWAP_BUFFERS_CALLED.store(true, Ordering::SeqCst);
+    let Some(&Trampolines { opengl32_wgl_swap_buffers }) = TRAMPOLINES.get() else {
+        // The hooks were removed while this call was in flight: they're disabled
+        // by now, so call the original function without rendering.
+        trace!("OpenGL3 trampolines uninitialized, skipping render");
+        return match get_opengl_wglswapbuffers_addr() {
+            Ok(wgl_swap_buffers) => wgl_swap_buffers(dc),
+            Err(_) => FALSE,
+        };
+    };
+
+    // Only the swaps of the window the overlay was drawn on are reported to
+    // the render loop.
+    let rendered = if skip_startup_present() {
+        trace!("Skipping startup present");
+        false
+    } else {
+        if game_hwnd().is_none() {
+            set_game_hwnd(WindowFromDC(dc));
+        }
 
-    if let Err(e) = render(dc) {
-        error!("Render error: {e:?}");
+        render(dc).unwrap_or_else(|e| {
+            error!("Render error: {e:?}");
+            false
+        })
+    };
+
+    timings::record(present_start.elapsed());
+
+    if rendered {
+        if let Some(mut pipeline) = PIPELINE.get().and_then(|pipeline| pipeline.try_lock()) {
+            pipeline.render_loop().before_present();
+        }
     }
 
     trace!("Call OpenGL3 wglSwapBuffers trampoline");
-    opengl32_wgl_swap_buffers(dc);
+    let result = opengl32_wgl_swap_buffers(dc);
+
+    if rendered {
+        if let Some(mut pipeline) = PIPELINE.get().and_then(|pipeline| pipeline.try_lock()) {
+            let hresult = if result.as_bool() { S_OK } else { Error::from_win32().code() };
+            pipeline.render_loop().after_present(hresult);
+        }
+    }
+
+    result
 }
 
 // Get the address of wglSwapBuffers in opengl32.dll
-unsafe fn get_opengl_wglswapbuffers_addr() -> OpenGl32wglSwapBuffersType {
+unsafe fn get_opengl_wglswapbuffers_addr(
+) -> std::result::Result<OpenGl32wglSwapBuffersType, ApplyError> {
     // Grab a handle to opengl32.dll
     let opengl32dll = CString::new("opengl32.dll").unwrap();
     let opengl32module = GetModuleHandleA(PCSTR(opengl32dll.as_ptr() as *mut _))
-        .expect("failed finding opengl32.dll");
+        .map_err(|_| ApplyError::ModuleNotLoaded(String::from("opengl32.dll")))?;
 
     // Grab the address of wglSwapBuffers
     let wglswapbuffers = CString::new("wglSwapBuffers").unwrap();
-    let wglswapbuffers_func =
-        GetProcAddress(opengl32module, PCSTR(wglswapbuffers.as_ptr() as *mut _)).unwrap();
+    let Some(wglswapbuffers_func) =
+        GetProcAddress(opengl32module, PCSTR(wglswapbuffers.as_ptr() as *mut _))
+    else {
+        return Err(ApplyError::PresentAddrFailed(Error::from_win32().code()));
+    };
 
-    mem::transmute::<unsafe extern "system" fn() -> isize, OpenGl32wglSwapBuffersType>(
+    Ok(mem::transmute::<unsafe extern "system" fn() -> isize, OpenGl32wglSwapBuffersType>(
         wglswapbuffers_func,
-    )
+    ))
 }
 
 /// Hooks for OpenGL 3.
+///
+/// Applications using OpenGL through a translation layer such as ANGLE
+/// present through DXGI, and never call `wglSwapBuffers`: use the DirectX 11
+/// hooks for them. If `wglSwapBuffers` isn't called within a few seconds of
+/// applying the hooks, a warning is logged, pointing at DXGI if it's loaded.
 pub struct ImguiOpenGl3Hooks([MhHook; 1]);
 
 impl ImguiOpenGl3Hooks {
@@ -105,18 +239,31 @@ impl ImguiOpenGl3Hooks {
     ///
     /// yolo
     pub unsafe fn new<T>(t: T) -> Self
+    where
+        T: ImguiRenderLoop + Send + Sync + 'static,
+    {
+        Self::try_new(t).expect("couldn't create OpenGL 3 hooks")
+    }
+
+    /// Like [`new`](Self::new), but returns an error instead of panicking if
+    /// the functions to hook can't be found or hooked.
+    ///
+    /// # Safety
+    ///
+    /// yolo
+    pub unsafe fn try_new<T>(t: T) -> std::result::Result<Self, ApplyError>
     where
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
         // Grab the addresses
-        let hook_opengl_swap_buffers_address = get_opengl_wglswapbuffers_addr();
+        let hook_opengl_swap_buffers_address = get_opengl_wglswapbuffers_addr()?;
 
         // Create detours
         let hook_opengl_wgl_swap_buffers = MhHook::new(
             hook_opengl_swap_buffers_address as *mut _,
             opengl32_wgl_swap_buffers_impl as *mut _,
-        )
-        .expect("couldn't create opengl32.wglSwapBuffers hook");
+        )?
+        .with_name("opengl32.wglSwapBuffers");
 
         // Initialize the render loop and store detours
         RENDER_LOOP.get_or_init(move || Box::new(t));
@@ -125,27 +272,62 @@ impl ImguiOpenGl3Hooks {
                 hook_opengl_wgl_swap_buffers.trampoline(),
             ),
         });
+        start_swap_buffers_watchdog();
 
-        Self([hook_opengl_wgl_swap_buffers])
+        Ok(Self([hook_opengl_wgl_swap_buffers]))
     }
 }
 
 impl Hooks for ImguiOpenGl3Hooks {
-    fn from_render_loop<T>(t: T) -> Box<Self>
+    fn from_render_loop<T>(t: T) -> std::result::Result<Box<Self>, ApplyError>
     where
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static,
     {
-        Box::new(unsafe { ImguiOpenGl3Hooks::new(t) })
+        unsafe { Self::try_new(t) }.map(Box::new)
+    }
+
+    fn required_modules() -> &'static [&'static str] {
+        &["opengl32.dll"]
     }
 
     fn hooks(&self) -> &[MhHook] {
         &self.0
     }
 
+    fn backend(&self) -> Option<RenderBackend> {
+        Some(RenderBackend::OpenGl3)
+    }
+
     unsafe fn unhook(&mut self) {
+        stop_swap_buffers_watchdog();
         TRAMPOLINES.take();
         PIPELINE.take().map(|p| p.into_inner().take());
+        font_atlas::reset();
         RENDER_LOOP.take();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use windows::core::s;
+    use windows::Win32::System::LibraryLoader::LoadLibraryA;
+
+    use super::*;
+
+    #[test]
+    fn test_swap_buffers_after_unhook() {
+        // A call racing `unhook` finds the trampolines gone: it must reach
+        // wglSwapBuffers through opengl32.dll, which fails on a null device
+        // context, without rendering nor recording a present.
+        unsafe { LoadLibraryA(s!("opengl32.dll")) }.unwrap();
+        unsafe { TRAMPOLINES.take() };
+        let samples = || timings::aggregate().map_or(0, |timings| timings.samples);
+        let samples_before = samples();
+
+        let result = unsafe { opengl32_wgl_swap_buffers_impl(HDC(0)) };
+        assert_eq!(result, unsafe { get_opengl_wglswapbuffers_addr().unwrap()(HDC(0)) });
+        assert_eq!(result, FALSE);
+        assert_eq!(samples(), samples_before);
+    }
+}
@@ -0,0 +1,91 @@
+//! Input messages of the game window, timestamped when received and buffered
+//! for [`drain_input_events`](crate::drain_input_events).
+//!
+//! Enabled via
+//! [`HudhookBuilder::with_input_queue`](crate::HudhookBuilder::with_input_queue).
+
+use std::collections::VecDeque;
+
+use parking_lot::Mutex;
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::System::Performance::QueryPerformanceCounter;
+use windows::Win32::UI::WindowsAndMessaging::{
+    WM_INPUT, WM_KEYFIRST, WM_KEYLAST, WM_MOUSEFIRST, WM_MOUSELAST,
+};
+
+static EVENTS: Mutex<VecDeque<InputEvent>> = Mutex::new(VecDeque::new());
+
+/// Input message received by the game window.
+///
+/// Retrieve them with [`drain_input_events`](crate::drain_input_events).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Window the message was sent to.
+    pub hwnd: HWND,
+    /// Message identifier, e.g. `WM_KEYDOWN`.
+    pub message: u32,
+    /// First parameter of the message.
+    pub wparam: WPARAM,
+    /// Second parameter of the message. Handles, e.g. the `HRAWINPUT` of
+    /// `WM_INPUT`, are only valid while the message is processed, and can't be
+    /// read anymore.
+    pub lparam: LPARAM,
+    /// Value of `QueryPerformanceCounter` when the message was received. Its
+    /// frequency is given by `QueryPerformanceFrequency`.
+    pub timestamp: i64,
+}
+
+/// Whether the queue records messages of type `message`: keyboard and mouse
+/// messages, and raw input.
+pub(crate) fn is_input_message(message: u32) -> bool {
+    matches!(message, WM_KEYFIRST..=WM_KEYLAST | WM_MOUSEFIRST..=WM_MOUSELAST | WM_INPUT)
+}
+
+/// Current value of `QueryPerformanceCounter`, to timestamp a message as it is
+/// received.
+pub(crate) fn timestamp() -> i64 {
+    let mut counter = 0;
+    // Can't fail on Windows XP and later.
+    unsafe { QueryPerformanceCounter(&mut counter) }.ok();
+    counter
+}
+
+/// Buffer an event, dropping the oldest ones to keep at most `capacity`.
+pub(crate) fn push(event: InputEvent, capacity: usize) {
+    let mut events = EVENTS.lock();
+    while events.len() >= capacity.max(1) {
+        events.pop_front();
+    }
+    events.push_back(event);
+}
+
+/// Take the buffered events, oldest first.
+pub(crate) fn drain() -> Vec<InputEvent> {
+    EVENTS.lock().drain(..).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::UI::WindowsAndMessaging::{WM_KEYDOWN, WM_LBUTTONDOWN, WM_PAINT};
+
+    use super::*;
+
+    fn event(message: u32, timestamp: i64) -> InputEvent {
+        InputEvent { hwnd: HWND(0), message, wparam: WPARAM(0), lparam: LPARAM(0), timestamp }
+    }
+
+    #[test]
+    fn test_input_queue() {
+        assert!(is_input_message(WM_KEYDOWN));
+        assert!(is_input_message(WM_LBUTTONDOWN));
+        assert!(is_input_message(WM_INPUT));
+        assert!(!is_input_message(WM_PAINT));
+
+        for timestamp in 0..5 {
+            push(event(WM_KEYDOWN, timestamp), 3);
+        }
+        let timestamps: Vec<_> = drain().iter().map(|event| event.timestamp).collect();
+        assert_eq!(timestamps, vec![2, 3, 4]);
+        assert!(drain().is_empty());
+    }
+}
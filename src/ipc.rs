@@ -0,0 +1,192 @@
+//! Named pipe through which other processes send messages to the render loop,
+//! enabled via
+//! [`HudhookBuilder::with_ipc_pipe`](crate::HudhookBuilder::with_ipc_pipe).
+//!
+//! A thread of its own accepts one client at a time on
+//! `\\.\pipe\hudhook-<pid>`, and queues the messages it reads. They are
+//! handed to [`ImguiRenderLoop::on_ipc_message`](crate::ImguiRenderLoop::on_ipc_message)
+//! on the render thread, before the next frame is built.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::os::windows::io::AsRawHandle;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tracing::{debug, error, warn};
+use windows::core::{Error, Result, HSTRING};
+use windows::Win32::Foundation::{
+    CloseHandle, ERROR_BROKEN_PIPE, ERROR_MORE_DATA, ERROR_OPERATION_ABORTED, ERROR_PIPE_CONNECTED,
+    HANDLE,
+};
+use windows::Win32::Storage::FileSystem::{
+    ReadFile, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_INBOUND,
+};
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_MESSAGE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_MESSAGE, PIPE_WAIT,
+};
+use windows::Win32::System::Threading::GetCurrentProcessId;
+use windows::Win32::System::IO::CancelSynchronousIo;
+
+// Messages received while no frame is rendered are dropped past this many,
+// oldest first.
+const MAX_QUEUED_MESSAGES: usize = 256;
+const BUFFER_SIZE: u32 = 64 * 1024;
+// Clients sending a larger message are disconnected.
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+static MESSAGES: Mutex<VecDeque<Vec<u8>>> = Mutex::new(VecDeque::new());
+static LISTENER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Name of the pipe of the process with id `pid`.
+pub(crate) fn pipe_name(pid: u32) -> String {
+    format!(r"\\.\pipe\hudhook-{pid}")
+}
+
+/// Start listening on the pipe of the current process, if not listening yet.
+pub(crate) fn start() {
+    let mut listener = LISTENER.lock();
+    if listener.is_some() {
+        return;
+    }
+
+    STOP.store(false, Ordering::SeqCst);
+    match thread::Builder::new().name(String::from("hudhook-ipc")).spawn(listen) {
+        Ok(thread) => *listener = Some(thread),
+        Err(e) => error!("Could not spawn the IPC thread: {e:?}"),
+    }
+}
+
+/// Stop listening, disconnecting the client if any, and drop the messages
+/// that weren't delivered.
+pub(crate) fn stop() {
+    let Some(thread) = LISTENER.lock().take() else {
+        return;
+    };
+
+    STOP.store(true, Ordering::SeqCst);
+    // The thread blocks waiting for a client or for a message: cancel until
+    // it notices, as a cancellation is lost if it isn't blocked yet.
+    let thread_handle = HANDLE(thread.as_raw_handle() as isize);
+    while !thread.is_finished() {
+        unsafe { CancelSynchronousIo(thread_handle) }.ok();
+        thread::sleep(Duration::from_millis(1));
+    }
+    thread.join().ok();
+
+    MESSAGES.lock().clear();
+}
+
+/// Take the messages received since the last call, oldest first.
+pub(crate) fn drain() -> Vec<Vec<u8>> {
+    MESSAGES.lock().drain(..).collect()
+}
+
+fn push(message: Vec<u8>) {
+    let mut messages = MESSAGES.lock();
+    if messages.len() >= MAX_QUEUED_MESSAGES {
+        warn!("IPC message queue full, dropping the oldest message");
+        messages.pop_front();
+    }
+    messages.push_back(message);
+}
+
+fn listen() {
+    let name = pipe_name(unsafe { GetCurrentProcessId() });
+    debug!("Listening on {name}");
+
+    // Fail rather than share the name with a pipe created beforehand by
+    // another process, which would receive the messages meant for the
+    // overlay, and only accept clients of the local machine. Who can connect
+    // otherwise depends on the default security descriptor of the process.
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            &HSTRING::from(name.as_str()),
+            PIPE_ACCESS_INBOUND | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_TYPE_MESSAGE | PIPE_READMODE_MESSAGE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            0,
+            BUFFER_SIZE,
+            0,
+            None,
+        )
+    };
+    if pipe.is_invalid() {
+        error!("Could not create {name}: {:?}", Error::from_win32());
+        return;
+    }
+
+    while !STOP.load(Ordering::SeqCst) {
+        match unsafe { ConnectNamedPipe(pipe, None) } {
+            Ok(()) => {},
+            Err(e) if e.code() == ERROR_PIPE_CONNECTED.to_hresult() => {},
+            Err(e) if e.code() == ERROR_OPERATION_ABORTED.to_hresult() => continue,
+            Err(e) => {
+                error!("Could not accept an IPC client: {e:?}");
+                break;
+            },
+        }
+
+        debug!("IPC client connected");
+        if let Err(e) = read_messages(pipe) {
+            if e.code() != ERROR_OPERATION_ABORTED.to_hresult() {
+                error!("Could not read from the IPC client: {e:?}");
+            }
+        }
+        debug!("IPC client disconnected");
+        unsafe { DisconnectNamedPipe(pipe) }.ok();
+    }
+
+    unsafe { CloseHandle(pipe) }.ok();
+}
+
+// Read messages from the connected client until it disconnects, or sends a
+// message larger than `MAX_MESSAGE_SIZE`.
+fn read_messages(pipe: HANDLE) -> Result<()> {
+    let mut buffer = vec![0u8; BUFFER_SIZE as usize];
+    let mut message = Vec::new();
+
+    while !STOP.load(Ordering::SeqCst) {
+        let mut read = 0;
+        let result = unsafe { ReadFile(pipe, Some(&mut buffer), Some(&mut read), None) };
+        if message.len() + read as usize > MAX_MESSAGE_SIZE {
+            warn!("IPC message larger than {MAX_MESSAGE_SIZE} bytes, disconnecting the client");
+            return Ok(());
+        }
+
+        match result {
+            Ok(()) => {
+                message.extend_from_slice(&buffer[..read as usize]);
+                push(mem::take(&mut message));
+            },
+            // The message is larger than the buffer: keep reading it.
+            Err(e) if e.code() == ERROR_MORE_DATA.to_hresult() => {
+                message.extend_from_slice(&buffer[..read as usize]);
+            },
+            Err(e) if e.code() == ERROR_BROKEN_PIPE.to_hresult() => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_queue() {
+        for i in 0..MAX_QUEUED_MESSAGES + 2 {
+            push(vec![i as u8]);
+        }
+        let messages = drain();
+        assert_eq!(messages.len(), MAX_QUEUED_MESSAGES);
+        assert_eq!(messages[0], vec![2]);
+        assert!(drain().is_empty());
+    }
+}
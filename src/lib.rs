@@ -114,33 +114,73 @@
 #![allow(static_mut_refs)]
 #![deny(missing_docs)]
 
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::thread;
+use std::any::Any;
+use std::ffi::c_void;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, ThreadId};
+use std::time::Duration;
 
 pub use imgui;
-use imgui::{Context, Io, TextureId, Ui};
+use imgui::{BackendFlags, ConfigFlags, Context, Io, TextureId, Ui};
 use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
 pub use tracing;
 use tracing::{error, trace, warn};
 pub use windows;
-use windows::core::Error;
-use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, WPARAM};
+use windows::core::{Error, HRESULT, HSTRING};
+use windows::Win32::Foundation::{ERROR_INVALID_WINDOW_HANDLE, HINSTANCE, HWND, LPARAM, WPARAM};
+#[cfg(feature = "dx12")]
+use windows::Win32::Graphics::Direct3D12::ID3D12GraphicsCommandList;
+use windows::Win32::Graphics::Direct3D9::D3DFORMAT;
+use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT;
 use windows::Win32::System::Console::{
     AllocConsole, FreeConsole, GetConsoleMode, GetStdHandle, SetConsoleMode, CONSOLE_MODE,
     ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_OUTPUT_HANDLE,
 };
 use windows::Win32::System::LibraryLoader::FreeLibraryAndExitThread;
+use windows::Win32::UI::Input::KeyboardAndMouse::VIRTUAL_KEY;
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetWindowTextLengthW, GetWindowTextW, IsWindow, SetWindowTextW,
+};
 
 use crate::mh::{MH_ApplyQueued, MH_Initialize, MH_Uninitialize, MhHook, MH_STATUS};
+use crate::options::Options;
 use crate::util::HookEjectionBarrier;
 
+pub(crate) mod diagnostics;
+pub(crate) mod draw_list;
+pub(crate) mod error;
+pub(crate) mod frame_clock;
 pub mod hooks;
 #[cfg(feature = "inject")]
 pub mod inject;
+pub(crate) mod input_queue;
+#[cfg(feature = "ipc")]
+pub(crate) mod ipc;
 pub mod mh;
+pub mod monitor;
+#[cfg(feature = "offscreen")]
+pub mod offscreen;
+pub(crate) mod options;
 pub(crate) mod renderer;
+#[cfg(feature = "serde")]
+pub(crate) mod style;
+pub(crate) mod timings;
 
+pub use diagnostics::{
+    build_info, detected_conflicts, installed_hooks, version, BuildInfo, HookInfo, OverlayConflict,
+};
+pub use draw_list::{DrawListOverlay, DrawListRenderLoop};
+pub use error::{ApplyError, TextureError};
+pub use input_queue::InputEvent;
+pub use monitor::{current_monitor, monitors, MonitorInfo};
 pub use renderer::msg_filter::MessageFilter;
+#[cfg(feature = "serde")]
+pub use style::{load_style_from_file, save_style_to_file};
+pub use timings::PresentHookTimings;
 
 pub mod util;
 
@@ -150,12 +190,244 @@ static mut HUDHOOK: OnceCell<Hudhook> = OnceCell::new();
 static CONSOLE_ALLOCATED: AtomicBool = AtomicBool::new(false);
 static EJECT_REQUESTED: AtomicBool = AtomicBool::new(false);
 static HOOK_EJECTION_BARRIER: HookEjectionBarrier = HookEjectionBarrier::new();
+static GAME_HWND: AtomicIsize = AtomicIsize::new(0);
+static DEMO_WINDOW: AtomicBool = AtomicBool::new(false);
+static FPS_OVERLAY: AtomicBool = AtomicBool::new(false);
+static UI_THREAD: Mutex<Option<ThreadId>> = Mutex::new(None);
+static BACK_BUFFER: Mutex<Option<BackBufferInfo>> = Mutex::new(None);
+// Game window and its title before `RenderContext::set_window_title` changed
+// it, restored when unhooking.
+static ORIGINAL_WINDOW_TITLE: Mutex<Option<(isize, String)>> = Mutex::new(None);
+
+/// Graphics API of a renderer, as reported by [`RenderContext::device_raw`]
+/// and [`active_backend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderBackend {
+    /// DirectX 9. The device is an `IDirect3DDevice9`.
+    Dx9,
+    /// DirectX 11. The device is an `ID3D11Device`.
+    Dx11,
+    /// DirectX 12. The device is an `ID3D12Device`.
+    Dx12,
+    /// OpenGL 3. The device is the `HGLRC` of the rendering context.
+    OpenGl3,
+}
+
+/// How the overlay intercepts the messages of the game window, set via
+/// [`HudhookBuilder::with_wndproc_mode`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WndProcMode {
+    /// Replace the window procedure via `SetWindowLongPtrW`, and restore the
+    /// previous one when unhooking.
+    ///
+    /// If another library replaces the window procedure after the overlay and
+    /// restores it out of order, input breaks for either of them.
+    #[default]
+    Replace,
+    /// Subclass the window via `SetWindowSubclass`, which composes with other
+    /// libraries subclassing the window regardless of the order in which
+    /// they come and go.
+    ///
+    /// Subclasses can only be installed from the thread owning the window.
+    /// When the game presents from another thread, this falls back to
+    /// [`WndProcMode::Replace`], with a warning.
+    Subclass,
+}
+
+/// Which messages the overlay takes the mouse position from, set via
+/// [`HudhookBuilder::with_mouse_source`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MouseSource {
+    /// `WM_MOUSEMOVE`, i.e. the position of the cursor in the client area.
+    WndProc,
+    /// The mouse motion of `WM_INPUT`, delivered when the game or the overlay
+    /// registered for raw mouse input. Relative motion is added to the last
+    /// position, without pointer acceleration.
+    RawInput,
+    /// Both, in the order they are received.
+    #[default]
+    Both,
+}
+
+/// Color space of the values the DirectX 11 and 12 renderers write to the
+/// render target, set via [`HudhookBuilder::with_color_space`].
+///
+/// imgui colors and textures are 8-bit sRGB.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Write the colors as they are, sRGB-encoded. This is right for 8-bit
+    /// `UNORM` back buffers, which most games present.
+    #[default]
+    Srgb,
+    /// Convert the colors to linear in the pixel shader before blending.
+    /// Use this when the game renders to a `*_UNORM_SRGB` render target
+    /// view or to a floating point back buffer (e.g. HDR scRGB), where the
+    /// overlay comes out too bright and washed out otherwise.
+    Linear,
+}
+
+/// Channel order in which the OpenGL renderer uploads texture data, set via
+/// [`HudhookBuilder::with_opengl_texture_format`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpenGlTextureFormat {
+    /// Upload the data as RGBA, which is the order of the font atlas and of
+    /// the data passed to [`RenderContext::load_texture`].
+    #[default]
+    Rgba,
+    /// Upload the data as BGRA, swapping the red and blue channels.
+    Bgra,
+}
+
+/// How the DirectX 12 renderer waits for the GPU to be done with its
+/// resources, e.g. when unhooking or when reusing a frame context, set via
+/// [`HudhookBuilder::with_fence_strategy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FenceStrategy {
+    /// Poll the fence until it completes. This returns the soonest, but keeps
+    /// a CPU core busy for as long as the wait lasts.
+    Spin,
+    /// Poll the fence for a few tens of microseconds, which covers the waits
+    /// that are about to complete, then block like [`FenceStrategy::Block`].
+    Hybrid,
+    /// Block the thread on an event signaled by the fence. This uses no CPU
+    /// time while waiting, which is friendlier to battery-powered devices,
+    /// but waking up the thread takes longer.
+    #[default]
+    Block,
+}
+
+/// Pixel format of a back buffer, as reported by [`BackBufferInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackBufferFormat {
+    /// Format of the buffers of a DirectX 11 or 12 swap chain.
+    Dxgi(DXGI_FORMAT),
+    /// Format of the back buffer of a DirectX 9 device.
+    D3d9(D3DFORMAT),
+    /// The format isn't known, as with OpenGL 3, where the default
+    /// framebuffer doesn't expose one.
+    Unknown,
+}
+
+/// Size and format of the back buffer the overlay draws into, as reported by
+/// [`RenderContext::back_buffer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackBufferInfo {
+    /// Width of the back buffer, in pixels.
+    pub width: u32,
+    /// Height of the back buffer, in pixels.
+    pub height: u32,
+    /// Pixel format of the back buffer.
+    pub format: BackBufferFormat,
+}
+
+/// Corner of the screen the built-in widgets are drawn in, e.g. the toasts
+/// queued via [`RenderContext::notify`] (see
+/// [`HudhookBuilder::with_toast_corner`]) or the frame rate readout (see
+/// [`HudhookBuilder::with_fps_overlay`]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScreenCorner {
+    /// Top left corner.
+    TopLeft,
+    /// Top right corner.
+    TopRight,
+    /// Bottom left corner.
+    BottomLeft,
+    /// Bottom right corner.
+    #[default]
+    BottomRight,
+}
+
+/// Appearance of the toasts queued via [`RenderContext::notify`], set via
+/// [`HudhookBuilder::with_toast_style`].
+///
+/// Colors are RGBA, with components between `0.0` and `1.0`. Sizes are in
+/// pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToastStyle {
+    /// Background color.
+    pub background: [f32; 4],
+    /// Text color.
+    pub text: [f32; 4],
+    /// Space between the text and the edges of the toast.
+    pub padding: [f32; 2],
+    /// Space between the toasts, and between the toasts and the edges of the
+    /// screen.
+    pub margin: f32,
+    /// Radius of the corners of the toast.
+    pub rounding: f32,
+    /// Time over which a toast fades out, at the end of its duration.
+    pub fade: Duration,
+}
+
+impl Default for ToastStyle {
+    fn default() -> Self {
+        Self {
+            background: [0.1, 0.1, 0.1, 0.9],
+            text: [1.0, 1.0, 1.0, 1.0],
+            padding: [10.0, 6.0],
+            margin: 10.0,
+            rounding: 4.0,
+            fade: Duration::from_millis(300),
+        }
+    }
+}
+
+/// Region of the display the imgui windows are kept in, set via
+/// [`HudhookBuilder::with_work_area`].
+///
+/// Coordinates are in pixels, relative to the top left corner of the display.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorkArea {
+    /// Top left corner of the region.
+    pub min: [f32; 2],
+    /// Bottom right corner of the region.
+    pub max: [f32; 2],
+}
+
+/// Window the overlay is mirrored to, e.g. for a capture or streaming
+/// monitor, set via [`HudhookBuilder::with_mirror_window`].
+///
+/// Coordinates and sizes are in pixels, in virtual screen coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirrorConfig {
+    /// Position of the top left corner of the window. See [`monitors`] to
+    /// place it on another display.
+    pub position: [i32; 2],
+    /// Size of the window, or `None` for the size of the game's back buffer.
+    /// The overlay is stretched to fit.
+    pub size: Option<[u32; 2]>,
+    /// Title of the window, as listed by capture software.
+    pub title: String,
+    /// Color the window is cleared to under the overlay, e.g. a chroma key.
+    /// RGBA, with components between `0.0` and `1.0`.
+    pub background: [f32; 4],
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            position: [0, 0],
+            size: None,
+            title: String::from("hudhook mirror"),
+            background: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
 
 /// Texture Loader for ImguiRenderLoop callbacks to load and replace textures
 pub trait RenderContext {
     /// Load texture and return TextureId to use. Invoke it in your
     /// [`crate::ImguiRenderLoop::initialize`] method for setting up textures.
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId, Error>;
+    ///
+    /// Textures larger than the device supports, or than set via
+    /// [`HudhookBuilder::with_max_texture_size`], are rejected with
+    /// [`TextureError::TooLarge`].
+    fn load_texture(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<TextureId, TextureError>;
 
     /// Upload an image to an existing texture, replacing its content. Invoke it
     /// in your [`crate::ImguiRenderLoop::before_render`] method for
@@ -166,7 +438,279 @@ pub trait RenderContext {
         data: &[u8],
         width: u32,
         height: u32,
-    ) -> Result<(), Error>;
+    ) -> Result<(), TextureError>;
+
+    /// Load an image file and upload it as a texture, returning the
+    /// TextureId to use. The formats supported by the [`image`](https://docs.rs/image)
+    /// crate are accepted.
+    ///
+    /// Textures are cached by canonical path, so loading the same file more
+    /// than once returns the same texture. Missing files and unsupported
+    /// formats are reported as errors.
+    ///
+    /// Requires the `image-loader` feature, and is currently implemented by
+    /// the DirectX 11 and DirectX 12 renderers only.
+    #[cfg(feature = "image-loader")]
+    fn load_texture_from_path(
+        &mut self,
+        _path: &std::path::Path,
+    ) -> Result<TextureId, TextureError> {
+        Err(Error::from_hresult(windows::Win32::Foundation::E_NOTIMPL).into())
+    }
+
+    /// Retrieve the device the renderer draws with, as a raw pointer, along
+    /// with the graphics API it belongs to.
+    ///
+    /// This is an escape hatch for what the texture functions don't cover,
+    /// such as custom pipelines or feature queries: cast the pointer to the
+    /// interface documented on the [`RenderBackend`] variant, e.g. with
+    /// `ID3D12Device::from_raw_borrowed`. Returns `None` with renderers
+    /// that don't expose their device.
+    ///
+    /// # Safety
+    ///
+    /// The pointer is borrowed from the renderer, without adding a reference.
+    /// It is only valid during the callback it was retrieved in (e.g.
+    /// [`ImguiRenderLoop::initialize`] or [`ImguiRenderLoop::before_render`]):
+    /// clone the interface to keep it for longer, and never release the raw
+    /// pointer itself. Any state changed through the device must be restored
+    /// before returning, as the renderer doesn't expect it.
+    unsafe fn device_raw(&self) -> Option<(RenderBackend, *mut c_void)> {
+        None
+    }
+
+    /// Retrieve the DXGI swap chain the overlay is being drawn into, as a raw
+    /// `IDXGISwapChain` pointer. Returns `None` with renderers that don't draw
+    /// into a DXGI swap chain.
+    ///
+    /// The overlay is only drawn into one swap chain per process, as there is
+    /// a single imgui context and render loop: the first one that presents,
+    /// or the one that replaces it in the same window. The presents of the
+    /// other swap chains, e.g. of the editor view of an application presenting
+    /// several of them, are left untouched. Compare the pointer across frames
+    /// to find out which swap chain the overlay is in.
+    ///
+    /// # Safety
+    ///
+    /// The same as [`RenderContext::device_raw`]: the pointer is borrowed and
+    /// only valid during the callback it was retrieved in.
+    unsafe fn swap_chain_raw(&self) -> Option<*mut c_void> {
+        None
+    }
+
+    /// Retrieve the time of the current frame, captured right before
+    /// [`ImguiRenderLoop::before_render`] and measured from the creation of
+    /// the first renderer.
+    ///
+    /// The clock is monotonic, shared by every renderer and keeps running when
+    /// the renderer is rebuilt, e.g. after a device reset. All the callbacks
+    /// of a frame observe the same value.
+    fn time(&self) -> Duration {
+        frame_clock::time()
+    }
+
+    /// Retrieve the number of frames rendered so far, which is also the index
+    /// of the current frame, starting at 0.
+    fn frame_count(&self) -> u64 {
+        frame_clock::frame_count()
+    }
+
+    /// Rebuild the imgui font atlas and upload it to the GPU again, e.g. after
+    /// replacing the fonts to switch languages.
+    ///
+    /// Change the fonts via `ctx.fonts()` in
+    /// [`ImguiRenderLoop::before_render`], then call this from the same
+    /// callback: the atlas is rebuilt and uploaded right after it returns,
+    /// before the frame is built, as imgui doesn't allow changing the atlas
+    /// while a frame is in progress. Fonts added in
+    /// [`ImguiRenderLoop::initialize`] don't need it, as the atlas is first
+    /// uploaded after that callback returns.
+    ///
+    /// If the size of the atlas didn't change, the font texture is updated in
+    /// place. Otherwise, the atlas is uploaded to a new texture, which gets its
+    /// own shader resource view with DirectX 12, and the old one is no longer
+    /// used.
+    fn rebuild_font_atlas(&mut self) {
+        renderer::request_font_atlas_rebuild();
+    }
+
+    /// Replace the whole font atlas with a new one, whose fonts are added by
+    /// `build`, e.g. to switch from a pixel font to a smooth one.
+    ///
+    /// The new atlas is built and rasterized on a thread of its own, which
+    /// `build` is called from, then swapped in before one of the next frames
+    /// and uploaded like a rebuilt one: no frame is drawn with a partially
+    /// built atlas. Until then, the imgui context is suspended: the last frame
+    /// is drawn again, without calling [`ImguiRenderLoop::after_imgui_render`],
+    /// and the input received meanwhile is processed afterwards. Font sizes
+    /// are relative to the DPI of the window at startup, like the ones added
+    /// in [`ImguiRenderLoop::initialize`], and scaled to the current one.
+    ///
+    /// The fonts of the previous atlas are destroyed, including the ones
+    /// pushed by their `FontId`: keep the ones returned by
+    /// `FontAtlas::add_font` in `build` instead. Requesting another
+    /// replacement before the next frame drops this one, and one requested
+    /// while an atlas is built is built next.
+    fn replace_font_atlas(&mut self, build: Box<dyn FnOnce(&mut imgui::FontAtlas) + Send>) {
+        renderer::font_atlas::request_replacement(build);
+    }
+
+    /// Stop capturing the mouse and keyboard right away, instead of a frame
+    /// later when imgui notices that no window wants them anymore, e.g. when
+    /// the last overlay window closes.
+    ///
+    /// Call this from [`ImguiRenderLoop::before_render`]: once it returns,
+    /// `io.want_capture_mouse`, `io.want_capture_keyboard` and
+    /// `io.want_text_input` are reset, the keys and mouse buttons held down
+    /// are released, and window messages are no longer blocked, regardless of
+    /// [`ImguiRenderLoop::message_filter`]. The filter is computed again at
+    /// the next frame, from the state of this one.
+    fn release_input(&mut self) {
+        renderer::request_input_release();
+    }
+
+    /// Force the mouse cursor to be shown or hidden over the game window,
+    /// regardless of whether the overlay captures the mouse, e.g. to show a
+    /// cursor while passing the input through to the game, or to hide it
+    /// during a cutscene.
+    ///
+    /// While forced, the cursor is drawn by imgui, by setting
+    /// `io.mouse_draw_cursor` at every frame, and the OS cursor is hidden over
+    /// the client area of the window, so that the two don't show up at once.
+    /// This takes precedence over the `io.mouse_draw_cursor` set by the
+    /// render loop, which is given back by
+    /// [`RenderContext::reset_mouse_cursor_visible`]. The OS cursor is
+    /// updated the next time it moves, and only if the overlay handles the
+    /// window messages, see [`HudhookBuilder::with_input`].
+    ///
+    /// Call this from [`ImguiRenderLoop::before_render`] for it to apply to the
+    /// frame being built.
+    fn set_mouse_cursor_visible(&mut self, visible: bool) {
+        renderer::set_mouse_cursor_visible(Some(visible));
+    }
+
+    /// Stop forcing the mouse cursor visibility set via
+    /// [`RenderContext::set_mouse_cursor_visible`], leaving the cursor to imgui
+    /// and the game again.
+    fn reset_mouse_cursor_visible(&mut self) {
+        renderer::set_mouse_cursor_visible(None);
+    }
+
+    /// Retrieve the mouse cursor visibility forced via
+    /// [`RenderContext::set_mouse_cursor_visible`], or `None` if it isn't.
+    fn mouse_cursor_visible(&self) -> Option<bool> {
+        renderer::mouse_cursor_visible()
+    }
+
+    /// Retrieve the size and format of the back buffer the overlay draws into.
+    ///
+    /// The back buffer is read from the swap chain (DirectX 11/12), the device
+    /// (DirectX 9) or the window (OpenGL 3) the game presents with, before
+    /// the renderer is created, so it is already known in
+    /// [`ImguiRenderLoop::initialize`], e.g. to create an off-screen render
+    /// target of matching size and format. It is updated at every frame,
+    /// which reflects resizes and mode switches. Returns `None` if it couldn't
+    /// be retrieved.
+    fn back_buffer(&self) -> Option<BackBufferInfo> {
+        *BACK_BUFFER.lock()
+    }
+
+    /// Retrieve the command list the overlay is being recorded into, as a raw
+    /// pointer, for the render loop to record its own commands on top of it
+    /// in [`ImguiRenderLoop::after_imgui_render`].
+    ///
+    /// Only the DirectX 12 renderer records into a command list, which is an
+    /// `ID3D12GraphicsCommandList`: cast it, e.g. with
+    /// `ID3D12GraphicsCommandList::from_raw_borrowed`. Returns `None` outside
+    /// of [`ImguiRenderLoop::after_imgui_render`], and with the other
+    /// renderers, which draw immediately through the device retrieved with
+    /// [`RenderContext::device_raw`].
+    ///
+    /// # Safety
+    ///
+    /// The pointer is borrowed from the renderer, without adding a reference,
+    /// and must not be used after the callback returns. See
+    /// [`ImguiRenderLoop::after_imgui_render`] for the state the command list
+    /// must be left in.
+    unsafe fn command_list_raw(&self) -> Option<*mut c_void> {
+        None
+    }
+
+    /// Retrieve the DirectX 12 command list the overlay is being recorded
+    /// into, to append draw calls to it in
+    /// [`ImguiRenderLoop::after_imgui_render`] instead of submitting a command
+    /// list of your own.
+    ///
+    /// When the render loop is called, the imgui draw data has been recorded,
+    /// and the command list has bound:
+    ///
+    /// - the back buffer, in the `D3D12_RESOURCE_STATE_RENDER_TARGET` state, as
+    ///   the only render target, without a depth stencil view;
+    /// - the shader visible descriptor heap holding the textures loaded via
+    ///   [`RenderContext::load_texture`];
+    /// - the root signature and pipeline state of the overlay, a viewport
+    ///   covering the display and the scissor rectangle of the last imgui draw
+    ///   command.
+    ///
+    /// Returns `None` outside of [`ImguiRenderLoop::after_imgui_render`], and
+    /// with the other renderers. This is the typed counterpart of
+    /// [`RenderContext::command_list_raw`], with the same rules.
+    #[cfg(feature = "dx12")]
+    fn dx12_command_list(&self) -> Option<&ID3D12GraphicsCommandList> {
+        None
+    }
+
+    /// Show a toast with `text` for `duration`, e.g. "Overlay loaded" or
+    /// "Config saved".
+    ///
+    /// Toasts are drawn by [`hudhook`](crate) after the render loop UI, on top
+    /// of every window, without taking input. They're stacked from the corner
+    /// set via [`HudhookBuilder::with_toast_corner`], the oldest one closest
+    /// to it, and fade out at the end of their duration. The duration is
+    /// measured with [`RenderContext::time`], from the current frame.
+    fn notify(&mut self, text: &str, duration: Duration) {
+        renderer::toasts::push(text, duration);
+    }
+
+    /// Retrieve the title of the game window, see [`game_hwnd`].
+    ///
+    /// Fails with `ERROR_INVALID_WINDOW_HANDLE` if the game window isn't known
+    /// or was destroyed.
+    fn window_title(&self) -> Result<String, Error> {
+        window_title(existing_game_hwnd()?)
+    }
+
+    /// Set the title of the game window, e.g. to append `[Modded]` to
+    /// the one read via [`RenderContext::window_title`].
+    ///
+    /// The title the window had before the first change is given back when
+    /// the hooks are removed. This sends `WM_SETTEXT` to the window, so when
+    /// the game presents from another thread than the one owning the window,
+    /// it waits for that thread to process the message.
+    ///
+    /// Fails with `ERROR_INVALID_WINDOW_HANDLE` if the game window isn't known
+    /// or was destroyed.
+    fn set_window_title(&mut self, title: &str) -> Result<(), Error> {
+        let hwnd = existing_game_hwnd()?;
+        {
+            let mut original = ORIGINAL_WINDOW_TITLE.lock();
+            if original.is_none() {
+                *original = Some((hwnd.0, window_title(hwnd)?));
+            }
+        }
+
+        unsafe { SetWindowTextW(hwnd, &HSTRING::from(title)) }
+    }
+}
+
+impl dyn RenderContext + '_ {
+    /// Retrieve the data set via [`HudhookBuilder::with_user_data`], e.g. to
+    /// share state between render loops without a global of their own.
+    ///
+    /// Returns `None` if no data was set, or if it isn't a `T`.
+    pub fn user_data<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        options::get().user_data.clone()?.downcast::<T>().ok()
+    }
 }
 
 /// Defines the `on_wnd_proc` state.
@@ -194,6 +738,23 @@ pub enum OnWndProc {
     Break,
 }
 
+/// Arguments of an `IDXGISwapChain::ResizeBuffers` call, as seen by
+/// [`ImguiRenderLoop::on_resize_buffers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResizeParams {
+    /// Number of buffers in the swap chain, or `0` to preserve it.
+    pub buffer_count: u32,
+    /// New width of the back buffer, or `0` to match the client area.
+    pub width: u32,
+    /// New height of the back buffer, or `0` to match the client area.
+    pub height: u32,
+    /// New format of the back buffer, or `DXGI_FORMAT_UNKNOWN` to preserve
+    /// it.
+    pub new_format: DXGI_FORMAT,
+    /// `DXGI_SWAP_CHAIN_FLAG` values for the swap chain.
+    pub flags: u32,
+}
+
 /// Allocate a Windows console.
 pub fn alloc_console() -> Result<(), Error> {
     if !CONSOLE_ALLOCATED.swap(true, Ordering::SeqCst) {
@@ -233,6 +794,233 @@ pub fn free_console() -> Result<(), Error> {
     Ok(())
 }
 
+/// Retrieve the handle of the window the hooked application renders to.
+///
+/// The handle becomes available as soon as the hooks have seen the swap chain
+/// (DirectX 11/12), the device (DirectX 9) or the device context (OpenGL 3),
+/// i.e. at the first intercepted present call, before the renderer is
+/// initialized. Returns `None` until then.
+pub fn game_hwnd() -> Option<HWND> {
+    match GAME_HWND.load(Ordering::SeqCst) {
+        0 => None,
+        hwnd => Some(HWND(hwnd)),
+    }
+}
+
+/// Retrieve the id of the thread the render loop runs on.
+///
+/// imgui is not thread safe: the render loop is invoked on whatever thread
+/// calls the hooked present function, and the imgui context must not be
+/// touched from any other thread. The id is recorded when the renderer is
+/// first initialized, see [`HudhookBuilder::with_ui_thread_pinning`]. Returns
+/// `None` until then.
+pub fn ui_thread_id() -> Option<ThreadId> {
+    *UI_THREAD.lock()
+}
+
+// Handle of the game window, if it still exists.
+fn existing_game_hwnd() -> Result<HWND, Error> {
+    match game_hwnd() {
+        Some(hwnd) if unsafe { IsWindow(hwnd) }.as_bool() => Ok(hwnd),
+        _ => Err(Error::from(ERROR_INVALID_WINDOW_HANDLE.to_hresult())),
+    }
+}
+
+fn window_title(hwnd: HWND) -> Result<String, Error> {
+    let len = unsafe { GetWindowTextLengthW(hwnd) };
+    let mut title = vec![0u16; len as usize + 1];
+    let len = unsafe { GetWindowTextW(hwnd, &mut title) };
+    Ok(String::from_utf16_lossy(&title[..len as usize]))
+}
+
+// Give the game window its title back, if it was changed via
+// `RenderContext::set_window_title`.
+fn restore_window_title() {
+    let Some((hwnd, title)) = ORIGINAL_WINDOW_TITLE.lock().take() else {
+        return;
+    };
+
+    let hwnd = HWND(hwnd);
+    if unsafe { IsWindow(hwnd) }.as_bool() {
+        if let Err(e) = unsafe { SetWindowTextW(hwnd, &HSTRING::from(title)) } {
+            error!("Could not restore window title: {e:?}");
+        }
+    }
+}
+
+/// Record the back buffer the overlay draws into.
+pub(crate) fn set_back_buffer(back_buffer: BackBufferInfo) {
+    *BACK_BUFFER.lock() = Some(back_buffer);
+}
+
+/// Store the game window handle if it wasn't already known.
+pub(crate) fn set_game_hwnd(hwnd: HWND) {
+    let _ = GAME_HWND.compare_exchange(0, hwnd.0, Ordering::SeqCst, Ordering::SeqCst);
+}
+
+/// Show or hide the imgui demo window.
+///
+/// The demo window is drawn by [`hudhook`](crate) after your render loop, and
+/// is a quick way to check that hooking, rendering and input all work
+/// independently of your own UI code. It can also be enabled at build time
+/// via [`HudhookBuilder::with_demo_window`], and closing it from its title
+/// bar hides it again. Next to it, a small window shows the [`build_info`] to
+/// quote in bug reports.
+pub fn set_demo_window(visible: bool) {
+    DEMO_WINDOW.store(visible, Ordering::SeqCst);
+}
+
+/// Show or hide the frame rate readout.
+///
+/// The readout is drawn by [`hudhook`](crate) after your render loop, in the
+/// corner set via [`HudhookBuilder::with_fps_overlay`], which also shows it
+/// from the start. It reports the rate at which the game presents, counted
+/// from the present hook calls, and the median time the overlay spends in the
+/// present hook (see [`present_hook_timings`]), to check whether the overlay
+/// affects performance.
+pub fn set_fps_overlay(visible: bool) {
+    FPS_OVERLAY.store(visible, Ordering::SeqCst);
+}
+
+/// Whether the overlay captures the mouse or the keyboard, i.e. whether imgui
+/// set `io.want_capture_mouse` or `io.want_capture_keyboard` at the last
+/// frame, e.g. because the user hovers an overlay window or types in a text
+/// box.
+///
+/// This is a single atomic load, cheap enough to poll from any thread, e.g.
+/// to ignore the movement keys in the game logic while the overlay has focus.
+/// Returns `false` when no overlay is rendered.
+pub fn overlay_capturing_input() -> bool {
+    renderer::capturing_input()
+}
+
+/// Take the input messages received by the game window since the last call,
+/// oldest first, with the time they were received at.
+///
+/// Only buffered when enabled via [`HudhookBuilder::with_input_queue`]: this
+/// is the raw stream of keyboard, mouse and raw input messages, regardless of
+/// whether imgui wants the input, e.g. for recording macros. Messages are
+/// buffered by the overlay as it processes them, i.e. once per frame.
+pub fn drain_input_events() -> Vec<InputEvent> {
+    input_queue::drain()
+}
+
+/// Retrieve the p50/p95/p99 time spent by the overlay in the present hook,
+/// before the original `Present` is called, over the most recent presents.
+///
+/// This measures the frame time cost of [`hudhook`](crate) itself, including
+/// your render loop. The number of presents aggregated can be configured via
+/// [`HudhookBuilder::with_present_timing_window`]. Returns `None` until the
+/// first present is intercepted.
+pub fn present_hook_timings() -> Option<PresentHookTimings> {
+    timings::aggregate()
+}
+
+/// Retrieve the NT handle of the texture the frames are copied to when
+/// enabled via [`HudhookBuilder::with_shared_capture`].
+///
+/// The handle is a duplicate owned by the caller, which must close it with
+/// `CloseHandle`: it stays valid after the texture is recreated, and keeps
+/// the texture it refers to alive. It belongs to the game process: duplicate
+/// it into the consumer process with `DuplicateHandle`, and open it there with
+/// `ID3D12Device::OpenSharedHandle` (or `ID3D11Device1::OpenSharedResource1`).
+/// The texture has the size and format of the back buffers, and is in the
+/// `D3D12_RESOURCE_STATE_COMMON` state between copies.
+///
+/// The texture is recreated, with a new handle, when the back buffers change
+/// size or format, and no longer written to once the hooks are released: poll
+/// this function to notice. Returns `None` until the first frame is copied.
+///
+/// # Synchronization
+///
+/// Every frame is copied to the same texture. Wait on the fence returned by
+/// [`shared_capture_fence`] before reading it, and read it quickly, e.g. by
+/// copying it to a texture of your own: the copy of the next frame isn't
+/// synchronized with the consumer, and would tear its reads.
+#[cfg(feature = "dx12")]
+pub fn shared_capture_handle() -> Option<windows::Win32::Foundation::HANDLE> {
+    renderer::shared_capture::texture_handle()
+}
+
+/// Retrieve the NT handle of the fence signaled once a frame is copied to the
+/// texture of [`shared_capture_handle`], along with the value it was last
+/// signaled with, which increases by one every frame.
+///
+/// Like the one of the texture, the handle is a duplicate owned by the caller,
+/// which must close it with `CloseHandle`, and must be duplicated into the
+/// consumer process, and opened there with `ID3D12Device::OpenSharedHandle`.
+/// Returns `None` until the first frame is copied.
+#[cfg(feature = "dx12")]
+pub fn shared_capture_fence() -> Option<(windows::Win32::Foundation::HANDLE, u64)> {
+    renderer::shared_capture::fence_handle()
+}
+
+/// Sample the color of the pixel at `(x, y)` of the game's back buffer, in
+/// client coordinates of the game window, e.g. for a color picker under the
+/// cursor, or to detect the state of the game UI.
+///
+/// The pixel is copied at the next frame, before the overlay is drawn over it,
+/// and read back once the GPU is done with that frame. This returns the color
+/// sampled last if it is the one of `(x, y)`, and requests `(x, y)` again:
+/// call it at every frame, e.g. from [`ImguiRenderLoop::render`]. The first
+/// calls for a pixel return `None`, and the color then lags a frame or two
+/// behind.
+///
+/// The color is converted to 8-bit RGBA from 8-bit RGBA and BGRA, 10-bit RGB
+/// (scaled as is, e.g. PQ-encoded for HDR10) and 16-bit float back buffers
+/// (scRGB, clamped to SDR and sRGB-encoded). Returns `None` for other
+/// formats, and for pixels outside the back buffer. DirectX 12 only.
+#[cfg(feature = "dx12")]
+pub fn sample_pixel(x: i32, y: i32) -> Option<[u8; 4]> {
+    renderer::pixel_sample::request([x, y])
+}
+
+/// Retrieve the graphics API the overlay renders with.
+///
+/// Returns `None` until the hooks are applied, and with
+/// [`ImguiDualDxHooks`](crate::hooks::dual_dx::ImguiDualDxHooks), until the
+/// game presents with either DirectX 11 or DirectX 12. This lets a render
+/// loop enable features depending on the backend, and is worth including in
+/// bug reports.
+pub fn active_backend() -> Option<RenderBackend> {
+    unsafe { HUDHOOK.get() }?.hooks.iter().find_map(|hooks| hooks.backend())
+}
+
+/// Replace the render loop at runtime, without unhooking.
+///
+/// At the next frame, the current render loop is dropped, along with the
+/// fonts it added, and [`ImguiRenderLoop::initialize`] of `render_loop` is
+/// invoked with the same imgui context and renderer. The window layout and
+/// the textures loaded so far carry over. This enables hot-reloading the UI
+/// while developing an overlay.
+///
+/// If several replacements are queued before the next present, only the last
+/// one is used.
+pub fn replace_render_loop(render_loop: Box<dyn ImguiRenderLoop + Send + Sync>) {
+    hooks::set_render_loop_replacement(render_loop);
+}
+
+/// Save the imgui window state to the file set via
+/// [`HudhookBuilder::with_ini_filename`].
+///
+/// This is how settings are persisted when
+/// [`HudhookBuilder::with_ini_autosave`] is disabled: call it with the context
+/// passed to [`ImguiRenderLoop::before_render`], e.g. when
+/// [`Io::want_save_ini_settings`] is set or before ejecting. Does nothing if
+/// no file was set.
+pub fn save_ini_settings(ctx: &mut Context) -> std::io::Result<()> {
+    let Some(ini_filename) = options::get().ini_filename.clone() else {
+        return Ok(());
+    };
+
+    let mut data = String::new();
+    ctx.save_ini_settings(&mut data);
+    std::fs::write(ini_filename, data)?;
+    ctx.io_mut().want_save_ini_settings = false;
+
+    Ok(())
+}
+
 /// Disable hooks and eject the DLL.
 ///
 /// ## Ejecting a DLL
@@ -244,6 +1032,10 @@ pub fn free_console() -> Result<(), Error> {
 ///
 /// Befor calling [`eject`], make sure to perform any manual cleanup (e.g.
 /// dropping/resetting the contents of static mutable variables).
+///
+/// If the window procedure was subclassed (see [`WndProcMode::Subclass`]) and
+/// the subclass can't be removed, e.g. because the window thread hangs, the
+/// hooks are disabled but the DLL stays loaded.
 pub fn eject() {
     trace!("Requesting eject");
     EJECT_REQUESTED.store(true, Ordering::SeqCst);
@@ -270,6 +1062,13 @@ unsafe fn perform_eject() {
         // we continue to free the library.
         HOOK_EJECTION_BARRIER.wait_for_all_guards();
 
+        // A window still routing its messages through the module would crash
+        // once it's unloaded.
+        if renderer::subclass_left() {
+            error!("A window subclass is still installed, not unloading the module");
+            return;
+        }
+
         if let Some(module) = MODULE.take() {
             FreeLibraryAndExitThread(module, 0);
         }
@@ -302,8 +1101,82 @@ pub trait ImguiRenderLoop {
     }
 
     /// Called every frame. Use the provided `ui` object to build your UI.
+    ///
+    /// On DirectX 11 and 12, `io.display_size` is the size of the back
+    /// buffer, and the mouse position is mapped to it from the client area
+    /// according to the scaling of the swap chain, e.g. when the game draws
+    /// at a fixed resolution with `DXGI_SCALING_NONE` in a larger window.
     fn render(&mut self, ui: &mut Ui);
 
+    /// Called every frame instead of [`ImguiRenderLoop::render`], which it
+    /// invokes by default. Return whether the frame needs to be drawn even if
+    /// imgui produced nothing to draw.
+    ///
+    /// When this returns `false` and the draw data of the frame has no
+    /// vertices, e.g. because every window of an auto-hiding overlay is
+    /// closed, the renderer is skipped for that frame: no state is touched,
+    /// and nothing is submitted to the GPU.
+    /// [`ImguiRenderLoop::after_imgui_render`] isn't invoked either, so keep
+    /// returning `true` if you draw there. The default returns `true`.
+    fn render_ui(&mut self, ui: &mut Ui) -> bool {
+        self.render(ui);
+        true
+    }
+
+    /// Called after the imgui draw data has been rendered into the back
+    /// buffer, to draw on top of it with native draw calls, e.g. custom
+    /// shaders or post effects.
+    ///
+    /// With DirectX 12, the draw calls are recorded into the command list
+    /// retrieved with `RenderContext::dx12_command_list` or
+    /// [`RenderContext::command_list_raw`], right before the
+    /// back buffer is transitioned back for presenting. The back buffer is in
+    /// the `D3D12_RESOURCE_STATE_RENDER_TARGET` state, and bound as the only
+    /// render target: it must be left in that state, and any other resource
+    /// transitioned by the render loop must be transitioned back before
+    /// returning. The pipeline state, root signature and descriptor heaps can
+    /// be changed freely, and the command list must not be closed.
+    ///
+    /// With the other renderers, the draw calls are issued right away through
+    /// the device retrieved with [`RenderContext::device_raw`], or the current
+    /// OpenGL context, whose state must be restored before returning.
+    ///
+    /// Invoked at every present, including the ones drawing the last frame
+    /// again with [`HudhookBuilder::with_min_frame_interval`].
+    fn after_imgui_render<'a>(&'a mut self, _render_context: &'a mut dyn RenderContext) {}
+
+    /// Called when the game window gains focus, e.g. when the user switches
+    /// back to the game.
+    ///
+    /// Invoked once per transition, when the corresponding `WM_ACTIVATEAPP`
+    /// or `WM_ACTIVATE` message is processed, before the next frame is
+    /// rendered.
+    fn focus_gained(&mut self) {}
+
+    /// Called when the game window loses focus, e.g. when the user switches
+    /// to another application. Use it to pause animations or save state.
+    ///
+    /// Keys and mouse buttons held down at that point are released in imgui.
+    /// See [`ImguiRenderLoop::focus_gained`] for when it is invoked.
+    fn focus_lost(&mut self) {}
+
+    /// Called when files are dropped onto the game window, e.g. to open them
+    /// in an asset browser or a config editor. `pos` is where they were
+    /// dropped, in client coordinates, to tell which window they were dropped
+    /// onto.
+    ///
+    /// Drag and drop is enabled on the game window when the overlay is
+    /// initialized with input. If the game already accepts dropped files, it
+    /// still receives them as well. Invoked before the next frame is built.
+    fn on_files_dropped(&mut self, _paths: &[PathBuf], _pos: [f32; 2]) {}
+
+    /// Called with each message received on the named pipe enabled via
+    /// [`HudhookBuilder::with_ipc_pipe`], e.g. to apply settings sent by an
+    /// external configurator. Invoked before the next frame is built, in the
+    /// order the messages were received.
+    #[cfg(feature = "ipc")]
+    fn on_ipc_message(&mut self, _message: &[u8]) {}
+
     /// Called during the window procedure.
     fn on_wnd_proc(
         &self,
@@ -321,6 +1194,44 @@ pub trait ImguiRenderLoop {
     fn message_filter(&self, _io: &Io) -> MessageFilter {
         MessageFilter::empty()
     }
+
+    /// Called before the game's `ResizeBuffers` call is forwarded. The
+    /// arguments can be rewritten in place, e.g. to force a buffer count or
+    /// a back buffer format. Return `false` to skip the call altogether.
+    ///
+    /// This is a power-user escape hatch: the game does not know its
+    /// arguments were changed, and misusing this can easily break it.
+    ///
+    /// The arguments are the game's: a `buffer_count` of 0 preserves the
+    /// current count, which the swap chain reports via `GetDesc` once
+    /// resized.
+    ///
+    /// Currently only invoked by the DirectX 12 hooks.
+    fn on_resize_buffers(&self, _params: &mut ResizeParams) -> bool {
+        true
+    }
+
+    /// Called right before the game's present call is forwarded, after the
+    /// overlay draw commands have been submitted. Use this for last-moment
+    /// work that needs to know the frame is about to flip.
+    ///
+    /// Only invoked for the presents the overlay was drawn for: those of the
+    /// swap chain the overlay renders to (the window it was created for with
+    /// OpenGL), once the render loop has been initialized. Presents skipped
+    /// at startup, those of other swap chains or windows, and those whose
+    /// render failed aren't reported, and neither are they while the
+    /// pipeline is busy on another thread.
+    fn before_present(&self) {}
+
+    /// Called right after the game's present call returned, with its result.
+    /// Use this for frame-accurate telemetry or to detect present failures.
+    ///
+    /// For OpenGL, the result is `S_OK` if `wglSwapBuffers` succeeded, and
+    /// derived from the last error otherwise.
+    ///
+    /// Invoked for the same presents as
+    /// [`before_present`](Self::before_present).
+    fn after_present(&self, _result: HRESULT) {}
 }
 
 /// Generic trait for platform-specific hooks.
@@ -333,18 +1244,55 @@ pub trait ImguiRenderLoop {
 /// - [`ImguiDx9Hooks`](crate::hooks::dx9::ImguiDx9Hooks)
 /// - [`ImguiDx11Hooks`](crate::hooks::dx11::ImguiDx11Hooks)
 /// - [`ImguiDx12Hooks`](crate::hooks::dx12::ImguiDx12Hooks)
+/// - [`ImguiDualDxHooks`](crate::hooks::dual_dx::ImguiDualDxHooks)
 /// - [`ImguiOpenGl3Hooks`](crate::hooks::opengl3::ImguiOpenGl3Hooks)
 pub trait Hooks {
     /// Construct a boxed instance of the implementor, storing the provided
     /// render loop where appropriate.
-    fn from_render_loop<T>(t: T) -> Box<Self>
+    ///
+    /// Fails if the functions to hook can't be found or hooked.
+    fn from_render_loop<T>(t: T) -> Result<Box<Self>, ApplyError>
     where
         Self: Sized,
         T: ImguiRenderLoop + Send + Sync + 'static;
 
+    /// Return the modules that must be loaded in the process before the hooks
+    /// can be constructed, e.g. `opengl32.dll`.
+    ///
+    /// [`Hudhook::apply`] waits for them, as configured via
+    /// [`HudhookBuilder::with_install_timeout`].
+    fn required_modules() -> &'static [&'static str]
+    where
+        Self: Sized,
+    {
+        &[]
+    }
+
     /// Return the list of hooks to be enabled, in order.
     fn hooks(&self) -> &[MhHook];
 
+    /// Return the trampolines of the hooks, in the order of [`Hooks::hooks`].
+    /// Calling one invokes the original function, e.g.
+    /// `IDXGISwapChain::Present`, without going through the overlay. Tell them
+    /// apart with [`MhHook::name`], or retrieve them along with the hooked
+    /// functions via [`installed_hooks`] once the hooks are applied.
+    ///
+    /// # Safety
+    ///
+    /// The pointers are type-erased: they must be transmuted to the signature
+    /// of the hooked function before being called. The trampolines are freed
+    /// when the hooks are removed, e.g. by ejecting, and must not be called
+    /// afterwards.
+    unsafe fn trampolines(&self) -> Vec<*const c_void> {
+        self.hooks().iter().map(|hook| hook.trampoline() as *const c_void).collect()
+    }
+
+    /// Return the graphics API the hooks render with, or `None` if it isn't
+    /// known (yet), e.g. for hooks that pick it at the first present.
+    fn backend(&self) -> Option<RenderBackend> {
+        None
+    }
+
     /// Cleanup global data and disable the hooks.
     ///
     /// # Safety
@@ -354,7 +1302,18 @@ pub trait Hooks {
 }
 
 /// Holds all the activated hooks and manages their lifetime.
-pub struct Hudhook(Vec<Box<dyn Hooks>>);
+pub struct Hudhook {
+    hooks: Vec<Box<dyn Hooks>>,
+    pending_hooks: Vec<PendingHooks>,
+    options: Options,
+}
+
+// Hooks added to the builder, constructed by `apply` once the modules they
+// require are loaded.
+struct PendingHooks {
+    required_modules: &'static [&'static str],
+    construct: Box<dyn FnOnce() -> Result<Box<dyn Hooks>, ApplyError>>,
+}
 unsafe impl Send for Hudhook {}
 unsafe impl Sync for Hudhook {}
 
@@ -375,16 +1334,56 @@ impl Hudhook {
             _ => unreachable!(),
         }
 
-        Hudhook(Vec::new())
+        Hudhook { hooks: Vec::new(), pending_hooks: Vec::new(), options: Options::default() }
     }
 
     /// Return an iterator of all the activated raw hooks.
     fn hooks(&self) -> impl IntoIterator<Item = &MhHook> {
-        self.0.iter().flat_map(|h| h.hooks())
+        // Swap chain methods hooked after the hooks were applied.
+        #[cfg(feature = "dx12")]
+        let rehooked = hooks::dx12::rehooked_methods();
+        #[cfg(not(feature = "dx12"))]
+        let rehooked = Vec::new();
+
+        self.hooks.iter().flat_map(|h| h.hooks()).chain(rehooked)
     }
 
     /// Apply the hooks.
-    pub fn apply(self) -> Result<(), MH_STATUS> {
+    ///
+    /// The hooks are constructed first, which requires the modules they hook
+    /// to be loaded in the process. If they aren't, this waits for them, for
+    /// at most the timeout set via [`HudhookBuilder::with_install_timeout`],
+    /// and returns [`ApplyError::ModuleNotLoaded`] if it elapses.
+    pub fn apply(mut self) -> Result<(), ApplyError> {
+        if unsafe { HUDHOOK.get() }.is_some() {
+            return Err(ApplyError::AlreadyApplied);
+        }
+        if self.pending_hooks.is_empty() && self.hooks.is_empty() {
+            return Err(ApplyError::NoBackendDetected);
+        }
+
+        // Publish the options before the hooks are created, as probing for the
+        // functions to hook depends on them, and before any hook can fire.
+        options::set(self.options.clone());
+        set_demo_window(self.options.demo_window);
+        set_fps_overlay(self.options.fps_overlay);
+        timings::set_window(self.options.present_timing_window);
+        renderer::reset_wnd_procs_suspended();
+        renderer::set_mouse_cursor_visible(None);
+        diagnostics::detect_conflicts(&self.options.known_conflicts);
+        #[cfg(feature = "ipc")]
+        if self.options.ipc_pipe {
+            ipc::start();
+        }
+
+        let install_timeout = self.options.install_timeout;
+        for pending_hooks in mem::take(&mut self.pending_hooks) {
+            hooks::wait_for_modules(pending_hooks.required_modules, install_timeout)?;
+            self.hooks.push((pending_hooks.construct)()?);
+        }
+
+        hooks::allow_pipeline_init();
+
         // Queue enabling all the hooks.
         for hook in self.hooks() {
             unsafe { hook.queue_enable()? };
@@ -392,12 +1391,54 @@ impl Hudhook {
 
         // Apply the queue of enable actions.
         unsafe { MH_ApplyQueued().ok_context("MH_ApplyQueued")? };
+        diagnostics::set_hooks_enabled(true);
 
         unsafe { HUDHOOK.set(self).ok() };
 
         Ok(())
     }
 
+    /// Disable or enable again the hooks applied via [`Hudhook::apply`],
+    /// without removing them, e.g. to measure the overhead of the overlay or
+    /// to hand over to another overlay.
+    ///
+    /// While the hooks are disabled, the game calls the hooked functions
+    /// directly and the overlay isn't drawn. The window procedures replaced by
+    /// the overlay are restored, so that input goes straight to the game, and
+    /// installed again when the hooks are enabled. With
+    /// [`WndProcMode::Subclass`], the subclass stays, and forwards the
+    /// messages untouched. The renderer and the render loop are kept, and
+    /// resume from where they were.
+    ///
+    /// Fails with `MH_ERROR_NOT_INITIALIZED` if no hooks were applied.
+    pub fn set_hooks_enabled(enabled: bool) -> Result<(), MH_STATUS> {
+        let Some(hudhook) = (unsafe { HUDHOOK.get() }) else {
+            return Err(MH_STATUS::MH_ERROR_NOT_INITIALIZED);
+        };
+
+        // Hand the windows back before the overlay stops rendering, and take
+        // them again only once it renders.
+        if !enabled {
+            renderer::set_wnd_procs_suspended(true);
+        }
+
+        for hook in hudhook.hooks() {
+            if enabled {
+                unsafe { hook.queue_enable()? };
+            } else {
+                unsafe { hook.queue_disable()? };
+            }
+        }
+        unsafe { MH_ApplyQueued().ok_context("MH_ApplyQueued")? };
+        diagnostics::set_hooks_enabled(enabled);
+
+        if enabled {
+            renderer::set_wnd_procs_suspended(false);
+        }
+
+        Ok(())
+    }
+
     /// Disable and cleanup the hooks.
     pub fn unapply(&mut self) -> Result<(), MH_STATUS> {
         trace!("Unapply hook");
@@ -408,14 +1449,30 @@ impl Hudhook {
 
         // Apply the queue of disable actions.
         unsafe { MH_ApplyQueued().ok_context("MH_ApplyQueued")? };
+        diagnostics::set_hooks_enabled(false);
 
         // Uninitialize minhook.
         unsafe { MH_Uninitialize().ok_context("MH_Uninitialize")? };
 
+        // Wait for a pipeline being created by a present still in flight, so
+        // that it isn't dropped half-constructed, and don't let presents still
+        // in flight create one afterwards.
+        let _pipeline_init_guard = hooks::forbid_pipeline_init();
+
         // Invoke cleanup for all hooks.
-        for hook in &mut self.0 {
+        for hook in &mut self.hooks {
             unsafe { hook.unhook() };
         }
+        restore_window_title();
+        GAME_HWND.store(0, Ordering::SeqCst);
+        UI_THREAD.lock().take();
+        hooks::take_render_loop_replacement();
+        renderer::discard_saved_settings();
+        hooks::reset_startup_present_skip();
+        timings::reset();
+        diagnostics::release_instance_marker();
+        #[cfg(feature = "ipc")]
+        ipc::stop();
         trace!("Finished removing hook");
 
         Ok(())
@@ -458,11 +1515,128 @@ pub struct HudhookBuilder(Hudhook);
 
 impl HudhookBuilder {
     /// Add a hook object.
+    ///
+    /// The hooks are constructed by [`Hudhook::apply`], once the modules they
+    /// require are loaded.
     pub fn with<T: Hooks + 'static>(
         mut self,
         render_loop: impl ImguiRenderLoop + Send + Sync + 'static,
     ) -> Self {
-        self.0 .0.push(T::from_render_loop(render_loop));
+        self.0.pending_hooks.push(PendingHooks {
+            required_modules: T::required_modules(),
+            construct: Box::new(move || {
+                T::from_render_loop(render_loop).map(|hooks| hooks as Box<dyn Hooks>)
+            }),
+        });
+        self
+    }
+
+    /// Set how long [`Hudhook::apply`] waits for the modules required by the
+    /// hooks to be loaded, e.g. `opengl32.dll` for
+    /// [`ImguiOpenGl3Hooks`](crate::hooks::opengl3::ImguiOpenGl3Hooks).
+    /// Defaults to zero, i.e. applying fails right away if they aren't.
+    ///
+    /// When the DLL is injected very early, e.g. into a suspended process,
+    /// the game may not have loaded its graphics modules yet. With a timeout,
+    /// they are checked for again with an exponential backoff, up to a
+    /// quarter of a second apart, and applying fails with
+    /// [`ApplyError::ModuleNotLoaded`] if the timeout elapses.
+    pub fn with_install_timeout(mut self, install_timeout: Duration) -> Self {
+        self.0.options.install_timeout = install_timeout;
+        self
+    }
+
+    /// Add modules of overlays known to conflict with [`hudhook`](crate), by
+    /// file name, e.g. `"MyOverlay64.dll"`.
+    ///
+    /// When the hooks are applied, the modules loaded in the process are
+    /// matched against these, ignoring case, and against a built-in list
+    /// covering RivaTuner Statistics Server, Discord and Steam. A warning
+    /// lists the matches, which are also reported by [`detected_conflicts`],
+    /// along with other copies of [`hudhook`](crate) hooking the process.
+    pub fn with_known_conflicts<I, S>(mut self, modules: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.0.options.known_conflicts.extend(modules.into_iter().map(Into::into));
+        self
+    }
+
+    /// Set imgui configuration flags (e.g.
+    /// [`ConfigFlags::NAV_ENABLE_KEYBOARD`],
+    /// [`ConfigFlags::NAV_ENABLE_GAMEPAD`] or
+    /// [`ConfigFlags::NO_MOUSE_CURSOR_CHANGE`]).
+    ///
+    /// The flags are applied to the context before
+    /// [`ImguiRenderLoop::initialize`] is called, and are reapplied every time
+    /// the renderer is recreated (e.g. after a device reset), so they are not
+    /// lost along the way. They are merged with the flags set by the renderer.
+    ///
+    /// Flags that depend on platform backend support, such as
+    /// `ConfigFlags::VIEWPORTS_ENABLE` (available with the `imgui-docking`
+    /// feature), have no effect as multiple viewports are not implemented.
+    /// `ConfigFlags::NAV_ENABLE_SET_MOUSE_POS` additionally requires
+    /// [`BackendFlags::HAS_SET_MOUSE_POS`] to be set.
+    pub fn with_config_flags(mut self, config_flags: ConfigFlags) -> Self {
+        self.0.options.config_flags = config_flags;
+        self
+    }
+
+    /// Submit a dockspace covering the main viewport at every frame, before
+    /// [`ImguiRenderLoop::render`], so that the windows of the overlay can be
+    /// docked to the sides of the screen and to each other. Defaults to
+    /// `false`.
+    ///
+    /// Enables `ConfigFlags::DOCKING_ENABLE`. The dockspace has no background,
+    /// and its central node lets the game show through.
+    #[cfg(feature = "imgui-docking")]
+    pub fn with_dockspace(mut self, dockspace: bool) -> Self {
+        self.0.options.dockspace = dockspace;
+        self
+    }
+
+    /// Set the initial dock layout, as the contents of an imgui `.ini` file,
+    /// e.g. `include_str!("layout.ini")`.
+    ///
+    /// The layout is loaded when the imgui context is created, unless the
+    /// file set via [`HudhookBuilder::with_ini_filename`] exists: the layout
+    /// saved by the user takes precedence.
+    #[cfg(feature = "imgui-docking")]
+    pub fn with_dock_layout(mut self, dock_layout: &'static str) -> Self {
+        self.0.options.dock_layout = Some(dock_layout);
+        self
+    }
+
+    /// Set imgui backend flags, in addition to the ones set by the renderer.
+    ///
+    /// Only set flags for capabilities you implement yourself, e.g. setting
+    /// [`BackendFlags::HAS_SET_MOUSE_POS`] if you are going to honor
+    /// [`Io::want_set_mouse_pos`] in your render loop.
+    pub fn with_backend_flags(mut self, backend_flags: BackendFlags) -> Self {
+        self.0.options.backend_flags = backend_flags;
+        self
+    }
+
+    /// Override the name imgui reports as its platform backend
+    /// ([`Context::platform_name`]). Defaults to
+    /// `hudhook-win32@<version>`.
+    ///
+    /// The name is set before [`ImguiRenderLoop::initialize`] is called, so
+    /// the render loop and other imgui-aware code can read it from there on.
+    pub fn with_platform_name<S: Into<String>>(mut self, platform_name: S) -> Self {
+        self.0.options.platform_name = Some(platform_name.into());
+        self
+    }
+
+    /// Override the name imgui reports as its renderer backend
+    /// ([`Context::renderer_name`]). Defaults to the renderer in use, e.g.
+    /// `hudhook-dx12@<version>`.
+    ///
+    /// Like [`HudhookBuilder::with_platform_name`], the name is set before
+    /// [`ImguiRenderLoop::initialize`] is called.
+    pub fn with_renderer_name<S: Into<String>>(mut self, renderer_name: S) -> Self {
+        self.0.options.renderer_name = Some(renderer_name.into());
         self
     }
 
@@ -472,6 +1646,573 @@ impl HudhookBuilder {
         self
     }
 
+    /// Drive the overlay cursor from raw mouse input.
+    ///
+    /// Games that put the mouse in relative mode keep the OS cursor hidden and
+    /// clamped or recentered, so its position does not reflect the user's
+    /// intent. When enabled, relative `WM_INPUT` mouse motion is accumulated
+    /// into a virtual cursor, clamped to the window's client area, which is
+    /// used as the imgui mouse position while the overlay is capturing mouse
+    /// input (i.e. while [`ImguiRenderLoop::message_filter`] contains
+    /// [`MessageFilter::InputMouse`]).
+    ///
+    /// The game window is registered for raw mouse input, unless the game
+    /// already registered for it itself.
+    pub fn with_raw_input_cursor(mut self, raw_input_cursor: bool) -> Self {
+        self.0.options.raw_input_cursor = raw_input_cursor;
+        self
+    }
+
+    /// Let mouse input through to the game while the cursor isn't over the
+    /// overlay, so that clicks on the transparent parts of the screen reach
+    /// it. Defaults to `false`.
+    ///
+    /// The overlay counts as under the cursor when imgui wanted the mouse at
+    /// the end of the last frame (`io.want_capture_mouse`), i.e. while one of
+    /// its windows is hovered or dragged. Mouse messages (including raw mouse
+    /// input) are then blocked according to
+    /// [`ImguiRenderLoop::message_filter`] as usual, and are never blocked
+    /// otherwise, regardless of the filter. Keyboard input is filtered
+    /// independently.
+    ///
+    /// Build purely informational windows with
+    /// [`imgui::WindowFlags::NO_INPUTS`] (or
+    /// [`imgui::WindowFlags::NO_MOUSE_INPUTS`]) so that hovering them doesn't
+    /// count either.
+    pub fn with_mouse_passthrough_on_transparent(mut self, mouse_passthrough: bool) -> Self {
+        self.0.options.mouse_passthrough = mouse_passthrough;
+        self
+    }
+
+    /// Choose which messages the mouse position is taken from. Defaults to
+    /// [`MouseSource::Both`].
+    ///
+    /// Games that register for raw mouse input also send its relative motion
+    /// to the window, which doesn't follow the cursor once accelerated or
+    /// clipped: when the two disagree, the overlay cursor jitters. Use
+    /// [`MouseSource::WndProc`] to follow the cursor only. The buttons and the
+    /// wheel are taken from both regardless, and the virtual cursor of
+    /// [`HudhookBuilder::with_raw_input_cursor`] isn't affected.
+    pub fn with_mouse_source(mut self, mouse_source: MouseSource) -> Self {
+        self.0.options.mouse_source = mouse_source;
+        self
+    }
+
+    /// Customize how Windows virtual keys are translated to imgui keys.
+    ///
+    /// The mapping applies to both regular and raw keyboard input. Return
+    /// `None` for keys that imgui should not see at all. By default, the
+    /// usual US layout correspondence is used.
+    pub fn with_key_map(mut self, key_map: fn(VIRTUAL_KEY) -> Option<imgui::Key>) -> Self {
+        self.0.options.key_map = Some(key_map);
+        self
+    }
+
+    /// Never swallow the given keys, even while the overlay is capturing
+    /// keyboard input through [`ImguiRenderLoop::message_filter`].
+    ///
+    /// Use this for keys that are critical to the game, such as push-to-talk.
+    /// Key messages for the listed keys always reach the game, and are not
+    /// forwarded to imgui. Generic modifiers (e.g. `VK_SHIFT`) match both
+    /// sides, while specific ones (e.g. `VK_LSHIFT`) only match their side.
+    /// Raw keyboard input is not affected.
+    pub fn with_keyboard_shortcut_swallow_list(mut self, keys: &[VIRTUAL_KEY]) -> Self {
+        self.0.options.passthrough_keys = keys.to_vec();
+        self
+    }
+
+    /// Record the DirectX 12 overlay into a ring of three command lists, each
+    /// with its own allocator and vertex and index buffers, instead of a
+    /// single one. Defaults to `false`.
+    ///
+    /// With a single command list, recording a frame waits for the GPU to be
+    /// done with the previous overlay frame, as the command list and buffers
+    /// are reused right away. With the ring, it only waits for the frame
+    /// that used the same slot three presents earlier, which removes the
+    /// stall when the CPU runs ahead of the GPU at high frame rates. The gain
+    /// depends on how far ahead the game lets the CPU run, so measure it
+    /// with [`present_hook_timings`]. The cost is three times the memory for
+    /// vertex and index buffers.
+    pub fn with_command_list_ring(mut self, command_list_ring: bool) -> Self {
+        self.0.options.command_list_ring = command_list_ring;
+        self
+    }
+
+    /// Set the initial capacity, in vertices, of the vertex buffer used by the
+    /// DirectX renderers. Defaults to 5000.
+    ///
+    /// The buffer is reused across frames and only ever grows: whenever the
+    /// draw data doesn't fit, a larger GPU buffer is allocated and kept from
+    /// then on. Overlays that draw a lot (e.g. tables with thousands of rows)
+    /// can preallocate a larger buffer to avoid reallocating during the first
+    /// frames.
+    pub fn with_vertex_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.0.options.vertex_buffer_capacity = capacity.max(1);
+        self
+    }
+
+    /// Set the initial capacity, in indices, of the index buffer used by the
+    /// DirectX renderers. Defaults to 10000.
+    ///
+    /// See [`HudhookBuilder::with_vertex_buffer_capacity`] for the growth
+    /// policy.
+    pub fn with_index_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.0.options.index_buffer_capacity = capacity.max(1);
+        self
+    }
+
+    /// Limit the width and height of the textures, including the font atlas,
+    /// to `max_texture_size`, below what the device supports. Defaults to the
+    /// device limit.
+    ///
+    /// Larger textures are rejected with [`TextureError::TooLarge`], e.g. to
+    /// keep the overlay within a memory budget.
+    pub fn with_max_texture_size(mut self, max_texture_size: u32) -> Self {
+        self.0.options.max_texture_size = Some(max_texture_size);
+        self
+    }
+
+    /// Leave the first `n` intercepted present calls alone before initializing
+    /// the renderer. Defaults to 0.
+    ///
+    /// Some games present a few transient swap chains or back buffers during
+    /// startup (splash screens, launchers, video players). Initializing the
+    /// overlay against them can crash or end up rendering into a throwaway
+    /// surface. Skipped presents only call the original function.
+    ///
+    /// There is no universally right value: a couple of seconds' worth of
+    /// frames (e.g. 120 to 300) is usually enough to get past the startup
+    /// sequence. If the overlay never shows up, the value is too high for the
+    /// time you waited; if the game still crashes at startup, it's too low.
+    pub fn with_startup_present_skip(mut self, n: usize) -> Self {
+        self.0.options.startup_present_skip = n;
+        self
+    }
+
+    /// Show the imgui demo window on top of your render loop. Defaults to
+    /// `false`.
+    ///
+    /// Useful to triage whether a problem lies in [`hudhook`](crate) or in
+    /// your own code. It can be toggled at runtime via [`set_demo_window`].
+    pub fn with_demo_window(mut self, visible: bool) -> Self {
+        self.0.options.demo_window = visible;
+        self
+    }
+
+    /// Persist the imgui window state (positions, sizes, collapsed state) to
+    /// the given `.ini` file. Defaults to no file.
+    ///
+    /// The file is loaded when the renderer is initialized. See
+    /// [`HudhookBuilder::with_ini_autosave`] for when it is saved.
+    pub fn with_ini_filename<P: Into<PathBuf>>(mut self, ini_filename: P) -> Self {
+        self.0.options.ini_filename = Some(ini_filename.into());
+        self
+    }
+
+    /// Load the imgui style from the given TOML file, before
+    /// [`ImguiRenderLoop::initialize`]. Defaults to no file, i.e. the default
+    /// dark style.
+    ///
+    /// See [`load_style_from_file`] for the format, and
+    /// [`save_style_to_file`] to write one from an existing style. A file
+    /// that can't be loaded is logged and leaves the default style in place.
+    #[cfg(feature = "serde")]
+    pub fn with_style_file<P: Into<PathBuf>>(mut self, style_file: P) -> Self {
+        self.0.options.style_file = Some(style_file.into());
+        self
+    }
+
+    /// Let imgui save the `.ini` file on its own. Defaults to `true`.
+    ///
+    /// imgui saves the file a few seconds after a window moves, and again
+    /// when its context is dropped, which happens while unhooking. When the
+    /// DLL is being ejected, the latter can write to a path that is gone or
+    /// block on the file system at the worst time. When disabled, the file
+    /// is only ever read at startup, and saving it is up to you via
+    /// [`save_ini_settings`].
+    pub fn with_ini_autosave(mut self, ini_autosave: bool) -> Self {
+        self.0.options.ini_autosave = ini_autosave;
+        self
+    }
+
+    /// Override the sync interval the game passes to `IDXGISwapChain::Present`
+    /// (DirectX 11 and 12), e.g. `Some(0)` to disable VSync or `Some(1)` to
+    /// force it. Defaults to `None`, leaving it alone.
+    ///
+    /// The present flags are never changed. Overrides that would make
+    /// `Present` fail are ignored, with a warning: sync intervals above 4,
+    /// and non-zero sync intervals while the game allows tearing
+    /// (`DXGI_PRESENT_ALLOW_TEARING`, used for variable refresh rate).
+    pub fn with_sync_interval(mut self, sync_interval: Option<u32>) -> Self {
+        self.0.options.sync_interval = sync_interval;
+        self
+    }
+
+    /// Build the overlay UI at most once per `min_frame_interval`, e.g.
+    /// `Duration::from_millis(33)` to update it at about 30 FPS while the game
+    /// runs much faster. Defaults to [`Duration::ZERO`], building it at every
+    /// present.
+    ///
+    /// The game is not throttled: at the presents in between, the draw data of
+    /// the last frame is drawn again into the new back buffer, which saves the
+    /// CPU time spent in the render loop and in imgui. The render loop
+    /// callbacks, including [`ImguiRenderLoop::before_render`], are only
+    /// invoked when a frame is built, except
+    /// [`ImguiRenderLoop::after_imgui_render`], so animations and anything
+    /// drawn by the UI update at the throttled rate, and input is processed
+    /// with up to one interval of latency. Texture contents updated in place
+    /// still show up at the next present.
+    pub fn with_min_frame_interval(mut self, min_frame_interval: Duration) -> Self {
+        self.0.options.min_frame_interval = min_frame_interval;
+        self
+    }
+
+    /// Enable input for the overlay. Defaults to `true`.
+    ///
+    /// When disabled, the window procedure of the game is never replaced nor
+    /// subclassed, e.g. for environments where anti-cheat software monitors
+    /// it. The overlay is display-only: the render loop still runs, but imgui
+    /// receives no mouse or keyboard input, and
+    /// [`ImguiRenderLoop::on_wnd_proc`] and
+    /// [`ImguiRenderLoop::message_filter`] have no effect.
+    pub fn with_input(mut self, input: bool) -> Self {
+        self.0.options.input = input;
+        self
+    }
+
+    /// Set how the overlay intercepts the messages of the game window.
+    /// Defaults to [`WndProcMode::Replace`].
+    ///
+    /// Try [`WndProcMode::Subclass`] if input stops working when the overlay
+    /// runs alongside other overlays or input hooks.
+    pub fn with_wndproc_mode(mut self, wndproc_mode: WndProcMode) -> Self {
+        self.0.options.wndproc_mode = wndproc_mode;
+        self
+    }
+
+    /// Enable the Direct3D 12 debug layer, which validates resource states,
+    /// descriptors and API usage. Defaults to `false`, and should stay off in
+    /// release builds, as it slows rendering down considerably.
+    ///
+    /// The debug layer is enabled right away, and only applies to devices
+    /// created afterwards: call this before
+    /// [`with::<ImguiDx12Hooks>`](Self::with), and inject the DLL before the
+    /// game creates its device, or the layer only covers the device
+    /// [`hudhook`](crate) creates to find the functions to hook. Its messages
+    /// are logged every frame by the renderer, and errors break into the
+    /// debugger, if one is attached.
+    ///
+    /// Requires the Graphics Tools optional feature of Windows.
+    #[cfg(feature = "dx12")]
+    pub fn with_d3d12_debug_layer(mut self, d3d12_debug_layer: bool) -> Self {
+        if d3d12_debug_layer {
+            util::enable_debug_interface();
+        }
+        self.0.options.d3d12_debug_layer = d3d12_debug_layer;
+        self
+    }
+
+    /// Honor the frame latency of DirectX 12 swap chains created with
+    /// `DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT`. Defaults to
+    /// `false`.
+    ///
+    /// Games using such swap chains wait on the frame latency waitable object
+    /// before sampling input for a frame, so that the CPU never runs further
+    /// ahead of the display than the maximum frame latency. Without this, the
+    /// renderer reuses its command list after waiting for the GPU to be done
+    /// with the previous overlay frame, which blocks inside `Present`, after
+    /// the game sampled input, whenever the game allows more than one frame
+    /// of latency. With this, the renderer keeps as many command lists as the
+    /// maximum frame latency of the swap chain, so the wait is already
+    /// covered by the one the game does on the waitable object, and `Present`
+    /// doesn't block on the overlay. The cost is the memory for one set of
+    /// vertex and index buffers per frame of latency.
+    ///
+    /// Swap chains without the flag are unaffected. Measure the impact for
+    /// your game with [`present_hook_timings`]: it is the time the overlay
+    /// adds before the original `Present`, which is where the stall shows.
+    #[cfg(feature = "dx12")]
+    pub fn with_swapchain_waitable_object(mut self, swapchain_waitable_object: bool) -> Self {
+        self.0.options.swapchain_waitable_object = swapchain_waitable_object;
+        self
+    }
+
+    /// Copy every frame of DirectX 12 games, with the overlay on top, to a
+    /// texture other processes can open, e.g. to encode it. Defaults to
+    /// `false`.
+    ///
+    /// See [`shared_capture_handle`] for how to access it. The copy is
+    /// recorded on the command list of the overlay, and costs a full-screen
+    /// copy on the GPU every frame.
+    #[cfg(feature = "dx12")]
+    pub fn with_shared_capture(mut self, shared_capture: bool) -> Self {
+        self.0.options.shared_capture = shared_capture;
+        self
+    }
+
+    /// Let [`ImguiDx11Hooks`](crate::hooks::dx11::ImguiDx11Hooks) draw on the
+    /// swap chains of Direct3D 12 games, via a D3D11On12 device. Defaults to
+    /// `false`.
+    ///
+    /// Some Direct3D 12 games compose their UI with Direct3D 11 through
+    /// D3D11On12, and present with a Direct3D 12 swap chain. With this, when
+    /// the swap chain belongs to Direct3D 12, the overlay creates its own
+    /// D3D11On12 device over the command queue of the swap chain, and draws
+    /// on the current back buffer wrapped as a Direct3D 11 texture, which is
+    /// acquired for the draw and released before the game presents.
+    ///
+    /// Swap chains created with a Direct3D 11 device are unaffected. Prefer
+    /// [`ImguiDx12Hooks`](crate::hooks::dx12::ImguiDx12Hooks) when they work
+    /// for the game, as they don't go through the translation layer.
+    #[cfg(feature = "dx11")]
+    pub fn with_d3d11on12(mut self, d3d11on12: bool) -> Self {
+        self.0.options.d3d11on12 = d3d11on12;
+        self
+    }
+
+    /// Record the Direct3D 11 overlay on a deferred context, and submit it to
+    /// the immediate context as a command list. Defaults to `false`.
+    ///
+    /// By default, the overlay draws on the immediate context, saving the
+    /// state it touches (render targets, viewports, blend, rasterizer,
+    /// shaders, buffers) beforehand and restoring it afterwards. Engines that
+    /// also drive the immediate context from other threads can still see
+    /// their state corrupted in between. With this, the overlay's state
+    /// changes, buffer uploads and texture updates stay on its own deferred
+    /// context, and the immediate context only executes the finished command
+    /// list, restoring its own state afterwards.
+    ///
+    /// The runtime emulates command lists when the driver doesn't support
+    /// them. If the deferred context can't be created, e.g. because the
+    /// device is single-threaded, the overlay falls back to the immediate
+    /// context.
+    #[cfg(feature = "dx11")]
+    pub fn with_dx11_deferred_context(mut self, dx11_deferred_context: bool) -> Self {
+        self.0.options.dx11_deferred_context = dx11_deferred_context;
+        self
+    }
+
+    /// Make the threads probing for the functions to hook and rendering the
+    /// overlay per-monitor (v2) DPI aware while they do. Defaults to `false`.
+    ///
+    /// Window sizes and coordinates, e.g. from `GetClientRect` or
+    /// `ScreenToClient`, are scaled according to the DPI awareness of the
+    /// calling thread. If the game is per-monitor DPI aware but the thread it
+    /// presents from isn't, the overlay can be sized wrong, or the cursor
+    /// offset, on scaled displays. The previous DPI awareness of the thread is
+    /// restored right after, but this remains opt-in, as the game may not
+    /// expect its threads to change DPI awareness even temporarily.
+    pub fn with_per_monitor_dpi_awareness(mut self, per_monitor_dpi_awareness: bool) -> Self {
+        self.0.options.per_monitor_dpi_awareness = per_monitor_dpi_awareness;
+        self
+    }
+
+    /// Set how the DirectX 12 renderer waits for the GPU. Defaults to
+    /// [`FenceStrategy::Block`]. See [`FenceStrategy`] for the tradeoffs.
+    #[cfg(feature = "dx12")]
+    pub fn with_fence_strategy(mut self, fence_strategy: FenceStrategy) -> Self {
+        self.0.options.fence_strategy = fence_strategy;
+        self
+    }
+
+    /// Set the color space the DirectX 11 and 12 renderers write the overlay
+    /// in, to match the color management of the game. Defaults to
+    /// [`ColorSpace::Srgb`]. See [`ColorSpace`] for when to change it.
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.0.options.color_space = color_space;
+        self
+    }
+
+    /// Draw the overlay with premultiplied alpha. Defaults to `false`.
+    ///
+    /// Enable this when the game composites its back buffer expecting
+    /// premultiplied alpha, e.g. a transparent window or a layer blended by
+    /// the compositor, where the overlay otherwise shows dark or bright
+    /// fringes around its edges. The vertex colors are then premultiplied by
+    /// the renderers, and blended with `ONE` / `INV_SRC_ALPHA`. The font
+    /// atlas and the images loaded from files are premultiplied on upload,
+    /// but the pixels passed to [`RenderContext::load_texture`] must be
+    /// premultiplied by the caller.
+    pub fn with_premultiplied_alpha(mut self, premultiplied_alpha: bool) -> Self {
+        self.0.options.premultiplied_alpha = premultiplied_alpha;
+        self
+    }
+
+    /// Set the corner of the screen the toasts queued via
+    /// [`RenderContext::notify`] are stacked from. Defaults to
+    /// [`ScreenCorner::BottomRight`].
+    pub fn with_toast_corner(mut self, corner: ScreenCorner) -> Self {
+        self.0.options.toast_corner = corner;
+        self
+    }
+
+    /// Show the frame rate readout in the given corner of the screen. It can
+    /// be toggled at runtime via [`set_fps_overlay`].
+    pub fn with_fps_overlay(mut self, corner: ScreenCorner) -> Self {
+        self.0.options.fps_overlay = true;
+        self.0.options.fps_overlay_corner = corner;
+        self
+    }
+
+    /// Set how often the frame rate readout is refreshed, from the presents
+    /// counted in between. Defaults to 500 milliseconds.
+    pub fn with_fps_overlay_interval(mut self, interval: Duration) -> Self {
+        self.0.options.fps_overlay_interval = interval;
+        self
+    }
+
+    /// Set the appearance of the toasts queued via [`RenderContext::notify`].
+    /// Defaults to [`ToastStyle::default`].
+    pub fn with_toast_style(mut self, style: ToastStyle) -> Self {
+        self.0.options.toast_style = style;
+        self
+    }
+
+    /// Keep the imgui windows in a region of the display, e.g. for an overlay
+    /// meant to occupy a sidebar. Defaults to `None`, i.e. the whole display.
+    ///
+    /// The work area of the main viewport is restricted to the region at every
+    /// frame. imgui keeps the windows visible within it, as it does with the
+    /// display, and places popups and tooltips inside it; windows laid out
+    /// from the work position and size of the main viewport follow it. The
+    /// region is intersected with the display, so that it shrinks along with
+    /// the window, and is ignored while none of it is visible.
+    pub fn with_work_area(mut self, work_area: Option<WorkArea>) -> Self {
+        self.0.options.work_area = work_area;
+        self
+    }
+
+    /// Mirror the overlay to a borderless window of its own, e.g. to capture
+    /// it on a streaming monitor separately from the game. Defaults to `None`.
+    ///
+    /// Experimental, and only supported by the DirectX 11 and DirectX 12
+    /// hooks. The draw data of every frame is rendered a second time, to a
+    /// swap chain created on the device of the game for the window, which is
+    /// opened on the first frame and closed when the hooks are removed. It
+    /// can be dragged around with the mouse. With DirectX 12, enable
+    /// [`HudhookBuilder::with_command_list_ring`] so that the second render
+    /// doesn't wait for the GPU to finish the first.
+    pub fn with_mirror_window(mut self, mirror_window: Option<MirrorConfig>) -> Self {
+        self.0.options.mirror_window = mirror_window;
+        self
+    }
+
+    /// Set the channel order in which the OpenGL renderer uploads texture
+    /// data. Defaults to [`OpenGlTextureFormat::Rgba`].
+    ///
+    /// Textures are always stored as `GL_RGBA8`. Only pick
+    /// [`OpenGlTextureFormat::Bgra`] if the fonts and images of the overlay
+    /// come out with the red and blue channels swapped on a given driver, or
+    /// if you load BGRA data into textures yourself.
+    pub fn with_opengl_texture_format(mut self, format: OpenGlTextureFormat) -> Self {
+        self.0.options.opengl_texture_format = format;
+        self
+    }
+
+    /// Enable `GL_FRAMEBUFFER_SRGB` while the OpenGL renderer draws the
+    /// overlay. Defaults to `false`.
+    ///
+    /// The state of the game is restored afterwards either way. Leave it
+    /// disabled unless the game renders to an sRGB framebuffer without
+    /// enabling `GL_FRAMEBUFFER_SRGB` itself, and the overlay comes out too
+    /// dark.
+    pub fn with_srgb_framebuffer(mut self, enabled: bool) -> Self {
+        self.0.options.srgb_framebuffer = enabled;
+        self
+    }
+
+    /// Recover from the unbalanced calls of the render loop instead of letting
+    /// imgui assert on them, which crashes the game. Defaults to `false`.
+    ///
+    /// A window that is begun but not ended, an ID that is pushed but not
+    /// popped, and the like, are closed at the end of every frame. Each error
+    /// is logged along with the imgui message, and passed to the handler set
+    /// via [`HudhookBuilder::with_imgui_error_handler`]. Enable it in release
+    /// builds to keep the game running through bugs in the overlay UI, and
+    /// leave it disabled while developing to catch them. Other imgui
+    /// assertions still abort.
+    pub fn with_imgui_error_recovery(mut self, imgui_error_recovery: bool) -> Self {
+        self.0.options.imgui_error_recovery = imgui_error_recovery;
+        self
+    }
+
+    /// Invoke `handler` with the imgui message of every error recovered from,
+    /// see [`HudhookBuilder::with_imgui_error_recovery`]. The message is the
+    /// format string of imgui, e.g. `Recovered from missing End() for '%s'`.
+    ///
+    /// The handler is called from within imgui: it must not panic.
+    pub fn with_imgui_error_handler(mut self, handler: fn(&str)) -> Self {
+        self.0.options.imgui_error_handler = Some(handler);
+        self
+    }
+
+    /// Invoke `callback` once the first frame is rendered, e.g. to create
+    /// resources that need the device to be fully set up, which it may not be
+    /// yet in [`ImguiRenderLoop::initialize`].
+    ///
+    /// It is called once per renderer: if the renderer is recreated, e.g.
+    /// after the device is lost, it is called again after its first frame.
+    pub fn with_on_first_frame(mut self, callback: fn(&mut dyn RenderContext)) -> Self {
+        self.0.options.on_first_frame = Some(callback);
+        self
+    }
+
+    /// Associate `user_data` with the hooks, for the render loop to retrieve
+    /// via [`RenderContext::user_data`]. Defaults to none.
+    pub fn with_user_data<T: Any + Send + Sync>(mut self, user_data: T) -> Self {
+        self.0.options.user_data = Some(Arc::new(user_data));
+        self
+    }
+
+    /// Buffer the input messages received by the game window, up to
+    /// `capacity` of them, for [`drain_input_events`]. The oldest messages are
+    /// dropped once it's full. Defaults to 0, i.e. disabled.
+    ///
+    /// Each keyboard, mouse and raw input message is timestamped with
+    /// `QueryPerformanceCounter` as the window procedure receives it.
+    pub fn with_input_queue(mut self, capacity: usize) -> Self {
+        self.0.options.input_queue_capacity = capacity;
+        self
+    }
+
+    /// Listen on the named pipe `\\.\pipe\hudhook-<pid>`, where `<pid>` is
+    /// the id of the game process, and hand the messages written to it to
+    /// [`ImguiRenderLoop::on_ipc_message`]. Defaults to `false`.
+    ///
+    /// The pipe is in message mode: each write of the client is delivered as
+    /// one message. One client is connected at a time, and the next one is
+    /// accepted when it disconnects. Only processes of the same user, and
+    /// administrators, can connect. The pipe is closed when the hooks are
+    /// removed.
+    #[cfg(feature = "ipc")]
+    pub fn with_ipc_pipe(mut self, ipc_pipe: bool) -> Self {
+        self.0.options.ipc_pipe = ipc_pipe;
+        self
+    }
+
+    /// Check that the render loop always runs on the same thread. Defaults to
+    /// `false`.
+    ///
+    /// The thread of the first [`ImguiRenderLoop::initialize`] is recorded
+    /// (see [`ui_thread_id`]), and every later call to
+    /// [`ImguiRenderLoop::initialize`], [`ImguiRenderLoop::before_render`] or
+    /// [`ImguiRenderLoop::render`] from another thread is refused: the error is
+    /// logged and the frame is skipped, the game presenting as if the hooks
+    /// weren't there. Some games present from several threads, and imgui
+    /// state shared across threads without synchronization leads to crashes
+    /// that are hard to track down.
+    pub fn with_ui_thread_pinning(mut self, ui_thread_pinning: bool) -> Self {
+        self.0.options.ui_thread_pinning = ui_thread_pinning;
+        self
+    }
+
+    /// Set how many of the most recent presents are aggregated by
+    /// [`present_hook_timings`]. Defaults to 600 and is capped at 4096.
+    pub fn with_present_timing_window(mut self, presents: usize) -> Self {
+        self.0.options.present_timing_window = presents;
+        self
+    }
+
     /// Build the [`Hudhook`] object.
     pub fn build(self) -> Hudhook {
         self.0
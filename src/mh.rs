@@ -83,6 +83,7 @@ pub struct MhHook {
     addr: *mut c_void,
     hook_impl: *mut c_void,
     trampoline: *mut c_void,
+    name: &'static str,
 }
 
 impl MhHook {
@@ -93,9 +94,32 @@ impl MhHook {
         let mut trampoline = null_mut();
         MH_CreateHook(addr, hook_impl, &mut trampoline).ok_context("MH_CreateHook")?;
 
-        Ok(Self { addr, hook_impl, trampoline })
+        Ok(Self { addr, hook_impl, trampoline, name: "" })
     }
 
+    /// Name the hooked function, for diagnostics (see
+    /// [`installed_hooks`](crate::installed_hooks)).
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Name of the hooked function, or an empty string if it wasn't named.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Address of the hooked function.
+    pub fn target(&self) -> *mut c_void {
+        self.addr
+    }
+
+    /// Address of the function called instead of the hooked function.
+    pub fn detour(&self) -> *mut c_void {
+        self.hook_impl
+    }
+
+    /// Address of the trampoline, which calls the original function.
     pub fn trampoline(&self) -> *mut c_void {
         self.trampoline
     }
@@ -0,0 +1,124 @@
+//! Display monitor geometry.
+//!
+//! Safe wrappers around `EnumDisplayMonitors` and `GetMonitorInfoW` for
+//! overlays that position elements relative to the monitor edges.
+
+use tracing::warn;
+use windows::Win32::Foundation::{BOOL, LPARAM, RECT};
+use windows::Win32::Graphics::Gdi::{
+    EnumDisplayMonitors, GetMonitorInfoW, MonitorFromWindow, HDC, HMONITOR, MONITORINFO,
+    MONITOR_DEFAULTTONEAREST,
+};
+use windows::Win32::UI::HiDpi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI};
+use windows::Win32::UI::WindowsAndMessaging::{MONITORINFOF_PRIMARY, USER_DEFAULT_SCREEN_DPI};
+
+use crate::game_hwnd;
+
+/// Geometry of a display monitor, in virtual screen coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorInfo {
+    /// Handle of the monitor.
+    pub handle: HMONITOR,
+    /// Full bounds of the monitor.
+    pub bounds: RECT,
+    /// Work area of the monitor, i.e. its bounds minus the taskbar and
+    /// docked toolbars.
+    pub work_area: RECT,
+    /// Effective DPI of the monitor. 96 is 100% scaling.
+    pub dpi: u32,
+    /// Whether this is the primary monitor.
+    pub primary: bool,
+    /// Whether the game window is (mostly) on this monitor. Always `false`
+    /// until the game window is known, see [`game_hwnd`](crate::game_hwnd).
+    pub game_window: bool,
+}
+
+/// Enumerate the display monitors.
+pub fn monitors() -> Vec<MonitorInfo> {
+    unsafe extern "system" fn enum_callback(
+        hmonitor: HMONITOR,
+        _: HDC,
+        _: *mut RECT,
+        lparam: LPARAM,
+    ) -> BOOL {
+        let handles = &mut *(lparam.0 as *mut Vec<HMONITOR>);
+        handles.push(hmonitor);
+        BOOL::from(true)
+    }
+
+    let mut handles: Vec<HMONITOR> = Vec::new();
+    let enumerated = unsafe {
+        EnumDisplayMonitors(
+            HDC::default(),
+            None,
+            Some(enum_callback),
+            LPARAM(&mut handles as *mut Vec<HMONITOR> as isize),
+        )
+    };
+    if !enumerated.as_bool() {
+        warn!("Could not enumerate display monitors");
+    }
+
+    let game_monitor = game_monitor();
+    handles.into_iter().filter_map(|handle| monitor_info(handle, game_monitor)).collect()
+}
+
+/// Retrieve the monitor the game window is on, or `None` until the game window
+/// is known.
+pub fn current_monitor() -> Option<MonitorInfo> {
+    let game_monitor = game_monitor()?;
+    monitor_info(game_monitor, Some(game_monitor))
+}
+
+fn game_monitor() -> Option<HMONITOR> {
+    game_hwnd().map(|hwnd| unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST) })
+}
+
+fn monitor_info(handle: HMONITOR, game_monitor: Option<HMONITOR>) -> Option<MonitorInfo> {
+    let mut info =
+        MONITORINFO { cbSize: std::mem::size_of::<MONITORINFO>() as u32, ..Default::default() };
+    if !unsafe { GetMonitorInfoW(handle, &mut info) }.as_bool() {
+        warn!("Could not get info for monitor {handle:?}");
+        return None;
+    }
+
+    let (mut dpi_x, mut dpi_y) = (0, 0);
+    let dpi = match unsafe { GetDpiForMonitor(handle, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y) } {
+        Ok(()) => dpi_x,
+        Err(e) => {
+            warn!("Could not get DPI for monitor {handle:?}: {e:?}");
+            USER_DEFAULT_SCREEN_DPI
+        },
+    };
+
+    Some(MonitorInfo {
+        handle,
+        bounds: info.rcMonitor,
+        work_area: info.rcWork,
+        dpi,
+        primary: info.dwFlags & MONITORINFOF_PRIMARY != 0,
+        game_window: game_monitor == Some(handle),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monitors() {
+        // The monitor setup depends on the machine: only check consistency.
+        let monitors = monitors();
+        assert!(monitors.iter().filter(|monitor| monitor.primary).count() <= 1);
+
+        for monitor in monitors {
+            let MonitorInfo { bounds, work_area, dpi, game_window, .. } = monitor;
+            assert!(work_area.left >= bounds.left && work_area.right <= bounds.right);
+            assert!(work_area.top >= bounds.top && work_area.bottom <= bounds.bottom);
+            assert!(dpi > 0);
+            assert!(!game_window);
+        }
+
+        assert_eq!(current_monitor(), None);
+    }
+}
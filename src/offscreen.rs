@@ -0,0 +1,367 @@
+//! Offscreen rendering for DirectX 12.
+//!
+//! Renders an [`ImguiRenderLoop`] to an offscreen render target and reads the
+//! pixels back, without hooking any game. This is useful for snapshot testing
+//! overlays in CI, or for tools that render UI to textures.
+//!
+//! ```no_run
+//! # use hudhook::*;
+//! # use hudhook::windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_R8G8B8A8_UNORM;
+//! # struct MyRenderLoop;
+//! # impl ImguiRenderLoop for MyRenderLoop {
+//! #     fn render(&mut self, ui: &mut imgui::Ui) {}
+//! # }
+//! let rgba =
+//!     hudhook::offscreen::render_once(&mut MyRenderLoop, 800, 600, DXGI_FORMAT_R8G8B8A8_UNORM)
+//!         .unwrap();
+//! assert_eq!(rgba.len(), 800 * 600 * 4);
+//! ```
+//!
+//! imgui only supports one active context at a time, so this can't be used
+//! while a hooked render loop is running in the same process: rendering
+//! returns an error instead.
+
+use std::mem::ManuallyDrop;
+use std::{mem, ptr, slice};
+
+use imgui::{sys, Context};
+use tracing::{error, warn};
+use windows::core::{Error, Interface, Result};
+use windows::Win32::Foundation::{E_INVALIDARG, E_NOT_VALID_STATE};
+use windows::Win32::Graphics::Direct3D::D3D_FEATURE_LEVEL_11_0;
+use windows::Win32::Graphics::Direct3D12::*;
+use windows::Win32::Graphics::Dxgi::Common::*;
+use windows::Win32::Graphics::Dxgi::{CreateDXGIFactory2, IDXGIAdapter, IDXGIFactory4};
+
+use crate::renderer::{D3D12RenderEngine, RenderEngine};
+use crate::{options, util, ImguiRenderLoop};
+
+/// Render a single frame of `render_loop` to an offscreen render target of
+/// the given dimensions and format, and return its pixels.
+///
+/// The supported formats are `DXGI_FORMAT_R8G8B8A8_UNORM` and
+/// `DXGI_FORMAT_B8G8R8A8_UNORM`. Regardless of the format, the returned
+/// buffer is tightly packed RGBA8, row by row, `width * height * 4` bytes
+/// long. The background is transparent black.
+///
+/// A new device, imgui context and renderer are created for every call, so
+/// [`ImguiRenderLoop::initialize`] is invoked each time. Without a hardware
+/// adapter supporting Direct3D 12, e.g. on CI runners, the WARP software
+/// adapter is used. Fails with
+/// `E_NOT_VALID_STATE` if another imgui context is active, e.g. the one of
+/// hooks applied in the same process.
+pub fn render_once<R: ImguiRenderLoop + ?Sized>(
+    render_loop: &mut R,
+    width: u32,
+    height: u32,
+    format: DXGI_FORMAT,
+) -> Result<Vec<u8>> {
+    if width == 0 || height == 0 {
+        error!("Invalid offscreen render target size: {width}x{height}");
+        return Err(Error::from_hresult(E_INVALIDARG));
+    }
+
+    if format != DXGI_FORMAT_R8G8B8A8_UNORM && format != DXGI_FORMAT_B8G8R8A8_UNORM {
+        error!("Unsupported offscreen render target format: {format:?}");
+        return Err(Error::from_hresult(E_INVALIDARG));
+    }
+
+    // Creating a context while another one is active panics.
+    if !unsafe { sys::igGetCurrentContext() }.is_null() {
+        error!("Can't render offscreen while another imgui context is active");
+        return Err(Error::from_hresult(E_NOT_VALID_STATE));
+    }
+
+    let device = create_device()?;
+
+    let command_queue: ID3D12CommandQueue = unsafe {
+        device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
+            Type: D3D12_COMMAND_LIST_TYPE_DIRECT,
+            Priority: 0,
+            Flags: D3D12_COMMAND_QUEUE_FLAG_NONE,
+            NodeMask: 0,
+        })
+    }?;
+
+    let render_target = unsafe { create_render_target(&device, width, height, format) }?;
+
+    let mut ctx = Context::create();
+    {
+        let options = options::get();
+        let io = ctx.io_mut();
+        io.display_size = [width as f32, height as f32];
+        io.config_flags |= options.config_flags;
+        io.backend_flags |= options.backend_flags;
+    }
+
+    let mut engine = D3D12RenderEngine::new(&command_queue, &mut ctx)?;
+
+    render_loop.initialize(&mut ctx, &mut engine);
+    engine.setup_fonts(&mut ctx)?;
+    render_loop.before_render(&mut ctx, &mut engine);
+
+    let ui = ctx.frame();
+    render_loop.render_ui(ui);
+    let draw_data = ctx.render();
+
+    engine.render(draw_data, render_target.clone())?;
+    engine.flush()?;
+
+    let mut pixels = unsafe { read_back(&device, &command_queue, &render_target) }?;
+
+    if format == DXGI_FORMAT_B8G8R8A8_UNORM {
+        pixels.chunks_exact_mut(4).for_each(|px| px.swap(0, 2));
+    }
+
+    Ok(pixels)
+}
+
+// Create a device on the default adapter, or on the WARP adapter if it
+// doesn't support Direct3D 12.
+fn create_device() -> Result<ID3D12Device> {
+    util::try_out_ptr(|v| unsafe { D3D12CreateDevice(None, D3D_FEATURE_LEVEL_11_0, v) }).or_else(
+        |e| {
+            warn!("Could not create a hardware device, falling back to WARP: {e:?}");
+            let factory: IDXGIFactory4 = unsafe { CreateDXGIFactory2(0) }?;
+            let adapter: IDXGIAdapter = unsafe { factory.EnumWarpAdapter() }?;
+            util::try_out_ptr(|v| unsafe { D3D12CreateDevice(&adapter, D3D_FEATURE_LEVEL_11_0, v) })
+        },
+    )
+}
+
+unsafe fn create_render_target(
+    device: &ID3D12Device,
+    width: u32,
+    height: u32,
+    format: DXGI_FORMAT,
+) -> Result<ID3D12Resource> {
+    util::try_out_ptr(|v| {
+        device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_DEFAULT,
+                CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+                MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+                CreationNodeMask: Default::default(),
+                VisibleNodeMask: Default::default(),
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                Alignment: 0,
+                Width: width as _,
+                Height: height,
+                DepthOrArraySize: 1,
+                MipLevels: 1,
+                Format: format,
+                SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+                Flags: D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+            },
+            // The render engine expects render targets in the present state,
+            // which is the same as the common state.
+            D3D12_RESOURCE_STATE_COMMON,
+            None,
+            v,
+        )
+    })
+}
+
+unsafe fn read_back(
+    device: &ID3D12Device,
+    command_queue: &ID3D12CommandQueue,
+    render_target: &ID3D12Resource,
+) -> Result<Vec<u8>> {
+    let desc = render_target.GetDesc();
+    let width = desc.Width as usize;
+    let height = desc.Height as usize;
+
+    let mut footprint = D3D12_PLACED_SUBRESOURCE_FOOTPRINT::default();
+    let mut total_bytes = 0u64;
+    device.GetCopyableFootprints(
+        &desc,
+        0,
+        1,
+        0,
+        Some(&mut footprint),
+        None,
+        None,
+        Some(&mut total_bytes),
+    );
+
+    let readback_buffer: ID3D12Resource = util::try_out_ptr(|v| {
+        device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_READBACK,
+                CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+                MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+                CreationNodeMask: Default::default(),
+                VisibleNodeMask: Default::default(),
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+                Alignment: 0,
+                Width: total_bytes,
+                Height: 1,
+                DepthOrArraySize: 1,
+                MipLevels: 1,
+                Format: DXGI_FORMAT_UNKNOWN,
+                SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+                Flags: D3D12_RESOURCE_FLAG_NONE,
+            },
+            D3D12_RESOURCE_STATE_COPY_DEST,
+            None,
+            v,
+        )
+    })?;
+
+    let command_allocator: ID3D12CommandAllocator =
+        device.CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT)?;
+    let command_list: ID3D12GraphicsCommandList =
+        device.CreateCommandList(0, D3D12_COMMAND_LIST_TYPE_DIRECT, &command_allocator, None)?;
+
+    let copy = CopyCommands {
+        src_location: D3D12_TEXTURE_COPY_LOCATION {
+            pResource: ManuallyDrop::new(Some(render_target.clone())),
+            Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: 0 },
+        },
+        dst_location: D3D12_TEXTURE_COPY_LOCATION {
+            pResource: ManuallyDrop::new(Some(readback_buffer.clone())),
+            Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { PlacedFootprint: footprint },
+        },
+        to_copy_barriers: [util::create_barrier(
+            render_target,
+            D3D12_RESOURCE_STATE_COMMON,
+            D3D12_RESOURCE_STATE_COPY_SOURCE,
+        )],
+        from_copy_barriers: [util::create_barrier(
+            render_target,
+            D3D12_RESOURCE_STATE_COPY_SOURCE,
+            D3D12_RESOURCE_STATE_COMMON,
+        )],
+    };
+
+    command_list.ResourceBarrier(&copy.to_copy_barriers);
+    command_list.CopyTextureRegion(&copy.dst_location, 0, 0, 0, &copy.src_location, None);
+    command_list.ResourceBarrier(&copy.from_copy_barriers);
+    command_list.Close()?;
+
+    let fence = util::Fence::new(device)?;
+    command_queue.ExecuteCommandLists(&[Some(command_list.cast()?)]);
+    fence.incr();
+    command_queue.Signal(fence.fence(), fence.value())?;
+    fence.wait()?;
+    drop(copy);
+
+    let row_size = width * 4;
+    let row_pitch = footprint.Footprint.RowPitch as usize;
+    let mut pixels = vec![0u8; row_size * height];
+
+    let mut readback_ptr = ptr::null_mut();
+    readback_buffer.Map(
+        0,
+        Some(&D3D12_RANGE { Begin: 0, End: total_bytes as usize }),
+        Some(&mut readback_ptr),
+    )?;
+    let readback_data = slice::from_raw_parts(
+        (readback_ptr as *const u8).add(footprint.Offset as usize),
+        (total_bytes - footprint.Offset) as usize,
+    );
+    pixels
+        .chunks_exact_mut(row_size)
+        .zip(readback_data.chunks(row_pitch))
+        .for_each(|(dst, src)| dst.copy_from_slice(&src[..row_size]));
+    readback_buffer.Unmap(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }));
+
+    Ok(pixels)
+}
+
+// Copy locations and barriers of the readback. They hold references to the
+// render target and the readback buffer, released however `read_back`
+// returns.
+struct CopyCommands {
+    src_location: D3D12_TEXTURE_COPY_LOCATION,
+    dst_location: D3D12_TEXTURE_COPY_LOCATION,
+    to_copy_barriers: [D3D12_RESOURCE_BARRIER; 1],
+    from_copy_barriers: [D3D12_RESOURCE_BARRIER; 1],
+}
+
+impl Drop for CopyCommands {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.src_location.pResource);
+            ManuallyDrop::drop(&mut self.dst_location.pResource);
+        }
+        for barrier in self.to_copy_barriers.iter_mut().chain(&mut self.from_copy_barriers) {
+            util::drop_barrier(mem::replace(barrier, D3D12_RESOURCE_BARRIER::default()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct EmptyRenderLoop;
+
+    impl ImguiRenderLoop for EmptyRenderLoop {
+        fn render(&mut self, _ui: &mut imgui::Ui) {}
+    }
+
+    // Fills a red rectangle from (16, 16) to (48, 48).
+    struct RectRenderLoop;
+
+    impl ImguiRenderLoop for RectRenderLoop {
+        fn render(&mut self, ui: &mut imgui::Ui) {
+            ui.get_foreground_draw_list()
+                .add_rect([16., 16.], [48., 48.], [1., 0., 0., 1.])
+                .filled(true)
+                .build();
+        }
+    }
+
+    #[test]
+    fn test_render_once() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+
+        let (width, height) = (64, 48);
+        let pixel = |pixels: &[u8], x: usize, y: usize| -> [u8; 4] {
+            let offset = (y * width as usize + x) * 4;
+            pixels[offset..offset + 4].try_into().unwrap()
+        };
+
+        // Both formats come back as RGBA.
+        for format in [DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_B8G8R8A8_UNORM] {
+            let pixels = render_once(&mut RectRenderLoop, width, height, format)
+                .unwrap_or_else(|e| panic!("Couldn't render {format:?}: {e:?}"));
+
+            assert_eq!(pixels.len(), (width * height * 4) as usize);
+            assert_eq!(pixel(&pixels, 32, 32), [255, 0, 0, 255], "inside the rect in {format:?}");
+            assert_eq!(pixel(&pixels, 4, 4), [0, 0, 0, 0], "outside the rect in {format:?}");
+            assert_eq!(pixel(&pixels, 56, 40), [0, 0, 0, 0], "outside the rect in {format:?}");
+        }
+    }
+
+    #[test]
+    fn test_render_once_invalid_args() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+
+        let result = render_once(&mut EmptyRenderLoop, 0, 64, DXGI_FORMAT_R8G8B8A8_UNORM);
+        assert_eq!(result.map_err(|e| e.code()), Err(E_INVALIDARG));
+
+        let result = render_once(&mut EmptyRenderLoop, 64, 64, DXGI_FORMAT_R16G16B16A16_FLOAT);
+        assert_eq!(result.map_err(|e| e.code()), Err(E_INVALIDARG));
+    }
+
+    #[test]
+    fn test_render_once_with_active_context() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+        let _ctx = crate::renderer::test_context();
+
+        let result = render_once(&mut EmptyRenderLoop, 64, 64, DXGI_FORMAT_R8G8B8A8_UNORM);
+        assert_eq!(result.map_err(|e| e.code()), Err(E_NOT_VALID_STATE));
+    }
+}
@@ -0,0 +1,151 @@
+//! Configuration collected by [`HudhookBuilder`](crate::HudhookBuilder) and
+//! consumed by the rendering pipelines.
+//!
+//! The options are published when [`Hudhook::apply`](crate::Hudhook::apply) is
+//! invoked, before the hooks are enabled, so that every pipeline (including
+//! the ones rebuilt after a device reset or a resize) observes the same values.
+
+use std::any::Any;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use imgui::{BackendFlags, ConfigFlags, Key};
+use once_cell::sync::Lazy;
+use parking_lot::{RwLock, RwLockReadGuard};
+use windows::Win32::UI::Input::KeyboardAndMouse::VIRTUAL_KEY;
+
+use crate::{
+    diagnostics, timings, ColorSpace, FenceStrategy, MirrorConfig, MouseSource,
+    OpenGlTextureFormat, RenderContext, ScreenCorner, ToastStyle, WndProcMode, WorkArea,
+};
+
+static OPTIONS: Lazy<RwLock<Options>> = Lazy::new(|| RwLock::new(Options::default()));
+
+#[derive(Clone)]
+pub(crate) struct Options {
+    pub(crate) config_flags: ConfigFlags,
+    pub(crate) dockspace: bool,
+    pub(crate) dock_layout: Option<&'static str>,
+    pub(crate) backend_flags: BackendFlags,
+    pub(crate) platform_name: Option<String>,
+    pub(crate) renderer_name: Option<String>,
+    pub(crate) raw_input_cursor: bool,
+    pub(crate) mouse_passthrough: bool,
+    pub(crate) vertex_buffer_capacity: usize,
+    pub(crate) index_buffer_capacity: usize,
+    pub(crate) max_texture_size: Option<u32>,
+    pub(crate) startup_present_skip: usize,
+    pub(crate) demo_window: bool,
+    pub(crate) key_map: Option<fn(VIRTUAL_KEY) -> Option<Key>>,
+    pub(crate) passthrough_keys: Vec<VIRTUAL_KEY>,
+    pub(crate) command_list_ring: bool,
+    pub(crate) ini_filename: Option<PathBuf>,
+    pub(crate) style_file: Option<PathBuf>,
+    pub(crate) ini_autosave: bool,
+    pub(crate) sync_interval: Option<u32>,
+    pub(crate) opengl_texture_format: OpenGlTextureFormat,
+    pub(crate) srgb_framebuffer: bool,
+    pub(crate) ui_thread_pinning: bool,
+    pub(crate) color_space: ColorSpace,
+    pub(crate) premultiplied_alpha: bool,
+    pub(crate) input: bool,
+    pub(crate) d3d12_debug_layer: bool,
+    pub(crate) fence_strategy: FenceStrategy,
+    pub(crate) swapchain_waitable_object: bool,
+    pub(crate) shared_capture: bool,
+    pub(crate) d3d11on12: bool,
+    pub(crate) dx11_deferred_context: bool,
+    pub(crate) install_timeout: Duration,
+    pub(crate) per_monitor_dpi_awareness: bool,
+    pub(crate) min_frame_interval: Duration,
+    pub(crate) toast_corner: ScreenCorner,
+    pub(crate) toast_style: ToastStyle,
+    pub(crate) present_timing_window: usize,
+    pub(crate) fps_overlay: bool,
+    pub(crate) fps_overlay_corner: ScreenCorner,
+    pub(crate) fps_overlay_interval: Duration,
+    pub(crate) work_area: Option<WorkArea>,
+    pub(crate) mirror_window: Option<MirrorConfig>,
+    pub(crate) imgui_error_recovery: bool,
+    pub(crate) imgui_error_handler: Option<fn(&str)>,
+    pub(crate) on_first_frame: Option<fn(&mut dyn RenderContext)>,
+    pub(crate) user_data: Option<Arc<dyn Any + Send + Sync>>,
+    pub(crate) input_queue_capacity: usize,
+    pub(crate) ipc_pipe: bool,
+    pub(crate) mouse_source: MouseSource,
+    pub(crate) wndproc_mode: WndProcMode,
+    pub(crate) known_conflicts: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            config_flags: ConfigFlags::empty(),
+            dockspace: false,
+            dock_layout: None,
+            backend_flags: BackendFlags::empty(),
+            platform_name: None,
+            renderer_name: None,
+            raw_input_cursor: false,
+            mouse_passthrough: false,
+            vertex_buffer_capacity: 5000,
+            index_buffer_capacity: 10000,
+            max_texture_size: None,
+            startup_present_skip: 0,
+            demo_window: false,
+            key_map: None,
+            passthrough_keys: Vec::new(),
+            command_list_ring: false,
+            ini_filename: None,
+            style_file: None,
+            ini_autosave: true,
+            sync_interval: None,
+            opengl_texture_format: OpenGlTextureFormat::Rgba,
+            srgb_framebuffer: false,
+            ui_thread_pinning: false,
+            color_space: ColorSpace::Srgb,
+            premultiplied_alpha: false,
+            input: true,
+            d3d12_debug_layer: false,
+            fence_strategy: FenceStrategy::Block,
+            swapchain_waitable_object: false,
+            shared_capture: false,
+            d3d11on12: false,
+            dx11_deferred_context: false,
+            install_timeout: Duration::ZERO,
+            per_monitor_dpi_awareness: false,
+            min_frame_interval: Duration::ZERO,
+            toast_corner: ScreenCorner::BottomRight,
+            toast_style: ToastStyle::default(),
+            present_timing_window: timings::DEFAULT_WINDOW,
+            fps_overlay: false,
+            fps_overlay_corner: ScreenCorner::TopRight,
+            fps_overlay_interval: Duration::from_millis(500),
+            work_area: None,
+            mirror_window: None,
+            imgui_error_recovery: false,
+            imgui_error_handler: None,
+            on_first_frame: None,
+            user_data: None,
+            input_queue_capacity: 0,
+            ipc_pipe: false,
+            mouse_source: MouseSource::Both,
+            wndproc_mode: WndProcMode::Replace,
+            known_conflicts: diagnostics::DEFAULT_KNOWN_CONFLICTS
+                .iter()
+                .map(|&module| module.to_owned())
+                .collect(),
+        }
+    }
+}
+
+/// Retrieve the currently published options.
+pub(crate) fn get() -> RwLockReadGuard<'static, Options> {
+    OPTIONS.read()
+}
+
+/// Publish a new set of options.
+pub(crate) fn set(options: Options) {
+    *OPTIONS.write() = options;
+}
@@ -4,39 +4,82 @@ use std::{mem, ptr, slice};
 
 use imgui::internal::RawWrapper;
 use imgui::{BackendFlags, Context, DrawCmd, DrawData, DrawIdx, DrawVert, TextureId};
-use tracing::error;
-use windows::core::{s, Error, Result, HRESULT};
+use tracing::{error, trace};
+use windows::core::{s, Error, Interface, Result, HRESULT};
 use windows::Win32::Foundation::RECT;
 use windows::Win32::Graphics::Direct3D::Fxc::D3DCompile;
 use windows::Win32::Graphics::Direct3D::*;
 use windows::Win32::Graphics::Direct3D11::*;
+use windows::Win32::Graphics::Direct3D11on12::{
+    D3D11On12CreateDevice, ID3D11On12Device, D3D11_RESOURCE_FLAGS,
+};
+use windows::Win32::Graphics::Direct3D12::{
+    ID3D12CommandQueue, ID3D12Device, ID3D12Resource, D3D12_RESOURCE_STATE_PRESENT,
+};
 use windows::Win32::Graphics::Dxgi::Common::*;
+use windows::Win32::Graphics::Dxgi::IDXGISwapChain3;
 
-use crate::renderer::RenderEngine;
-use crate::{util, RenderContext};
+use crate::renderer::{check_texture_size, font_atlas_pixels, shader_defines, RenderEngine};
+use crate::{options, util, ColorSpace, RenderBackend, RenderContext, TextureError};
 
 pub struct D3D11RenderEngine {
     device: ID3D11Device,
+    // The context the overlay records into: the immediate context, or a
+    // deferred one when `deferred` is set.
     device_context: ID3D11DeviceContext,
+    deferred: bool,
 
     shader_program: ShaderProgram,
     texture_heap: TextureHeap,
+    #[cfg(feature = "image-loader")]
+    texture_paths: std::collections::HashMap<std::path::PathBuf, TextureId>,
 
     vertex_buffer: Buffer<DrawVert>,
     index_buffer: Buffer<DrawIdx>,
     projection_buffer: Buffer<[[f32; 4]; 4]>,
+    // Color to clear the next render target with, if any.
+    clear_color: Option<[f32; 4]>,
+    // Device layer the overlay is drawn through on Direct3D 12 swap chains.
+    d3d11on12: Option<D3D11On12>,
+    // Raw pointer of the swap chain the next frame is drawn into, for
+    // `RenderContext::swap_chain_raw`.
+    swap_chain: usize,
 }
 
 impl D3D11RenderEngine {
     pub fn new(device: &ID3D11Device, ctx: &mut Context) -> Result<Self> {
         let device = device.clone();
-        let device_context = unsafe { device.GetImmediateContext() }?;
 
-        let vertex_buffer = Buffer::new(&device, 5000, D3D11_BIND_VERTEX_BUFFER)?;
-        let index_buffer = Buffer::new(&device, 10000, D3D11_BIND_INDEX_BUFFER)?;
+        let (
+            vertex_buffer_capacity,
+            index_buffer_capacity,
+            color_space,
+            premultiplied_alpha,
+            deferred,
+        ) = {
+            let options = options::get();
+            (
+                options.vertex_buffer_capacity,
+                options.index_buffer_capacity,
+                options.color_space,
+                options.premultiplied_alpha,
+                options.dx11_deferred_context,
+            )
+        };
+
+        let (device_context, deferred) = match deferred.then(|| create_deferred_context(&device)) {
+            Some(Ok(device_context)) => (device_context, true),
+            Some(Err(e)) => {
+                error!("Could not create a deferred context, using the immediate one: {e:?}");
+                (unsafe { device.GetImmediateContext() }?, false)
+            },
+            None => (unsafe { device.GetImmediateContext() }?, false),
+        };
+        let vertex_buffer = Buffer::new(&device, vertex_buffer_capacity, D3D11_BIND_VERTEX_BUFFER)?;
+        let index_buffer = Buffer::new(&device, index_buffer_capacity, D3D11_BIND_INDEX_BUFFER)?;
         let projection_buffer = Buffer::new(&device, 1, D3D11_BIND_CONSTANT_BUFFER)?;
 
-        let shader_program = ShaderProgram::new(&device)?;
+        let shader_program = ShaderProgram::new(&device, color_space, premultiplied_alpha)?;
         let texture_heap = TextureHeap::new(&device, &device_context)?;
 
         ctx.set_ini_filename(None);
@@ -46,17 +89,159 @@ impl D3D11RenderEngine {
         Ok(Self {
             device,
             device_context,
+            deferred,
             shader_program,
             texture_heap,
+            #[cfg(feature = "image-loader")]
+            texture_paths: Default::default(),
             vertex_buffer,
             index_buffer,
             projection_buffer,
+            clear_color: None,
+            d3d11on12: None,
+            swap_chain: 0,
         })
     }
+
+    /// Create a renderer drawing on the back buffers of a Direct3D 12 swap
+    /// chain, through `d3d11on12`.
+    pub(crate) fn with_d3d11on12(d3d11on12: D3D11On12, ctx: &mut Context) -> Result<Self> {
+        let device = d3d11on12.device.cast()?;
+        let mut engine = Self::new(&device, ctx)?;
+        engine.d3d11on12 = Some(d3d11on12);
+        Ok(engine)
+    }
+
+    /// Set the swap chain the next frame is drawn into, as a raw pointer.
+    pub fn set_swap_chain(&mut self, swap_chain: *mut c_void) {
+        self.swap_chain = swap_chain as usize;
+    }
+
+    /// The D3D11On12 layer, if the renderer draws on a Direct3D 12 swap chain.
+    pub(crate) fn d3d11on12(&mut self) -> Option<&mut D3D11On12> {
+        self.d3d11on12.as_mut()
+    }
+
+    /// Device the overlay is rendered with.
+    pub fn device(&self) -> &ID3D11Device {
+        &self.device
+    }
+
+    /// Clear the render target of the next render to `color` before drawing,
+    /// for targets the game doesn't draw to.
+    pub fn clear_next_target(&mut self, color: [f32; 4]) {
+        self.clear_color = Some(color);
+    }
+}
+
+/// Direct3D 11 device submitting to the command queue of a Direct3D 12 swap
+/// chain, so that the overlay can be drawn on its back buffers.
+pub(crate) struct D3D11On12 {
+    device: ID3D11On12Device,
+    device_context: ID3D11DeviceContext,
+    // Back buffers wrapped as Direct3D 11 textures, by buffer index, along with
+    // the buffers they wrap.
+    back_buffers: Vec<Option<(ID3D12Resource, ID3D11Texture2D)>>,
+}
+
+impl D3D11On12 {
+    pub(crate) fn new(command_queue: &ID3D12CommandQueue) -> Result<Self> {
+        let device12: ID3D12Device = util::try_out_ptr(|v| unsafe { command_queue.GetDevice(v) })?;
+
+        let mut device = None;
+        let mut device_context = None;
+        unsafe {
+            D3D11On12CreateDevice(
+                &device12,
+                D3D11_CREATE_DEVICE_BGRA_SUPPORT.0 as _,
+                None,
+                Some(&[Some(command_queue.cast()?)]),
+                0,
+                Some(&mut device),
+                Some(&mut device_context),
+                None,
+            )
+        }?;
+
+        let (Some(device), Some(device_context)) = (device, device_context) else {
+            error!("D3D11On12CreateDevice returned no device");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
+
+        Ok(Self { device: device.cast()?, device_context, back_buffers: Vec::new() })
+    }
+
+    /// Wrap the current back buffer of `swap_chain` and acquire it for
+    /// rendering. The wrappers are kept across frames.
+    pub(crate) fn acquire_back_buffer(
+        &mut self,
+        swap_chain: &IDXGISwapChain3,
+    ) -> Result<ID3D11Texture2D> {
+        let index = unsafe { swap_chain.GetCurrentBackBufferIndex() } as usize;
+        let back_buffer: ID3D12Resource = unsafe { swap_chain.GetBuffer(index as u32) }?;
+
+        if self.back_buffers.len() <= index {
+            self.back_buffers.resize(index + 1, None);
+        }
+        let target = match &self.back_buffers[index] {
+            Some((wrapped, target)) if *wrapped == back_buffer => target.clone(),
+            _ => {
+                trace!("Wrapping back buffer {index}");
+                let flags = D3D11_RESOURCE_FLAGS {
+                    BindFlags: D3D11_BIND_RENDER_TARGET.0 as _,
+                    ..Default::default()
+                };
+                let target: ID3D11Texture2D = unsafe {
+                    self.device.CreateWrappedResource(
+                        &back_buffer,
+                        &flags,
+                        D3D12_RESOURCE_STATE_PRESENT,
+                        D3D12_RESOURCE_STATE_PRESENT,
+                    )
+                }?;
+                self.back_buffers[index] = Some((back_buffer, target.clone()));
+                target
+            },
+        };
+
+        unsafe { self.device.AcquireWrappedResources(&[Some(target.cast()?)]) };
+        Ok(target)
+    }
+
+    /// Hand a back buffer acquired via
+    /// [`acquire_back_buffer`](Self::acquire_back_buffer) back to the swap
+    /// chain, and submit the overlay to the command queue before the game
+    /// presents.
+    pub(crate) fn release_back_buffer(&mut self, target: &ID3D11Texture2D) -> Result<()> {
+        unsafe {
+            self.device.ReleaseWrappedResources(&[Some(target.cast()?)]);
+            self.device_context.Flush();
+        }
+        Ok(())
+    }
+
+    /// Drop the wrapped back buffers, as any reference to them makes
+    /// `ResizeBuffers` fail.
+    pub(crate) fn release_back_buffers(&mut self) {
+        if self.back_buffers.is_empty() {
+            return;
+        }
+
+        trace!("Releasing {} wrapped back buffers", self.back_buffers.len());
+        self.back_buffers.clear();
+        // The wrappers are destroyed once the commands referencing them are
+        // submitted.
+        unsafe { self.device_context.Flush() };
+    }
 }
 
 impl RenderContext for D3D11RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+    fn load_texture(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> std::result::Result<TextureId, TextureError> {
         unsafe { self.texture_heap.create_texture(data, width, height) }
     }
 
@@ -66,29 +251,74 @@ impl RenderContext for D3D11RenderEngine {
         data: &[u8],
         width: u32,
         height: u32,
-    ) -> Result<()> {
-        unsafe { self.texture_heap.update_texture(texture_id, data, width, height) }
+    ) -> std::result::Result<(), TextureError> {
+        Ok(unsafe { self.texture_heap.update_texture(texture_id, data, width, height) }?)
+    }
+
+    #[cfg(feature = "image-loader")]
+    fn load_texture_from_path(
+        &mut self,
+        path: &std::path::Path,
+    ) -> std::result::Result<TextureId, TextureError> {
+        self.load_texture_from_path_cached(path)
+    }
+
+    unsafe fn device_raw(&self) -> Option<(RenderBackend, *mut c_void)> {
+        Some((RenderBackend::Dx11, self.device.as_raw()))
+    }
+
+    unsafe fn swap_chain_raw(&self) -> Option<*mut c_void> {
+        (self.swap_chain != 0).then_some(self.swap_chain as *mut c_void)
     }
 }
 
 impl RenderEngine for D3D11RenderEngine {
     type RenderTarget = ID3D11Texture2D;
 
+    #[cfg(feature = "image-loader")]
+    fn texture_paths(
+        &mut self,
+    ) -> Option<&mut std::collections::HashMap<std::path::PathBuf, TextureId>> {
+        Some(&mut self.texture_paths)
+    }
+
     fn render(
         &mut self,
         draw_data: &imgui::DrawData,
         render_target: Self::RenderTarget,
     ) -> Result<()> {
         unsafe {
-            let state_backup = StateBackup::backup(&self.device_context);
-
             let render_target: ID3D11RenderTargetView = util::try_out_ptr(|v| {
                 self.device.CreateRenderTargetView(&render_target, None, Some(v))
             })?;
 
-            self.device_context.OMSetRenderTargets(Some(&[Some(render_target)]), None);
-            self.render_draw_data(draw_data)?;
-            state_backup.restore(&self.device_context);
+            if self.deferred {
+                if let Some(color) = self.clear_color.take() {
+                    self.device_context.ClearRenderTargetView(&render_target, &color);
+                }
+                self.device_context.OMSetRenderTargets(Some(&[Some(render_target)]), None);
+                let result = self.render_draw_data(draw_data);
+
+                // Always close the command list, so that a failed frame doesn't
+                // leak its commands into the next one.
+                let command_list =
+                    util::try_out_ptr(|v| self.device_context.FinishCommandList(false, Some(v)))?;
+                result?;
+
+                // Executing with `RestoreContextState` leaves the state of the
+                // immediate context as the game left it.
+                self.device.GetImmediateContext()?.ExecuteCommandList(&command_list, true);
+            } else {
+                let state_backup = StateBackup::backup(&self.device_context);
+
+                if let Some(color) = self.clear_color.take() {
+                    self.device_context.ClearRenderTargetView(&render_target, &color);
+                }
+                self.device_context.OMSetRenderTargets(Some(&[Some(render_target)]), None);
+                let result = self.render_draw_data(draw_data);
+                state_backup.restore(&self.device_context);
+                result?;
+            }
         };
 
         Ok(())
@@ -97,8 +327,10 @@ impl RenderEngine for D3D11RenderEngine {
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
         let fonts_texture = fonts.build_rgba32_texture();
-        fonts.tex_id =
-            self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
+        fonts.tex_id = {
+            let data = font_atlas_pixels(fonts_texture.data);
+            self.load_texture(&data, fonts_texture.width, fonts_texture.height)?
+        };
         Ok(())
     }
 }
@@ -237,6 +469,24 @@ impl D3D11RenderEngine {
     }
 }
 
+// Create a deferred context to record the overlay into. The runtime emulates
+// command lists when the driver doesn't support them.
+fn create_deferred_context(device: &ID3D11Device) -> Result<ID3D11DeviceContext> {
+    let mut threading = D3D11_FEATURE_DATA_THREADING::default();
+    unsafe {
+        device.CheckFeatureSupport(
+            D3D11_FEATURE_THREADING,
+            &mut threading as *mut _ as *mut c_void,
+            mem::size_of::<D3D11_FEATURE_DATA_THREADING>() as u32,
+        )
+    }?;
+    if !threading.DriverCommandLists.as_bool() {
+        trace!("The driver doesn't support command lists, they will be emulated");
+    }
+
+    util::try_out_ptr(|v| unsafe { device.CreateDeferredContext(0, Some(v)) })
+}
+
 struct ShaderProgram {
     vertex_shader: ID3D11VertexShader,
     pixel_shader: ID3D11PixelShader,
@@ -248,7 +498,11 @@ struct ShaderProgram {
 }
 
 impl ShaderProgram {
-    fn new(device: &ID3D11Device) -> Result<Self> {
+    fn new(
+        device: &ID3D11Device,
+        color_space: ColorSpace,
+        premultiplied_alpha: bool,
+    ) -> Result<Self> {
         const VERTEX_SHADER_SRC: &str = r"
         cbuffer vertex_buffer: register(b0) {
             float4x4 projection;
@@ -285,13 +539,25 @@ impl ShaderProgram {
         Texture2D texture0: register(t0);
         SamplerState sampler0: register(s0);
 
+        float3 srgb_to_linear(float3 col) {
+          return col <= 0.04045f ? col / 12.92f : pow((col + 0.055f) / 1.055f, 2.4f);
+        }
+
         float4 main(PS_INPUT input): SV_Target {
-          float4 col = input.col * texture0.Sample(sampler0, input.uv);
-          return col;
+          float4 col = input.col;
+          float4 tex = texture0.Sample(sampler0, input.uv);
+        #ifdef LINEAR_OUTPUT
+          col.rgb = srgb_to_linear(col.rgb);
+          tex.rgb = srgb_to_linear(tex.rgb);
+        #endif
+        #ifdef PREMULTIPLIED_ALPHA
+          col.rgb *= col.a;
+        #endif
+          return col * tex;
         }
         ";
 
-        let vs_blob: ID3DBlob = util::try_out_err_blob(|v, err_blob| unsafe {
+        let vs_blob: ID3DBlob = util::try_out_opt_err_blob(|v, err_blob| unsafe {
             D3DCompile(
                 VERTEX_SHADER_SRC.as_ptr() as _,
                 VERTEX_SHADER_SRC.len(),
@@ -306,15 +572,15 @@ impl ShaderProgram {
                 Some(err_blob),
             )
         })
-        .map_err(util::print_error_blob("Compiling vertex shader"))
-        .expect("D3DCompile");
+        .map_err(util::print_opt_error_blob("Compiling vertex shader"))?;
 
-        let ps_blob = util::try_out_err_blob(|v, err_blob| unsafe {
+        let defines = shader_defines(color_space, premultiplied_alpha);
+        let ps_blob = util::try_out_opt_err_blob(|v, err_blob| unsafe {
             D3DCompile(
                 PIXEL_SHADER_SRC.as_ptr() as _,
                 PIXEL_SHADER_SRC.len(),
                 None,
-                None,
+                defines.as_ref().map(|defines| defines.as_ptr()),
                 None,
                 s!("main\0"),
                 s!("ps_4_0\0"),
@@ -324,8 +590,7 @@ impl ShaderProgram {
                 Some(err_blob),
             )
         })
-        .map_err(util::print_error_blob("Compiling pixel shader"))
-        .expect("D3DCompile");
+        .map_err(util::print_opt_error_blob("Compiling pixel shader"))?;
 
         let vertex_shader = util::try_out_ptr(|v| unsafe {
             let ptr = vs_blob.GetBufferPointer();
@@ -394,6 +659,11 @@ impl ShaderProgram {
                 Some(v),
             )
         })?;
+        let (src_blend, src_blend_alpha, dest_blend_alpha) = if premultiplied_alpha {
+            (D3D11_BLEND_ONE, D3D11_BLEND_ONE, D3D11_BLEND_INV_SRC_ALPHA)
+        } else {
+            (D3D11_BLEND_SRC_ALPHA, D3D11_BLEND_INV_SRC_ALPHA, D3D11_BLEND_ZERO)
+        };
         let blend_state = util::try_out_ptr(|v| unsafe {
             device.CreateBlendState(
                 &D3D11_BLEND_DESC {
@@ -402,11 +672,11 @@ impl ShaderProgram {
                     RenderTarget: [
                         D3D11_RENDER_TARGET_BLEND_DESC {
                             BlendEnable: true.into(),
-                            SrcBlend: D3D11_BLEND_SRC_ALPHA,
+                            SrcBlend: src_blend,
                             DestBlend: D3D11_BLEND_INV_SRC_ALPHA,
                             BlendOp: D3D11_BLEND_OP_ADD,
-                            SrcBlendAlpha: D3D11_BLEND_INV_SRC_ALPHA,
-                            DestBlendAlpha: D3D11_BLEND_ZERO,
+                            SrcBlendAlpha: src_blend_alpha,
+                            DestBlendAlpha: dest_blend_alpha,
                             BlendOpAlpha: D3D11_BLEND_OP_ADD,
                             RenderTargetWriteMask: D3D11_COLOR_WRITE_ENABLE_ALL.0 as _,
                         },
@@ -538,6 +808,7 @@ impl<T> Buffer<T> {
     ) -> Result<()> {
         let capacity = self.data.capacity();
         if capacity > self.resource_capacity {
+            trace!("Growing buffer from {} to {capacity} elements", self.resource_capacity);
             drop(mem::replace(
                 &mut self.resource,
                 Self::create_resource(device, capacity, self.bind_flag)?,
@@ -576,6 +847,16 @@ struct Texture {
     height: u32,
 }
 
+// Largest width and height of the textures of the feature level of `device`.
+fn max_texture_dimension(device: &ID3D11Device) -> u32 {
+    match unsafe { device.GetFeatureLevel() } {
+        level if level.0 >= D3D_FEATURE_LEVEL_11_0.0 => D3D11_REQ_TEXTURE2D_U_OR_V_DIMENSION,
+        level if level.0 >= D3D_FEATURE_LEVEL_10_0.0 => 8192,
+        level if level.0 >= D3D_FEATURE_LEVEL_9_3.0 => 4096,
+        _ => 2048,
+    }
+}
+
 struct TextureHeap {
     device: ID3D11Device,
     device_context: ID3D11DeviceContext,
@@ -591,7 +872,14 @@ impl TextureHeap {
         })
     }
 
-    unsafe fn create_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+    unsafe fn create_texture(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> std::result::Result<TextureId, TextureError> {
+        let max = max_texture_dimension(&self.device);
+        check_texture_size(width, height, [max, max])?;
         let resource: ID3D11Texture2D = util::try_out_ptr(|v| {
             self.device.CreateTexture2D(
                 &D3D11_TEXTURE2D_DESC {
@@ -665,8 +953,12 @@ impl TextureHeap {
 }
 
 const BACKUP_OBJECT_COUNT: usize = 16;
+const BACKUP_RENDER_TARGET_COUNT: usize = D3D11_SIMULTANEOUS_RENDER_TARGET_COUNT as usize;
 
 struct StateBackup {
+    render_targets: [Option<ID3D11RenderTargetView>; BACKUP_RENDER_TARGET_COUNT],
+    depth_stencil_view: Option<ID3D11DepthStencilView>,
+
     scissor_count: u32,
     scissor_rects: [RECT; BACKUP_OBJECT_COUNT],
     viewport_count: u32,
@@ -706,6 +998,11 @@ struct StateBackup {
 
 impl StateBackup {
     unsafe fn backup(device_context: &ID3D11DeviceContext) -> StateBackup {
+        let mut render_targets: [Option<ID3D11RenderTargetView>; BACKUP_RENDER_TARGET_COUNT] =
+            Default::default();
+        let mut depth_stencil_view = None;
+        device_context.OMGetRenderTargets(Some(&mut render_targets), Some(&mut depth_stencil_view));
+
         let mut scissor_count = 0;
         let mut scissor_rects: [RECT; BACKUP_OBJECT_COUNT] = Default::default();
         device_context.RSGetScissorRects(&mut scissor_count, None);
@@ -776,6 +1073,8 @@ impl StateBackup {
         let input_layout = device_context.IAGetInputLayout().ok();
 
         Self {
+            render_targets,
+            depth_stencil_view,
             scissor_count,
             scissor_rects,
             viewport_count,
@@ -807,6 +1106,8 @@ impl StateBackup {
     }
 
     unsafe fn restore(self, device_context: &ID3D11DeviceContext) {
+        device_context
+            .OMSetRenderTargets(Some(&self.render_targets), self.depth_stencil_view.as_ref());
         device_context.RSSetScissorRects(Some(&self.scissor_rects[..self.scissor_count as usize]));
         device_context.RSSetViewports(Some(&self.viewports[..self.viewport_count as usize]));
 
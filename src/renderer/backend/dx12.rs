@@ -1,60 +1,112 @@
 // NOTE: see this for ManuallyDrop instances https://github.com/microsoft/windows-rs/issues/2386
 
+use std::collections::VecDeque;
 use std::ffi::c_void;
 use std::mem::{offset_of, ManuallyDrop};
 use std::{mem, ptr, slice};
 
 use imgui::internal::RawWrapper;
 use imgui::{BackendFlags, Context, DrawCmd, DrawData, DrawIdx, DrawVert, TextureId};
-use tracing::error;
-use windows::core::{s, w, Error, Interface, Result, HRESULT};
+use tracing::{error, trace, warn};
+use windows::core::{s, w, Error, IUnknown, Interface, Result, HRESULT};
 use windows::Win32::Foundation::*;
 use windows::Win32::Graphics::Direct3D::Fxc::*;
 use windows::Win32::Graphics::Direct3D::*;
 use windows::Win32::Graphics::Direct3D12::*;
 use windows::Win32::Graphics::Dxgi::Common::*;
+use windows::Win32::System::Diagnostics::Debug::IsDebuggerPresent;
 
-use crate::renderer::RenderEngine;
+use crate::renderer::pixel_sample::{self, PixelSampler};
+use crate::renderer::shared_capture::SharedCapture;
+use crate::renderer::{check_texture_size, font_atlas_pixels, shader_defines, RenderEngine};
 use crate::util::{self, Fence};
-use crate::RenderContext;
+use crate::{options, ColorSpace, RenderBackend, RenderContext, TextureError};
 
 pub struct D3D12RenderEngine {
     device: ID3D12Device,
 
     command_queue: ID3D12CommandQueue,
-    command_allocator: ID3D12CommandAllocator,
-    command_list: ID3D12GraphicsCommandList,
+    frame_contexts: Vec<FrameContext>,
+    frame_index: usize,
 
     #[allow(unused)]
     rtv_heap: ID3D12DescriptorHeap,
     rtv_heap_start: D3D12_CPU_DESCRIPTOR_HANDLE,
     texture_heap: TextureHeap,
+    #[cfg(feature = "image-loader")]
+    texture_paths: std::collections::HashMap<std::path::PathBuf, TextureId>,
 
     root_signature: ID3D12RootSignature,
     pipeline_state: ID3D12PipelineState,
+    rtv_format: DXGI_FORMAT,
+    color_space: ColorSpace,
+    premultiplied_alpha: bool,
 
-    vertex_buffer: Buffer<DrawVert>,
-    index_buffer: Buffer<u16>,
     projection_buffer: [[f32; 4]; 4],
 
     fence: Fence,
+    deferred_frees: DeferredFrees,
+    info_queue: Option<ID3D12InfoQueue>,
+    // The command list being recorded, while the render loop draws on top of
+    // the overlay.
+    recording_command_list: Option<ID3D12GraphicsCommandList>,
+    // Color to clear the next render target with, if any.
+    clear_color: Option<[f32; 4]>,
+    // Whether to copy the next render target to the shared capture texture.
+    capture_next_target: bool,
+    shared_capture: Option<SharedCapture>,
+    // Client coordinates and back buffer coordinates of the pixel to sample
+    // from the next render target.
+    pixel_sample_request: Option<([i32; 2], [u32; 2])>,
+    pixel_sampler: Option<PixelSampler>,
+    // Raw pointer of the swap chain the next frame is drawn into, for
+    // `RenderContext::swap_chain_raw`.
+    swap_chain: usize,
 }
 
 impl D3D12RenderEngine {
     pub fn new(command_queue: &ID3D12CommandQueue, ctx: &mut Context) -> Result<Self> {
-        let (device, command_queue, command_allocator, command_list) =
-            unsafe { create_command_objects(command_queue) }?;
+        let device: ID3D12Device = util::try_out_ptr(|v| unsafe { command_queue.GetDevice(v) })?;
+        let command_queue = command_queue.clone();
 
         let (rtv_heap, texture_heap) = unsafe { create_heaps(&device) }?;
         let rtv_heap_start = unsafe { rtv_heap.GetCPUDescriptorHandleForHeapStart() };
 
-        let (root_signature, pipeline_state) = unsafe { create_shader_program(&device) }?;
+        let (
+            vertex_buffer_capacity,
+            index_buffer_capacity,
+            command_list_ring,
+            color_space,
+            premultiplied_alpha,
+            d3d12_debug_layer,
+        ) = {
+            let options = options::get();
+            (
+                options.vertex_buffer_capacity,
+                options.index_buffer_capacity,
+                options.command_list_ring,
+                options.color_space,
+                options.premultiplied_alpha,
+                options.d3d12_debug_layer,
+            )
+        };
 
-        let vertex_buffer = Buffer::new(&device, 5000)?;
-        let index_buffer = Buffer::new(&device, 10000)?;
+        let rtv_format = DXGI_FORMAT_B8G8R8A8_UNORM;
+        let (root_signature, pipeline_state) = unsafe {
+            create_shader_program(&device, rtv_format, color_space, premultiplied_alpha)
+        }?;
+        let frame_context_count = if command_list_ring { COMMAND_LIST_RING_SIZE } else { 1 };
+        let frame_contexts = (0..frame_context_count)
+            .map(|_| unsafe {
+                FrameContext::new(&device, vertex_buffer_capacity, index_buffer_capacity)
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         let fence = Fence::new(&device)?;
 
+        let info_queue =
+            if d3d12_debug_layer { unsafe { create_info_queue(&device) } } else { None };
+
         ctx.set_ini_filename(None);
         ctx.io_mut().backend_flags |= BackendFlags::RENDERER_HAS_VTX_OFFSET;
         ctx.set_renderer_name(String::from(concat!("hudhook-dx12@", env!("CARGO_PKG_VERSION"))));
@@ -62,26 +114,137 @@ impl D3D12RenderEngine {
         Ok(Self {
             device,
             command_queue,
-            command_allocator,
-            command_list,
+            frame_contexts,
+            frame_index: 0,
             rtv_heap,
             rtv_heap_start,
             texture_heap,
+            #[cfg(feature = "image-loader")]
+            texture_paths: Default::default(),
             root_signature,
             pipeline_state,
-            vertex_buffer,
-            index_buffer,
+            rtv_format,
+            color_space,
+            premultiplied_alpha,
             projection_buffer: Default::default(),
             fence,
+            // Per frame in flight: the back buffer, and the vertex and index
+            // buffers when they are grown.
+            deferred_frees: DeferredFrees::with_capacity(frame_context_count * 3),
+            info_queue,
+            recording_command_list: None,
+            clear_color: None,
+            capture_next_target: false,
+            shared_capture: None,
+            pixel_sample_request: None,
+            pixel_sampler: None,
+            swap_chain: 0,
         })
     }
+
+    /// Clear the render target of the next render to `color` before drawing,
+    /// for targets the game doesn't draw to.
+    pub fn clear_next_target(&mut self, color: [f32; 4]) {
+        self.clear_color = Some(color);
+    }
+
+    /// Copy the render target of the next render to the shared capture
+    /// texture once the overlay is drawn.
+    pub fn capture_next_target(&mut self) {
+        self.capture_next_target = true;
+    }
+
+    /// Sample the pixel at `texel` of the next render target, before the
+    /// overlay is drawn, for the client coordinates `point` of
+    /// [`sample_pixel`](crate::sample_pixel). If the previous sample isn't
+    /// read back yet, `point` is requested again for the next frame instead.
+    pub fn sample_next_target(&mut self, point: [i32; 2], texel: [u32; 2]) {
+        self.pixel_sample_request = Some((point, texel));
+    }
+
+    /// Set the swap chain the next frame is drawn into, as a raw pointer.
+    pub fn set_swap_chain(&mut self, swap_chain: *mut c_void) {
+        self.swap_chain = swap_chain as usize;
+    }
+
+    /// Command queue the overlay is submitted to.
+    pub fn command_queue(&self) -> &ID3D12CommandQueue {
+        &self.command_queue
+    }
+
+    /// Device the overlay is rendered with.
+    pub fn device(&self) -> &ID3D12Device {
+        &self.device
+    }
+
+    /// Make sure at least `count` frames can be in flight before recording a
+    /// frame waits for the GPU.
+    pub fn reserve_frame_contexts(&mut self, count: usize) -> Result<()> {
+        if count <= self.frame_contexts.len() {
+            return Ok(());
+        }
+
+        trace!("Growing frame contexts from {} to {count}", self.frame_contexts.len());
+        let (vertex_buffer_capacity, index_buffer_capacity) = {
+            let options = options::get();
+            (options.vertex_buffer_capacity, options.index_buffer_capacity)
+        };
+
+        // The new contexts have never been submitted, so they can be appended
+        // without disturbing the order in which the others are reused.
+        for _ in self.frame_contexts.len()..count {
+            self.frame_contexts.push(unsafe {
+                FrameContext::new(&self.device, vertex_buffer_capacity, index_buffer_capacity)
+            }?);
+        }
+
+        Ok(())
+    }
+
+    /// Wait for all the submitted work to complete and release every resource
+    /// that was kept alive for it.
+    ///
+    /// This must be called before the swap chain buffers are resized, as
+    /// `ResizeBuffers` fails if any reference to the back buffers is alive.
+    pub fn flush(&mut self) -> Result<()> {
+        self.fence.wait()?;
+        self.deferred_frees.collect(self.fence.completed_value());
+        Ok(())
+    }
+}
+
+impl Drop for D3D12RenderEngine {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Could not flush render engine: {e:?}");
+        }
+    }
 }
 
 impl RenderContext for D3D12RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+    fn load_texture(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> std::result::Result<TextureId, TextureError> {
         unsafe {
-            let texture_id = self.texture_heap.create_texture(width, height)?;
-            self.texture_heap.upload_texture(texture_id, data, width, height)?;
+            // The command lists in flight, and the one being recorded if any,
+            // may still reference the shader visible heap if it is replaced.
+            let texture_id = self.texture_heap.create_texture(
+                width,
+                height,
+                &mut self.deferred_frees,
+                self.fence.value() + 1,
+                self.fence.completed_value(),
+            )?;
+            self.texture_heap.upload_texture(
+                &self.command_queue,
+                texture_id,
+                data,
+                width,
+                height,
+            )?;
             Ok(texture_id)
         }
     }
@@ -92,45 +255,187 @@ impl RenderContext for D3D12RenderEngine {
         data: &[u8],
         width: u32,
         height: u32,
-    ) -> Result<()> {
-        unsafe { self.texture_heap.upload_texture(texture_id, data, width, height) }
+    ) -> std::result::Result<(), TextureError> {
+        Ok(unsafe {
+            self.texture_heap.upload_texture(&self.command_queue, texture_id, data, width, height)
+        }?)
+    }
+
+    #[cfg(feature = "image-loader")]
+    fn load_texture_from_path(
+        &mut self,
+        path: &std::path::Path,
+    ) -> std::result::Result<TextureId, TextureError> {
+        self.load_texture_from_path_cached(path)
+    }
+
+    unsafe fn device_raw(&self) -> Option<(RenderBackend, *mut c_void)> {
+        Some((RenderBackend::Dx12, self.device.as_raw()))
+    }
+
+    unsafe fn swap_chain_raw(&self) -> Option<*mut c_void> {
+        (self.swap_chain != 0).then_some(self.swap_chain as *mut c_void)
+    }
+
+    unsafe fn command_list_raw(&self) -> Option<*mut c_void> {
+        self.recording_command_list.as_ref().map(|command_list| command_list.as_raw())
+    }
+
+    fn dx12_command_list(&self) -> Option<&ID3D12GraphicsCommandList> {
+        self.recording_command_list.as_ref()
     }
 }
 
 impl RenderEngine for D3D12RenderEngine {
     type RenderTarget = ID3D12Resource;
 
+    #[cfg(feature = "image-loader")]
+    fn texture_paths(
+        &mut self,
+    ) -> Option<&mut std::collections::HashMap<std::path::PathBuf, TextureId>> {
+        Some(&mut self.texture_paths)
+    }
+
+    // Once the vertex and index buffers have grown to fit the largest frame,
+    // this doesn't allocate: the barriers live on the stack, and the frame
+    // contexts and the deferred frees queue are reused across frames.
     fn render(&mut self, draw_data: &DrawData, render_target: Self::RenderTarget) -> Result<()> {
+        self.render_with(draw_data, render_target, &mut |_| {})
+    }
+
+    fn render_with(
+        &mut self,
+        draw_data: &DrawData,
+        render_target: Self::RenderTarget,
+        after_render: &mut dyn FnMut(&mut dyn RenderContext),
+    ) -> Result<()> {
+        // Report what the debug layer caught since the last frame, including
+        // in a frame that failed.
+        if let Some(info_queue) = &self.info_queue {
+            util::print_d3d12_debug_messages(info_queue);
+        }
+
         unsafe {
+            // Wait for the last frame recorded with this frame context before
+            // reusing its command allocator and vertex and index buffers, then
+            // release the resources that are no longer in use. Without the
+            // command list ring, this is the previous frame.
+            let frame_index = self.frame_index;
+            self.fence.wait_for(self.frame_contexts[frame_index].fence_value)?;
+            self.deferred_frees.collect(self.fence.completed_value());
+
+            // The back buffer format can change across `ResizeBuffers` calls,
+            // and the pipeline state must match the render target format.
+            let rtv_format = render_target.GetDesc().Format;
+            if rtv_format != self.rtv_format {
+                trace!("Rebuilding pipeline state for format {rtv_format:?}");
+                let (root_signature, pipeline_state) = create_shader_program(
+                    &self.device,
+                    rtv_format,
+                    self.color_space,
+                    self.premultiplied_alpha,
+                )?;
+                self.root_signature = root_signature;
+                self.pipeline_state = pipeline_state;
+                self.rtv_format = rtv_format;
+            }
+
             self.device.CreateRenderTargetView(&render_target, None, self.rtv_heap_start);
 
-            self.command_allocator.Reset()?;
-            self.command_list.Reset(&self.command_allocator, None)?;
+            let FrameContext { command_allocator, command_list, .. } =
+                &self.frame_contexts[frame_index];
+            let command_list = command_list.clone();
+            command_allocator.Reset()?;
+            command_list.Reset(command_allocator, None)?;
+
+            // Read back the pixel sampled by a previous frame, if the GPU is
+            // done with it, before sampling the next one.
+            if let Some(pixel_sampler) = &mut self.pixel_sampler {
+                pixel_sampler.resolve(self.fence.completed_value())?;
+            }
+            let sample = match (&self.pixel_sampler, self.pixel_sample_request.take()) {
+                (Some(pixel_sampler), Some((point, _))) if pixel_sampler.is_pending() => {
+                    pixel_sample::retry(point);
+                    None
+                },
+                (_, sample) => sample,
+            };
+            if sample.is_some() && self.pixel_sampler.is_none() {
+                self.pixel_sampler = Some(PixelSampler::new(&self.device)?);
+            }
 
             let present_to_rtv_barriers = [util::create_barrier(
                 &render_target,
                 D3D12_RESOURCE_STATE_PRESENT,
-                D3D12_RESOURCE_STATE_RENDER_TARGET,
+                if sample.is_some() {
+                    D3D12_RESOURCE_STATE_COPY_SOURCE
+                } else {
+                    D3D12_RESOURCE_STATE_RENDER_TARGET
+                },
             )];
 
+            let capture = mem::take(&mut self.capture_next_target);
+            if capture && self.shared_capture.is_none() {
+                self.shared_capture = Some(SharedCapture::new(&self.device)?);
+            }
+
             let rtv_to_present_barriers = [util::create_barrier(
                 &render_target,
-                D3D12_RESOURCE_STATE_RENDER_TARGET,
+                if capture {
+                    D3D12_RESOURCE_STATE_COPY_SOURCE
+                } else {
+                    D3D12_RESOURCE_STATE_RENDER_TARGET
+                },
                 D3D12_RESOURCE_STATE_COMMON,
             )];
 
-            self.command_list.ResourceBarrier(&present_to_rtv_barriers);
-            self.command_list.OMSetRenderTargets(1, Some(&self.rtv_heap_start), false, None);
-            self.command_list.SetDescriptorHeaps(&[Some(self.texture_heap.srv_heap.clone())]);
+            command_list.ResourceBarrier(&present_to_rtv_barriers);
+            if let (Some(pixel_sampler), Some((point, texel))) = (&mut self.pixel_sampler, sample) {
+                pixel_sampler.record_copy(
+                    &command_list,
+                    &render_target,
+                    point,
+                    texel,
+                    self.fence.value() + 1,
+                );
+            }
+            if let Some(color) = self.clear_color.take() {
+                command_list.ClearRenderTargetView(self.rtv_heap_start, &color, None);
+            }
+            command_list.OMSetRenderTargets(1, Some(&self.rtv_heap_start), false, None);
+            command_list.SetDescriptorHeaps(&[Some(self.texture_heap.srv_heap.clone())]);
 
-            self.render_draw_data(draw_data)?;
+            self.render_draw_data(frame_index, draw_data)?;
 
-            self.command_list.ResourceBarrier(&rtv_to_present_barriers);
-            self.command_list.Close()?;
-            self.command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
-            self.command_queue.Signal(self.fence.fence(), self.fence.value())?;
-            self.fence.wait()?;
+            self.recording_command_list = Some(command_list.clone());
+            after_render(self);
+            self.recording_command_list = None;
+
+            // A texture replaced by the capture may still be copied to by the
+            // frames in flight.
+            let replaced_capture_texture = match (&mut self.shared_capture, capture) {
+                (Some(shared_capture), true) => {
+                    shared_capture.record_copy(&command_list, &render_target)?
+                },
+                _ => None,
+            };
+
+            command_list.ResourceBarrier(&rtv_to_present_barriers);
+            command_list.Close()?;
+            self.command_queue.ExecuteCommandLists(&[Some(command_list.cast()?)]);
             self.fence.incr();
+            self.command_queue.Signal(self.fence.fence(), self.fence.value())?;
+            if let (Some(shared_capture), true) = (&mut self.shared_capture, capture) {
+                shared_capture.signal(&self.command_queue)?;
+            }
+            if let Some(texture) = replaced_capture_texture {
+                self.deferred_frees.push(self.fence.value(), texture);
+            }
+            self.frame_contexts[frame_index].fence_value = self.fence.value();
+            self.frame_index = (frame_index + 1) % self.frame_contexts.len();
+
+            // The back buffer stays referenced until the GPU is done with it.
+            self.deferred_frees.push(self.fence.value(), render_target);
 
             present_to_rtv_barriers.into_iter().for_each(util::drop_barrier);
             rtv_to_present_barriers.into_iter().for_each(util::drop_barrier);
@@ -142,20 +447,43 @@ impl RenderEngine for D3D12RenderEngine {
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
         let fonts_texture = fonts.build_rgba32_texture();
-        fonts.tex_id =
-            self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
+        fonts.tex_id = {
+            let data = font_atlas_pixels(fonts_texture.data);
+            self.load_texture(&data, fonts_texture.width, fonts_texture.height)?
+        };
         Ok(())
     }
+
+    // The frames in flight may still sample the current texture, even when the
+    // atlas has the same size: it is uploaded to a new texture instead, and
+    // the current one is released once they are done.
+    fn upload_font_texture(
+        &mut self,
+        current: Option<(TextureId, [u32; 2])>,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> std::result::Result<TextureId, TextureError> {
+        let texture_id = self.load_texture(data, width, height)?;
+        let fence_value = self.fence.value();
+        if let Some(resource) =
+            current.and_then(|(current, _)| self.texture_heap.release_texture(current, fence_value))
+        {
+            self.deferred_frees.push(fence_value, resource);
+        }
+        Ok(texture_id)
+    }
 }
 
 impl D3D12RenderEngine {
-    unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
+    unsafe fn render_draw_data(&mut self, frame_index: usize, draw_data: &DrawData) -> Result<()> {
         if draw_data.total_vtx_count == 0 {
             return Ok(());
         }
 
-        self.vertex_buffer.clear();
-        self.index_buffer.clear();
+        let frame = &mut self.frame_contexts[frame_index];
+        frame.vertex_buffer.clear();
+        frame.index_buffer.clear();
 
         draw_data
             .draw_lists()
@@ -163,12 +491,14 @@ impl D3D12RenderEngine {
                 (draw_list.vtx_buffer().iter().copied(), draw_list.idx_buffer().iter().copied())
             })
             .for_each(|(vertices, indices)| {
-                self.vertex_buffer.extend(vertices);
-                self.index_buffer.extend(indices);
+                frame.vertex_buffer.extend(vertices);
+                frame.index_buffer.extend(indices);
             });
 
-        self.vertex_buffer.upload(&self.device)?;
-        self.index_buffer.upload(&self.device)?;
+        // The replaced buffers are in use until this frame's signal completes.
+        let fence_value = self.fence.value() + 1;
+        frame.vertex_buffer.upload(&self.device, &mut self.deferred_frees, fence_value)?;
+        frame.index_buffer.upload(&self.device, &mut self.deferred_frees, fence_value)?;
 
         self.projection_buffer = {
             let [l, t, r, b] = [
@@ -186,7 +516,8 @@ impl D3D12RenderEngine {
             ]]
         };
 
-        self.setup_render_state(draw_data);
+        let frame = &self.frame_contexts[frame_index];
+        self.setup_render_state(frame, draw_data);
 
         let mut vtx_offset = 0usize;
         let mut idx_offset = 0usize;
@@ -207,9 +538,9 @@ impl D3D12RenderEngine {
                         if r.right > r.left && r.bottom > r.top {
                             let tex_handle =
                                 self.texture_heap.textures[cmd_params.texture_id.id()].gpu_desc;
-                            self.command_list.SetGraphicsRootDescriptorTable(1, tex_handle);
-                            self.command_list.RSSetScissorRects(&[r]);
-                            self.command_list.DrawIndexedInstanced(
+                            frame.command_list.SetGraphicsRootDescriptorTable(1, tex_handle);
+                            frame.command_list.RSSetScissorRects(&[r]);
+                            frame.command_list.DrawIndexedInstanced(
                                 count as _,
                                 1,
                                 (cmd_params.idx_offset + idx_offset) as _,
@@ -222,7 +553,7 @@ impl D3D12RenderEngine {
                         // Q: looking at the commands recorded in here, it
                         // doesn't seem like this should have any effect
                         // whatsoever. What am I doing wrong?
-                        self.setup_render_state(draw_data);
+                        self.setup_render_state(frame, draw_data);
                     },
                     DrawCmd::RawCallback { callback, raw_cmd } => callback(cl.raw(), raw_cmd),
                 }
@@ -234,8 +565,10 @@ impl D3D12RenderEngine {
         Ok(())
     }
 
-    unsafe fn setup_render_state(&self, draw_data: &DrawData) {
-        self.command_list.RSSetViewports(&[D3D12_VIEWPORT {
+    unsafe fn setup_render_state(&self, frame: &FrameContext, draw_data: &DrawData) {
+        let FrameContext { command_list, vertex_buffer, index_buffer, .. } = frame;
+
+        command_list.RSSetViewports(&[D3D12_VIEWPORT {
             TopLeftX: 0f32,
             TopLeftY: 0f32,
             Width: draw_data.display_size[0],
@@ -244,54 +577,75 @@ impl D3D12RenderEngine {
             MaxDepth: 1f32,
         }]);
 
-        self.command_list.IASetVertexBuffers(
+        command_list.IASetVertexBuffers(
             0,
             Some(&[D3D12_VERTEX_BUFFER_VIEW {
-                BufferLocation: self.vertex_buffer.resource.GetGPUVirtualAddress(),
-                SizeInBytes: (self.vertex_buffer.data.len() * mem::size_of::<DrawVert>()) as _,
+                BufferLocation: vertex_buffer.resource.GetGPUVirtualAddress(),
+                SizeInBytes: (vertex_buffer.data.len() * mem::size_of::<DrawVert>()) as _,
                 StrideInBytes: mem::size_of::<DrawVert>() as _,
             }]),
         );
 
-        self.command_list.IASetIndexBuffer(Some(&D3D12_INDEX_BUFFER_VIEW {
-            BufferLocation: self.index_buffer.resource.GetGPUVirtualAddress(),
-            SizeInBytes: (self.index_buffer.data.len() * mem::size_of::<DrawIdx>()) as _,
+        command_list.IASetIndexBuffer(Some(&D3D12_INDEX_BUFFER_VIEW {
+            BufferLocation: index_buffer.resource.GetGPUVirtualAddress(),
+            SizeInBytes: (index_buffer.data.len() * mem::size_of::<DrawIdx>()) as _,
             Format: if mem::size_of::<DrawIdx>() == 2 {
                 DXGI_FORMAT_R16_UINT
             } else {
                 DXGI_FORMAT_R32_UINT
             },
         }));
-        self.command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
-        self.command_list.SetPipelineState(&self.pipeline_state);
-        self.command_list.SetGraphicsRootSignature(&self.root_signature);
-        self.command_list.SetGraphicsRoot32BitConstants(
+        command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+        command_list.SetPipelineState(&self.pipeline_state);
+        command_list.SetGraphicsRootSignature(&self.root_signature);
+        command_list.SetGraphicsRoot32BitConstants(
             0,
             16,
             self.projection_buffer.as_ptr() as *const c_void,
             0,
         );
-        self.command_list.OMSetBlendFactor(Some(&[0f32; 4]));
+        command_list.OMSetBlendFactor(Some(&[0f32; 4]));
     }
 }
 
-unsafe fn create_command_objects(
-    command_queue: &ID3D12CommandQueue,
-) -> Result<(ID3D12Device, ID3D12CommandQueue, ID3D12CommandAllocator, ID3D12GraphicsCommandList)> {
-    let device: ID3D12Device = util::try_out_ptr(|v| unsafe { command_queue.GetDevice(v) })?;
-    let command_queue = command_queue.clone();
+// Number of frame contexts when the command list ring is enabled.
+const COMMAND_LIST_RING_SIZE: usize = 3;
+
+// Everything needed to record a frame. The GPU may still be reading from it
+// until the fence reaches `fence_value`.
+struct FrameContext {
+    command_allocator: ID3D12CommandAllocator,
+    command_list: ID3D12GraphicsCommandList,
+    vertex_buffer: Buffer<DrawVert>,
+    index_buffer: Buffer<u16>,
+    fence_value: u64,
+}
+
+impl FrameContext {
+    unsafe fn new(
+        device: &ID3D12Device,
+        vertex_buffer_capacity: usize,
+        index_buffer_capacity: usize,
+    ) -> Result<Self> {
+        let command_allocator: ID3D12CommandAllocator =
+            device.CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT)?;
 
-    let command_allocator: ID3D12CommandAllocator =
-        device.CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT)?;
+        let command_list: ID3D12GraphicsCommandList = device.CreateCommandList(
+            0,
+            D3D12_COMMAND_LIST_TYPE_DIRECT,
+            &command_allocator,
+            None,
+        )?;
+        command_list.Close()?;
 
-    let command_list: ID3D12GraphicsCommandList =
-        device.CreateCommandList(0, D3D12_COMMAND_LIST_TYPE_DIRECT, &command_allocator, None)?;
-    command_list.Close()?;
+        command_allocator.SetName(w!("hudhook Render Engine Command Allocator"))?;
+        command_list.SetName(w!("hudhook Render Engine Command List"))?;
 
-    command_allocator.SetName(w!("hudhook Render Engine Command Allocator"))?;
-    command_list.SetName(w!("hudhook Render Engine Command List"))?;
+        let vertex_buffer = Buffer::new(device, vertex_buffer_capacity)?;
+        let index_buffer = Buffer::new(device, index_buffer_capacity)?;
 
-    Ok((device, command_queue, command_allocator, command_list))
+        Ok(Self { command_allocator, command_list, vertex_buffer, index_buffer, fence_value: 0 })
+    }
 }
 
 unsafe fn create_heaps(device: &ID3D12Device) -> Result<(ID3D12DescriptorHeap, TextureHeap)> {
@@ -316,8 +670,33 @@ unsafe fn create_heaps(device: &ID3D12Device) -> Result<(ID3D12DescriptorHeap, T
     Ok((rtv_heap, texture_heap))
 }
 
+// Retrieve the info queue of a device created with the debug layer enabled.
+// Errors break into the debugger, if one is attached.
+unsafe fn create_info_queue(device: &ID3D12Device) -> Option<ID3D12InfoQueue> {
+    let info_queue = match device.cast::<ID3D12InfoQueue>() {
+        Ok(info_queue) => info_queue,
+        Err(e) => {
+            warn!("The Direct3D 12 debug layer is not enabled for the device: {e:?}");
+            return None;
+        },
+    };
+
+    if IsDebuggerPresent().as_bool() {
+        for severity in [D3D12_MESSAGE_SEVERITY_CORRUPTION, D3D12_MESSAGE_SEVERITY_ERROR] {
+            if let Err(e) = info_queue.SetBreakOnSeverity(severity, true) {
+                warn!("Could not break on {severity:?} messages: {e:?}");
+            }
+        }
+    }
+
+    Some(info_queue)
+}
+
 unsafe fn create_shader_program(
     device: &ID3D12Device,
+    rtv_format: DXGI_FORMAT,
+    color_space: ColorSpace,
+    premultiplied_alpha: bool,
 ) -> Result<(ID3D12RootSignature, ID3D12PipelineState)> {
     let parameters = [
         D3D12_ROOT_PARAMETER {
@@ -374,7 +753,7 @@ unsafe fn create_shader_program(
             | D3D12_ROOT_SIGNATURE_FLAG_DENY_GEOMETRY_SHADER_ROOT_ACCESS,
     };
 
-    let blob: ID3DBlob = util::try_out_err_blob(|v, err_blob| {
+    let blob: ID3DBlob = util::try_out_opt_err_blob(|v, err_blob| {
         D3D12SerializeRootSignature(
             &root_signature_desc,
             D3D_ROOT_SIGNATURE_VERSION_1_0,
@@ -382,8 +761,7 @@ unsafe fn create_shader_program(
             Some(err_blob),
         )
     })
-    .map_err(util::print_error_blob("Serializing root signature"))
-    .expect("D3D12SerializeRootSignature");
+    .map_err(util::print_opt_error_blob("Serializing root signature"))?;
 
     let root_signature: ID3D12RootSignature = device.CreateRootSignature(
         0,
@@ -425,12 +803,24 @@ unsafe fn create_shader_program(
     SamplerState sampler0: register(s0);
     Texture2D texture0: register(t0);
 
+    float3 srgb_to_linear(float3 col) {
+      return col <= 0.04045f ? col / 12.92f : pow((col + 0.055f) / 1.055f, 2.4f);
+    }
+
     float4 main(PS_INPUT input): SV_Target {
-      float4 out_col = input.col * texture0.Sample(sampler0, input.uv);
-      return out_col;
+      float4 col = input.col;
+      float4 tex = texture0.Sample(sampler0, input.uv);
+    #ifdef LINEAR_OUTPUT
+      col.rgb = srgb_to_linear(col.rgb);
+      tex.rgb = srgb_to_linear(tex.rgb);
+    #endif
+    #ifdef PREMULTIPLIED_ALPHA
+      col.rgb *= col.a;
+    #endif
+      return col * tex;
     }"#;
 
-    let vtx_shader: ID3DBlob = util::try_out_err_blob(|v, err_blob| unsafe {
+    let vtx_shader: ID3DBlob = util::try_out_opt_err_blob(|v, err_blob| unsafe {
         D3DCompile(
             VS.as_ptr() as _,
             VS.len(),
@@ -445,15 +835,15 @@ unsafe fn create_shader_program(
             Some(err_blob),
         )
     })
-    .map_err(util::print_error_blob("Compiling vertex shader"))
-    .expect("D3DCompile");
+    .map_err(util::print_opt_error_blob("Compiling vertex shader"))?;
 
-    let pix_shader = util::try_out_err_blob(|v, err_blob| unsafe {
+    let defines = shader_defines(color_space, premultiplied_alpha);
+    let pix_shader = util::try_out_opt_err_blob(|v, err_blob| unsafe {
         D3DCompile(
             PS.as_ptr() as _,
             PS.len(),
             None,
-            None,
+            defines.as_ref().map(|defines| defines.as_ptr()),
             None::<&ID3DInclude>,
             s!("main\0"),
             s!("ps_5_0\0"),
@@ -463,8 +853,7 @@ unsafe fn create_shader_program(
             Some(err_blob),
         )
     })
-    .map_err(util::print_error_blob("Compiling pixel shader"))
-    .expect("D3DCompile");
+    .map_err(util::print_opt_error_blob("Compiling pixel shader"))?;
 
     let input_elements = [
         D3D12_INPUT_ELEMENT_DESC {
@@ -505,7 +894,7 @@ unsafe fn create_shader_program(
         SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
         Flags: D3D12_PIPELINE_STATE_FLAG_NONE,
         RTVFormats: [
-            DXGI_FORMAT_B8G8R8A8_UNORM,
+            rtv_format,
             Default::default(),
             Default::default(),
             Default::default(),
@@ -534,7 +923,11 @@ unsafe fn create_shader_program(
                 D3D12_RENDER_TARGET_BLEND_DESC {
                     BlendEnable: true.into(),
                     LogicOpEnable: false.into(),
-                    SrcBlend: D3D12_BLEND_SRC_ALPHA,
+                    SrcBlend: if premultiplied_alpha {
+                        D3D12_BLEND_ONE
+                    } else {
+                        D3D12_BLEND_SRC_ALPHA
+                    },
                     DestBlend: D3D12_BLEND_INV_SRC_ALPHA,
                     BlendOp: D3D12_BLEND_OP_ADD,
                     SrcBlendAlpha: D3D12_BLEND_ONE,
@@ -626,10 +1019,18 @@ impl<T> Buffer<T> {
         self.data.extend(it)
     }
 
-    fn upload(&mut self, device: &ID3D12Device) -> Result<()> {
+    fn upload(
+        &mut self,
+        device: &ID3D12Device,
+        deferred_frees: &mut DeferredFrees,
+        fence_value: u64,
+    ) -> Result<()> {
         let capacity = self.data.capacity();
         if capacity > self.resource_capacity {
-            drop(mem::replace(&mut self.resource, Self::create_resource(device, capacity)?));
+            trace!("Growing buffer from {} to {capacity} elements", self.resource_capacity);
+            let old_resource =
+                mem::replace(&mut self.resource, Self::create_resource(device, capacity)?);
+            deferred_frees.push(fence_value, old_resource);
             self.resource_capacity = capacity;
         }
 
@@ -644,13 +1045,41 @@ impl<T> Buffer<T> {
     }
 }
 
+// Resources and descriptor heaps that may still be referenced by command
+// lists in flight, each paired with the fence value after which it is safe to
+// release it.
+struct DeferredFrees(VecDeque<(u64, IUnknown)>);
+
+impl DeferredFrees {
+    fn with_capacity(capacity: usize) -> Self {
+        Self(VecDeque::with_capacity(capacity))
+    }
+
+    fn push(&mut self, fence_value: u64, resource: impl Into<IUnknown>) {
+        self.0.push_back((fence_value, resource.into()));
+    }
+
+    // Release all the resources whose fence value has been reached.
+    fn collect(&mut self, completed_value: u64) {
+        while let Some(&(fence_value, _)) = self.0.front() {
+            if fence_value > completed_value {
+                break;
+            }
+            self.0.pop_front();
+        }
+    }
+}
+
 #[derive(Debug)]
 #[allow(unused)]
 struct Texture {
-    resource: ID3D12Resource,
+    // Taken once the texture is released.
+    resource: Option<ID3D12Resource>,
     gpu_desc: D3D12_GPU_DESCRIPTOR_HANDLE,
     width: u32,
     height: u32,
+    // Whether the texture was uploaded to, and left readable by the shaders.
+    uploaded: bool,
 }
 
 struct TextureHeap {
@@ -658,7 +1087,9 @@ struct TextureHeap {
     srv_heap: ID3D12DescriptorHeap,
     srv_staging_heap: ID3D12DescriptorHeap,
     textures: Vec<Texture>,
-    command_queue: ID3D12CommandQueue,
+    // Descriptor slots of released textures, each paired with the fence value
+    // after which the frames in flight no longer read the descriptor.
+    free_slots: VecDeque<(u64, usize)>,
     command_allocator: ID3D12CommandAllocator,
     command_list: ID3D12GraphicsCommandList,
     fence: Fence,
@@ -666,15 +1097,6 @@ struct TextureHeap {
 
 impl TextureHeap {
     fn new(device: &ID3D12Device, srv_heap: ID3D12DescriptorHeap) -> Result<Self> {
-        let command_queue = unsafe {
-            device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
-                Type: D3D12_COMMAND_LIST_TYPE_DIRECT,
-                Priority: 0,
-                Flags: D3D12_COMMAND_QUEUE_FLAG_NONE,
-                NodeMask: 0,
-            })
-        }?;
-
         let command_allocator: ID3D12CommandAllocator =
             unsafe { device.CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT) }?;
 
@@ -704,14 +1126,46 @@ impl TextureHeap {
             srv_heap,
             srv_staging_heap,
             textures: Vec::new(),
-            command_queue,
+            free_slots: VecDeque::new(),
             command_allocator,
             command_list,
             fence,
         })
     }
 
-    unsafe fn resize_heap(&mut self) -> Result<()> {
+    // Take the resource of a texture no longer drawn with, to be released once
+    // the fence reaches `fence_value`. Its descriptor slot is reused by the
+    // textures created after that.
+    fn release_texture(
+        &mut self,
+        texture_id: TextureId,
+        fence_value: u64,
+    ) -> Option<ID3D12Resource> {
+        let resource =
+            self.textures.get_mut(texture_id.id()).and_then(|texture| texture.resource.take())?;
+        self.free_slots.push_back((fence_value, texture_id.id()));
+        Some(resource)
+    }
+
+    // Take the slot of a released texture whose descriptor is no longer read
+    // by the frames in flight, if any.
+    fn take_free_slot(&mut self, completed_value: u64) -> Option<usize> {
+        match self.free_slots.front() {
+            Some(&(fence_value, _)) if fence_value <= completed_value => {
+                self.free_slots.pop_front().map(|(_, slot)| slot)
+            },
+            _ => None,
+        }
+    }
+
+    // Grow the heaps if they are full. The shader visible heap being replaced
+    // is released once the fence reaches `fence_value`; the staging heap is
+    // only ever read by the CPU, so it can go right away.
+    unsafe fn resize_heap(
+        &mut self,
+        deferred_frees: &mut DeferredFrees,
+        fence_value: u64,
+    ) -> Result<()> {
         let mut desc = self.srv_heap.GetDesc();
         let mut desc_staging = self.srv_staging_heap.GetDesc();
         let old_num_descriptors = desc.NumDescriptors;
@@ -735,7 +1189,7 @@ impl TextureHeap {
                 srv_staging_heap.GetCPUDescriptorHandleForHeapStart(),
                 D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
             );
-            self.srv_heap = srv_heap;
+            deferred_frees.push(fence_value, mem::replace(&mut self.srv_heap, srv_heap));
             self.srv_staging_heap = srv_staging_heap;
 
             // Adjust texture GPU pointers.
@@ -754,8 +1208,25 @@ impl TextureHeap {
         Ok(())
     }
 
-    unsafe fn create_texture(&mut self, width: u32, height: u32) -> Result<TextureId> {
-        self.resize_heap()?;
+    unsafe fn create_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        deferred_frees: &mut DeferredFrees,
+        fence_value: u64,
+        completed_value: u64,
+    ) -> std::result::Result<TextureId, TextureError> {
+        // Supported by every Direct3D 12 device.
+        let max = D3D12_REQ_TEXTURE2D_U_OR_V_DIMENSION;
+        check_texture_size(width, height, [max, max])?;
+
+        let texture_index = match self.take_free_slot(completed_value) {
+            Some(slot) => slot,
+            None => {
+                self.resize_heap(deferred_frees, fence_value)?;
+                self.textures.len()
+            },
+        };
 
         let cpu_heap_stg_start = self.srv_staging_heap.GetCPUDescriptorHandleForHeapStart();
         let cpu_heap_start = self.srv_heap.GetCPUDescriptorHandleForHeapStart();
@@ -763,18 +1234,16 @@ impl TextureHeap {
         let heap_inc_size =
             self.device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV);
 
-        let texture_index = self.textures.len() as u32;
-
         let cpu_desc_stg = D3D12_CPU_DESCRIPTOR_HANDLE {
-            ptr: cpu_heap_stg_start.ptr + (texture_index * heap_inc_size) as usize,
+            ptr: cpu_heap_stg_start.ptr + texture_index * heap_inc_size as usize,
         };
 
         let cpu_desc = D3D12_CPU_DESCRIPTOR_HANDLE {
-            ptr: cpu_heap_start.ptr + (texture_index * heap_inc_size) as usize,
+            ptr: cpu_heap_start.ptr + texture_index * heap_inc_size as usize,
         };
 
         let gpu_desc = D3D12_GPU_DESCRIPTOR_HANDLE {
-            ptr: gpu_heap_start.ptr + (texture_index * heap_inc_size) as u64,
+            ptr: gpu_heap_start.ptr + (texture_index * heap_inc_size as usize) as u64,
         };
 
         let texture: ID3D12Resource = util::try_out_ptr(|v| unsafe {
@@ -830,20 +1299,32 @@ impl TextureHeap {
             D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
         );
 
-        let id = TextureId::from(self.textures.len());
-        self.textures.push(Texture { resource: texture.clone(), gpu_desc, width, height });
+        let texture = Texture { resource: Some(texture), gpu_desc, width, height, uploaded: false };
+        if texture_index == self.textures.len() {
+            self.textures.push(texture);
+        } else {
+            self.textures[texture_index] = texture;
+        }
 
-        Ok(id)
+        Ok(TextureId::from(texture_index))
     }
 
+    // Upload `data` to the texture on `command_queue`, the one the overlay is
+    // rendered with: the copy waits for the frames in flight, which may still
+    // sample the texture, and is complete before the next frame is submitted.
     unsafe fn upload_texture(
         &mut self,
+        command_queue: &ID3D12CommandQueue,
         texture_id: TextureId,
         data: &[u8],
         width: u32,
         height: u32,
     ) -> Result<()> {
-        let texture = &self.textures[texture_id.id()];
+        let texture = &mut self.textures[texture_id.id()];
+        let Some(resource) = texture.resource.clone() else {
+            error!("Texture {texture_id:?} was released");
+            return Err(Error::from_hresult(HRESULT(-1)));
+        };
         if texture.width != width || texture.height != height {
             error!(
                 "image size {width}x{height} do not match expected {}x{}",
@@ -902,7 +1383,7 @@ impl TextureHeap {
         self.command_list.Reset(&self.command_allocator, None)?;
 
         let dst_location = D3D12_TEXTURE_COPY_LOCATION {
-            pResource: ManuallyDrop::new(Some(texture.resource.clone())),
+            pResource: ManuallyDrop::new(Some(resource.clone())),
             Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
             Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: 0 },
         };
@@ -924,20 +1405,32 @@ impl TextureHeap {
             },
         };
 
+        let replace_barriers = texture.uploaded.then(|| {
+            [util::create_barrier(
+                &resource,
+                D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+                D3D12_RESOURCE_STATE_COPY_DEST,
+            )]
+        });
+        if let Some(replace_barriers) = &replace_barriers {
+            self.command_list.ResourceBarrier(replace_barriers);
+        }
         self.command_list.CopyTextureRegion(&dst_location, 0, 0, 0, &src_location, None);
         let barriers = [util::create_barrier(
-            &texture.resource,
+            &resource,
             D3D12_RESOURCE_STATE_COPY_DEST,
             D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
         )];
 
         self.command_list.ResourceBarrier(&barriers);
         self.command_list.Close()?;
-        self.command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
-        self.command_queue.Signal(self.fence.fence(), self.fence.value())?;
+        command_queue.ExecuteCommandLists(&[Some(self.command_list.cast()?)]);
+        command_queue.Signal(self.fence.fence(), self.fence.value())?;
         self.fence.wait()?;
         self.fence.incr();
+        texture.uploaded = true;
 
+        replace_barriers.into_iter().flatten().for_each(util::drop_barrier);
         barriers.into_iter().for_each(util::drop_barrier);
 
         // Apparently, leaking the upload buffer into the location is necessary.
@@ -1,17 +1,18 @@
 // Based on https://github.com/Veykril/imgui-dx9-renderer
 
+use std::ffi::c_void;
 use std::{mem, ptr};
 
 use imgui::internal::RawWrapper;
 use imgui::{BackendFlags, Context, DrawCmd, DrawData, DrawIdx, TextureId};
-use tracing::error;
-use windows::core::{Error, Result, HRESULT};
+use tracing::{error, trace};
+use windows::core::{Error, Interface, Result, HRESULT};
 use windows::Foundation::Numerics::Matrix4x4;
 use windows::Win32::Foundation::RECT;
 use windows::Win32::Graphics::Direct3D9::*;
 
-use crate::renderer::RenderEngine;
-use crate::{util, RenderContext};
+use crate::renderer::{check_texture_size, font_atlas_pixels, premultiply_alpha, RenderEngine};
+use crate::{options, util, RenderBackend, RenderContext, TextureError};
 
 const D3DFVF_CUSTOMVERTEX: u32 = D3DFVF_XYZ | D3DFVF_DIFFUSE | D3DFVF_TEX1;
 const MAT_IDENTITY: Matrix4x4 = Matrix4x4 {
@@ -48,6 +49,7 @@ pub struct D3D9RenderEngine {
     vertex_buffer: Buffer<IDirect3DVertexBuffer9, CustomVertex>,
     index_buffer: Buffer<IDirect3DIndexBuffer9, DrawIdx>,
     projection_buffer: Matrix4x4,
+    premultiplied_alpha: bool,
 }
 
 impl D3D9RenderEngine {
@@ -56,20 +58,40 @@ impl D3D9RenderEngine {
 
         let texture_heap = TextureHeap::new(&device)?;
 
-        let vertex_buffer = Buffer::new(&device, 5000)?;
-        let index_buffer = Buffer::new(&device, 10000)?;
+        let (vertex_buffer_capacity, index_buffer_capacity, premultiplied_alpha) = {
+            let options = options::get();
+            (
+                options.vertex_buffer_capacity,
+                options.index_buffer_capacity,
+                options.premultiplied_alpha,
+            )
+        };
+        let vertex_buffer = Buffer::new(&device, vertex_buffer_capacity)?;
+        let index_buffer = Buffer::new(&device, index_buffer_capacity)?;
         let projection_buffer = Default::default();
 
         ctx.set_ini_filename(None);
         ctx.io_mut().backend_flags |= BackendFlags::RENDERER_HAS_VTX_OFFSET;
         ctx.set_renderer_name(String::from(concat!("hudhook-dx9@", env!("CARGO_PKG_VERSION"))));
 
-        Ok(Self { device, texture_heap, vertex_buffer, index_buffer, projection_buffer })
+        Ok(Self {
+            device,
+            texture_heap,
+            vertex_buffer,
+            index_buffer,
+            projection_buffer,
+            premultiplied_alpha,
+        })
     }
 }
 
 impl RenderContext for D3D9RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+    fn load_texture(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> std::result::Result<TextureId, TextureError> {
         unsafe {
             let texture_id = self.texture_heap.create_texture(width, height)?;
             self.texture_heap.upload_texture(texture_id, data, width, height)?;
@@ -83,8 +105,12 @@ impl RenderContext for D3D9RenderEngine {
         data: &[u8],
         width: u32,
         height: u32,
-    ) -> Result<()> {
-        unsafe { self.texture_heap.upload_texture(texture_id, data, width, height) }
+    ) -> std::result::Result<(), TextureError> {
+        Ok(unsafe { self.texture_heap.upload_texture(texture_id, data, width, height) }?)
+    }
+
+    unsafe fn device_raw(&self) -> Option<(RenderBackend, *mut c_void)> {
+        Some((RenderBackend::Dx9, self.device.as_raw()))
     }
 }
 
@@ -108,8 +134,10 @@ impl RenderEngine for D3D9RenderEngine {
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
         let fonts_texture = fonts.build_rgba32_texture();
-        fonts.tex_id =
-            self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
+        fonts.tex_id = {
+            let data = font_atlas_pixels(fonts_texture.data);
+            self.load_texture(&data, fonts_texture.width, fonts_texture.height)?
+        };
         Ok(())
     }
 }
@@ -123,6 +151,8 @@ impl D3D9RenderEngine {
         self.vertex_buffer.clear();
         self.index_buffer.clear();
 
+        // Without pixel shaders, the vertex colors are premultiplied here.
+        let premultiplied_alpha = self.premultiplied_alpha;
         draw_data
             .draw_lists()
             .map(|draw_list| {
@@ -130,10 +160,17 @@ impl D3D9RenderEngine {
             })
             .for_each(|(vertices, indices)| {
                 // CPU swizzle FTW
-                self.vertex_buffer.extend(vertices.map(|draw_vert| CustomVertex {
-                    pos: [draw_vert.pos[0], draw_vert.pos[1], 0.0],
-                    col: [draw_vert.col[2], draw_vert.col[1], draw_vert.col[0], draw_vert.col[3]],
-                    uv: draw_vert.uv,
+                self.vertex_buffer.extend(vertices.map(|draw_vert| {
+                    let mut col =
+                        [draw_vert.col[2], draw_vert.col[1], draw_vert.col[0], draw_vert.col[3]];
+                    if premultiplied_alpha {
+                        premultiply_alpha(&mut col);
+                    }
+                    CustomVertex {
+                        pos: [draw_vert.pos[0], draw_vert.pos[1], 0.0],
+                        col,
+                        uv: draw_vert.uv,
+                    }
                 }));
                 self.index_buffer.extend(indices);
             });
@@ -231,7 +268,8 @@ impl D3D9RenderEngine {
         self.device.SetRenderState(D3DRS_ALPHABLENDENABLE, true.into())?;
         self.device.SetRenderState(D3DRS_ALPHATESTENABLE, false.into())?;
         self.device.SetRenderState(D3DRS_BLENDOP, D3DBLENDOP_ADD.0 as u32)?;
-        self.device.SetRenderState(D3DRS_SRCBLEND, D3DBLEND_SRCALPHA.0 as u32)?;
+        let src_blend = if self.premultiplied_alpha { D3DBLEND_ONE } else { D3DBLEND_SRCALPHA };
+        self.device.SetRenderState(D3DRS_SRCBLEND, src_blend.0 as u32)?;
         self.device.SetRenderState(D3DRS_DESTBLEND, D3DBLEND_INVSRCALPHA.0 as u32)?;
         self.device.SetRenderState(D3DRS_SCISSORTESTENABLE, true.into())?;
         self.device.SetRenderState(D3DRS_SHADEMODE, D3DSHADE_GOURAUD.0 as u32)?;
@@ -290,6 +328,7 @@ impl<B: BufferType, T> Buffer<B, T> {
     fn upload(&mut self, device: &IDirect3DDevice9) -> Result<()> {
         let capacity = self.data.capacity();
         if capacity > self.resource_capacity {
+            trace!("Growing buffer from {} to {capacity} elements", self.resource_capacity);
             drop(mem::replace(&mut self.resource, B::create_resource(device, capacity)?));
             self.resource_capacity = capacity;
         }
@@ -387,7 +426,14 @@ impl TextureHeap {
         &self.textures[texture_id.id()].resource
     }
 
-    unsafe fn create_texture(&mut self, width: u32, height: u32) -> Result<TextureId> {
+    unsafe fn create_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> std::result::Result<TextureId, TextureError> {
+        let caps = util::try_out_param(|v| self.device.GetDeviceCaps(v))?;
+        check_texture_size(width, height, [caps.MaxTextureWidth, caps.MaxTextureHeight])?;
+
         let resource = util::try_out_ptr(|v| {
             self.device.CreateTexture(
                 width,
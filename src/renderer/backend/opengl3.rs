@@ -1,20 +1,21 @@
 // Based on https://github.com/michaelfairley/rust-imgui-opengl-renderer/
 
-use std::ffi::{c_void, CString};
+use std::borrow::Cow;
+use std::ffi::{c_char, c_void, CStr, CString};
 use std::mem::{self, offset_of};
 
 use gl::types::*;
 use imgui::internal::RawWrapper;
 use imgui::{Context, DrawCmd, DrawData, DrawIdx, DrawVert, TextureId};
 use once_cell::sync::OnceCell;
-use tracing::error;
+use tracing::{debug, error};
 use windows::core::{s, Error, Result, HRESULT, PCSTR};
 use windows::Win32::Foundation::{FARPROC, HINSTANCE};
 use windows::Win32::Graphics::OpenGL::*;
 use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryA};
 
-use crate::renderer::RenderEngine;
-use crate::{util, RenderContext};
+use crate::renderer::{check_texture_size, font_atlas_pixels, RenderEngine};
+use crate::{options, util, OpenGlTextureFormat, RenderBackend, RenderContext, TextureError};
 
 mod gl {
     #![allow(
@@ -61,6 +62,8 @@ pub struct OpenGl3RenderEngine {
     vertex_buffer: GLuint,
     index_buffer: GLuint,
     projection_buffer: [[f32; 4]; 4],
+    premultiplied_alpha: bool,
+    srgb_framebuffer: bool,
 
     texture_heap: TextureHeap,
 }
@@ -69,8 +72,13 @@ impl OpenGl3RenderEngine {
     pub fn new(ctx: &mut Context) -> Result<Self> {
         let gl = gl::Gl::load_with(|s| unsafe { load_func(CString::new(s).unwrap()) });
 
+        let (opengl_texture_format, premultiplied_alpha, srgb_framebuffer) = {
+            let options = options::get();
+            (options.opengl_texture_format, options.premultiplied_alpha, options.srgb_framebuffer)
+        };
+
         let (program, projection_loc, position_loc, color_loc, uv_loc, texture_loc) =
-            unsafe { create_shader_program(&gl) };
+            unsafe { create_shader_program(&gl, premultiplied_alpha) };
 
         let vertex_buffer = util::out_param(|x| unsafe { gl.GenBuffers(1, x) });
         let index_buffer = util::out_param(|x| unsafe { gl.GenBuffers(1, x) });
@@ -78,7 +86,13 @@ impl OpenGl3RenderEngine {
 
         let vao = util::out_param(|x| unsafe { gl.GenVertexArrays(1, x) });
 
-        let texture_heap = TextureHeap::new();
+        unsafe { log_context_info(&gl) };
+
+        let upload_format = match opengl_texture_format {
+            OpenGlTextureFormat::Rgba => gl::RGBA,
+            OpenGlTextureFormat::Bgra => gl::BGRA,
+        };
+        let texture_heap = TextureHeap::new(upload_format);
 
         ctx.set_ini_filename(None);
         ctx.set_renderer_name(String::from(concat!("hudhook-opengl3@", env!("CARGO_PKG_VERSION"))));
@@ -95,13 +109,20 @@ impl OpenGl3RenderEngine {
             vertex_buffer,
             index_buffer,
             projection_buffer,
+            premultiplied_alpha,
+            srgb_framebuffer,
             texture_heap,
         })
     }
 }
 
 impl RenderContext for OpenGl3RenderEngine {
-    fn load_texture(&mut self, data: &[u8], width: u32, height: u32) -> Result<TextureId> {
+    fn load_texture(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> std::result::Result<TextureId, TextureError> {
         unsafe { self.texture_heap.create_texture(&self.gl, data, width, height) }
     }
 
@@ -111,8 +132,14 @@ impl RenderContext for OpenGl3RenderEngine {
         data: &[u8],
         width: u32,
         height: u32,
-    ) -> Result<()> {
-        unsafe { self.texture_heap.update_texture(&self.gl, texture_id, data, width, height) }
+    ) -> std::result::Result<(), TextureError> {
+        Ok(unsafe { self.texture_heap.update_texture(&self.gl, texture_id, data, width, height) }?)
+    }
+
+    unsafe fn device_raw(&self) -> Option<(RenderBackend, *mut c_void)> {
+        // The renderer has no device of its own: it draws with the rendering
+        // context current on the thread, which is the game's.
+        Some((RenderBackend::OpenGl3, wglGetCurrentContext().0 as *mut c_void))
     }
 }
 
@@ -122,27 +149,25 @@ impl RenderEngine for OpenGl3RenderEngine {
     fn render(&mut self, draw_data: &DrawData, _render_target: Self::RenderTarget) -> Result<()> {
         unsafe {
             let state_backup = StateBackup::backup(&self.gl);
-            self.render_draw_data(draw_data)?;
+            let result = self.render_draw_data(draw_data);
             state_backup.restore(&self.gl);
+            result
         }
-        Ok(())
     }
 
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()> {
         let fonts = ctx.fonts();
         let fonts_texture = fonts.build_rgba32_texture();
-        fonts.tex_id =
-            self.load_texture(fonts_texture.data, fonts_texture.width, fonts_texture.height)?;
+        fonts.tex_id = {
+            let data = font_atlas_pixels(fonts_texture.data);
+            self.load_texture(&data, fonts_texture.width, fonts_texture.height)?
+        };
         Ok(())
     }
 }
 
 impl OpenGl3RenderEngine {
     unsafe fn render_draw_data(&mut self, draw_data: &DrawData) -> Result<()> {
-        let [clip_offset_x, clip_offset_y] = draw_data.display_pos;
-        let [clip_scale_w, clip_scale_h] = draw_data.framebuffer_scale;
-        let fb_height = clip_scale_h * draw_data.display_size[1];
-
         self.projection_buffer = {
             let [l, t, r, b] = [
                 draw_data.display_pos[0],
@@ -165,23 +190,13 @@ impl OpenGl3RenderEngine {
             for cmd in cl.commands() {
                 match cmd {
                     DrawCmd::Elements { count, cmd_params } => {
-                        let [cx, cy, cz, cw] = cmd_params.clip_rect;
-
-                        let clip_min_x = (cx - clip_offset_x) * clip_scale_w;
-                        let clip_min_y = (cy - clip_offset_y) * clip_scale_h;
-                        let clip_max_x = (cz - clip_offset_x) * clip_scale_w;
-                        let clip_max_y = (cw - clip_offset_y) * clip_scale_h;
-
-                        if clip_max_x <= clip_min_x || clip_max_y <= clip_min_y {
+                        let Some([x, y, width, height]) =
+                            scissor_box(draw_data, cmd_params.clip_rect)
+                        else {
                             continue;
-                        }
+                        };
 
-                        self.gl.Scissor(
-                            clip_min_x as i32,
-                            (fb_height - clip_max_y) as i32,
-                            (clip_max_x - clip_min_x) as i32,
-                            (clip_max_y - clip_min_y) as i32,
-                        );
+                        self.gl.Scissor(x, y, width, height);
                         self.gl.ActiveTexture(gl::TEXTURE0);
                         self.gl.BindTexture(
                             gl::TEXTURE_2D,
@@ -227,15 +242,21 @@ impl OpenGl3RenderEngine {
         self.gl.Enable(gl::BLEND);
         self.gl.BlendEquation(gl::FUNC_ADD);
         self.gl.BlendFuncSeparate(
-            gl::SRC_ALPHA,
+            if self.premultiplied_alpha { gl::ONE } else { gl::SRC_ALPHA },
             gl::ONE_MINUS_SRC_ALPHA,
             gl::ONE,
             gl::ONE_MINUS_SRC_ALPHA,
         );
         self.gl.Disable(gl::CULL_FACE);
         self.gl.Disable(gl::DEPTH_TEST);
+        if self.srgb_framebuffer {
+            self.gl.Enable(gl::FRAMEBUFFER_SRGB);
+        } else {
+            self.gl.Disable(gl::FRAMEBUFFER_SRGB);
+        }
         self.gl.Disable(gl::STENCIL_TEST);
         self.gl.Enable(gl::SCISSOR_TEST);
+        self.gl.Disable(gl::PRIMITIVE_RESTART);
         self.gl.PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
 
         self.gl.Viewport(
@@ -290,7 +311,10 @@ impl OpenGl3RenderEngine {
     }
 }
 
-unsafe fn create_shader_program(gl: &gl::Gl) -> (GLuint, GLuint, GLuint, GLuint, GLuint, GLuint) {
+unsafe fn create_shader_program(
+    gl: &gl::Gl,
+    premultiplied_alpha: bool,
+) -> (GLuint, GLuint, GLuint, GLuint, GLuint, GLuint) {
     const VS: &[u8] = b"
     #version 130
 
@@ -309,28 +333,40 @@ unsafe fn create_shader_program(gl: &gl::Gl) -> (GLuint, GLuint, GLuint, GLuint,
     }
     \0";
 
+    // `#version` must come first, so the definitions go in a source string of
+    // their own after it.
+    const FS_VERSION: &[u8] = b"#version 130\n";
     const FS: &[u8] = b"
-    #version 130
-
     uniform sampler2D tex;
     in vec2 frag_uv;
     in vec4 frag_color;
     out vec4 out_color;
 
     void main() {
-        out_color = frag_color * texture(tex, frag_uv.st);
+        vec4 color = frag_color;
+    #ifdef PREMULTIPLIED_ALPHA
+        color.rgb *= color.a;
+    #endif
+        out_color = color * texture(tex, frag_uv.st);
     }
     \0";
+    let fs_defines: &[u8] =
+        if premultiplied_alpha { b"#define PREMULTIPLIED_ALPHA\n" } else { b"" };
 
     let program = gl.CreateProgram();
     let vertex_shader = gl.CreateShader(gl::VERTEX_SHADER);
     let fragment_shader = gl.CreateShader(gl::FRAGMENT_SHADER);
     let vertex_source = [VS.as_ptr() as *const GLchar];
-    let fragment_source = [FS.as_ptr() as *const GLchar];
+    let fragment_source = [FS_VERSION, fs_defines, FS].map(|fs| fs.as_ptr() as *const GLchar);
     let vertex_source_len = [VS.len() as i32];
-    let fragment_source_len = [FS.len() as i32];
+    let fragment_source_len = [FS_VERSION, fs_defines, FS].map(|fs| fs.len() as i32);
     gl.ShaderSource(vertex_shader, 1, vertex_source.as_ptr(), vertex_source_len.as_ptr());
-    gl.ShaderSource(fragment_shader, 1, fragment_source.as_ptr(), fragment_source_len.as_ptr());
+    gl.ShaderSource(
+        fragment_shader,
+        fragment_source.len() as i32,
+        fragment_source.as_ptr(),
+        fragment_source_len.as_ptr(),
+    );
     gl.CompileShader(vertex_shader);
     gl.CompileShader(fragment_shader);
     gl.AttachShader(program, vertex_shader);
@@ -349,8 +385,37 @@ unsafe fn create_shader_program(gl: &gl::Gl) -> (GLuint, GLuint, GLuint, GLuint,
     (program, projection_loc, position_loc, color_loc, uv_loc, texture_loc)
 }
 
+unsafe fn gl_string(gl: &gl::Gl, name: GLenum) -> Cow<'_, str> {
+    let s = gl.GetString(name);
+    if s.is_null() {
+        Cow::Borrowed("?")
+    } else {
+        CStr::from_ptr(s as *const c_char).to_string_lossy()
+    }
+}
+
+// Log which driver and kind of context the renderer runs on. The renderer
+// only relies on core profile features, so that it works on both core and
+// compatibility contexts.
+unsafe fn log_context_info(gl: &gl::Gl) {
+    let profile_mask = util::out_param(|x| gl.GetIntegerv(gl::CONTEXT_PROFILE_MASK, x));
+    let profile = if profile_mask as GLenum & gl::CONTEXT_CORE_PROFILE_BIT != 0 {
+        "core"
+    } else {
+        "compatibility"
+    };
+
+    debug!(
+        "OpenGL {} ({profile} profile), {} {}",
+        gl_string(gl, gl::VERSION),
+        gl_string(gl, gl::VENDOR),
+        gl_string(gl, gl::RENDERER)
+    );
+}
+
 struct TextureHeap {
     textures: Vec<Texture>,
+    upload_format: GLenum,
 }
 struct Texture {
     gl_texture: GLuint,
@@ -359,8 +424,8 @@ struct Texture {
 }
 
 impl TextureHeap {
-    fn new() -> Self {
-        Self { textures: Vec::new() }
+    fn new(upload_format: GLenum) -> Self {
+        Self { textures: Vec::new(), upload_format }
     }
 
     fn get(&self, texture_id: TextureId) -> &Texture {
@@ -373,29 +438,37 @@ impl TextureHeap {
         data: &[u8],
         width: u32,
         height: u32,
-    ) -> Result<TextureId> {
-        let texture = util::out_param(|x| gl.GenTextures(1, x));
+    ) -> std::result::Result<TextureId, TextureError> {
+        let max = util::out_param(|x| gl.GetIntegerv(gl::MAX_TEXTURE_SIZE, x)) as u32;
+        check_texture_size(width, height, [max, max])?;
 
-        let mut bound_texture = 0;
-        gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut bound_texture);
+        let texture = util::out_param(|x| gl.GenTextures(1, x));
 
+        // The binding to restore is the one of the texture unit used here, not
+        // of the active one.
+        let last_active_texture = util::out_param(|x| gl.GetIntegerv(gl::ACTIVE_TEXTURE, x));
         gl.ActiveTexture(gl::TEXTURE0);
+        let bound_texture = util::out_param(|x| gl.GetIntegerv(gl::TEXTURE_BINDING_2D, x));
+
         gl.BindTexture(gl::TEXTURE_2D, texture);
         gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
         gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
 
+        let unpack_state = UnpackState::reset(gl);
         gl.TexImage2D(
             gl::TEXTURE_2D,
             0,
-            gl::RGBA as GLint,
+            gl::RGBA8 as GLint,
             width as GLint,
             height as GLint,
             0,
-            gl::RGBA,
+            self.upload_format,
             gl::UNSIGNED_BYTE,
             data.as_ptr() as *const c_void,
         );
+        unpack_state.restore(gl);
         gl.BindTexture(gl::TEXTURE_2D, bound_texture as _);
+        gl.ActiveTexture(last_active_texture as _);
 
         let id = TextureId::from(self.textures.len());
         self.textures.push(Texture { gl_texture: texture, width, height });
@@ -420,12 +493,13 @@ impl TextureHeap {
             return Err(Error::from_hresult(HRESULT(-1)));
         }
 
-        let mut bound_texture = 0;
-        gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut bound_texture);
-
+        let last_active_texture = util::out_param(|x| gl.GetIntegerv(gl::ACTIVE_TEXTURE, x));
         gl.ActiveTexture(gl::TEXTURE0);
+        let bound_texture = util::out_param(|x| gl.GetIntegerv(gl::TEXTURE_BINDING_2D, x));
+
         gl.BindTexture(gl::TEXTURE_2D, texture_info.gl_texture);
 
+        let unpack_state = UnpackState::reset(gl);
         gl.TexSubImage2D(
             gl::TEXTURE_2D,
             0,
@@ -433,17 +507,88 @@ impl TextureHeap {
             0,
             width as GLint,
             height as GLint,
-            gl::RGBA,
+            self.upload_format,
             gl::UNSIGNED_BYTE,
             data.as_ptr() as *const c_void,
         );
+        unpack_state.restore(gl);
 
         gl.BindTexture(gl::TEXTURE_2D, bound_texture as _);
+        gl.ActiveTexture(last_active_texture as _);
 
         Ok(())
     }
 }
 
+// Convert an imgui clip rectangle, relative to the top left corner of the
+// display, to a scissor box, relative to the bottom left corner of the
+// framebuffer as OpenGL expects. The rectangle is clamped to the framebuffer
+// first: the vertical flip is computed against the framebuffer height, so
+// coordinates outside of it, once truncated to integers, would land one pixel
+// off. Return `None` if the rectangle is empty.
+fn scissor_box(draw_data: &DrawData, clip_rect: [f32; 4]) -> Option<[GLint; 4]> {
+    let [clip_offset_x, clip_offset_y] = draw_data.display_pos;
+    let [clip_scale_w, clip_scale_h] = draw_data.framebuffer_scale;
+    // Same as the viewport size.
+    let fb_width = (clip_scale_w * draw_data.display_size[0]) as GLint;
+    let fb_height = (clip_scale_h * draw_data.display_size[1]) as GLint;
+
+    let [cx, cy, cz, cw] = clip_rect;
+    let clip_min_x = (((cx - clip_offset_x) * clip_scale_w) as GLint).clamp(0, fb_width);
+    let clip_min_y = (((cy - clip_offset_y) * clip_scale_h) as GLint).clamp(0, fb_height);
+    let clip_max_x = (((cz - clip_offset_x) * clip_scale_w) as GLint).clamp(0, fb_width);
+    let clip_max_y = (((cw - clip_offset_y) * clip_scale_h) as GLint).clamp(0, fb_height);
+
+    if clip_max_x <= clip_min_x || clip_max_y <= clip_min_y {
+        return None;
+    }
+
+    Some([clip_min_x, fb_height - clip_max_y, clip_max_x - clip_min_x, clip_max_y - clip_min_y])
+}
+
+// Pixel unpacking state set by the game, which would otherwise apply to the
+// texture uploads: e.g. a bound pixel unpack buffer makes OpenGL read from it
+// instead of from the given pointer, and a row length or skip garbles the
+// image.
+struct UnpackState {
+    pixel_unpack_buffer: i32,
+    row_length: i32,
+    skip_pixels: i32,
+    skip_rows: i32,
+    alignment: i32,
+}
+
+impl UnpackState {
+    // Back up the unpacking state and reset it to tightly packed rows.
+    unsafe fn reset(gl: &gl::Gl) -> Self {
+        let state = Self {
+            pixel_unpack_buffer: util::out_param(|x| {
+                gl.GetIntegerv(gl::PIXEL_UNPACK_BUFFER_BINDING, x)
+            }),
+            row_length: util::out_param(|x| gl.GetIntegerv(gl::UNPACK_ROW_LENGTH, x)),
+            skip_pixels: util::out_param(|x| gl.GetIntegerv(gl::UNPACK_SKIP_PIXELS, x)),
+            skip_rows: util::out_param(|x| gl.GetIntegerv(gl::UNPACK_SKIP_ROWS, x)),
+            alignment: util::out_param(|x| gl.GetIntegerv(gl::UNPACK_ALIGNMENT, x)),
+        };
+
+        gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+        gl.PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        gl.PixelStorei(gl::UNPACK_SKIP_PIXELS, 0);
+        gl.PixelStorei(gl::UNPACK_SKIP_ROWS, 0);
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+
+        state
+    }
+
+    unsafe fn restore(self, gl: &gl::Gl) {
+        gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, self.pixel_unpack_buffer as _);
+        gl.PixelStorei(gl::UNPACK_ROW_LENGTH, self.row_length);
+        gl.PixelStorei(gl::UNPACK_SKIP_PIXELS, self.skip_pixels);
+        gl.PixelStorei(gl::UNPACK_SKIP_ROWS, self.skip_rows);
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, self.alignment);
+    }
+}
+
 struct StateBackup {
     last_active_texture: i32,
     last_program: i32,
@@ -464,7 +609,10 @@ struct StateBackup {
     last_enable_blend: bool,
     last_enable_cull_face: bool,
     last_enable_depth_test: bool,
+    last_enable_stencil_test: bool,
     last_enable_scissor_test: bool,
+    last_enable_primitive_restart: bool,
+    last_enable_framebuffer_srgb: bool,
 }
 
 impl StateBackup {
@@ -499,7 +647,10 @@ impl StateBackup {
         let last_enable_blend = gl.IsEnabled(gl::BLEND) == gl::TRUE;
         let last_enable_cull_face = gl.IsEnabled(gl::CULL_FACE) == gl::TRUE;
         let last_enable_depth_test = gl.IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
+        let last_enable_stencil_test = gl.IsEnabled(gl::STENCIL_TEST) == gl::TRUE;
         let last_enable_scissor_test = gl.IsEnabled(gl::SCISSOR_TEST) == gl::TRUE;
+        let last_enable_primitive_restart = gl.IsEnabled(gl::PRIMITIVE_RESTART) == gl::TRUE;
+        let last_enable_framebuffer_srgb = gl.IsEnabled(gl::FRAMEBUFFER_SRGB) == gl::TRUE;
 
         StateBackup {
             last_active_texture,
@@ -521,7 +672,10 @@ impl StateBackup {
             last_enable_blend,
             last_enable_cull_face,
             last_enable_depth_test,
+            last_enable_stencil_test,
             last_enable_scissor_test,
+            last_enable_primitive_restart,
+            last_enable_framebuffer_srgb,
         }
     }
 
@@ -546,7 +700,10 @@ impl StateBackup {
             last_enable_blend,
             last_enable_cull_face,
             last_enable_depth_test,
+            last_enable_stencil_test,
             last_enable_scissor_test,
+            last_enable_primitive_restart,
+            last_enable_framebuffer_srgb,
         } = self;
 
         gl.UseProgram(last_program as _);
@@ -580,11 +737,26 @@ impl StateBackup {
         } else {
             gl.Disable(gl::DEPTH_TEST)
         };
+        if last_enable_stencil_test {
+            gl.Enable(gl::STENCIL_TEST)
+        } else {
+            gl.Disable(gl::STENCIL_TEST)
+        };
         if last_enable_scissor_test {
             gl.Enable(gl::SCISSOR_TEST)
         } else {
             gl.Disable(gl::SCISSOR_TEST)
         };
+        if last_enable_primitive_restart {
+            gl.Enable(gl::PRIMITIVE_RESTART)
+        } else {
+            gl.Disable(gl::PRIMITIVE_RESTART)
+        };
+        if last_enable_framebuffer_srgb {
+            gl.Enable(gl::FRAMEBUFFER_SRGB)
+        } else {
+            gl.Disable(gl::FRAMEBUFFER_SRGB)
+        };
         gl.PolygonMode(gl::FRONT_AND_BACK, last_polygon_mode[0] as _);
         gl.Viewport(
             last_viewport[0] as _,
@@ -600,3 +772,59 @@ impl StateBackup {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use imgui::Condition;
+
+    use super::*;
+
+    #[test]
+    fn test_scissor_box() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+        let mut ctx = crate::renderer::test_context();
+
+        // A window hanging off the top of the screen, with a clipped child.
+        let ui = ctx.frame();
+        ui.window("Top")
+            .position([100., -20.], Condition::Always)
+            .size([300., 200.], Condition::Always)
+            .build(|| {
+                ui.child_window("Clipped").size([200., 60.]).build(|| {
+                    for i in 0..20 {
+                        ui.text(format!("Line {i}"));
+                    }
+                });
+            });
+        let draw_data = ctx.render();
+
+        let mut scissor_boxes = Vec::new();
+        for draw_list in draw_data.draw_lists() {
+            for cmd in draw_list.commands() {
+                let DrawCmd::Elements { cmd_params, .. } = cmd else {
+                    continue;
+                };
+                let Some(scissor_box) = scissor_box(draw_data, cmd_params.clip_rect) else {
+                    continue;
+                };
+
+                // Flipping the scissor box back yields the clip rectangle,
+                // clamped to the screen.
+                let [x, y, width, height] = scissor_box;
+                let [cx, cy, cz, cw] = cmd_params.clip_rect;
+                assert!(x >= 0 && y >= 0 && x + width <= 800 && y + height <= 600);
+                assert_eq!(x, cx.max(0.) as GLint);
+                assert_eq!(600 - (y + height), cy.max(0.) as GLint);
+                assert_eq!(x + width, cz.min(800.) as GLint);
+                assert_eq!(600 - y, cw.min(600.) as GLint);
+
+                scissor_boxes.push(scissor_box);
+            }
+        }
+
+        // The window is clipped at the top edge of the framebuffer, and the
+        // child within it.
+        assert!(scissor_boxes.iter().any(|&[_, y, _, height]| y + height == 600));
+        assert!(scissor_boxes.iter().any(|&[_, y, _, height]| y + height < 600 && height <= 60));
+    }
+}
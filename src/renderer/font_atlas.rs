@@ -0,0 +1,225 @@
+//! Replacement of the whole font atlas at runtime, requested via
+//! [`RenderContext::replace_font_atlas`](crate::RenderContext::replace_font_atlas).
+//!
+//! The new atlas is built and rasterized on a thread of its own, then swapped
+//! with the one of the context between two frames, so that no frame is drawn
+//! with a partially built atlas.
+//!
+//! Building the atlas allocates through imgui, which counts the allocations in
+//! the current context. The current context is global, not per thread: it is
+//! suspended while the atlas is built, and the pipeline draws its last frame
+//! again meanwhile.
+
+use std::thread::{self, JoinHandle};
+use std::{mem, ptr};
+
+use imgui::internal::RawCast;
+use imgui::{sys, Context, DrawData, FontAtlas};
+use parking_lot::Mutex;
+use tracing::{debug, error};
+
+/// Callback adding the fonts of a new atlas.
+pub(crate) type FontAtlasBuilder = Box<dyn FnOnce(&mut FontAtlas) + Send>;
+
+// The callback of the last replacement requested, until it is built.
+static REQUESTED: Mutex<Option<FontAtlasBuilder>> = Mutex::new(None);
+
+/// Atlas built outside of any imgui context, destroyed when dropped.
+pub(crate) struct BuiltFontAtlas {
+    raw: *mut sys::ImFontAtlas,
+    // Factor the font sizes were scaled by, for the DPI of the window.
+    scale: f32,
+}
+
+// The atlas isn't shared: it moves from the thread building it to the render
+// thread.
+unsafe impl Send for BuiltFontAtlas {}
+
+impl Drop for BuiltFontAtlas {
+    fn drop(&mut self) {
+        unsafe { sys::ImFontAtlas_destroy(self.raw) };
+    }
+}
+
+impl BuiltFontAtlas {
+    fn build(build: FontAtlasBuilder, scale: f32) -> Self {
+        let atlas = Self { raw: unsafe { sys::ImFontAtlas_ImFontAtlas() }, scale };
+        let fonts = unsafe { FontAtlas::from_raw_mut(&mut *atlas.raw) };
+        build(fonts);
+        unsafe { scale_font_sizes(fonts, scale) };
+        fonts.build_rgba32_texture();
+        atlas
+    }
+
+    /// Swap the atlas of `ctx` with this one, scaling the font sizes by
+    /// `scale` first if the atlas was built at another one. The previous atlas
+    /// is destroyed, and the new one must be uploaded again.
+    ///
+    /// Must not be called while a frame is being built.
+    pub(crate) fn swap_into(mut self, ctx: &mut Context, scale: f32) {
+        if self.scale != scale {
+            let fonts = unsafe { FontAtlas::from_raw_mut(&mut *self.raw) };
+            unsafe { scale_font_sizes(fonts, scale / self.scale) };
+            fonts.clear_tex_data();
+            self.scale = scale;
+        }
+
+        let current: *mut sys::ImFontAtlas = unsafe { ctx.fonts().raw_mut() };
+        unsafe {
+            mem::swap(&mut *current, &mut *self.raw);
+            // The fonts point back to the atlas containing them, which was
+            // just moved.
+            set_container_atlas(current);
+            set_container_atlas(self.raw);
+            // The default font, if any, belonged to the previous atlas: the
+            // first font of the new one is used instead.
+            ctx.io_mut().raw_mut().FontDefault = ptr::null_mut();
+        }
+    }
+}
+
+/// Request the atlas to be replaced by one built by `build`. A previous
+/// request that wasn't built yet is dropped.
+pub(crate) fn request_replacement(build: FontAtlasBuilder) {
+    *REQUESTED.lock() = Some(build);
+}
+
+/// Forget the replacement requested, if any, when the hooks are cleaned up.
+pub(crate) fn reset() {
+    REQUESTED.lock().take();
+}
+
+/// Atlas being built on the thread of its own, while the context is
+/// suspended.
+pub(crate) struct FontAtlasBuild {
+    handle: Option<JoinHandle<BuiltFontAtlas>>,
+    // The context suspended, made current again once the atlas is built.
+    ctx: *mut sys::ImGuiContext,
+    // The last frame built in the context, if any, drawn again meanwhile.
+    draw_data: *mut sys::ImDrawData,
+}
+
+// The context and the last frame are only accessed by the thread owning the
+// pipeline.
+unsafe impl Send for FontAtlasBuild {}
+
+impl Drop for FontAtlasBuild {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+impl FontAtlasBuild {
+    /// Start building the atlas requested last, if any, with font sizes scaled
+    /// by `scale`, and suspend the current context until it is built.
+    ///
+    /// Must not be called while a frame is being built.
+    pub(crate) fn start_requested(scale: f32) -> Option<Self> {
+        let build = REQUESTED.lock().take()?;
+
+        debug!("Building replacement font atlas");
+        let ctx = unsafe { sys::igGetCurrentContext() };
+        let draw_data = unsafe { sys::igGetDrawData() };
+        let draw_data = if !draw_data.is_null() && unsafe { (*draw_data).Valid } {
+            draw_data
+        } else {
+            ptr::null_mut()
+        };
+
+        unsafe { sys::igSetCurrentContext(ptr::null_mut()) };
+        let spawned = thread::Builder::new()
+            .name(String::from("hudhook-font-atlas"))
+            .spawn(move || BuiltFontAtlas::build(build, scale));
+        match spawned {
+            Ok(handle) => Some(Self { handle: Some(handle), ctx, draw_data }),
+            Err(e) => {
+                unsafe { sys::igSetCurrentContext(ctx) };
+                error!("Could not spawn the font atlas thread: {e:?}");
+                None
+            },
+        }
+    }
+
+    /// Whether the atlas is built, and [`FontAtlasBuild::finish`] won't block.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.handle.as_ref().map_or(true, JoinHandle::is_finished)
+    }
+
+    /// The last frame built before the context was suspended, if any.
+    pub(crate) fn draw_data(&self) -> Option<&DrawData> {
+        unsafe { self.draw_data.as_ref().map(|draw_data| DrawData::from_raw(draw_data)) }
+    }
+
+    /// Wait for the atlas to be built, and make the suspended context current
+    /// again. `None` if building the atlas panicked.
+    pub(crate) fn finish(mut self) -> Option<BuiltFontAtlas> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Option<BuiltFontAtlas> {
+        let handle = self.handle.take()?;
+        let built = handle.join();
+        unsafe { sys::igSetCurrentContext(self.ctx) };
+        match built {
+            Ok(atlas) => Some(atlas),
+            Err(_) => {
+                error!("Could not build the replacement font atlas");
+                None
+            },
+        }
+    }
+}
+
+unsafe fn scale_font_sizes(fonts: &mut FontAtlas, scale: f32) {
+    if scale == 1. {
+        return;
+    }
+    let config_data = &fonts.raw_mut().ConfigData;
+    for i in 0..config_data.Size as usize {
+        (*config_data.Data.add(i)).SizePixels *= scale;
+    }
+}
+
+unsafe fn set_container_atlas(atlas: *mut sys::ImFontAtlas) {
+    let fonts = &(*atlas).Fonts;
+    for i in 0..fonts.Size as usize {
+        (**fonts.Data.add(i)).ContainerAtlas = atlas;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use imgui::{FontConfig, FontSource};
+
+    use super::*;
+
+    #[test]
+    fn test_swap_font_atlas() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+        let mut ctx = crate::renderer::test_context();
+
+        request_replacement(Box::new(|fonts| {
+            for size_pixels in [13., 20.] {
+                fonts.add_font(&[FontSource::DefaultFontData {
+                    config: Some(FontConfig { size_pixels, ..Default::default() }),
+                }]);
+            }
+        }));
+        let build = FontAtlasBuild::start_requested(1.).unwrap();
+        assert!(unsafe { sys::igGetCurrentContext() }.is_null());
+        let atlas = build.finish().unwrap();
+        assert!(!unsafe { sys::igGetCurrentContext() }.is_null());
+        atlas.swap_into(&mut ctx, 2.);
+
+        let fonts = ctx.fonts();
+        let raw = unsafe { fonts.raw_mut() } as *mut sys::ImFontAtlas;
+        let fonts = unsafe { &(*raw).Fonts };
+        assert_eq!(fonts.Size, 2);
+        assert!(unsafe { (**fonts.Data).ContainerAtlas } == raw);
+        assert_eq!(unsafe { (*(*raw).ConfigData.Data.add(1)).SizePixels }, 40.);
+
+        ctx.fonts().build_rgba32_texture();
+        ctx.frame().text("Hello");
+        ctx.render();
+    }
+}
@@ -0,0 +1,102 @@
+//! Frame rate readout shown via [`set_fps_overlay`](crate::set_fps_overlay),
+//! drawn by [`hudhook`](crate) on top of the render loop UI.
+
+use std::time::{Duration, Instant};
+
+use imgui::Ui;
+use parking_lot::Mutex;
+
+use crate::{options, timings, PresentHookTimings, ScreenCorner};
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const PADDING: [f32; 2] = [6.0, 4.0];
+const MARGIN: f32 = 10.0;
+
+// The readout is only refreshed once per update interval, so that it can be
+// read, from the presents counted over that interval.
+struct State {
+    since: Instant,
+    present_count: u64,
+    text: Option<String>,
+}
+
+// Text of the readout, from the presents intercepted over `elapsed` and the
+// time spent in the present hook.
+fn readout(presents: u64, elapsed: Duration, timings: Option<PresentHookTimings>) -> String {
+    let fps = presents as f64 / elapsed.as_secs_f64();
+    let frame_time = if presents > 0 { 1000. / fps } else { 0. };
+    let mut text = format!("{fps:.0} FPS ({frame_time:.2} ms)");
+    if let Some(timings) = timings {
+        let cost = timings.p50.as_secs_f64() * 1000.;
+        text.push_str(&format!("\nOverlay: {cost:.2} ms"));
+    }
+    text
+}
+
+/// Draw the frame rate of the game and the cost of the overlay in the
+/// configured corner, on the foreground draw list.
+pub(crate) fn render(ui: &Ui) {
+    let (corner, update_interval) = {
+        let options = options::get();
+        (options.fps_overlay_corner, options.fps_overlay_interval)
+    };
+
+    let now = Instant::now();
+    let present_count = timings::present_count();
+
+    let mut state = STATE.lock();
+    let state = state.get_or_insert_with(|| State { since: now, present_count, text: None });
+
+    let elapsed = now.duration_since(state.since);
+    if elapsed >= update_interval && !elapsed.is_zero() {
+        let presents = present_count.saturating_sub(state.present_count);
+        state.text = Some(readout(presents, elapsed, timings::aggregate()));
+        state.since = now;
+        state.present_count = present_count;
+    }
+
+    let Some(text) = &state.text else {
+        return;
+    };
+
+    let [display_width, display_height] = ui.io().display_size;
+    let [text_width, text_height] = ui.calc_text_size(text);
+    let [width, height] = [text_width + PADDING[0] * 2., text_height + PADDING[1] * 2.];
+
+    let x = match corner {
+        ScreenCorner::TopLeft | ScreenCorner::BottomLeft => MARGIN,
+        ScreenCorner::TopRight | ScreenCorner::BottomRight => display_width - MARGIN - width,
+    };
+    let y = match corner {
+        ScreenCorner::TopLeft | ScreenCorner::TopRight => MARGIN,
+        ScreenCorner::BottomLeft | ScreenCorner::BottomRight => display_height - MARGIN - height,
+    };
+
+    let draw_list = ui.get_foreground_draw_list();
+    draw_list.add_rect([x, y], [x + width, y + height], BACKGROUND).filled(true).build();
+    draw_list.add_text([x + PADDING[0], y + PADDING[1]], TEXT, text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readout() {
+        let timings = PresentHookTimings {
+            samples: 120,
+            p50: Duration::from_micros(250),
+            p95: Duration::from_micros(400),
+            p99: Duration::from_micros(900),
+        };
+
+        assert_eq!(
+            readout(120, Duration::from_secs(2), Some(timings)),
+            "60 FPS (16.67 ms)\nOverlay: 0.25 ms"
+        );
+        assert_eq!(readout(0, Duration::from_millis(500), None), "0 FPS (0.00 ms)");
+    }
+}
@@ -0,0 +1,65 @@
+//! Recovery from the errors of the render loop that imgui detects at the end
+//! of a frame, set up via
+//! [`HudhookBuilder::with_imgui_error_recovery`](crate::HudhookBuilder::with_imgui_error_recovery).
+
+use std::ffi::{c_char, c_void, CStr};
+use std::mem;
+
+use imgui::sys;
+use tracing::error;
+
+// imgui passes the arguments of the message after its format string. They
+// can't be read without variadic functions, which are unstable: only the
+// format string is reported, e.g. `Recovered from missing End() for '%s'`.
+type LogCallback = unsafe extern "C" fn(user_data: *mut c_void, fmt: *const c_char);
+
+/// Recover from the unbalanced calls in the frame being built, e.g. a missing
+/// `End` or `PopID`, which imgui would otherwise assert on when rendering it.
+/// Each error is logged, and passed to `handler` if any.
+pub(crate) fn recover(handler: Option<fn(&str)>) {
+    unsafe {
+        // Arguments past the format string are ignored by the callee, which
+        // is sound with the C calling convention.
+        let log_callback = mem::transmute::<LogCallback, sys::ImGuiErrorLogCallback>(log_error);
+        sys::igErrorCheckEndFrameRecover(log_callback, &handler as *const _ as *mut c_void);
+    }
+}
+
+unsafe extern "C" fn log_error(user_data: *mut c_void, fmt: *const c_char) {
+    let message = CStr::from_ptr(fmt).to_string_lossy();
+    error!("imgui error: {message}");
+
+    if let Some(handler) = *(user_data as *const Option<fn(&str)>) {
+        handler(&message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    fn record(message: &str) {
+        MESSAGES.lock().push(message.to_owned());
+    }
+
+    #[test]
+    fn test_recover() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+        let mut ctx = crate::renderer::test_context();
+
+        let ui = ctx.frame();
+        mem::forget(ui.window("unbalanced").begin());
+        mem::forget(ui.push_id("id"));
+        recover(Some(record));
+        ctx.render();
+
+        let messages = MESSAGES.lock();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains("PopID()"));
+        assert!(messages[1].contains("End()"));
+    }
+}
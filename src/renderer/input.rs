@@ -4,17 +4,22 @@ use std::ffi::c_void;
 use std::mem::size_of;
 
 use imgui::{Io, Key, MouseButton};
+use windows::Win32::Devices::HumanInterfaceDevice::{
+    HID_USAGE_GENERIC_MOUSE, HID_USAGE_PAGE_GENERIC,
+};
 use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
 use windows::Win32::UI::Input::KeyboardAndMouse::*;
 use windows::Win32::UI::Input::{
-    GetRawInputData, HRAWINPUT, MOUSE_MOVE_ABSOLUTE, RAWINPUT, RAWINPUTHEADER, RAWKEYBOARD,
-    RAWMOUSE, RID_DEVICE_INFO_TYPE, RID_INPUT, RIM_TYPEKEYBOARD, RIM_TYPEMOUSE,
+    GetRawInputData, GetRegisteredRawInputDevices, RegisterRawInputDevices, HRAWINPUT,
+    MOUSE_MOVE_ABSOLUTE, RAWINPUT, RAWINPUTDEVICE, RAWINPUTDEVICE_FLAGS, RAWINPUTHEADER,
+    RAWKEYBOARD, RAWMOUSE, RIDEV_REMOVE, RID_DEVICE_INFO_TYPE, RID_HEADER, RID_INPUT,
+    RIM_TYPEKEYBOARD, RIM_TYPEMOUSE,
 };
 use windows::Win32::UI::WindowsAndMessaging::*;
 
 use super::keys::vk_to_imgui;
 use crate::renderer::{Pipeline, RenderEngine};
-use crate::{OnWndProc, OnWndProcState};
+use crate::{options, util, MouseSource, OnWndProc, OnWndProcState};
 
 pub type WndProcType =
     unsafe extern "system" fn(hwnd: HWND, umsg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT;
@@ -52,7 +57,7 @@ pub fn lowordi(l: u32) -> i16 {
 // Given the RAWINPUT structure, check each possible mouse flag status and
 // update the Io object accordingly. Both the key_down indices associated to the
 // mouse click (VK_...) and the values in mouse_down are updated.
-fn handle_raw_mouse_input(io: &mut Io, raw_mouse: &RAWMOUSE) {
+fn handle_raw_mouse_input(io: &mut Io, raw_mouse: &RAWMOUSE, mouse_pos: bool) {
     let button_data = unsafe { raw_mouse.Anonymous.Anonymous };
     let button_flags = button_data.usButtonFlags as u32;
 
@@ -92,6 +97,10 @@ fn handle_raw_mouse_input(io: &mut Io, raw_mouse: &RAWMOUSE) {
 
     io.add_mouse_wheel_event([wheel_delta_x, wheel_delta_y]);
 
+    if !mouse_pos {
+        return;
+    }
+
     let mouse_flags = raw_mouse.usFlags;
     let (last_x, last_y) = (raw_mouse.lLastX as f32, raw_mouse.lLastY as f32);
 
@@ -143,7 +152,7 @@ fn handle_raw_keyboard_input(io: &mut Io, raw_keyboard: &RAWKEYBOARD) {
     // If the virtual key is in the allowed array range, set the appropriate status
     // of key_down for that virtual key.
     if virtual_key < 0xFF {
-        if let Some(key) = vk_to_imgui(VIRTUAL_KEY(virtual_key as _)) {
+        if let Some(key) = map_key(VIRTUAL_KEY(virtual_key as _)) {
             if is_key_down {
                 io.add_key_event(key, true);
             }
@@ -154,8 +163,8 @@ fn handle_raw_keyboard_input(io: &mut Io, raw_keyboard: &RAWKEYBOARD) {
     }
 }
 
-// Handle WM_INPUT events.
-fn handle_raw_input(io: &mut Io, WPARAM(wparam): WPARAM, LPARAM(lparam): LPARAM) {
+// Read the RAWINPUT structure associated to a WM_INPUT message.
+fn read_raw_input(LPARAM(lparam): LPARAM) -> Option<RAWINPUT> {
     let mut raw_data = RAWINPUT { ..Default::default() };
     let mut raw_data_size = size_of::<RAWINPUT>() as u32;
     let raw_data_header_size = size_of::<RAWINPUTHEADER>() as u32;
@@ -171,10 +180,19 @@ fn handle_raw_input(io: &mut Io, WPARAM(wparam): WPARAM, LPARAM(lparam): LPARAM)
         )
     };
 
-    // If GetRawInputData errors out, return false.
+    // If GetRawInputData errors out, return nothing.
     if r == u32::MAX {
-        return;
+        None
+    } else {
+        Some(raw_data)
     }
+}
+
+// Handle WM_INPUT events.
+fn handle_raw_input(io: &mut Io, WPARAM(wparam): WPARAM, lparam: LPARAM, mouse_pos: bool) {
+    let Some(raw_data) = read_raw_input(lparam) else {
+        return;
+    };
 
     // Ignore messages when window is not focused.
     if (wparam as u32 & 0xFFu32) != RIM_INPUT {
@@ -184,7 +202,7 @@ fn handle_raw_input(io: &mut Io, WPARAM(wparam): WPARAM, LPARAM(lparam): LPARAM)
     // Dispatch to the appropriate raw input processing method.
     match RID_DEVICE_INFO_TYPE(raw_data.header.dwType) {
         RIM_TYPEMOUSE => {
-            handle_raw_mouse_input(io, unsafe { &raw_data.data.mouse });
+            handle_raw_mouse_input(io, unsafe { &raw_data.data.mouse }, mouse_pos);
         },
         RIM_TYPEKEYBOARD => {
             handle_raw_keyboard_input(io, unsafe { &raw_data.data.keyboard });
@@ -193,10 +211,154 @@ fn handle_raw_input(io: &mut Io, WPARAM(wparam): WPARAM, LPARAM(lparam): LPARAM)
     }
 }
 
+// Check whether a message carries mouse input, either regular or raw.
+//
+// This must be called from the window procedure, while the raw input handle
+// is still valid.
+pub fn is_mouse_message(umsg: u32, LPARAM(lparam): LPARAM) -> bool {
+    match umsg {
+        WM_MOUSEFIRST..=WM_MOUSELAST => true,
+        WM_INPUT => {
+            let mut header = RAWINPUTHEADER::default();
+            let mut header_size = size_of::<RAWINPUTHEADER>() as u32;
+            let r = unsafe {
+                GetRawInputData(
+                    HRAWINPUT(lparam),
+                    RID_HEADER,
+                    Some(&mut header as *mut _ as *mut c_void),
+                    &mut header_size,
+                    size_of::<RAWINPUTHEADER>() as u32,
+                )
+            };
+
+            r != u32::MAX && RID_DEVICE_INFO_TYPE(header.dwType) == RIM_TYPEMOUSE
+        },
+        _ => false,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Virtual cursor
+////////////////////////////////////////////////////////////////////////////////
+
+// Register the window for raw mouse input, unless the application already
+// registered for it, returning whether it was registered.
+//
+// Only one registration per device class is allowed per application, and
+// registering again would replace the application's own settings (e.g.
+// `RIDEV_NOLEGACY`). If the application already registered the mouse, the
+// WM_INPUT messages are already flowing and there is nothing to do.
+pub fn register_raw_mouse(hwnd: HWND) -> windows::core::Result<bool> {
+    if registered_raw_mouse()?.is_some() {
+        return Ok(false);
+    }
+
+    unsafe {
+        RegisterRawInputDevices(
+            &[RAWINPUTDEVICE {
+                usUsagePage: HID_USAGE_PAGE_GENERIC,
+                usUsage: HID_USAGE_GENERIC_MOUSE,
+                dwFlags: RAWINPUTDEVICE_FLAGS(0),
+                hwndTarget: hwnd,
+            }],
+            size_of::<RAWINPUTDEVICE>() as u32,
+        )
+    }?;
+
+    Ok(true)
+}
+
+// Undo `register_raw_mouse` for the window.
+//
+// The registration is left alone if it no longer targets the window, e.g. as
+// the application registered the mouse itself in the meantime.
+pub fn unregister_raw_mouse(hwnd: HWND) -> windows::core::Result<()> {
+    if registered_raw_mouse()?.map_or(true, |device| device.hwndTarget != hwnd) {
+        return Ok(());
+    }
+
+    unsafe {
+        RegisterRawInputDevices(
+            &[RAWINPUTDEVICE {
+                usUsagePage: HID_USAGE_PAGE_GENERIC,
+                usUsage: HID_USAGE_GENERIC_MOUSE,
+                dwFlags: RIDEV_REMOVE,
+                hwndTarget: HWND(0),
+            }],
+            size_of::<RAWINPUTDEVICE>() as u32,
+        )
+    }
+}
+
+// Raw input registration of the application for the mouse, if any.
+fn registered_raw_mouse() -> windows::core::Result<Option<RAWINPUTDEVICE>> {
+    let mut num_devices = 0u32;
+    let device_size = size_of::<RAWINPUTDEVICE>() as u32;
+    unsafe { GetRegisteredRawInputDevices(None, &mut num_devices, device_size) };
+
+    let mut devices = vec![RAWINPUTDEVICE::default(); num_devices as usize];
+    if num_devices > 0
+        && unsafe {
+            GetRegisteredRawInputDevices(Some(devices.as_mut_ptr()), &mut num_devices, device_size)
+        } == u32::MAX
+    {
+        return Err(windows::core::Error::from_win32());
+    }
+
+    Ok(devices.into_iter().find(|device| {
+        device.usUsagePage == HID_USAGE_PAGE_GENERIC && device.usUsage == HID_USAGE_GENERIC_MOUSE
+    }))
+}
+
+// Accumulate relative raw mouse motion into a virtual cursor position,
+// clamped to the client area of the window.
+//
+// This must be called from the window procedure, while the raw input handle
+// is still valid.
+pub fn update_virtual_cursor(cursor: &mut [f32; 2], hwnd: HWND, lparam: LPARAM) {
+    let Some(raw_data) = read_raw_input(lparam) else {
+        return;
+    };
+
+    if RID_DEVICE_INFO_TYPE(raw_data.header.dwType) != RIM_TYPEMOUSE {
+        return;
+    }
+
+    let raw_mouse = unsafe { &raw_data.data.mouse };
+    if (raw_mouse.usFlags.0 & MOUSE_MOVE_ABSOLUTE.0) != 0 {
+        return;
+    }
+
+    let (width, height) = util::win_size(hwnd);
+    cursor[0] = (cursor[0] + raw_mouse.lLastX as f32).clamp(0.0, width.max(0) as f32);
+    cursor[1] = (cursor[1] + raw_mouse.lLastY as f32).clamp(0.0, height.max(0) as f32);
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Regular input
 ////////////////////////////////////////////////////////////////////////////////
 
+// Translate a virtual key to an imgui key, honoring the key map configured
+// through the builder.
+fn map_key(virtual_key: VIRTUAL_KEY) -> Option<Key> {
+    match options::get().key_map {
+        Some(key_map) => key_map(virtual_key),
+        None => vk_to_imgui(virtual_key),
+    }
+}
+
+/// Whether `umsg` is a key message for one of the `keys`, which must always
+/// reach the application. Both the generic (e.g. `VK_SHIFT`) and the
+/// left/right specific (e.g. `VK_LSHIFT`) virtual keys are matched.
+pub fn is_passthrough_key(umsg: u32, wparam: WPARAM, lparam: LPARAM, keys: &[VIRTUAL_KEY]) -> bool {
+    if keys.is_empty() || !matches!(umsg, WM_KEYDOWN | WM_SYSKEYDOWN | WM_KEYUP | WM_SYSKEYUP) {
+        return false;
+    }
+
+    let virtual_key = VIRTUAL_KEY(wparam.0 as _);
+    keys.contains(&virtual_key) || keys.contains(&map_vkey(virtual_key.0, lparam.0 as _))
+}
+
 fn map_vkey(wparam: u16, lparam: usize) -> VIRTUAL_KEY {
     match VIRTUAL_KEY(wparam) {
         VK_SHIFT => unsafe {
@@ -232,7 +394,7 @@ fn handle_input(io: &mut Io, state: u32, WPARAM(wparam): WPARAM, LPARAM(lparam):
     let is_key_down = (state == WM_KEYDOWN) || (state == WM_SYSKEYDOWN);
     let scancode = map_vkey(wparam as _, lparam as _);
 
-    if let Some(key) = vk_to_imgui(scancode) {
+    if let Some(key) = map_key(scancode) {
         io.add_key_event(key, is_key_down);
     }
 
@@ -286,6 +448,10 @@ pub fn imgui_wnd_proc_impl<T: RenderEngine>(
         OnWndProcState::Pre,
     );
 
+    let virtual_cursor_active = pipeline.virtual_cursor_active();
+    let mouse_source = pipeline.mouse_source();
+    let passthrough_key = pipeline.is_passthrough_key(umsg, WPARAM(wparam), LPARAM(lparam));
+    let client_mapping = pipeline.client_mapping();
     let io = pipeline.context().io_mut();
     if wnd_proc_decision == OnWndProc::Break {
         pipeline.render_loop().on_wnd_proc(
@@ -299,7 +465,13 @@ pub fn imgui_wnd_proc_impl<T: RenderEngine>(
     }
 
     match umsg {
-        WM_INPUT => handle_raw_input(io, WPARAM(wparam), LPARAM(lparam)),
+        WM_INPUT => handle_raw_input(
+            io,
+            WPARAM(wparam),
+            LPARAM(lparam),
+            mouse_source != MouseSource::WndProc,
+        ),
+        WM_KEYDOWN | WM_SYSKEYDOWN | WM_KEYUP | WM_SYSKEYUP if passthrough_key => {},
         state @ (WM_KEYDOWN | WM_SYSKEYDOWN | WM_KEYUP | WM_SYSKEYUP) if wparam < 256 => {
             handle_input(io, state, WPARAM(wparam), LPARAM(lparam))
         },
@@ -349,15 +521,22 @@ pub fn imgui_wnd_proc_impl<T: RenderEngine>(
             let wheel_delta = WHEEL_DELTA as f32;
             io.add_mouse_wheel_event([(wheel_delta_wparam as i16 as f32) / wheel_delta, 0.0]);
         },
+        WM_MOUSEMOVE if virtual_cursor_active || mouse_source == MouseSource::RawInput => {},
         WM_MOUSEMOVE => {
             let x = lowordi(lparam as u32) as f32;
             let y = hiwordi(lparam as u32) as f32;
-            io.add_mouse_pos_event([x, y]);
+            io.add_mouse_pos_event(client_mapping.map([x, y]));
         },
         WM_CHAR => io.add_input_character(char::from_u32(wparam as u32).unwrap()),
+        WM_SIZE if wparam == SIZE_MINIMIZED as usize => pipeline.set_minimized(true),
+        // Sent whenever the client area changes, including while the window
+        // is dragged, even if the game keeps drawing to the same swap chain.
         WM_SIZE => {
+            pipeline.set_minimized(false);
             pipeline.resize(loword(lparam as u32) as u32, hiword(lparam as u32) as u32);
         },
+        WM_ACTIVATEAPP => pipeline.set_focused(wparam != 0),
+        WM_ACTIVATE => pipeline.set_focused(loword(wparam as u32) as u32 != WA_INACTIVE),
         _ => {},
     };
 
@@ -0,0 +1,280 @@
+//! Window the overlay is mirrored to, set via
+//! [`HudhookBuilder::with_mirror_window`](crate::HudhookBuilder::with_mirror_window).
+//!
+//! The window is opened on a thread of its own, which pumps its messages. The
+//! hooks render the draw data of every frame a second time to its swap chain,
+//! which they create on the device of the game.
+
+use std::mem;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use parking_lot::Mutex;
+use tracing::{debug, error};
+use windows::core::{w, Error, Result, HRESULT, HSTRING, PCWSTR};
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_ALPHA_MODE_IGNORE, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_SAMPLE_DESC,
+};
+use windows::Win32::Graphics::Dxgi::{
+    IDXGIFactory2, IDXGISwapChain, IDXGISwapChain1, DXGI_SCALING_STRETCH, DXGI_SWAP_CHAIN_DESC1,
+    DXGI_SWAP_EFFECT_FLIP_DISCARD, DXGI_USAGE_RENDER_TARGET_OUTPUT,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, LoadCursorW, PostMessageW,
+    PostQuitMessage, RegisterClassExW, ShowWindow, TranslateMessage, UnregisterClassW, HTCAPTION,
+    IDC_ARROW, MSG, SW_SHOWNOACTIVATE, WM_CLOSE, WM_DESTROY, WM_NCHITTEST, WNDCLASSEXW,
+    WS_EX_APPWINDOW, WS_EX_NOACTIVATE, WS_POPUP,
+};
+
+use crate::{options, MirrorConfig};
+
+const CLASS_NAME: PCWSTR = w!("HUDHOOK_MIRROR");
+
+static MIRROR: Mutex<MirrorState> = Mutex::new(MirrorState::Closed);
+
+enum MirrorState {
+    Closed,
+    Open(MirrorWindow),
+    // Opening the window failed: don't try again at every frame.
+    Failed,
+}
+
+/// Borderless window the overlay is mirrored to, and its swap chain.
+pub(crate) struct MirrorWindow {
+    hwnd: HWND,
+    thread: Option<JoinHandle<()>>,
+    background: [f32; 4],
+    // Created once the size of the back buffer of the game is known, and
+    // recreated when it changes.
+    swap_chain: Option<MirrorSwapChain>,
+}
+
+// Swap chain of the mirror window, and the size of its back buffers.
+struct MirrorSwapChain(IDXGISwapChain1, [u32; 2]);
+
+// The swap chain is only used with the mirror lock held, and DXGI objects are
+// free-threaded otherwise.
+unsafe impl Send for MirrorSwapChain {}
+
+impl MirrorWindow {
+    // Open the window on a thread of its own, which pumps its messages until it
+    // is closed.
+    fn open(config: &MirrorConfig, back_buffer_size: [u32; 2]) -> Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let title = config.title.clone();
+        let position = config.position;
+        let size = config.size.unwrap_or(back_buffer_size);
+
+        let thread = thread::Builder::new()
+            .name(String::from("hudhook-mirror"))
+            .spawn(move || unsafe {
+                let hwnd = create_window(&title, position, size);
+                tx.send(hwnd.as_ref().map(|hwnd| hwnd.0).map_err(Error::code)).ok();
+                if hwnd.is_ok() {
+                    pump_messages();
+                }
+            })
+            .map_err(|e| {
+                error!("Could not spawn the mirror window thread: {e:?}");
+                Error::from_hresult(HRESULT(-1))
+            })?;
+
+        match rx.recv() {
+            Ok(Ok(hwnd)) => Ok(Self {
+                hwnd: HWND(hwnd),
+                thread: Some(thread),
+                background: config.background,
+                swap_chain: None,
+            }),
+            Ok(Err(hresult)) => {
+                thread.join().ok();
+                unregister_class();
+                Err(Error::from_hresult(hresult))
+            },
+            Err(_) => {
+                error!("Mirror window thread exited before creating the window");
+                thread.join().ok();
+                Err(Error::from_hresult(HRESULT(-1)))
+            },
+        }
+    }
+
+    /// Color to clear the back buffers to before rendering the overlay.
+    pub(crate) fn background(&self) -> [f32; 4] {
+        self.background
+    }
+
+    /// Whether [`MirrorWindow::swap_chain`] would create a new swap chain for
+    /// back buffers of `size`, e.g. for the caller to release the references
+    /// it holds to the current back buffers.
+    pub(crate) fn needs_swap_chain(&self, size: [u32; 2]) -> bool {
+        !matches!(&self.swap_chain, Some(MirrorSwapChain(_, current)) if *current == size)
+    }
+
+    /// Swap chain of the window, with back buffers of `size`. If there is none
+    /// yet, or if it has another size, it is created with `create` from the
+    /// DXGI factory of the swap chain of the game.
+    pub(crate) fn swap_chain(
+        &mut self,
+        game_swap_chain: &IDXGISwapChain,
+        size: [u32; 2],
+        create: impl FnOnce(&IDXGIFactory2, HWND, &DXGI_SWAP_CHAIN_DESC1) -> Result<IDXGISwapChain1>,
+    ) -> Result<IDXGISwapChain1> {
+        if let Some(MirrorSwapChain(swap_chain, current)) = &self.swap_chain {
+            if *current == size {
+                return Ok(swap_chain.clone());
+            }
+        }
+
+        // A window can only have one swap chain at a time.
+        self.swap_chain = None;
+
+        let [width, height] = size;
+        debug!("Creating mirror swap chain of {width}x{height}");
+        let factory: IDXGIFactory2 = unsafe { game_swap_chain.GetParent() }?;
+        let desc = DXGI_SWAP_CHAIN_DESC1 {
+            Width: width,
+            Height: height,
+            Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+            SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+            BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+            BufferCount: 2,
+            Scaling: DXGI_SCALING_STRETCH,
+            SwapEffect: DXGI_SWAP_EFFECT_FLIP_DISCARD,
+            AlphaMode: DXGI_ALPHA_MODE_IGNORE,
+            ..Default::default()
+        };
+        let swap_chain = create(&factory, self.hwnd, &desc)?;
+        self.swap_chain = Some(MirrorSwapChain(swap_chain.clone(), size));
+
+        Ok(swap_chain)
+    }
+}
+
+impl Drop for MirrorWindow {
+    fn drop(&mut self) {
+        self.swap_chain = None;
+        if let Err(e) = unsafe { PostMessageW(self.hwnd, WM_CLOSE, WPARAM(0), LPARAM(0)) } {
+            error!("Could not close the mirror window: {e:?}");
+            return;
+        }
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        unregister_class();
+    }
+}
+
+/// Invoke `f` with the mirror window, if one is configured, opening it first
+/// with back buffers of `back_buffer_size` if it isn't open yet.
+pub(crate) fn with_mirror_window(
+    back_buffer_size: [u32; 2],
+    f: impl FnOnce(&mut MirrorWindow) -> Result<()>,
+) -> Result<()> {
+    let Some(config) = options::get().mirror_window.clone() else {
+        return Ok(());
+    };
+
+    let mut state = MIRROR.lock();
+    if let MirrorState::Closed = *state {
+        *state = match MirrorWindow::open(&config, back_buffer_size) {
+            Ok(window) => MirrorState::Open(window),
+            Err(e) => {
+                error!("Could not open the mirror window: {e:?}");
+                MirrorState::Failed
+            },
+        };
+    }
+
+    match &mut *state {
+        MirrorState::Open(window) => f(window),
+        MirrorState::Closed | MirrorState::Failed => Ok(()),
+    }
+}
+
+/// Close the mirror window, if it is open, e.g. when the hooks are removed.
+pub(crate) fn close() {
+    let state = mem::replace(&mut *MIRROR.lock(), MirrorState::Closed);
+    drop(state);
+}
+
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        // The window is borderless: drag it from anywhere.
+        WM_NCHITTEST => LRESULT(HTCAPTION as _),
+        WM_DESTROY => {
+            PostQuitMessage(0);
+            LRESULT(0)
+        },
+        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
+
+unsafe fn create_window(title: &str, [x, y]: [i32; 2], [width, height]: [u32; 2]) -> Result<HWND> {
+    let hinstance = GetModuleHandleW(None)?;
+    let wndclass = WNDCLASSEXW {
+        cbSize: mem::size_of::<WNDCLASSEXW>() as u32,
+        lpfnWndProc: Some(wnd_proc),
+        hInstance: hinstance.into(),
+        hCursor: LoadCursorW(None, IDC_ARROW)?,
+        lpszClassName: CLASS_NAME,
+        ..Default::default()
+    };
+    if RegisterClassExW(&wndclass) == 0 {
+        return Err(Error::from_win32());
+    }
+
+    // Not activated, so that it doesn't take the focus from the game.
+    let hwnd = CreateWindowExW(
+        WS_EX_APPWINDOW | WS_EX_NOACTIVATE,
+        wndclass.lpszClassName,
+        &HSTRING::from(title),
+        WS_POPUP,
+        x,
+        y,
+        width as i32,
+        height as i32,
+        None,
+        None,
+        wndclass.hInstance,
+        None,
+    );
+    if hwnd.0 == 0 {
+        return Err(Error::from_win32());
+    }
+    ShowWindow(hwnd, SW_SHOWNOACTIVATE);
+
+    Ok(hwnd)
+}
+
+// Unregister the window class once the window is destroyed, so that it can be
+// registered again when the window is reopened, e.g. after ejecting.
+fn unregister_class() {
+    unsafe {
+        let hinstance = match GetModuleHandleW(None) {
+            Ok(hinstance) => hinstance,
+            Err(e) => {
+                error!("GetModuleHandle: {e}");
+                return;
+            },
+        };
+        if let Err(e) = UnregisterClassW(CLASS_NAME, hinstance) {
+            error!("UnregisterClass: {e}");
+        }
+    }
+}
+
+unsafe fn pump_messages() {
+    let mut msg = MSG::default();
+    while GetMessageW(&mut msg, None, 0, 0).0 > 0 {
+        TranslateMessage(&msg);
+        DispatchMessageW(&msg);
+    }
+}
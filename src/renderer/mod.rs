@@ -1,27 +1,281 @@
 //! The [`hudhook`](crate) overlay rendering engine.
 mod backend;
+pub(crate) mod font_atlas;
+pub(crate) mod fps_overlay;
+mod imgui_errors;
 mod input;
 mod keys;
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) mod mirror;
 pub(crate) mod msg_filter;
 mod pipeline;
+#[cfg(feature = "dx12")]
+pub(crate) mod pixel_sample;
+#[cfg(feature = "dx12")]
+pub(crate) mod shared_capture;
+pub(crate) mod toasts;
+
+use std::borrow::Cow;
 
-use imgui::{Context, DrawData};
+use imgui::{Context, DrawData, TextureId};
+use tracing::error;
 use windows::core::Result;
 
-use crate::RenderContext;
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+use crate::ColorSpace;
+use crate::{options, RenderContext, TextureError};
 
 pub(crate) trait RenderEngine: RenderContext {
     type RenderTarget;
 
     fn render(&mut self, draw_data: &DrawData, render_target: Self::RenderTarget) -> Result<()>;
+
+    /// Render like [`RenderEngine::render`], invoking `after_render` once the
+    /// draw data is recorded, for the render loop to draw on top.
+    fn render_with(
+        &mut self,
+        draw_data: &DrawData,
+        render_target: Self::RenderTarget,
+        after_render: &mut dyn FnMut(&mut dyn RenderContext),
+    ) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.render(draw_data, render_target)?;
+        after_render(self);
+        Ok(())
+    }
     fn setup_fonts(&mut self, ctx: &mut Context) -> Result<()>;
+
+    /// Upload the font atlas, returning the texture to draw the fonts with.
+    /// `current` is the texture the atlas was last uploaded to, along with its
+    /// size: it is updated in place if the size didn't change, so that its
+    /// descriptor stays valid, and replaced by a new texture otherwise.
+    fn upload_font_texture(
+        &mut self,
+        current: Option<(TextureId, [u32; 2])>,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> std::result::Result<TextureId, TextureError> {
+        match current {
+            Some((texture_id, size)) if size == [width, height] => {
+                self.replace_texture(texture_id, data, width, height)?;
+                Ok(texture_id)
+            },
+            _ => self.load_texture(data, width, height),
+        }
+    }
+
+    /// Textures loaded from image files, by canonical path, if the renderer
+    /// loads them.
+    #[cfg(feature = "image-loader")]
+    fn texture_paths(
+        &mut self,
+    ) -> Option<&mut std::collections::HashMap<std::path::PathBuf, imgui::TextureId>> {
+        None
+    }
+
+    /// Load an image file as a texture via [`RenderContext::load_texture`],
+    /// once per canonical path, for [`RenderContext::load_texture_from_path`].
+    #[cfg(feature = "image-loader")]
+    fn load_texture_from_path_cached(
+        &mut self,
+        path: &std::path::Path,
+    ) -> std::result::Result<imgui::TextureId, TextureError> {
+        let path = canonicalize_image_path(path)?;
+        if let Some(&texture_id) = self.texture_paths().and_then(|paths| paths.get(&path)) {
+            return Ok(texture_id);
+        }
+
+        let image = decode_image(&path)?;
+        let texture_id = self.load_texture(image.as_raw(), image.width(), image.height())?;
+        if let Some(paths) = self.texture_paths() {
+            paths.insert(path, texture_id);
+        }
+
+        Ok(texture_id)
+    }
 }
 #[cfg(feature = "dx11")]
-pub(crate) use backend::dx11::D3D11RenderEngine;
+pub(crate) use backend::dx11::{D3D11On12, D3D11RenderEngine};
 #[cfg(feature = "dx12")]
 pub(crate) use backend::dx12::D3D12RenderEngine;
 #[cfg(feature = "dx9")]
 pub(crate) use backend::dx9::D3D9RenderEngine;
 #[cfg(feature = "opengl3")]
 pub(crate) use backend::opengl3::OpenGl3RenderEngine;
-pub(crate) use pipeline::Pipeline;
+pub(crate) use pipeline::{
+    capturing_input, discard_saved_settings, mouse_cursor_visible, request_font_atlas_rebuild,
+    request_input_release, reset_wnd_procs_suspended, set_mouse_cursor_visible,
+    set_wnd_procs_suspended, subclass_left, ClientMapping, Pipeline,
+};
+
+/// Preprocessor definitions of the DirectX pixel shaders for a color space and
+/// alpha mode, as passed to `D3DCompile`.
+#[cfg(any(feature = "dx11", feature = "dx12"))]
+pub(crate) fn shader_defines(
+    color_space: ColorSpace,
+    premultiplied_alpha: bool,
+) -> Option<Vec<windows::Win32::Graphics::Direct3D::D3D_SHADER_MACRO>> {
+    use windows::core::s;
+    use windows::Win32::Graphics::Direct3D::D3D_SHADER_MACRO;
+
+    let mut defines = Vec::new();
+    if color_space == ColorSpace::Linear {
+        defines.push(D3D_SHADER_MACRO { Name: s!("LINEAR_OUTPUT"), Definition: s!("1") });
+    }
+    if premultiplied_alpha {
+        defines.push(D3D_SHADER_MACRO { Name: s!("PREMULTIPLIED_ALPHA"), Definition: s!("1") });
+    }
+    if defines.is_empty() {
+        return None;
+    }
+
+    defines.push(D3D_SHADER_MACRO::default());
+    Some(defines)
+}
+
+/// Pixels of the font atlas as they should be uploaded: premultiplied by their
+/// alpha when the overlay is drawn with premultiplied alpha, as is otherwise.
+pub(crate) fn font_atlas_pixels(data: &[u8]) -> Cow<'_, [u8]> {
+    if options::get().premultiplied_alpha {
+        let mut data = data.to_vec();
+        premultiply_alpha(&mut data);
+        Cow::Owned(data)
+    } else {
+        Cow::Borrowed(data)
+    }
+}
+
+/// Multiply the color channels of RGBA (or BGRA) pixels by their alpha.
+pub(crate) fn premultiply_alpha(data: &mut [u8]) {
+    for pixel in data.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        for c in &mut pixel[..3] {
+            *c = ((*c as u32 * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
+/// Check that a texture of `width`x`height` can be created on a device whose
+/// textures are at most `max_width`x`max_height`, and within the limit set via
+/// [`HudhookBuilder::with_max_texture_size`](crate::HudhookBuilder::with_max_texture_size):
+/// otherwise, creating it fails deep in the driver, with an error that doesn't
+/// say why.
+pub(crate) fn check_texture_size(
+    width: u32,
+    height: u32,
+    [max_width, max_height]: [u32; 2],
+) -> std::result::Result<(), TextureError> {
+    let max = match options::get().max_texture_size {
+        Some(max_texture_size) => {
+            [max_width.min(max_texture_size), max_height.min(max_texture_size)]
+        },
+        None => [max_width, max_height],
+    };
+
+    if width == 0 || height == 0 {
+        error!("Invalid texture size {width}x{height}");
+        return Err(TextureError::Empty);
+    }
+    if width > max[0] || height > max[1] {
+        error!("Invalid texture size {width}x{height}: textures are at most {}x{}", max[0], max[1]);
+        return Err(TextureError::TooLarge { max });
+    }
+
+    Ok(())
+}
+
+/// imgui supports only one context at a time: tests creating one must hold
+/// this lock.
+#[cfg(test)]
+pub(crate) static TEST_CONTEXT_LOCK: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+
+/// Create an imgui context ready to render frames in tests, without an `.ini`
+/// file. Hold [`TEST_CONTEXT_LOCK`] while it is alive.
+#[cfg(test)]
+pub(crate) fn test_context() -> Context {
+    let mut ctx = Context::create();
+    ctx.set_ini_filename(None);
+    ctx.io_mut().display_size = [800., 600.];
+    ctx.fonts().build_rgba32_texture();
+    ctx
+}
+
+/// Resolve the path of an image file, so that it can be used as a key for
+/// caching textures.
+#[cfg(feature = "image-loader")]
+pub(crate) fn canonicalize_image_path(path: &std::path::Path) -> Result<std::path::PathBuf> {
+    path.canonicalize().map_err(|e| {
+        error!("Could not resolve image path {path:?}: {e}");
+        io_error_to_windows(e)
+    })
+}
+
+/// Read and decode an image file to RGBA8, premultiplied by alpha when the
+/// overlay is drawn with premultiplied alpha.
+#[cfg(feature = "image-loader")]
+pub(crate) fn decode_image(path: &std::path::Path) -> Result<image::RgbaImage> {
+    use windows::core::HRESULT;
+    use windows::Win32::Foundation::ERROR_BAD_FORMAT;
+
+    let image = image::io::Reader::open(path)
+        .map_err(|e| {
+            error!("Could not open image {path:?}: {e}");
+            io_error_to_windows(e)
+        })?
+        .with_guessed_format()
+        .map_err(|e| {
+            error!("Could not read image {path:?}: {e}");
+            io_error_to_windows(e)
+        })?
+        .decode()
+        .map_err(|e| {
+            error!("Could not decode image {path:?}: {e}");
+            windows::core::Error::from_hresult(HRESULT::from_win32(ERROR_BAD_FORMAT.0))
+        })?;
+
+    let mut image = image.into_rgba8();
+    if options::get().premultiplied_alpha {
+        premultiply_alpha(&mut image);
+    }
+
+    Ok(image)
+}
+
+#[cfg(any(feature = "image-loader", feature = "serde"))]
+pub(crate) fn io_error_to_windows(e: std::io::Error) -> windows::core::Error {
+    use windows::core::HRESULT;
+    use windows::Win32::Foundation::E_FAIL;
+
+    windows::core::Error::from_hresult(
+        e.raw_os_error().map(|code| HRESULT::from_win32(code as u32)).unwrap_or(E_FAIL),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_premultiply_alpha() {
+        let mut pixels = [255, 255, 255, 0, 255, 255, 255, 128, 200, 100, 50, 255, 255, 0, 255, 51];
+        premultiply_alpha(&mut pixels);
+        assert_eq!(pixels, [0, 0, 0, 0, 128, 128, 128, 128, 200, 100, 50, 255, 51, 0, 51, 51]);
+    }
+
+    #[test]
+    fn test_check_texture_size() {
+        assert_eq!(check_texture_size(16384, 1, [16384, 16384]), Ok(()));
+        assert_eq!(
+            check_texture_size(16385, 1, [16384, 16384]),
+            Err(TextureError::TooLarge { max: [16384, 16384] })
+        );
+        assert_eq!(
+            check_texture_size(1, 4097, [8192, 4096]),
+            Err(TextureError::TooLarge { max: [8192, 4096] })
+        );
+        assert_eq!(check_texture_size(0, 1, [16384, 16384]), Err(TextureError::Empty));
+    }
+}
@@ -1,28 +1,76 @@
 use std::collections::HashMap;
-use std::mem;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::ffi::OsString;
+use std::os::windows::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use std::{fs, io, mem, thread};
 
-use imgui::Context;
+use imgui::internal::RawCast;
+use imgui::{sys, Context, DrawData, Io, MouseButton, TextureId};
 use once_cell::sync::{Lazy, OnceCell};
 use parking_lot::Mutex;
-use tracing::error;
-use windows::core::{Error, Result, HRESULT};
-use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use tracing::{error, warn};
+use windows::core::{w, Error, Result, HRESULT, PCWSTR};
+use windows::Win32::Foundation::{
+    SetLastError, HANDLE, HWND, LPARAM, LRESULT, POINT, WIN32_ERROR, WPARAM,
+};
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::HiDpi::GetDpiForWindow;
+use windows::Win32::UI::Input::KeyboardAndMouse::VIRTUAL_KEY;
+use windows::Win32::UI::Shell::{
+    DefSubclassProc, DragAcceptFiles, DragFinish, DragQueryFileW, DragQueryPoint,
+    RemoveWindowSubclass, SetWindowSubclass, HDROP,
+};
 use windows::Win32::UI::WindowsAndMessaging::{
-    CallWindowProcW, DefWindowProcW, SetWindowLongPtrW, GWLP_WNDPROC,
+    CallWindowProcW, DefWindowProcW, GetAncestor, GetForegroundWindow, GetPropW, GetWindowLongPtrW,
+    GetWindowThreadProcessId, IsIconic, IsWindow, RegisterWindowMessageW, RemovePropW,
+    SendMessageTimeoutW, SetCursor, SetPropW, SetWindowLongPtrW, GA_ROOT, GA_ROOTOWNER,
+    GWLP_WNDPROC, GWL_EXSTYLE, HCURSOR, HTCLIENT, SMTO_ABORTIFHUNG, USER_DEFAULT_SCREEN_DPI,
+    WM_DPICHANGED, WM_DROPFILES, WM_INPUT, WM_SETCURSOR, WS_EX_ACCEPTFILES,
 };
 
-use crate::renderer::input::{imgui_wnd_proc_impl, WndProcType};
-use crate::renderer::RenderEngine;
-use crate::{util, ImguiRenderLoop, MessageFilter};
+use crate::input_queue::{self, InputEvent};
+use crate::renderer::font_atlas::FontAtlasBuild;
+use crate::renderer::input::{
+    imgui_wnd_proc_impl, is_mouse_message, is_passthrough_key, loword, register_raw_mouse,
+    unregister_raw_mouse, update_virtual_cursor, WndProcType,
+};
+use crate::renderer::{font_atlas_pixels, fps_overlay, imgui_errors, toasts, RenderEngine};
+use crate::{
+    frame_clock, options, util, ImguiRenderLoop, MessageFilter, MouseSource, RenderContext,
+    WndProcMode, WorkArea, DEMO_WINDOW, FPS_OVERLAY, UI_THREAD,
+};
 
 type RenderLoop = Box<dyn ImguiRenderLoop + Send + Sync>;
 
 static PIPELINE_STATES: Lazy<Mutex<HashMap<isize, Arc<PipelineSharedState>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
+// Message sent to a subclassed window to remove the subclass from the thread
+// that owns the window, as subclasses can't be removed from other threads.
+static REMOVE_SUBCLASS_MESSAGE: Lazy<u32> =
+    Lazy::new(|| unsafe { RegisterWindowMessageW(w!("hudhook_remove_subclass")) });
+// Whether a subclass couldn't be removed, in which case the window still calls
+// into the module and it must not be unloaded.
+static SUBCLASS_LEFT: AtomicBool = AtomicBool::new(false);
+// imgui settings (window positions and sizes, table column widths) of the
+// last pipeline torn down, restored into the next one.
+static SAVED_SETTINGS: Mutex<Option<String>> = Mutex::new(None);
+// Whether the render loop asked for the font atlas to be rebuilt.
+static FONT_ATLAS_REBUILD: AtomicBool = AtomicBool::new(false);
+// Whether the render loop asked for the input to be released to the game.
+static INPUT_RELEASE: AtomicBool = AtomicBool::new(false);
+// Whether the hooks are disabled, in which case the window messages go
+// straight to the application.
+static WND_PROCS_SUSPENDED: AtomicBool = AtomicBool::new(false);
+// Mouse cursor visibility forced by the render loop: 0 if it's left to imgui
+// and the application, 1 if hidden and 2 if shown.
+static MOUSE_CURSOR_VISIBLE: AtomicU8 = AtomicU8::new(0);
+// Whether imgui wanted the mouse or the keyboard at the last frame, for the
+// threads of the application to poll.
+static CAPTURING_INPUT: AtomicBool = AtomicBool::new(false);
 
 #[derive(Debug)]
 pub(crate) struct PipelineMessage(
@@ -30,16 +78,56 @@ pub(crate) struct PipelineMessage(
     pub(crate) u32,
     pub(crate) WPARAM,
     pub(crate) LPARAM,
+    // When the message was received, for the input queue.
+    pub(crate) Option<i64>,
 );
 
+// How the pipeline receives the messages of its window.
+pub(crate) enum WndProcHook {
+    // The window procedure was replaced, and this is the previous one.
+    Replaced(WndProcType),
+    // The window was subclassed via `SetWindowSubclass`.
+    Subclassed,
+}
+
 pub(crate) struct PipelineSharedState {
     pub(crate) message_filter: AtomicU32,
-    pub(crate) wnd_proc: WndProcType,
+    pub(crate) wnd_proc: Option<WndProcHook>,
     pub(crate) tx: Sender<PipelineMessage>,
+    pub(crate) virtual_cursor: Option<Mutex<[f32; 2]>>,
+    // Whether the window was registered for raw mouse input by the overlay,
+    // rather than by the application, and is to be unregistered.
+    pub(crate) raw_mouse_registered: bool,
+    pub(crate) mouse_passthrough: bool,
+    // Whether imgui wanted the mouse at the end of the last frame, i.e. the
+    // cursor hovers a window of the overlay, or drags one.
+    pub(crate) mouse_over_overlay: AtomicBool,
+    pub(crate) passthrough_keys: Vec<VIRTUAL_KEY>,
+    // DPI the window moved to, or 0. The fonts and the style are rescaled by
+    // the render thread, as the font texture has to be uploaded again.
+    pub(crate) pending_dpi: AtomicU32,
+    // Files dropped onto the window, delivered to the render loop by the
+    // render thread, as the drop handle is only valid in the window procedure.
+    pub(crate) dropped_files: Mutex<Vec<DroppedFiles>>,
+    // Whether drag and drop was enabled for the overlay, in which case the
+    // application doesn't expect the drops and the overlay releases them.
+    pub(crate) accept_files: bool,
+    // Capacity of the input queue, or 0 if the input messages aren't queued.
+    pub(crate) input_queue_capacity: usize,
+}
+
+// Paths of the files dropped onto the window, and the drop point in client
+// coordinates.
+pub(crate) struct DroppedFiles {
+    paths: Vec<PathBuf>,
+    pos: [f32; 2],
 }
 
 pub(crate) struct Pipeline<T: RenderEngine> {
     hwnd: HWND,
+    // The replacement font atlas being built, if any. Dropped before the
+    // context, which it suspends.
+    font_atlas_build: Option<FontAtlasBuild>,
     ctx: Context,
     engine: T,
     render_loop: RenderLoop,
@@ -47,6 +135,87 @@ pub(crate) struct Pipeline<T: RenderEngine> {
     shared_state: Arc<PipelineSharedState>,
     queue_buffer: OnceCell<Vec<PipelineMessage>>,
     start_of_first_frame: OnceCell<Instant>,
+    focused: bool,
+    // Whether the window is a child window, which doesn't receive activation
+    // messages: its focus is polled instead.
+    child_window: bool,
+    dpi: u32,
+    // DPI of the window when the pipeline was created, which the sizes of the
+    // fonts added by the render loop are relative to.
+    initial_dpi: u32,
+    font_texture: FontTexture,
+    // When the last frame was built, and whether the current present draws it
+    // again instead of building a new one.
+    last_frame_built: Option<Instant>,
+    reuse_frame: bool,
+    // Whether the renderer was skipped for the last frame built, as there was
+    // nothing to draw.
+    last_frame_empty: bool,
+    // Whether a frame was rendered, after which the first frame callback ran.
+    first_frame_rendered: bool,
+    // Whether the window is minimized, with a client area of 0x0: no frame is
+    // built until it's restored.
+    minimized: bool,
+    // The render loop's own `io.mouse_draw_cursor`, while the cursor
+    // visibility is forced.
+    mouse_draw_cursor: Option<bool>,
+    work_area: Option<WorkArea>,
+    mouse_source: MouseSource,
+    client_mapping: ClientMapping,
+}
+
+/// Mapping of the coordinates of the client area of the window to the ones of
+/// the back buffer, which the swap chain may present stretched, letterboxed
+/// or as is, regardless of the size of the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ClientMapping {
+    offset: [f32; 2],
+    scale: [f32; 2],
+}
+
+impl ClientMapping {
+    /// The back buffer is presented as is, from the top left corner.
+    pub(crate) const IDENTITY: Self = Self { offset: [0., 0.], scale: [1., 1.] };
+
+    /// The back buffer is stretched to fill the client area.
+    pub(crate) fn stretch(back_buffer: [f32; 2], client: [f32; 2]) -> Self {
+        if client[0] <= 0. || client[1] <= 0. {
+            return Self::IDENTITY;
+        }
+        Self { offset: [0., 0.], scale: [back_buffer[0] / client[0], back_buffer[1] / client[1]] }
+    }
+
+    /// The back buffer is scaled to fit the client area, keeping its aspect
+    /// ratio, and centered.
+    pub(crate) fn aspect_ratio_stretch(back_buffer: [f32; 2], client: [f32; 2]) -> Self {
+        if back_buffer[0] <= 0. || back_buffer[1] <= 0. {
+            return Self::IDENTITY;
+        }
+        let factor = f32::min(client[0] / back_buffer[0], client[1] / back_buffer[1]);
+        if factor <= 0. {
+            return Self::IDENTITY;
+        }
+        Self {
+            offset: [
+                (client[0] - back_buffer[0] * factor) / 2.,
+                (client[1] - back_buffer[1] * factor) / 2.,
+            ],
+            scale: [1. / factor, 1. / factor],
+        }
+    }
+
+    /// Map a position in the client area to the back buffer.
+    pub(crate) fn map(&self, [x, y]: [f32; 2]) -> [f32; 2] {
+        [(x - self.offset[0]) * self.scale[0], (y - self.offset[1]) * self.scale[1]]
+    }
+}
+
+// The texture the font atlas was last uploaded to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FontTexture {
+    id: TextureId,
+    width: u32,
+    height: u32,
 }
 
 impl<T: RenderEngine> Pipeline<T> {
@@ -56,61 +225,169 @@ impl<T: RenderEngine> Pipeline<T> {
         mut engine: T,
         mut render_loop: RenderLoop,
     ) -> std::result::Result<Self, (Error, RenderLoop)> {
+        frame_clock::start();
+
         let (width, height) = util::win_size(hwnd);
 
         ctx.io_mut().display_size = [width as f32, height as f32];
 
+        let (ini_filename, ini_autosave, work_area, mouse_source) = {
+            let options = options::get();
+            let io = ctx.io_mut();
+            io.config_flags |= options.config_flags;
+            io.backend_flags |= options.backend_flags;
+            #[cfg(feature = "imgui-docking")]
+            if options.dockspace {
+                io.config_flags |= imgui::ConfigFlags::DOCKING_ENABLE;
+            }
+            // Loading settings from memory keeps imgui from loading the `.ini`
+            // file later on: only fall back to the layout without one.
+            #[cfg(feature = "imgui-docking")]
+            if let Some(dock_layout) = options.dock_layout {
+                if !options.ini_filename.as_ref().is_some_and(|path| path.exists()) {
+                    ctx.load_ini_settings(dock_layout);
+                }
+            }
+            #[cfg(feature = "serde")]
+            if let Some(style_file) = &options.style_file {
+                crate::style::load_style_from_file(ctx.style_mut(), style_file).ok();
+            }
+            ctx.set_platform_name(options.platform_name.clone().unwrap_or_else(|| {
+                String::from(concat!("hudhook-win32@", env!("CARGO_PKG_VERSION")))
+            }));
+            if let Some(renderer_name) = options.renderer_name.clone() {
+                ctx.set_renderer_name(renderer_name);
+            }
+            (
+                options.ini_filename.clone(),
+                options.ini_autosave,
+                options.work_area,
+                options.mouse_source,
+            )
+        };
+
+        if let Some(ini_filename) = ini_filename {
+            load_ini_settings(&mut ctx, ini_filename, ini_autosave);
+        }
+
+        if let Err(e) = check_ui_thread("initialize") {
+            return Err((e, render_loop));
+        }
         render_loop.initialize(&mut ctx, &mut engine);
 
         if let Err(e) = engine.setup_fonts(&mut ctx) {
             return Err((e, render_loop));
         }
-
-        let wnd_proc = unsafe {
-            #[cfg(target_arch = "x86")]
-            type SwlpRet = i32;
-            #[cfg(target_arch = "x86_64")]
-            type SwlpRet = isize;
-
-            mem::transmute::<SwlpRet, WndProcType>(SetWindowLongPtrW(
-                hwnd,
-                GWLP_WNDPROC,
-                pipeline_wnd_proc as *const () as _,
-            ))
+        let font_texture = {
+            let fonts = ctx.fonts();
+            let id = fonts.tex_id;
+            let atlas = unsafe { fonts.raw_mut() };
+            FontTexture { id, width: atlas.TexWidth as u32, height: atlas.TexHeight as u32 }
         };
+        FONT_ATLAS_REBUILD.store(false, Ordering::SeqCst);
+        INPUT_RELEASE.store(false, Ordering::SeqCst);
 
-        let (tx, rx) = mpsc::channel();
-        let shared_state = Arc::new(PipelineSharedState {
-            message_filter: AtomicU32::new(MessageFilter::empty().bits()),
-            wnd_proc,
-            tx,
-        });
+        restore_settings(&mut ctx);
 
-        PIPELINE_STATES.lock().insert(hwnd.0, Arc::clone(&shared_state));
+        let (shared_state, rx) = attach_window(hwnd, width, height);
+        let dpi = window_dpi(hwnd);
 
         let queue_buffer = OnceCell::from(Vec::new());
 
         Ok(Self {
             hwnd,
+            font_atlas_build: None,
             ctx,
             engine,
             render_loop,
             rx,
-            shared_state: Arc::clone(&shared_state),
+            shared_state,
             queue_buffer,
             start_of_first_frame: OnceCell::new(),
+            focused: is_foreground_window(hwnd),
+            child_window: unsafe { GetAncestor(hwnd, GA_ROOT) } != hwnd,
+            dpi,
+            initial_dpi: dpi,
+            font_texture,
+            last_frame_built: None,
+            reuse_frame: false,
+            last_frame_empty: false,
+            first_frame_rendered: false,
+            minimized: unsafe { IsIconic(hwnd) }.as_bool(),
+            mouse_draw_cursor: None,
+            work_area,
+            mouse_source,
+            client_mapping: ClientMapping::IDENTITY,
         })
     }
 
     pub(crate) fn prepare_render(&mut self) -> Result<()> {
+        check_ui_thread("before_render")?;
+
+        // While the replacement atlas requested before is built, the context
+        // is suspended: the input stays queued, and the last frame is drawn
+        // again. The atlas is swapped in once it's built, and uploaded like a
+        // rebuilt one.
+        let font_scale = self.dpi as f32 / self.initial_dpi as f32;
+        if self.font_atlas_build.is_none() {
+            self.font_atlas_build = FontAtlasBuild::start_requested(font_scale);
+        }
+        if self.font_atlas_build.as_ref().is_some_and(|build| !build.is_finished()) {
+            self.reuse_frame = true;
+            return Ok(());
+        }
+        if let Some(atlas) = self.font_atlas_build.take().and_then(FontAtlasBuild::finish) {
+            atlas.swap_into(&mut self.ctx, font_scale);
+            FONT_ATLAS_REBUILD.store(true, Ordering::SeqCst);
+        }
+
         let mut queue_buffer = self.queue_buffer.take().unwrap();
         queue_buffer.clear();
         queue_buffer.extend(self.rx.try_iter());
-        queue_buffer.drain(..).for_each(|PipelineMessage(hwnd, umsg, wparam, lparam)| {
-            imgui_wnd_proc_impl(hwnd, umsg, wparam, lparam, self);
-        });
+        queue_buffer.drain(..).for_each(
+            |PipelineMessage(hwnd, umsg, wparam, lparam, timestamp)| {
+                if let Some(timestamp) = timestamp {
+                    let event = InputEvent { hwnd, message: umsg, wparam, lparam, timestamp };
+                    input_queue::push(event, self.shared_state.input_queue_capacity);
+                }
+                imgui_wnd_proc_impl(hwnd, umsg, wparam, lparam, self);
+            },
+        );
         self.queue_buffer.set(queue_buffer).expect("OnceCell should be empty");
 
+        // The input received meanwhile is queued in imgui, and processed when
+        // the next frame is built.
+        self.reuse_frame = self.reuse_last_frame();
+        if self.reuse_frame {
+            return Ok(());
+        }
+
+        match self.shared_state.pending_dpi.swap(0, Ordering::SeqCst) {
+            0 => {},
+            dpi => self.rescale(dpi)?,
+        }
+
+        if self.child_window {
+            self.set_focused(is_foreground_window(self.hwnd));
+        }
+
+        if self.virtual_cursor_active() {
+            if let Some(virtual_cursor) = &self.shared_state.virtual_cursor {
+                let pos = *virtual_cursor.lock();
+                self.ctx.io_mut().add_mouse_pos_event(pos);
+            }
+        }
+
+        let dropped_files = mem::take(&mut *self.shared_state.dropped_files.lock());
+        for DroppedFiles { paths, pos } in dropped_files {
+            self.render_loop.on_files_dropped(&paths, pos);
+        }
+
+        #[cfg(feature = "ipc")]
+        for message in crate::ipc::drain() {
+            self.render_loop.on_ipc_message(&message);
+        }
+
         let message_filter = self.render_loop.message_filter(self.ctx.io());
 
         self.shared_state.message_filter.store(message_filter.bits(), Ordering::SeqCst);
@@ -120,12 +397,73 @@ impl<T: RenderEngine> Pipeline<T> {
         io.nav_active = true;
         io.nav_visible = true;
 
+        frame_clock::begin_frame();
         self.render_loop.before_render(&mut self.ctx, &mut self.engine);
 
+        // The message filter was computed before the render loop asked for
+        // the input back, from the state of the previous frame.
+        if INPUT_RELEASE.swap(false, Ordering::SeqCst) {
+            release_input(self.ctx.io_mut());
+            self.shared_state.message_filter.store(MessageFilter::empty().bits(), Ordering::SeqCst);
+        }
+
+        if FONT_ATLAS_REBUILD.swap(false, Ordering::SeqCst) {
+            self.font_texture =
+                upload_font_atlas(&mut self.ctx, &mut self.engine, Some(self.font_texture))?;
+        }
+
+        self.apply_mouse_cursor_visibility();
+
         Ok(())
     }
 
+    // Draw the imgui cursor as forced by the render loop, and give the render
+    // loop its own setting back once it's no longer forced.
+    fn apply_mouse_cursor_visibility(&mut self) {
+        let io = self.ctx.io_mut();
+        match mouse_cursor_visible() {
+            Some(visible) => {
+                self.mouse_draw_cursor.get_or_insert(io.mouse_draw_cursor);
+                io.mouse_draw_cursor = visible;
+            },
+            None => {
+                if let Some(mouse_draw_cursor) = self.mouse_draw_cursor.take() {
+                    io.mouse_draw_cursor = mouse_draw_cursor;
+                }
+            },
+        }
+    }
+
     pub(crate) fn render(&mut self, render_target: T::RenderTarget) -> Result<()> {
+        if self.minimized {
+            return Ok(());
+        }
+
+        if let Some(build) = &self.font_atlas_build {
+            return match build.draw_data() {
+                Some(draw_data) if !self.last_frame_empty => {
+                    self.engine.render(draw_data, render_target)
+                },
+                _ => Ok(()),
+            };
+        }
+
+        if self.reuse_frame {
+            if self.last_frame_empty {
+                return Ok(());
+            }
+
+            // Valid until the next frame is built, as checked by
+            // `reuse_last_frame`.
+            let draw_data = unsafe { DrawData::from_raw(&*sys::igGetDrawData()) };
+            let render_loop = &mut self.render_loop;
+            return self.engine.render_with(draw_data, render_target, &mut |render_context| {
+                render_loop.after_imgui_render(render_context)
+            });
+        }
+
+        check_ui_thread("render")?;
+
         let delta_time = Instant::now()
             .checked_duration_since(*self.start_of_first_frame.get_or_init(Instant::now))
             .unwrap_or(Duration::ZERO)
@@ -143,59 +481,807 @@ impl<T: RenderEngine> Pipeline<T> {
         }
 
         let ui = self.ctx.frame();
-        self.render_loop.render(ui);
+        if let Some(work_area) = &self.work_area {
+            apply_work_area(work_area);
+        }
+        #[cfg(feature = "imgui-docking")]
+        if options::get().dockspace {
+            submit_dockspace();
+        }
+        let needs_draw = self.render_loop.render_ui(ui);
+
+        if DEMO_WINDOW.load(Ordering::SeqCst) {
+            let mut opened = true;
+            ui.show_demo_window(&mut opened);
+            ui.window("hudhook")
+                .always_auto_resize(true)
+                .build(|| ui.text(crate::build_info().to_string()));
+            if !opened {
+                DEMO_WINDOW.store(false, Ordering::SeqCst);
+            }
+        }
+        if FPS_OVERLAY.load(Ordering::SeqCst) {
+            fps_overlay::render(ui);
+        }
+        toasts::render(ui);
+
+        let (imgui_error_recovery, imgui_error_handler, shared_capture) = {
+            let options = options::get();
+            (options.imgui_error_recovery, options.imgui_error_handler, options.shared_capture)
+        };
+        if imgui_error_recovery {
+            imgui_errors::recover(imgui_error_handler);
+        }
+
         let draw_data = self.ctx.render();
 
-        self.engine.render(draw_data, render_target)?;
+        // The frames shared with other processes are copied by the renderer,
+        // even when the overlay is empty.
+        self.last_frame_empty = !needs_draw && !shared_capture && draw_data.total_vtx_count == 0;
+        if !self.last_frame_empty {
+            let render_loop = &mut self.render_loop;
+            self.engine.render_with(draw_data, render_target, &mut |render_context| {
+                render_loop.after_imgui_render(render_context)
+            })?;
+        }
+        if !self.first_frame_rendered {
+            self.first_frame_rendered = true;
+            let on_first_frame = options::get().on_first_frame;
+            if let Some(on_first_frame) = on_first_frame {
+                on_first_frame(&mut self.engine);
+            }
+        }
+        frame_clock::end_frame();
+        self.last_frame_built = Some(Instant::now());
+
+        let io = self.ctx.io();
+        CAPTURING_INPUT.store(io.want_capture_mouse || io.want_capture_keyboard, Ordering::SeqCst);
+        self.shared_state.mouse_over_overlay.store(io.want_capture_mouse, Ordering::SeqCst);
 
         Ok(())
     }
 
+    /// Draw the last frame again on another render target, e.g. the swap chain
+    /// of the mirror window. Does nothing if no frame was built yet.
+    #[cfg(any(feature = "dx11", feature = "dx12"))]
+    pub(crate) fn render_mirror(&mut self, render_target: T::RenderTarget) -> Result<()> {
+        if let Some(build) = &self.font_atlas_build {
+            return match build.draw_data() {
+                Some(draw_data) => self.engine.render(draw_data, render_target),
+                None => Ok(()),
+            };
+        }
+
+        let draw_data = unsafe { sys::igGetDrawData() };
+        if draw_data.is_null() || !unsafe { (*draw_data).Valid } {
+            return Ok(());
+        }
+
+        self.engine.render(unsafe { DrawData::from_raw(&*draw_data) }, render_target)
+    }
+
+    // Whether the minimum frame interval hasn't elapsed since the last frame
+    // was built, which can then be drawn again.
+    fn reuse_last_frame(&self) -> bool {
+        let Some(last_frame_built) = self.last_frame_built else {
+            return false;
+        };
+        if last_frame_built.elapsed() >= options::get().min_frame_interval {
+            return false;
+        }
+
+        let draw_data = unsafe { sys::igGetDrawData() };
+        !draw_data.is_null() && unsafe { (*draw_data).Valid }
+    }
+
+    pub(crate) fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
     pub(crate) fn context(&mut self) -> &mut Context {
         &mut self.ctx
     }
 
+    pub(crate) fn engine(&mut self) -> &mut T {
+        &mut self.engine
+    }
+
     pub(crate) fn render_loop(&mut self) -> &mut RenderLoop {
         &mut self.render_loop
     }
 
+    // Which messages the mouse position is taken from.
+    pub(crate) fn mouse_source(&self) -> MouseSource {
+        self.mouse_source
+    }
+
+    pub(crate) fn client_mapping(&self) -> ClientMapping {
+        self.client_mapping
+    }
+
+    // The virtual cursor drives the mouse position only while the overlay is
+    // capturing mouse input, as the game owns the cursor otherwise.
+    pub(crate) fn virtual_cursor_active(&self) -> bool {
+        self.shared_state.virtual_cursor.is_some()
+            && MessageFilter::from_bits_retain(
+                self.shared_state.message_filter.load(Ordering::SeqCst),
+            )
+            .contains(MessageFilter::InputMouse)
+    }
+
+    pub(crate) fn is_passthrough_key(&self, umsg: u32, wparam: WPARAM, lparam: LPARAM) -> bool {
+        is_passthrough_key(umsg, wparam, lparam, &self.shared_state.passthrough_keys)
+    }
+
+    // Notify imgui and the render loop of focus transitions, ignoring repeated
+    // notifications of the same state (e.g. `WM_ACTIVATEAPP` then
+    // `WM_ACTIVATE`).
+    pub(crate) fn set_focused(&mut self, focused: bool) {
+        if self.focused == focused {
+            return;
+        }
+        self.focused = focused;
+
+        // On focus loss, imgui releases the keys and mouse buttons held down:
+        // their release messages go to the newly focused window instead, and
+        // they would be stuck otherwise.
+        unsafe { sys::ImGuiIO_AddFocusEvent(self.ctx.io_mut().raw_mut(), focused) };
+
+        if focused {
+            self.render_loop.focus_gained();
+        } else {
+            self.render_loop.focus_lost();
+        }
+    }
+
+    // Scale the style and rebuild the fonts for the DPI of the monitor the
+    // window moved to, relative to the DPI so far.
+    fn rescale(&mut self, dpi: u32) -> Result<()> {
+        if dpi == self.dpi {
+            return Ok(());
+        }
+        let factor = dpi as f32 / self.dpi as f32;
+        self.dpi = dpi;
+
+        self.ctx.style_mut().scale_all_sizes(factor);
+
+        // The atlas keeps the font sources: rebuilding it from scaled
+        // configurations renders the glyphs sharp at the new size.
+        let fonts = self.ctx.fonts();
+        unsafe {
+            let config_data = &fonts.raw_mut().ConfigData;
+            for i in 0..config_data.Size as usize {
+                (*config_data.Data.add(i)).SizePixels *= factor;
+            }
+        }
+        fonts.clear_tex_data();
+        self.font_texture =
+            upload_font_atlas(&mut self.ctx, &mut self.engine, Some(self.font_texture))?;
+
+        Ok(())
+    }
+
     pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        // Resized again with the next frame built, once the context is no
+        // longer suspended.
+        if self.font_atlas_build.is_some() {
+            return;
+        }
         self.ctx.io_mut().display_size = [width as f32, height as f32];
     }
 
+    /// Track the window being minimized or restored. The display keeps the
+    /// size it had before, as a minimized window has no client area.
+    pub(crate) fn set_minimized(&mut self, minimized: bool) {
+        self.minimized = minimized;
+    }
+
+    /// Size the display to the back buffer of `width`x`height` rather than to
+    /// the client area, and map the mouse positions from one to the other as
+    /// the swap chain presents the back buffer. Called every frame, after
+    /// [`Pipeline::prepare_render`], as `WM_SIZE` resizes to the client area.
+    pub(crate) fn resize_back_buffer(&mut self, width: u32, height: u32, mapping: ClientMapping) {
+        self.resize(width, height);
+        self.client_mapping = mapping;
+    }
+
     pub(crate) fn cleanup(&mut self) {
-        unsafe {
-            SetWindowLongPtrW(self.hwnd, GWLP_WNDPROC, self.shared_state.wnd_proc as usize as _)
-        };
+        CAPTURING_INPUT.store(false, Ordering::SeqCst);
+
+        match self.shared_state.wnd_proc {
+            Some(WndProcHook::Replaced(wnd_proc)) => unsafe {
+                SetWindowLongPtrW(self.hwnd, GWLP_WNDPROC, wnd_proc as usize as _);
+                let _ = RemovePropW(self.hwnd, WND_PROC_PROP);
+            },
+            Some(WndProcHook::Subclassed) => unsafe { remove_subclass(self.hwnd) },
+            None => {},
+        }
+
+        if self.shared_state.accept_files {
+            unsafe { DragAcceptFiles(self.hwnd, false) };
+        }
+
+        if self.shared_state.raw_mouse_registered {
+            if let Err(e) = unregister_raw_mouse(self.hwnd) {
+                error!("Could not unregister raw mouse input: {e:?}");
+            }
+        }
+
+        // Keep `set_wnd_procs_suspended` away from the window from now on.
+        let mut pipeline_states = PIPELINE_STATES.lock();
+        if pipeline_states
+            .get(&self.hwnd.0)
+            .is_some_and(|shared_state| Arc::ptr_eq(shared_state, &self.shared_state))
+        {
+            pipeline_states.remove(&self.hwnd.0);
+        }
+    }
+
+    /// Move the pipeline to another window, e.g. after the game released the
+    /// swap chain it rendered into and presents to another one. The context
+    /// and the renderer stay: the messages of `hwnd` are routed to them
+    /// instead, and those of the previous window are given back.
+    pub(crate) fn retarget(&mut self, hwnd: HWND) {
+        if hwnd == self.hwnd {
+            return;
+        }
+        self.cleanup();
+
+        let (width, height) = util::win_size(hwnd);
+        (self.shared_state, self.rx) = attach_window(hwnd, width, height);
+        self.hwnd = hwnd;
+        self.focused = is_foreground_window(hwnd);
+        self.child_window = unsafe { GetAncestor(hwnd, GA_ROOT) } != hwnd;
+        self.minimized = unsafe { IsIconic(hwnd) }.as_bool();
+        self.client_mapping = ClientMapping::IDENTITY;
+        self.ctx.io_mut().display_size = [width as f32, height as f32];
+
+        match window_dpi(hwnd) {
+            dpi if dpi == self.dpi => {},
+            dpi => self.shared_state.pending_dpi.store(dpi, Ordering::SeqCst),
+        }
+    }
+
+    /// Hand the pipeline to another render loop. The old one is dropped, and
+    /// the fonts it added with it: the new one is initialized against the
+    /// same context and renderer.
+    pub(crate) fn replace_render_loop(&mut self, render_loop: RenderLoop) -> Result<()> {
+        self.render_loop = render_loop;
+        self.reinitialize(Some(self.font_texture))
+    }
+
+    /// Replace the renderer with one `create` builds for the same context,
+    /// e.g. after the game moved to another device. The render loop is
+    /// initialized again, as its textures belonged to the previous renderer.
+    pub(crate) fn replace_engine(
+        &mut self,
+        create: impl FnOnce(&mut Context) -> Result<T>,
+    ) -> Result<()> {
+        // The renderer configures the context as if it were a new one.
+        let ini_filename = self.ctx.ini_filename();
+        self.engine = create(&mut self.ctx)?;
+        self.ctx.set_ini_filename(ini_filename);
+        if let Some(renderer_name) = options::get().renderer_name.clone() {
+            self.ctx.set_renderer_name(renderer_name);
+        }
+
+        self.reinitialize(None)
+    }
+
+    // Initialize the render loop again, from an atlas without its fonts, and
+    // upload the atlas to `font_texture` if it still fits.
+    fn reinitialize(&mut self, font_texture: Option<FontTexture>) -> Result<()> {
+        if let Some(build) = self.font_atlas_build.take() {
+            build.finish();
+        }
+        self.ctx.fonts().clear();
+        unsafe { self.ctx.io_mut().raw_mut().FontDefault = std::ptr::null_mut() };
+
+        check_ui_thread("initialize")?;
+        self.render_loop.initialize(&mut self.ctx, &mut self.engine);
+
+        self.font_texture = upload_font_atlas(&mut self.ctx, &mut self.engine, font_texture)?;
+        FONT_ATLAS_REBUILD.store(false, Ordering::SeqCst);
+        self.first_frame_rendered = false;
+
+        Ok(())
     }
 
     pub(crate) fn take(mut self) -> RenderLoop {
         self.cleanup();
+        if let Some(build) = self.font_atlas_build.take() {
+            build.finish();
+        }
+        save_settings(&mut self.ctx);
         self.render_loop
     }
 }
 
+// Route the messages of `hwnd` to a pipeline, as configured, returning the
+// state shared with its window procedure and the receiving end of the
+// messages.
+fn attach_window(
+    hwnd: HWND,
+    width: i32,
+    height: i32,
+) -> (Arc<PipelineSharedState>, Receiver<PipelineMessage>) {
+    let (
+        raw_input_cursor,
+        mouse_passthrough,
+        passthrough_keys,
+        input,
+        wndproc_mode,
+        input_queue_capacity,
+    ) = {
+        let options = options::get();
+        (
+            options.raw_input_cursor,
+            options.mouse_passthrough,
+            options.passthrough_keys.clone(),
+            options.input,
+            options.wndproc_mode,
+            options.input_queue_capacity,
+        )
+    };
+
+    // Without input, the window procedure is never touched, and neither is
+    // the window by the cleanup.
+    let wnd_proc = input.then(|| unsafe { install_wnd_proc(hwnd, wndproc_mode) }).flatten();
+
+    let accept_files = wnd_proc.is_some() && unsafe { enable_drag_and_drop(hwnd) };
+
+    let (virtual_cursor, raw_mouse_registered) =
+        match (raw_input_cursor && wnd_proc.is_some()).then(|| register_raw_mouse(hwnd)) {
+            Some(Ok(registered)) => {
+                (Some(Mutex::new([width as f32 / 2., height as f32 / 2.])), registered)
+            },
+            Some(Err(e)) => {
+                error!("Could not register raw mouse input: {e:?}");
+                (None, false)
+            },
+            None => (None, false),
+        };
+
+    let (tx, rx) = mpsc::channel();
+    let shared_state = Arc::new(PipelineSharedState {
+        message_filter: AtomicU32::new(MessageFilter::empty().bits()),
+        wnd_proc,
+        tx,
+        virtual_cursor,
+        raw_mouse_registered,
+        mouse_passthrough,
+        mouse_over_overlay: AtomicBool::new(false),
+        passthrough_keys,
+        pending_dpi: AtomicU32::new(0),
+        dropped_files: Mutex::new(Vec::new()),
+        accept_files,
+        input_queue_capacity,
+    });
+
+    if wnd_proc.is_some() {
+        PIPELINE_STATES.lock().insert(hwnd.0, Arc::clone(&shared_state));
+    }
+
+    (shared_state, rx)
+}
+
+fn window_dpi(hwnd: HWND) -> u32 {
+    match unsafe { GetDpiForWindow(hwnd) } {
+        0 => USER_DEFAULT_SCREEN_DPI,
+        dpi => dpi,
+    }
+}
+
+// Whether the foreground window belongs to the same top-level window as
+// `hwnd`. Games may present to a child window of the window that gets
+// activated, and the foreground window may be one of their owned popups.
+fn is_foreground_window(hwnd: HWND) -> bool {
+    let foreground = unsafe { GetForegroundWindow() };
+    foreground.0 != 0 && (foreground == hwnd || is_same_root(foreground, hwnd))
+}
+
+// Whether two windows belong to the same top-level window, following both
+// parents and owners.
+fn is_same_root(hwnd: HWND, other: HWND) -> bool {
+    unsafe { GetAncestor(hwnd, GA_ROOTOWNER) == GetAncestor(other, GA_ROOTOWNER) }
+}
+
+// Record the thread the render loop first runs on and, if pinning is enabled,
+// refuse any later call from another thread. The hooks skip the frame and call
+// the original function, as they do for any render error.
+fn check_ui_thread(callback: &str) -> Result<()> {
+    let current = thread::current().id();
+    let pinned = *UI_THREAD.lock().get_or_insert(current);
+
+    if pinned != current && options::get().ui_thread_pinning {
+        error!("ImguiRenderLoop::{callback} called on {current:?}, pinned to {pinned:?}");
+        return Err(Error::from_hresult(HRESULT(-1)));
+    }
+    Ok(())
+}
+
+// With autosave, imgui loads the file at the first frame and saves it
+// periodically and when the context is dropped. Without it, the file is only
+// loaded here, so that the context never touches the disk on its own.
+fn load_ini_settings(ctx: &mut Context, ini_filename: PathBuf, ini_autosave: bool) {
+    if ini_autosave {
+        ctx.set_ini_filename(Some(ini_filename));
+        return;
+    }
+
+    match fs::read_to_string(&ini_filename) {
+        Ok(data) => ctx.load_ini_settings(&data),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => error!("Could not read {}: {e:?}", ini_filename.display()),
+    }
+}
+
+// Pipelines are torn down and rebuilt along with their imgui context when the
+// device is reset (DirectX 9), which would otherwise lose the layout of the
+// overlay for the rest of the session, even with no `.ini` file set.
+fn save_settings(ctx: &mut Context) {
+    let mut data = String::new();
+    ctx.save_ini_settings(&mut data);
+    *SAVED_SETTINGS.lock() = Some(data);
+}
+
+// Loading the settings from memory also keeps imgui from loading the `.ini`
+// file at the first frame: the settings saved at teardown are more recent.
+fn restore_settings(ctx: &mut Context) {
+    if let Some(data) = SAVED_SETTINGS.lock().take() {
+        ctx.load_ini_settings(&data);
+    }
+}
+
+/// Forget the settings of the last pipeline torn down, so that they don't leak
+/// into the pipeline of a later set of hooks.
+pub(crate) fn discard_saved_settings() {
+    SAVED_SETTINGS.lock().take();
+}
+
+/// Request the font atlas to be rebuilt and uploaded again before the next
+/// frame.
+pub(crate) fn request_font_atlas_rebuild() {
+    FONT_ATLAS_REBUILD.store(true, Ordering::SeqCst);
+}
+
+/// Request the input to be released to the game before the next frame.
+pub(crate) fn request_input_release() {
+    INPUT_RELEASE.store(true, Ordering::SeqCst);
+}
+
+/// Force the mouse cursor to be shown or hidden, or leave it to imgui and the
+/// application with `None`.
+pub(crate) fn set_mouse_cursor_visible(visible: Option<bool>) {
+    let state = match visible {
+        None => 0,
+        Some(false) => 1,
+        Some(true) => 2,
+    };
+    MOUSE_CURSOR_VISIBLE.store(state, Ordering::SeqCst);
+}
+
+/// Whether imgui wanted the mouse or the keyboard at the last frame.
+pub(crate) fn capturing_input() -> bool {
+    CAPTURING_INPUT.load(Ordering::SeqCst)
+}
+
+/// Mouse cursor visibility forced via [`set_mouse_cursor_visible`].
+pub(crate) fn mouse_cursor_visible() -> Option<bool> {
+    match MOUSE_CURSOR_VISIBLE.load(Ordering::SeqCst) {
+        1 => Some(false),
+        2 => Some(true),
+        _ => None,
+    }
+}
+
+// Restrict the work area of the main viewport, where imgui keeps the windows
+// visible and places popups, to `work_area`. Called at every frame, as imgui
+// resets it to the display minus the menu bars when a frame begins.
+fn apply_work_area(work_area: &WorkArea) {
+    let viewport = unsafe { &mut *sys::igGetMainViewport() };
+    let [left, top] = [viewport.WorkPos.x, viewport.WorkPos.y];
+    let [right, bottom] = [left + viewport.WorkSize.x, top + viewport.WorkSize.y];
+
+    let min = [work_area.min[0].clamp(left, right), work_area.min[1].clamp(top, bottom)];
+    let max = [work_area.max[0].clamp(left, right), work_area.max[1].clamp(top, bottom)];
+    if max[0] <= min[0] || max[1] <= min[1] {
+        return;
+    }
+
+    viewport.WorkPos = sys::ImVec2 { x: min[0], y: min[1] };
+    viewport.WorkSize = sys::ImVec2 { x: max[0] - min[0], y: max[1] - min[1] };
+}
+
+// Submit a dockspace over the work area of the main viewport. Its central node
+// is transparent to the game, and so is its host window, which has no
+// background.
+#[cfg(feature = "imgui-docking")]
+fn submit_dockspace() {
+    unsafe {
+        sys::igDockSpaceOverViewport(
+            sys::igGetMainViewport(),
+            sys::ImGuiDockNodeFlags_PassthruCentralNode as _,
+            std::ptr::null(),
+        );
+    }
+}
+
+// Stop capturing the input, and release the keys and mouse buttons held down,
+// so that no widget keeps reacting to them.
+fn release_input(io: &mut Io) {
+    io.want_capture_mouse = false;
+    io.want_capture_keyboard = false;
+    io.want_text_input = false;
+    CAPTURING_INPUT.store(false, Ordering::SeqCst);
+
+    unsafe { sys::ImGuiIO_ClearInputKeys(io.raw_mut()) };
+    for button in MouseButton::VARIANTS {
+        io.add_mouse_button_event(button, false);
+    }
+}
+
+// Build the font atlas and upload it, in place of the texture it was last
+// uploaded to, as the renderer sees fit.
+fn upload_font_atlas(
+    ctx: &mut Context,
+    engine: &mut impl RenderEngine,
+    current: Option<FontTexture>,
+) -> Result<FontTexture> {
+    let fonts = ctx.fonts();
+    let fonts_texture = fonts.build_rgba32_texture();
+    let (width, height) = (fonts_texture.width, fonts_texture.height);
+
+    let id = {
+        let data = font_atlas_pixels(fonts_texture.data);
+        let current = current.map(|current| (current.id, [current.width, current.height]));
+        engine.upload_font_texture(current, &data, width, height)?
+    };
+    fonts.tex_id = id;
+
+    Ok(FontTexture { id, width, height })
+}
+
+/// Hand the windows back to the application while the hooks are disabled, or
+/// route their messages through the pipelines again.
+///
+/// Replaced window procedures are restored, unless the window procedure was
+/// replaced again since. Subclasses stay, as they can only be installed from
+/// the thread owning the window, and forward the messages untouched.
+pub(crate) fn set_wnd_procs_suspended(suspended: bool) {
+    let pipeline_states = PIPELINE_STATES.lock();
+    WND_PROCS_SUSPENDED.store(suspended, Ordering::SeqCst);
+
+    for (&hwnd, shared_state) in pipeline_states.iter() {
+        let Some(WndProcHook::Replaced(wnd_proc)) = shared_state.wnd_proc else {
+            continue;
+        };
+        let (current, replacement) = if suspended {
+            (pipeline_wnd_proc as usize, wnd_proc as usize)
+        } else {
+            (wnd_proc as usize, pipeline_wnd_proc as usize)
+        };
+
+        let hwnd = HWND(hwnd);
+        unsafe {
+            if GetWindowLongPtrW(hwnd, GWLP_WNDPROC) as usize == current {
+                SetWindowLongPtrW(hwnd, GWLP_WNDPROC, replacement as _);
+            }
+        }
+    }
+}
+
+/// Forget that the hooks were disabled, e.g. when applying a new set of hooks
+/// after ejecting disabled ones.
+pub(crate) fn reset_wnd_procs_suspended() {
+    WND_PROCS_SUSPENDED.store(false, Ordering::SeqCst);
+}
+
+// Route the messages of the window through the pipeline. If that fails (e.g.
+// the window belongs to another process or is being destroyed), the pipeline
+// runs in render-only mode, without input.
+unsafe fn install_wnd_proc(hwnd: HWND, mode: WndProcMode) -> Option<WndProcHook> {
+    if mode == WndProcMode::Subclass {
+        // Subclasses can only be installed from the thread that owns the
+        // window, which is not always the one presenting.
+        if GetWindowThreadProcessId(hwnd, None) != GetCurrentThreadId() {
+            warn!("Window {hwnd:?} belongs to another thread, replacing its window procedure");
+        } else if SetWindowSubclass(hwnd, Some(pipeline_subclass_proc), SUBCLASS_ID, 0).as_bool() {
+            return Some(WndProcHook::Subclassed);
+        } else {
+            warn!("Could not subclass window {hwnd:?}, replacing its window procedure");
+        }
+    }
+
+    replace_wnd_proc(hwnd).map(WndProcHook::Replaced)
+}
+
+const SUBCLASS_ID: usize = 0x6875_6468; // "hudh"
+
+// Window property holding the window procedure replaced by `replace_wnd_proc`,
+// which can be read without locking the shared states.
+const WND_PROC_PROP: PCWSTR = w!("hudhook_wnd_proc");
+
+// Replace the window procedure with `pipeline_wnd_proc`, returning the
+// previous one.
+unsafe fn replace_wnd_proc(hwnd: HWND) -> Option<WndProcType> {
+    #[cfg(target_arch = "x86")]
+    type SwlpRet = i32;
+    #[cfg(target_arch = "x86_64")]
+    type SwlpRet = isize;
+
+    // A zero return value is only an error if the last error is set.
+    SetLastError(WIN32_ERROR(0));
+    let wnd_proc = SetWindowLongPtrW(hwnd, GWLP_WNDPROC, pipeline_wnd_proc as *const () as _);
+    if wnd_proc == 0 {
+        let e = Error::from_win32();
+        if e.code().is_err() {
+            error!("Could not install window procedure, input is disabled: {e:?}");
+        } else {
+            // There is no previous window procedure to forward messages to.
+            error!("Window {hwnd:?} has no window procedure, input is disabled");
+            SetWindowLongPtrW(hwnd, GWLP_WNDPROC, 0);
+        }
+        return None;
+    }
+
+    if let Err(e) = SetPropW(hwnd, WND_PROC_PROP, HANDLE(wnd_proc as isize)) {
+        warn!("Could not store the previous window procedure of {hwnd:?}: {e:?}");
+    }
+
+    Some(mem::transmute::<SwlpRet, WndProcType>(wnd_proc))
+}
+
+// Forward a message to the window procedure replaced by `replace_wnd_proc`,
+// for when the shared state of the window can't be retrieved.
+unsafe fn call_replaced_wnd_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    match GetPropW(hwnd, WND_PROC_PROP) {
+        HANDLE(0) => DefWindowProcW(hwnd, msg, wparam, lparam),
+        HANDLE(wnd_proc) => CallWindowProcW(
+            Some(mem::transmute::<isize, WndProcType>(wnd_proc)),
+            hwnd,
+            msg,
+            wparam,
+            lparam,
+        ),
+    }
+}
+
+const REMOVE_SUBCLASS_ATTEMPTS: u32 = 3;
+
+// Remove the subclass installed by `install_wnd_proc`, on the thread that owns
+// the window.
+unsafe fn remove_subclass(hwnd: HWND) {
+    if GetWindowThreadProcessId(hwnd, None) == GetCurrentThreadId() {
+        RemoveWindowSubclass(hwnd, Some(pipeline_subclass_proc), SUBCLASS_ID);
+        return;
+    }
+
+    // Time out rather than deadlock if the window thread is waiting on us, and
+    // retry in case it was only busy.
+    for attempt in 1..=REMOVE_SUBCLASS_ATTEMPTS {
+        let sent = SendMessageTimeoutW(
+            hwnd,
+            *REMOVE_SUBCLASS_MESSAGE,
+            WPARAM(0),
+            LPARAM(0),
+            SMTO_ABORTIFHUNG,
+            1000,
+            None,
+        );
+        if sent.0 != 0 {
+            return;
+        }
+
+        let e = Error::from_win32();
+        warn!("Could not remove the subclass of window {hwnd:?} (attempt {attempt}): {e:?}");
+        if !IsWindow(hwnd).as_bool() {
+            // The window is gone, and its subclass with it.
+            return;
+        }
+    }
+
+    error!("Could not remove the subclass of window {hwnd:?}, keeping the module loaded");
+    SUBCLASS_LEFT.store(true, Ordering::SeqCst);
+}
+
+/// Whether a window subclass couldn't be removed when tearing down a pipeline.
+/// The window still calls into the module, which must not be unloaded.
+pub(crate) fn subclass_left() -> bool {
+    SUBCLASS_LEFT.load(Ordering::SeqCst)
+}
+
+unsafe extern "system" fn pipeline_subclass_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    _: usize,
+    _: usize,
+) -> LRESULT {
+    if msg == *REMOVE_SUBCLASS_MESSAGE {
+        RemoveWindowSubclass(hwnd, Some(pipeline_subclass_proc), SUBCLASS_ID);
+        return LRESULT(0);
+    }
+
+    pipeline_wnd_proc_impl(hwnd, msg, wparam, lparam, DefSubclassProc)
+}
+
 unsafe extern "system" fn pipeline_wnd_proc(
     hwnd: HWND,
     msg: u32,
     wparam: WPARAM,
     lparam: LPARAM,
 ) -> LRESULT {
+    pipeline_wnd_proc_impl(hwnd, msg, wparam, lparam, call_replaced_wnd_proc)
+}
+
+// Feed a message to the pipeline. `default_wnd_proc` forwards the messages to
+// the window procedure of the application when the shared state of the window
+// can't be retrieved.
+unsafe fn pipeline_wnd_proc_impl(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    default_wnd_proc: unsafe fn(HWND, u32, WPARAM, LPARAM) -> LRESULT,
+) -> LRESULT {
+    if WND_PROCS_SUSPENDED.load(Ordering::SeqCst) {
+        return default_wnd_proc(hwnd, msg, wparam, lparam);
+    }
+
     let shared_state = {
-        let Some(shared_state_guard) = PIPELINE_STATES.try_lock() else {
+        // Wait a little for the lock rather than drop the message, but never
+        // stall the message loop of the game for long.
+        let Some(shared_state_guard) = PIPELINE_STATES.try_lock_for(Duration::from_millis(1))
+        else {
             error!("Could not lock shared state in window procedure");
-            return DefWindowProcW(hwnd, msg, wparam, lparam);
+            return default_wnd_proc(hwnd, msg, wparam, lparam);
         };
 
         let Some(shared_state) = shared_state_guard.get(&hwnd.0) else {
             error!("Could not get shared state for handle {hwnd:?}");
-            return DefWindowProcW(hwnd, msg, wparam, lparam);
+            return default_wnd_proc(hwnd, msg, wparam, lparam);
         };
 
         Arc::clone(shared_state)
     };
 
-    if let Err(e) = shared_state.tx.send(PipelineMessage(hwnd, msg, wparam, lparam)) {
+    if msg == WM_INPUT {
+        if let Some(virtual_cursor) = &shared_state.virtual_cursor {
+            update_virtual_cursor(&mut *virtual_cursor.lock(), hwnd, lparam);
+        }
+    }
+
+    if msg == WM_DPICHANGED {
+        // The horizontal and vertical DPI are always the same.
+        shared_state.pending_dpi.store(loword(wparam.0 as u32) as u32, Ordering::SeqCst);
+    }
+
+    if msg == WM_DROPFILES {
+        let hdrop = HDROP(wparam.0 as isize);
+        shared_state.dropped_files.lock().push(query_dropped_files(hdrop));
+
+        if shared_state.accept_files {
+            DragFinish(hdrop);
+            return LRESULT(0);
+        }
+    }
+
+    // While the visibility is forced, imgui draws the cursor if it's shown, so
+    // the OS cursor is hidden over the client area either way.
+    if msg == WM_SETCURSOR
+        && loword(lparam.0 as u32) as u32 == HTCLIENT
+        && mouse_cursor_visible().is_some()
+    {
+        SetCursor(HCURSOR(0));
+        return LRESULT(1);
+    }
+
+    // Timestamped now, as the message is only processed by the next frame.
+    let timestamp = (shared_state.input_queue_capacity > 0 && input_queue::is_input_message(msg))
+        .then(input_queue::timestamp);
+    if let Err(e) = shared_state.tx.send(PipelineMessage(hwnd, msg, wparam, lparam, timestamp)) {
         error!("Could not send window message through pipeline: {e:?}");
     }
 
@@ -204,9 +1290,353 @@ unsafe extern "system" fn pipeline_wnd_proc(
     let message_filter =
         MessageFilter::from_bits_retain(shared_state.message_filter.load(Ordering::SeqCst));
 
-    if message_filter.is_blocking(msg) {
+    if is_blocked(&shared_state, message_filter, msg, wparam, lparam) {
         LRESULT(1)
+    } else if let Some(WndProcHook::Replaced(wnd_proc)) = shared_state.wnd_proc {
+        CallWindowProcW(Some(wnd_proc), hwnd, msg, wparam, lparam)
     } else {
-        CallWindowProcW(Some(shared_state.wnd_proc), hwnd, msg, wparam, lparam)
+        default_wnd_proc(hwnd, msg, wparam, lparam)
+    }
+}
+
+// Let files be dropped onto the window, unless the application already does.
+// Returns whether drag and drop was enabled for the overlay.
+unsafe fn enable_drag_and_drop(hwnd: HWND) -> bool {
+    if GetWindowLongPtrW(hwnd, GWL_EXSTYLE) as u32 & WS_EX_ACCEPTFILES.0 != 0 {
+        return false;
+    }
+
+    DragAcceptFiles(hwnd, true);
+    true
+}
+
+// Read the paths and the drop point of a `WM_DROPFILES` message.
+unsafe fn query_dropped_files(hdrop: HDROP) -> DroppedFiles {
+    let count = DragQueryFileW(hdrop, u32::MAX, None);
+    let paths = (0..count)
+        .filter_map(|i| {
+            let mut path = vec![0u16; DragQueryFileW(hdrop, i, None) as usize + 1];
+            match DragQueryFileW(hdrop, i, Some(&mut path)) as usize {
+                0 => None,
+                len => Some(PathBuf::from(OsString::from_wide(&path[..len]))),
+            }
+        })
+        .collect();
+
+    let mut point = POINT::default();
+    DragQueryPoint(hdrop, &mut point);
+
+    DroppedFiles { paths, pos: [point.x as f32, point.y as f32] }
+}
+
+// Decide whether a message is withheld from the application.
+fn is_blocked(
+    shared_state: &PipelineSharedState,
+    message_filter: MessageFilter,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> bool {
+    // In passthrough mode, mouse input reaches the application unless the
+    // cursor is over the overlay, and so do the keys in the passthrough list,
+    // regardless of the filter.
+    let is_passthrough = (shared_state.mouse_passthrough
+        && !shared_state.mouse_over_overlay.load(Ordering::SeqCst)
+        && is_mouse_message(msg, lparam))
+        || is_passthrough_key(msg, wparam, lparam, &shared_state.passthrough_keys);
+
+    !is_passthrough && message_filter.is_blocking(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use imgui::Condition;
+    use windows::Win32::UI::Input::KeyboardAndMouse::{VK_A, VK_F13, VK_LSHIFT, VK_SHIFT};
+    use windows::Win32::UI::WindowsAndMessaging::{
+        CreateWindowExW, DestroyWindow, WINDOW_EX_STYLE, WM_KEYDOWN, WM_KEYUP, WM_LBUTTONDOWN,
+        WS_CHILD, WS_OVERLAPPEDWINDOW,
+    };
+
+    use super::*;
+    use crate::TextureError;
+
+    unsafe extern "system" fn wnd_proc(_: HWND, _: u32, _: WPARAM, _: LPARAM) -> LRESULT {
+        LRESULT(0)
+    }
+
+    // State of a window whose procedure was replaced, with the defaults of
+    // the options.
+    fn shared_state() -> PipelineSharedState {
+        PipelineSharedState {
+            message_filter: AtomicU32::new(0),
+            wnd_proc: Some(WndProcHook::Replaced(wnd_proc)),
+            tx: mpsc::channel().0,
+            virtual_cursor: None,
+            raw_mouse_registered: false,
+            mouse_passthrough: false,
+            mouse_over_overlay: AtomicBool::new(false),
+            passthrough_keys: Vec::new(),
+            pending_dpi: AtomicU32::new(0),
+            dropped_files: Mutex::new(Vec::new()),
+            accept_files: false,
+            input_queue_capacity: 0,
+        }
+    }
+
+    #[test]
+    fn test_passthrough_keys() {
+        let shared_state =
+            PipelineSharedState { passthrough_keys: vec![VK_F13, VK_LSHIFT], ..shared_state() };
+        let filter = MessageFilter::InputKeyboard;
+        let key = |virtual_key: VIRTUAL_KEY| WPARAM(virtual_key.0 as _);
+
+        assert!(!is_blocked(&shared_state, filter, WM_KEYDOWN, key(VK_F13), LPARAM(0)));
+        assert!(!is_blocked(&shared_state, filter, WM_KEYUP, key(VK_F13), LPARAM(0)));
+        assert!(is_blocked(&shared_state, filter, WM_KEYDOWN, key(VK_A), LPARAM(0)));
+        assert!(is_blocked(&shared_state, filter, WM_KEYUP, key(VK_A), LPARAM(0)));
+
+        // Left and right shift are told apart by their scan code.
+        assert!(!is_blocked(&shared_state, filter, WM_KEYDOWN, key(VK_SHIFT), LPARAM(0x2a << 16)));
+        assert!(is_blocked(&shared_state, filter, WM_KEYDOWN, key(VK_SHIFT), LPARAM(0x36 << 16)));
+
+        assert!(!is_blocked(
+            &shared_state,
+            MessageFilter::empty(),
+            WM_KEYDOWN,
+            key(VK_A),
+            LPARAM(0)
+        ));
+    }
+
+    #[test]
+    fn test_mouse_passthrough() {
+        let shared_state = PipelineSharedState { mouse_passthrough: true, ..shared_state() };
+        let filter = MessageFilter::InputAll;
+
+        assert!(!is_blocked(&shared_state, filter, WM_LBUTTONDOWN, WPARAM(0), LPARAM(0)));
+        assert!(is_blocked(&shared_state, filter, WM_KEYDOWN, WPARAM(0), LPARAM(0)));
+
+        // Over a window of the overlay, the filter applies again.
+        shared_state.mouse_over_overlay.store(true, Ordering::SeqCst);
+        assert!(is_blocked(&shared_state, filter, WM_LBUTTONDOWN, WPARAM(0), LPARAM(0)));
+    }
+
+    #[test]
+    fn test_settings_survive_rebuild() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+        let mut ctx = crate::renderer::test_context();
+        let ui = ctx.frame();
+        ui.window("Moved").position([123., 45.], Condition::Always).build(|| {});
+        ctx.render();
+        save_settings(&mut ctx);
+        drop(ctx);
+
+        let mut ctx = crate::renderer::test_context();
+        restore_settings(&mut ctx);
+        let ui = ctx.frame();
+        let pos = ui.window("Moved").build(|| ui.window_pos()).unwrap();
+        ctx.render();
+
+        assert_eq!(pos, [123., 45.]);
+        assert!(SAVED_SETTINGS.lock().is_none());
+    }
+
+    #[test]
+    fn test_dpi_changed() {
+        let hwnd = HWND(0x7e57);
+        let shared_state = Arc::new(shared_state());
+        PIPELINE_STATES.lock().insert(hwnd.0, Arc::clone(&shared_state));
+
+        let dpi = WPARAM((144 << 16) | 144);
+        unsafe { pipeline_wnd_proc(hwnd, WM_DPICHANGED, dpi, LPARAM(0)) };
+        PIPELINE_STATES.lock().remove(&hwnd.0);
+
+        assert_eq!(shared_state.pending_dpi.load(Ordering::SeqCst), 144);
+    }
+
+    #[test]
+    fn test_is_same_root() {
+        let create_window = |style, parent| unsafe {
+            CreateWindowExW(
+                WINDOW_EX_STYLE(0),
+                w!("STATIC"),
+                w!("hudhook"),
+                style,
+                0,
+                0,
+                100,
+                100,
+                parent,
+                None,
+                None,
+                None,
+            )
+        };
+        let parent = create_window(WS_OVERLAPPEDWINDOW, HWND(0));
+        let child = create_window(WS_CHILD, parent);
+        let grandchild = create_window(WS_CHILD, child);
+        let other = create_window(WS_OVERLAPPEDWINDOW, HWND(0));
+
+        assert!(is_same_root(child, parent));
+        assert!(is_same_root(parent, grandchild));
+        assert!(is_same_root(child, grandchild));
+        assert!(!is_same_root(other, child));
+
+        unsafe {
+            DestroyWindow(other).unwrap();
+            DestroyWindow(parent).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_work_area() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+        let mut ctx = crate::renderer::test_context();
+
+        let work_area = WorkArea { min: [500., 0.], max: [800., 600.] };
+        let work_rect = |ctx: &mut Context, display_size: [f32; 2]| {
+            ctx.io_mut().display_size = display_size;
+            ctx.frame();
+            apply_work_area(&work_area);
+            let viewport = unsafe { &*sys::igGetMainViewport() };
+            let work_pos = [viewport.WorkPos.x, viewport.WorkPos.y];
+            let work_size = [viewport.WorkSize.x, viewport.WorkSize.y];
+            ctx.render();
+            (work_pos, work_size)
+        };
+
+        assert_eq!(work_rect(&mut ctx, [800., 600.]), ([500., 0.], [300., 600.]));
+        // Shrinks along with the display.
+        assert_eq!(work_rect(&mut ctx, [640., 480.]), ([500., 0.], [140., 480.]));
+        // Ignored while none of it is visible.
+        assert_eq!(work_rect(&mut ctx, [400., 300.]), ([0., 0.], [400., 300.]));
+    }
+
+    #[test]
+    fn test_release_input() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+        let mut ctx = crate::renderer::test_context();
+
+        let io = ctx.io_mut();
+        io.add_key_event(imgui::Key::A, true);
+        io.add_mouse_button_event(MouseButton::Left, true);
+        let ui = ctx.frame();
+        assert!(ui.is_key_down(imgui::Key::A));
+        assert!(ui.is_mouse_down(MouseButton::Left));
+        ctx.render();
+
+        let io = ctx.io_mut();
+        io.want_capture_mouse = true;
+        io.want_capture_keyboard = true;
+        CAPTURING_INPUT.store(true, Ordering::SeqCst);
+        release_input(io);
+        assert!(!capturing_input());
+        assert!(!io.want_capture_mouse);
+        assert!(!io.want_capture_keyboard);
+
+        let ui = ctx.frame();
+        assert!(!ui.is_key_down(imgui::Key::A));
+        assert!(!ui.is_mouse_down(MouseButton::Left));
+        ctx.render();
+    }
+
+    // Records the textures instead of uploading them.
+    #[derive(Default)]
+    struct FakeRenderContext {
+        textures: Vec<(u32, u32)>,
+        replaced: Vec<TextureId>,
+    }
+
+    impl RenderContext for FakeRenderContext {
+        fn load_texture(
+            &mut self,
+            _: &[u8],
+            width: u32,
+            height: u32,
+        ) -> std::result::Result<TextureId, TextureError> {
+            self.textures.push((width, height));
+            Ok(TextureId::from(self.textures.len() - 1))
+        }
+
+        fn replace_texture(
+            &mut self,
+            texture_id: TextureId,
+            _: &[u8],
+            width: u32,
+            height: u32,
+        ) -> std::result::Result<(), TextureError> {
+            assert_eq!(self.textures[texture_id.id()], (width, height));
+            self.replaced.push(texture_id);
+            Ok(())
+        }
+    }
+
+    impl RenderEngine for FakeRenderContext {
+        type RenderTarget = ();
+
+        fn render(&mut self, _: &DrawData, _: ()) -> Result<()> {
+            Ok(())
+        }
+
+        fn setup_fonts(&mut self, _: &mut Context) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_switch_fonts() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+        let mut ctx = crate::renderer::test_context();
+        let mut render_context = FakeRenderContext::default();
+
+        let texture = upload_font_atlas(&mut ctx, &mut render_context, None).unwrap();
+        let draw_texture = |ctx: &mut Context| {
+            let ui = ctx.frame();
+            ui.window("Text").build(|| ui.text("Hello"));
+            let draw_data = ctx.render();
+            draw_data
+                .draw_lists()
+                .flat_map(|draw_list| draw_list.commands())
+                .find_map(|command| match command {
+                    imgui::DrawCmd::Elements { cmd_params, .. } => Some(cmd_params.texture_id),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        assert_eq!(draw_texture(&mut ctx), texture.id);
+
+        // Rebuilding the same atlas updates the texture in place.
+        let rebuilt = upload_font_atlas(&mut ctx, &mut render_context, Some(texture)).unwrap();
+        assert_eq!(rebuilt, texture);
+        assert_eq!(render_context.replaced, [texture.id]);
+
+        // A larger atlas doesn't fit the texture, and gets a new one that is
+        // used from the next frame on.
+        ctx.fonts().add_font(&[imgui::FontSource::DefaultFontData {
+            config: Some(imgui::FontConfig { size_pixels: 64., ..Default::default() }),
+        }]);
+        let switched = upload_font_atlas(&mut ctx, &mut render_context, Some(rebuilt)).unwrap();
+        assert_ne!(switched.id, texture.id);
+        assert_eq!(render_context.textures.len(), 2);
+        assert_eq!(draw_texture(&mut ctx), switched.id);
+    }
+
+    #[test]
+    fn test_client_mapping() {
+        // A 1280x720 back buffer in a 1920x1200 client area.
+        let back_buffer = [1280., 720.];
+        let client = [1920., 1200.];
+
+        assert_eq!(ClientMapping::IDENTITY.map([100., 50.]), [100., 50.]);
+
+        let stretch = ClientMapping::stretch(back_buffer, client);
+        assert_eq!(stretch.map([960., 600.]), [640., 360.]);
+
+        // Scaled by 1.5 and letterboxed by 60 pixels on top and bottom.
+        let letterbox = ClientMapping::aspect_ratio_stretch(back_buffer, client);
+        assert_eq!(letterbox.map([0., 60.]), [0., 0.]);
+        assert_eq!(letterbox.map([1920., 1140.]), [1280., 720.]);
+
+        assert_eq!(ClientMapping::stretch(back_buffer, [0., 0.]), ClientMapping::IDENTITY);
     }
 }
@@ -0,0 +1,282 @@
+//! Colors of single pixels of the back buffer, requested via
+//! [`sample_pixel`](crate::sample_pixel).
+//!
+//! The DirectX 12 renderer copies the requested pixel to a readback buffer
+//! before drawing the overlay, and reads it back at a later render, once the
+//! GPU is done with the frame.
+
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{ptr, slice};
+
+use parking_lot::Mutex;
+use tracing::warn;
+use windows::core::Result;
+use windows::Win32::Graphics::Direct3D12::{
+    ID3D12Device, ID3D12GraphicsCommandList, ID3D12Resource, D3D12_BOX,
+    D3D12_CPU_PAGE_PROPERTY_UNKNOWN, D3D12_HEAP_FLAG_NONE, D3D12_HEAP_PROPERTIES,
+    D3D12_HEAP_TYPE_READBACK, D3D12_MEMORY_POOL_UNKNOWN, D3D12_PLACED_SUBRESOURCE_FOOTPRINT,
+    D3D12_RANGE, D3D12_RESOURCE_DESC, D3D12_RESOURCE_DIMENSION_BUFFER, D3D12_RESOURCE_FLAG_NONE,
+    D3D12_RESOURCE_STATE_COPY_DEST, D3D12_RESOURCE_STATE_COPY_SOURCE,
+    D3D12_RESOURCE_STATE_RENDER_TARGET, D3D12_SUBRESOURCE_FOOTPRINT, D3D12_TEXTURE_COPY_LOCATION,
+    D3D12_TEXTURE_COPY_LOCATION_0, D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+    D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX, D3D12_TEXTURE_DATA_PITCH_ALIGNMENT,
+    D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+};
+use windows::Win32::Graphics::Dxgi::Common::*;
+
+use crate::util;
+
+// Client coordinates of the pixel to sample at the next render.
+static REQUEST: Mutex<Option<[i32; 2]>> = Mutex::new(None);
+// Client coordinates and color of the pixel sampled last.
+static SAMPLE: Mutex<Option<([i32; 2], [u8; 4])>> = Mutex::new(None);
+
+/// Request the pixel at `point` to be sampled, and return its color if it was
+/// the one sampled last.
+pub(crate) fn request(point: [i32; 2]) -> Option<[u8; 4]> {
+    *REQUEST.lock() = Some(point);
+    SAMPLE.lock().filter(|&(sampled, _)| sampled == point).map(|(_, color)| color)
+}
+
+/// Take the client coordinates of the pixel requested last, if any.
+pub(crate) fn take_request() -> Option<[i32; 2]> {
+    REQUEST.lock().take()
+}
+
+/// Request the pixel at `point` again, unless another one was requested since.
+pub(crate) fn retry(point: [i32; 2]) {
+    REQUEST.lock().get_or_insert(point);
+}
+
+/// Forget the pixel requested and the one sampled, e.g. when unhooking.
+pub(crate) fn reset() {
+    REQUEST.lock().take();
+    SAMPLE.lock().take();
+}
+
+/// Readback buffer of the DirectX 12 renderer, holding one pixel.
+pub(crate) struct PixelSampler {
+    readback_buffer: ID3D12Resource,
+    // Client coordinates and format of the pixel being copied, and the fence
+    // value signaled once it is.
+    pending: Option<([i32; 2], DXGI_FORMAT, u64)>,
+}
+
+impl PixelSampler {
+    pub(crate) fn new(device: &ID3D12Device) -> Result<Self> {
+        let readback_buffer: ID3D12Resource = util::try_out_ptr(|v| unsafe {
+            device.CreateCommittedResource(
+                &D3D12_HEAP_PROPERTIES {
+                    Type: D3D12_HEAP_TYPE_READBACK,
+                    CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+                    MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+                    CreationNodeMask: Default::default(),
+                    VisibleNodeMask: Default::default(),
+                },
+                D3D12_HEAP_FLAG_NONE,
+                &D3D12_RESOURCE_DESC {
+                    Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+                    Alignment: 0,
+                    // One row, of the minimum pitch.
+                    Width: D3D12_TEXTURE_DATA_PITCH_ALIGNMENT as u64,
+                    Height: 1,
+                    DepthOrArraySize: 1,
+                    MipLevels: 1,
+                    Format: DXGI_FORMAT_UNKNOWN,
+                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                    Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+                    Flags: D3D12_RESOURCE_FLAG_NONE,
+                },
+                D3D12_RESOURCE_STATE_COPY_DEST,
+                None,
+                v,
+            )
+        })?;
+
+        Ok(Self { readback_buffer, pending: None })
+    }
+
+    /// Whether a copy was recorded and not read back yet.
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Record the copy of the pixel at `texel` of `render_target`, in the copy
+    /// source state, leaving `render_target` in the render target state. The
+    /// color is read back by [`PixelSampler::resolve`] once `fence_value` is
+    /// reached, for the client coordinates `point`.
+    pub(crate) unsafe fn record_copy(
+        &mut self,
+        command_list: &ID3D12GraphicsCommandList,
+        render_target: &ID3D12Resource,
+        point: [i32; 2],
+        [x, y]: [u32; 2],
+        fence_value: u64,
+    ) {
+        let format = render_target.GetDesc().Format;
+
+        let src_location = D3D12_TEXTURE_COPY_LOCATION {
+            pResource: ManuallyDrop::new(Some(render_target.clone())),
+            Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 { SubresourceIndex: 0 },
+        };
+        let dst_location = D3D12_TEXTURE_COPY_LOCATION {
+            pResource: ManuallyDrop::new(Some(self.readback_buffer.clone())),
+            Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
+                PlacedFootprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT {
+                    Offset: 0,
+                    Footprint: D3D12_SUBRESOURCE_FOOTPRINT {
+                        Format: format,
+                        Width: 1,
+                        Height: 1,
+                        Depth: 1,
+                        RowPitch: D3D12_TEXTURE_DATA_PITCH_ALIGNMENT,
+                    },
+                },
+            },
+        };
+        let src_box = D3D12_BOX { left: x, top: y, front: 0, right: x + 1, bottom: y + 1, back: 1 };
+
+        let after_copy = [util::create_barrier(
+            render_target,
+            D3D12_RESOURCE_STATE_COPY_SOURCE,
+            D3D12_RESOURCE_STATE_RENDER_TARGET,
+        )];
+
+        command_list.CopyTextureRegion(&dst_location, 0, 0, 0, &src_location, Some(&src_box));
+        command_list.ResourceBarrier(&after_copy);
+
+        after_copy.into_iter().for_each(util::drop_barrier);
+
+        // Release the references taken by the copy locations.
+        let _ = ManuallyDrop::into_inner(src_location.pResource);
+        let _ = ManuallyDrop::into_inner(dst_location.pResource);
+
+        self.pending = Some((point, format, fence_value));
+    }
+
+    /// Read back the pixel copied last, if the GPU reached
+    /// `completed_fence_value` since.
+    pub(crate) unsafe fn resolve(&mut self, completed_fence_value: u64) -> Result<()> {
+        let Some((point, format, fence_value)) = self.pending else {
+            return Ok(());
+        };
+        if completed_fence_value < fence_value {
+            return Ok(());
+        }
+        self.pending = None;
+
+        let mut readback_ptr = ptr::null_mut();
+        self.readback_buffer.Map(
+            0,
+            Some(&D3D12_RANGE { Begin: 0, End: TEXEL_SIZE }),
+            Some(&mut readback_ptr),
+        )?;
+        let texel = slice::from_raw_parts(readback_ptr as *const u8, TEXEL_SIZE);
+        let color = texel_to_rgba8(format, texel);
+        self.readback_buffer.Unmap(0, Some(&D3D12_RANGE { Begin: 0, End: 0 }));
+
+        static UNSUPPORTED_FORMAT_LOGGED: AtomicBool = AtomicBool::new(false);
+        match color {
+            Some(color) => *SAMPLE.lock() = Some((point, color)),
+            None if !UNSUPPORTED_FORMAT_LOGGED.swap(true, Ordering::SeqCst) => {
+                warn!("Can't sample pixels of back buffers in format {format:?}")
+            },
+            None => {},
+        }
+
+        Ok(())
+    }
+}
+
+// Largest size of a pixel in the back buffer formats supported.
+const TEXEL_SIZE: usize = 8;
+
+/// Convert a pixel of a back buffer in `format` to 8-bit RGBA. Returns `None`
+/// for formats that back buffers aren't created with.
+///
+/// 10-bit colors are scaled down as they are, e.g. still PQ-encoded for HDR10.
+/// 16-bit float colors are linear (scRGB), and are clamped to SDR and
+/// sRGB-encoded.
+pub(crate) fn texel_to_rgba8(format: DXGI_FORMAT, texel: &[u8]) -> Option<[u8; 4]> {
+    match format {
+        DXGI_FORMAT_R8G8B8A8_UNORM
+        | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+        | DXGI_FORMAT_R8G8B8A8_TYPELESS => Some([texel[0], texel[1], texel[2], texel[3]]),
+        DXGI_FORMAT_B8G8R8A8_UNORM
+        | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB
+        | DXGI_FORMAT_B8G8R8A8_TYPELESS => Some([texel[2], texel[1], texel[0], texel[3]]),
+        DXGI_FORMAT_B8G8R8X8_UNORM | DXGI_FORMAT_B8G8R8X8_UNORM_SRGB => {
+            Some([texel[2], texel[1], texel[0], 0xff])
+        },
+        DXGI_FORMAT_R10G10B10A2_UNORM | DXGI_FORMAT_R10G10B10A2_TYPELESS => {
+            let value = u32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]]);
+            let channel = |shift: u32| (((value >> shift) & 0x3ff) >> 2) as u8;
+            Some([channel(0), channel(10), channel(20), ((value >> 30) * 0x55) as u8])
+        },
+        DXGI_FORMAT_R16G16B16A16_FLOAT => {
+            let channel =
+                |i: usize| f16_to_f32(u16::from_le_bytes([texel[2 * i], texel[2 * i + 1]]));
+            let srgb = |i: usize| (linear_to_srgb(channel(i).clamp(0., 1.)) * 255.).round() as u8;
+            Some([srgb(0), srgb(1), srgb(2), (channel(3).clamp(0., 1.) * 255.).round() as u8])
+        },
+        _ => None,
+    }
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0. => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1. + mantissa / 1024.) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_texel_to_rgba8() {
+        let texel = [0x10, 0x20, 0x30, 0x40, 0, 0, 0, 0];
+        assert_eq!(
+            texel_to_rgba8(DXGI_FORMAT_R8G8B8A8_UNORM, &texel),
+            Some([0x10, 0x20, 0x30, 0x40])
+        );
+        assert_eq!(
+            texel_to_rgba8(DXGI_FORMAT_B8G8R8A8_UNORM, &texel),
+            Some([0x30, 0x20, 0x10, 0x40])
+        );
+
+        // Red at full intensity, green at half, blue off, opaque.
+        let value: u32 = 0x3ff | (0x200 << 10) | (3 << 30);
+        let texel = [value.to_le_bytes(), [0; 4]].concat();
+        assert_eq!(
+            texel_to_rgba8(DXGI_FORMAT_R10G10B10A2_UNORM, &texel),
+            Some([0xff, 0x80, 0, 0xff])
+        );
+
+        // 1.0, 0.5, 2.0 (brighter than SDR white), 1.0.
+        let texel = [0x00, 0x3c, 0x00, 0x38, 0x00, 0x40, 0x00, 0x3c];
+        assert_eq!(
+            texel_to_rgba8(DXGI_FORMAT_R16G16B16A16_FLOAT, &texel),
+            Some([255, 188, 255, 255])
+        );
+
+        assert_eq!(texel_to_rgba8(DXGI_FORMAT_R32G32B32A32_FLOAT, &texel), None);
+    }
+}
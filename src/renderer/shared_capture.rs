@@ -0,0 +1,231 @@
+//! Copy of the back buffer, with the overlay on top, shared with other
+//! processes, set via
+//! [`HudhookBuilder::with_shared_capture`](crate::HudhookBuilder::with_shared_capture).
+//!
+//! The DirectX 12 renderer copies the back buffer to a texture created with
+//! `D3D12_HEAP_FLAG_SHARED` once the overlay is drawn, then signals a shared
+//! fence with the number of the frame copied.
+
+use parking_lot::Mutex;
+use tracing::{debug, error};
+use windows::core::{Result, PCWSTR};
+use windows::Win32::Foundation::{
+    CloseHandle, DuplicateHandle, BOOL, DUPLICATE_SAME_ACCESS, GENERIC_ALL, HANDLE,
+};
+use windows::Win32::Graphics::Direct3D12::{
+    ID3D12CommandQueue, ID3D12Device, ID3D12Fence, ID3D12GraphicsCommandList, ID3D12Resource,
+    D3D12_CPU_PAGE_PROPERTY_UNKNOWN, D3D12_FENCE_FLAG_SHARED, D3D12_HEAP_FLAG_SHARED,
+    D3D12_HEAP_PROPERTIES, D3D12_HEAP_TYPE_DEFAULT, D3D12_MEMORY_POOL_UNKNOWN, D3D12_RESOURCE_DESC,
+    D3D12_RESOURCE_FLAG_NONE, D3D12_RESOURCE_STATE_COMMON, D3D12_RESOURCE_STATE_COPY_DEST,
+    D3D12_RESOURCE_STATE_COPY_SOURCE, D3D12_RESOURCE_STATE_RENDER_TARGET,
+    D3D12_TEXTURE_LAYOUT_UNKNOWN,
+};
+use windows::Win32::Graphics::Dxgi::Common::DXGI_SAMPLE_DESC;
+use windows::Win32::System::Threading::GetCurrentProcess;
+
+use crate::util;
+
+// Handles of the current texture and of the fence, and the last value the
+// fence was signaled with.
+static SHARED: Mutex<Option<SharedHandles>> = Mutex::new(None);
+
+#[derive(Clone, Copy)]
+struct SharedHandles {
+    texture: Option<HANDLE>,
+    fence: HANDLE,
+    fence_value: u64,
+}
+
+/// Duplicate of the handle of the texture the frames are copied to, if any,
+/// owned by the caller.
+pub(crate) fn texture_handle() -> Option<HANDLE> {
+    // The lock keeps the render thread from closing the handle meanwhile.
+    let shared = SHARED.lock();
+    shared.and_then(|handles| duplicate_handle(handles.texture?))
+}
+
+/// Duplicate of the handle of the fence signaled once a frame is copied,
+/// owned by the caller, and the value it was last signaled with, if any.
+pub(crate) fn fence_handle() -> Option<(HANDLE, u64)> {
+    let shared = SHARED.lock();
+    shared.and_then(|handles| Some((duplicate_handle(handles.fence)?, handles.fence_value)))
+}
+
+/// Shared texture and fence of the DirectX 12 renderer.
+pub(crate) struct SharedCapture {
+    device: ID3D12Device,
+    fence: ID3D12Fence,
+    fence_handle: HANDLE,
+    fence_value: u64,
+    // Recreated when the back buffers change size or format.
+    texture: Option<(ID3D12Resource, HANDLE)>,
+}
+
+impl SharedCapture {
+    pub(crate) fn new(device: &ID3D12Device) -> Result<Self> {
+        let fence: ID3D12Fence = unsafe { device.CreateFence(0, D3D12_FENCE_FLAG_SHARED) }?;
+        let fence_handle =
+            unsafe { device.CreateSharedHandle(&fence, None, GENERIC_ALL.0, PCWSTR::null()) }?;
+        *SHARED.lock() = Some(SharedHandles { texture: None, fence: fence_handle, fence_value: 0 });
+
+        Ok(Self { device: device.clone(), fence, fence_handle, fence_value: 0, texture: None })
+    }
+
+    /// Record the copy of `render_target`, in the render target state, to the
+    /// shared texture, leaving `render_target` in the copy source state.
+    ///
+    /// If the shared texture is recreated, the previous one is returned: it
+    /// must be kept alive until the GPU is done with the frames submitted
+    /// before.
+    pub(crate) unsafe fn record_copy(
+        &mut self,
+        command_list: &ID3D12GraphicsCommandList,
+        render_target: &ID3D12Resource,
+    ) -> Result<Option<ID3D12Resource>> {
+        let desc = render_target.GetDesc();
+        let mut previous = None;
+        let texture = match &self.texture {
+            Some((texture, _)) if is_same_texture(&texture.GetDesc(), &desc) => texture.clone(),
+            _ => {
+                let (texture, replaced) = self.create_texture(&desc)?;
+                previous = replaced;
+                texture
+            },
+        };
+
+        let before_copy = [
+            util::create_barrier(
+                render_target,
+                D3D12_RESOURCE_STATE_RENDER_TARGET,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+            ),
+            util::create_barrier(
+                &texture,
+                D3D12_RESOURCE_STATE_COMMON,
+                D3D12_RESOURCE_STATE_COPY_DEST,
+            ),
+        ];
+        let after_copy = [util::create_barrier(
+            &texture,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+            D3D12_RESOURCE_STATE_COMMON,
+        )];
+
+        command_list.ResourceBarrier(&before_copy);
+        command_list.CopyResource(&texture, render_target);
+        command_list.ResourceBarrier(&after_copy);
+
+        before_copy.into_iter().for_each(util::drop_barrier);
+        after_copy.into_iter().for_each(util::drop_barrier);
+
+        Ok(previous)
+    }
+
+    /// Signal the shared fence once the copy recorded last is executed.
+    pub(crate) unsafe fn signal(&mut self, command_queue: &ID3D12CommandQueue) -> Result<()> {
+        self.fence_value += 1;
+        command_queue.Signal(&self.fence, self.fence_value)?;
+        if let Some(handles) = SHARED.lock().as_mut() {
+            handles.fence_value = self.fence_value;
+        }
+        Ok(())
+    }
+
+    // Replace the shared texture with one matching `desc`, returning the new
+    // one and the previous one, if any.
+    unsafe fn create_texture(
+        &mut self,
+        desc: &D3D12_RESOURCE_DESC,
+    ) -> Result<(ID3D12Resource, Option<ID3D12Resource>)> {
+        debug!("Creating shared capture texture of {}x{}", desc.Width, desc.Height);
+        let previous = self.texture.take().map(|(texture, handle)| {
+            close_handle(handle);
+            texture
+        });
+        if let Some(handles) = SHARED.lock().as_mut() {
+            handles.texture = None;
+        }
+
+        let texture: ID3D12Resource = util::try_out_ptr(|v| {
+            self.device.CreateCommittedResource(
+                &D3D12_HEAP_PROPERTIES {
+                    Type: D3D12_HEAP_TYPE_DEFAULT,
+                    CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+                    MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+                    CreationNodeMask: Default::default(),
+                    VisibleNodeMask: Default::default(),
+                },
+                D3D12_HEAP_FLAG_SHARED,
+                &D3D12_RESOURCE_DESC {
+                    Alignment: 0,
+                    DepthOrArraySize: 1,
+                    MipLevels: 1,
+                    SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+                    Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+                    Flags: D3D12_RESOURCE_FLAG_NONE,
+                    ..*desc
+                },
+                D3D12_RESOURCE_STATE_COMMON,
+                None,
+                v,
+            )
+        })?;
+        let handle =
+            self.device.CreateSharedHandle(&texture, None, GENERIC_ALL.0, PCWSTR::null())?;
+
+        if let Some(handles) = SHARED.lock().as_mut() {
+            handles.texture = Some(handle);
+        }
+        self.texture = Some((texture.clone(), handle));
+
+        Ok((texture, previous))
+    }
+}
+
+impl Drop for SharedCapture {
+    fn drop(&mut self) {
+        *SHARED.lock() = None;
+        if let Some((_, handle)) = self.texture.take() {
+            close_handle(handle);
+        }
+        close_handle(self.fence_handle);
+    }
+}
+
+// Whether a texture of `desc` can be copied to one of `shared`: copies need
+// the same size and a compatible format, and the back buffers are created
+// with the exact same ones.
+fn is_same_texture(shared: &D3D12_RESOURCE_DESC, desc: &D3D12_RESOURCE_DESC) -> bool {
+    shared.Width == desc.Width && shared.Height == desc.Height && shared.Format == desc.Format
+}
+
+// Duplicate `handle` within the current process, so that it stays valid once
+// the renderer closes its own.
+fn duplicate_handle(handle: HANDLE) -> Option<HANDLE> {
+    let process = unsafe { GetCurrentProcess() };
+    let mut duplicate = HANDLE::default();
+    match unsafe {
+        DuplicateHandle(
+            process,
+            handle,
+            process,
+            &mut duplicate,
+            0,
+            BOOL::from(false),
+            DUPLICATE_SAME_ACCESS,
+        )
+    } {
+        Ok(()) => Some(duplicate),
+        Err(e) => {
+            error!("Could not duplicate shared capture handle: {e:?}");
+            None
+        },
+    }
+}
+
+// The object stays alive as long as another process holds a handle to it.
+fn close_handle(handle: HANDLE) {
+    if let Err(e) = unsafe { CloseHandle(handle) } {
+        error!("Could not close shared capture handle: {e:?}");
+    }
+}
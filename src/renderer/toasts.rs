@@ -0,0 +1,135 @@
+//! Notifications queued via
+//! [`RenderContext::notify`](crate::RenderContext::notify) and drawn by
+//! [`hudhook`](crate) on top of the render loop UI.
+
+use std::time::Duration;
+
+use imgui::Ui;
+use parking_lot::Mutex;
+
+use crate::{frame_clock, options, ScreenCorner, ToastStyle};
+
+static TOASTS: Mutex<Vec<Toast>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, PartialEq)]
+struct Toast {
+    text: String,
+    // Frame time at which the toast was queued, and for how long it's shown.
+    shown_at: Duration,
+    duration: Duration,
+}
+
+impl Toast {
+    fn expires_at(&self) -> Duration {
+        self.shown_at.saturating_add(self.duration)
+    }
+
+    // Opacity of the toast, which fades out over the last `fade` of its
+    // duration.
+    fn alpha(&self, now: Duration, fade: Duration) -> f32 {
+        let remaining = self.expires_at().saturating_sub(now);
+        if fade.is_zero() || remaining >= fade {
+            1.0
+        } else {
+            remaining.as_secs_f32() / fade.as_secs_f32()
+        }
+    }
+}
+
+/// Queue a toast, shown from the current frame for `duration`.
+pub(crate) fn push(text: &str, duration: Duration) {
+    TOASTS.lock().push(Toast { text: text.to_owned(), shown_at: frame_clock::time(), duration });
+}
+
+// Drop the toasts whose duration elapsed.
+fn expire(toasts: &mut Vec<Toast>, now: Duration) {
+    toasts.retain(|toast| toast.expires_at() > now);
+}
+
+/// Draw the pending toasts on the foreground draw list, so that they're on top
+/// of every window, stacked from the configured corner with the oldest one
+/// closest to it.
+pub(crate) fn render(ui: &Ui) {
+    let now = frame_clock::time();
+    let mut toasts = TOASTS.lock();
+    expire(&mut toasts, now);
+    if toasts.is_empty() {
+        return;
+    }
+
+    let (corner, style) = {
+        let options = options::get();
+        (options.toast_corner, options.toast_style)
+    };
+    let ToastStyle { background, text, padding, margin, rounding, fade } = style;
+
+    let [display_width, display_height] = ui.io().display_size;
+    let draw_list = ui.get_foreground_draw_list();
+
+    let mut offset = margin;
+    for toast in toasts.iter() {
+        let [text_width, text_height] = ui.calc_text_size(&toast.text);
+        let [width, height] = [text_width + padding[0] * 2., text_height + padding[1] * 2.];
+
+        let x = match corner {
+            ScreenCorner::TopLeft | ScreenCorner::BottomLeft => margin,
+            ScreenCorner::TopRight | ScreenCorner::BottomRight => display_width - margin - width,
+        };
+        let y = match corner {
+            ScreenCorner::TopLeft | ScreenCorner::TopRight => offset,
+            ScreenCorner::BottomLeft | ScreenCorner::BottomRight => {
+                display_height - offset - height
+            },
+        };
+        offset += height + margin;
+
+        let alpha = toast.alpha(now, fade);
+        let fade_out = |[r, g, b, a]: [f32; 4]| [r, g, b, a * alpha];
+
+        draw_list
+            .add_rect([x, y], [x + width, y + height], fade_out(background))
+            .filled(true)
+            .rounding(rounding)
+            .build();
+        draw_list.add_text([x + padding[0], y + padding[1]], fade_out(text), &toast.text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toast(text: &str, shown_at: u64, duration: u64) -> Toast {
+        Toast {
+            text: text.to_owned(),
+            shown_at: Duration::from_millis(shown_at),
+            duration: Duration::from_millis(duration),
+        }
+    }
+
+    #[test]
+    fn test_toast_expiry() {
+        let mut toasts = vec![toast("loaded", 0, 1000), toast("saved", 500, 2000)];
+
+        expire(&mut toasts, Duration::from_millis(999));
+        assert_eq!(toasts.len(), 2);
+
+        expire(&mut toasts, Duration::from_millis(1000));
+        assert_eq!(toasts, vec![toast("saved", 500, 2000)]);
+
+        expire(&mut toasts, Duration::from_millis(2500));
+        assert!(toasts.is_empty());
+    }
+
+    #[test]
+    fn test_toast_fade() {
+        let toast = toast("saved", 0, 1000);
+        let fade = Duration::from_millis(200);
+
+        assert_eq!(toast.alpha(Duration::from_millis(500), fade), 1.0);
+        assert_eq!(toast.alpha(Duration::from_millis(800), fade), 1.0);
+        assert!((toast.alpha(Duration::from_millis(900), fade) - 0.5).abs() < 1e-6);
+        assert_eq!(toast.alpha(Duration::from_millis(1000), fade), 0.0);
+        assert_eq!(toast.alpha(Duration::from_millis(900), Duration::ZERO), 1.0);
+    }
+}
@@ -0,0 +1,179 @@
+//! imgui styles stored in TOML files, loaded at startup via
+//! [`HudhookBuilder::with_style_file`](crate::HudhookBuilder::with_style_file).
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use imgui::{Style, StyleColor};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use windows::core::{Error, Result, HRESULT};
+use windows::Win32::Foundation::ERROR_BAD_FORMAT;
+
+use crate::renderer::io_error_to_windows;
+
+macro_rules! style_file {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        #[derive(Default, Deserialize, Serialize)]
+        #[serde(default)]
+        struct StyleFile {
+            $(
+                #[serde(skip_serializing_if = "Option::is_none")]
+                $field: Option<$ty>,
+            )*
+            colors: BTreeMap<String, [f32; 4]>,
+            #[serde(flatten, skip_serializing)]
+            unknown: BTreeMap<String, toml::Value>,
+        }
+
+        impl StyleFile {
+            fn from_style(style: &Style) -> Self {
+                let colors = StyleColor::VARIANTS
+                    .iter()
+                    .map(|&color| (format!("{color:?}"), style[color]))
+                    .collect();
+
+                Self { $($field: Some(style.$field),)* colors, unknown: BTreeMap::new() }
+            }
+
+            fn apply(self, style: &mut Style) {
+                $(
+                    if let Some(value) = self.$field {
+                        style.$field = value;
+                    }
+                )*
+
+                for key in self.unknown.keys() {
+                    warn!("Unknown style key {key:?}");
+                }
+                for (name, value) in self.colors {
+                    match StyleColor::VARIANTS.iter().find(|color| format!("{color:?}") == name) {
+                        Some(&color) => style[color] = value,
+                        None => warn!("Unknown style color {name:?}"),
+                    }
+                }
+            }
+        }
+    };
+}
+
+style_file! {
+    alpha: f32,
+    disabled_alpha: f32,
+    window_padding: [f32; 2],
+    window_rounding: f32,
+    window_border_size: f32,
+    window_min_size: [f32; 2],
+    window_title_align: [f32; 2],
+    child_rounding: f32,
+    child_border_size: f32,
+    popup_rounding: f32,
+    popup_border_size: f32,
+    frame_padding: [f32; 2],
+    frame_rounding: f32,
+    frame_border_size: f32,
+    item_spacing: [f32; 2],
+    item_inner_spacing: [f32; 2],
+    cell_padding: [f32; 2],
+    touch_extra_padding: [f32; 2],
+    indent_spacing: f32,
+    columns_min_spacing: f32,
+    scrollbar_size: f32,
+    scrollbar_rounding: f32,
+    grab_min_size: f32,
+    grab_rounding: f32,
+    log_slider_deadzone: f32,
+    tab_rounding: f32,
+    tab_border_size: f32,
+    tab_min_width_for_close_button: f32,
+    button_text_align: [f32; 2],
+    selectable_text_align: [f32; 2],
+    display_window_padding: [f32; 2],
+    display_safe_area_padding: [f32; 2],
+    mouse_cursor_scale: f32,
+    anti_aliased_lines: bool,
+    anti_aliased_lines_use_tex: bool,
+    anti_aliased_fill: bool,
+    curve_tessellation_tol: f32,
+    circle_tesselation_max_error: f32,
+}
+
+/// Load the style file at `path` into `style`. Missing files and invalid
+/// TOML are reported as errors, leaving `style` untouched.
+///
+/// The file sets any of the sizes of [`Style`] by their field name, and any
+/// of the colors by their [`StyleColor`] name, as RGBA in `[0, 1]`:
+///
+/// ```toml
+/// window_rounding = 6.0
+/// frame_padding = [8.0, 4.0]
+///
+/// [colors]
+/// WindowBg = [0.1, 0.1, 0.12, 0.9]
+/// Button = [0.3, 0.2, 0.6, 1.0]
+/// ```
+///
+/// What the file doesn't set keeps its current value. Unknown keys are
+/// logged and skipped, so that files written for other versions still load.
+pub fn load_style_from_file(style: &mut Style, path: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        error!("Could not read style file {path:?}: {e}");
+        io_error_to_windows(e)
+    })?;
+    let style_file: StyleFile = toml::from_str(&text).map_err(|e| {
+        error!("Could not parse style file {path:?}: {e}");
+        Error::from_hresult(HRESULT::from_win32(ERROR_BAD_FORMAT.0))
+    })?;
+
+    style_file.apply(style);
+    Ok(())
+}
+
+/// Save every size and color of `style` to the style file at `path`, e.g. to
+/// start a theme from the style tweaked in the style editor.
+pub fn save_style_to_file(style: &Style, path: &Path) -> Result<()> {
+    let text = toml::to_string_pretty(&StyleFile::from_style(style)).map_err(|e| {
+        error!("Could not serialize style: {e}");
+        Error::from_hresult(HRESULT::from_win32(ERROR_BAD_FORMAT.0))
+    })?;
+
+    std::fs::write(path, text).map_err(|e| {
+        error!("Could not write style file {path:?}: {e}");
+        io_error_to_windows(e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style_file() {
+        let _lock = crate::renderer::TEST_CONTEXT_LOCK.lock();
+        let ctx = crate::renderer::test_context();
+
+        let mut style = *ctx.style();
+        style.window_rounding = 7.;
+        style[StyleColor::WindowBg] = [0.25, 0.5, 0.75, 1.];
+        let text = toml::to_string_pretty(&StyleFile::from_style(&style)).unwrap();
+
+        let mut loaded = *ctx.style();
+        toml::from_str::<StyleFile>(&text).unwrap().apply(&mut loaded);
+        assert_eq!(loaded.window_rounding, 7.);
+        assert_eq!(loaded[StyleColor::WindowBg], [0.25, 0.5, 0.75, 1.]);
+
+        let partial = r#"
+            frame_rounding = 3.0
+            future_size = 1.0
+
+            [colors]
+            Text = [1.0, 0.0, 0.0, 1.0]
+            FutureColor = [0.0, 0.0, 0.0, 1.0]
+        "#;
+        let mut loaded = *ctx.style();
+        toml::from_str::<StyleFile>(partial).unwrap().apply(&mut loaded);
+        assert_eq!(loaded.frame_rounding, 3.);
+        assert_eq!(loaded.window_rounding, ctx.style().window_rounding);
+        assert_eq!(loaded[StyleColor::Text], [1., 0., 0., 1.]);
+    }
+}
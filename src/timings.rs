@@ -0,0 +1,91 @@
+//! Rolling record of the time spent by the overlay in the present hooks.
+//!
+//! Samples are recorded without locking: every present call claims a slot of
+//! a ring buffer with an atomic increment and stores its duration there.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Maximum number of samples that can be aggregated.
+pub(crate) const MAX_WINDOW: usize = 4096;
+
+/// Number of samples aggregated by default.
+pub(crate) const DEFAULT_WINDOW: usize = 600;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SAMPLE: AtomicU32 = AtomicU32::new(0);
+
+// Durations, in microseconds.
+static SAMPLES: [AtomicU32; MAX_WINDOW] = [EMPTY_SAMPLE; MAX_WINDOW];
+static NEXT_SAMPLE: AtomicUsize = AtomicUsize::new(0);
+static WINDOW: AtomicUsize = AtomicUsize::new(DEFAULT_WINDOW);
+// Presents intercepted so far, which isn't reset along with the samples.
+static PRESENT_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Percentiles of the time spent by the overlay in the present hook, before
+/// the original `Present` is called.
+///
+/// Retrieve them with [`present_hook_timings`](crate::present_hook_timings).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresentHookTimings {
+    /// Number of samples the percentiles were computed from.
+    pub samples: usize,
+    /// Median.
+    pub p50: Duration,
+    /// 95th percentile.
+    pub p95: Duration,
+    /// 99th percentile.
+    pub p99: Duration,
+}
+
+/// Record the time spent in a present hook.
+pub(crate) fn record(duration: Duration) {
+    let window = WINDOW.load(Ordering::Relaxed);
+    let index = NEXT_SAMPLE.fetch_add(1, Ordering::Relaxed) % window;
+    let micros = u32::try_from(duration.as_micros()).unwrap_or(u32::MAX);
+    SAMPLES[index].store(micros, Ordering::Relaxed);
+    PRESENT_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of presents intercepted so far.
+pub(crate) fn present_count() -> u64 {
+    PRESENT_COUNT.load(Ordering::Relaxed)
+}
+
+/// Set how many of the most recent samples are aggregated, discarding the
+/// samples recorded so far.
+pub(crate) fn set_window(window: usize) {
+    WINDOW.store(window.clamp(1, MAX_WINDOW), Ordering::SeqCst);
+    reset();
+}
+
+/// Discard the samples recorded so far.
+pub(crate) fn reset() {
+    NEXT_SAMPLE.store(0, Ordering::SeqCst);
+}
+
+/// Compute the percentiles over the current window, or `None` if nothing was
+/// recorded yet.
+pub(crate) fn aggregate() -> Option<PresentHookTimings> {
+    let window = WINDOW.load(Ordering::SeqCst);
+    let count = NEXT_SAMPLE.load(Ordering::SeqCst).min(window);
+    if count == 0 {
+        return None;
+    }
+
+    let mut samples: Vec<u32> =
+        SAMPLES[..count].iter().map(|sample| sample.load(Ordering::Relaxed)).collect();
+    samples.sort_unstable();
+
+    let percentile = |p: f64| {
+        let index = ((count - 1) as f64 * p).round() as usize;
+        Duration::from_micros(samples[index] as u64)
+    };
+
+    Some(PresentHookTimings {
+        samples: count,
+        p50: percentile(0.50),
+        p95: percentile(0.95),
+        p99: percentile(0.99),
+    })
+}
@@ -2,23 +2,26 @@
 //! proven useful in client code as well.
 
 use std::ffi::{c_void, OsString};
-use std::fmt::Display;
+use std::fmt::{Debug, Display};
 use std::mem::{size_of, ManuallyDrop};
 use std::os::windows::ffi::OsStringExt;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use std::{hint, slice};
 
 use parking_lot::{RwLock, RwLockReadGuard};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 use windows::core::s;
 use windows::Win32::Foundation::{HANDLE, HMODULE, HWND, MAX_PATH, RECT};
 use windows::Win32::Graphics::Direct3D::ID3DBlob;
 use windows::Win32::Graphics::Direct3D12::{
-    D3D12GetDebugInterface, ID3D12Debug, ID3D12Device, ID3D12Fence, ID3D12Resource,
-    D3D12_FENCE_FLAG_NONE, D3D12_RESOURCE_BARRIER, D3D12_RESOURCE_BARRIER_0,
-    D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES, D3D12_RESOURCE_BARRIER_FLAG_NONE,
-    D3D12_RESOURCE_BARRIER_TYPE_TRANSITION, D3D12_RESOURCE_STATES,
-    D3D12_RESOURCE_TRANSITION_BARRIER,
+    D3D12GetDebugInterface, ID3D12Debug, ID3D12Device, ID3D12Fence, ID3D12InfoQueue,
+    ID3D12Resource, D3D12_FENCE_FLAG_NONE, D3D12_MESSAGE, D3D12_MESSAGE_SEVERITY_CORRUPTION,
+    D3D12_MESSAGE_SEVERITY_ERROR, D3D12_MESSAGE_SEVERITY_WARNING, D3D12_RESOURCE_BARRIER,
+    D3D12_RESOURCE_BARRIER_0, D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+    D3D12_RESOURCE_BARRIER_FLAG_NONE, D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
+    D3D12_RESOURCE_STATES, D3D12_RESOURCE_TRANSITION_BARRIER,
 };
 use windows::Win32::Graphics::Dxgi::{
     DXGIGetDebugInterface1, IDXGIInfoQueue, DXGI_DEBUG_ALL, DXGI_INFO_QUEUE_MESSAGE,
@@ -35,6 +38,8 @@ use windows::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
 use windows::Win32::System::Threading::{CreateEventExW, WaitForSingleObjectEx, CREATE_EVENT};
 use windows::Win32::UI::WindowsAndMessaging::GetClientRect;
 
+use crate::{options, FenceStrategy};
+
 /// Helper for fallible [`windows`] APIs that have an out-param with a default
 /// value.
 ///
@@ -79,6 +84,9 @@ where
 /// Helper for fallible [`windows`] APIs that have an optional pointer
 /// out-param and an optional pointer err-param.
 ///
+/// Panics if the API fails without filling the err-param, e.g. when running
+/// out of memory. Prefer [`try_out_opt_err_blob`].
+///
 /// # Example
 ///
 /// ```
@@ -92,7 +100,33 @@ where
 /// })
 /// .map_err(print_err_blob("Compiling vertex shader"))?;
 /// ```
-pub fn try_out_err_blob<T1, T2, F, E, O>(mut f: F) -> Result<T1, (E, T2)>
+pub fn try_out_err_blob<T1, T2, F, E, O>(f: F) -> Result<T1, (E, T2)>
+where
+    F: FnMut(&mut Option<T1>, &mut Option<T2>) -> Result<O, E>,
+{
+    try_out_opt_err_blob(f).map_err(|(e, t2)| (e, t2.unwrap()))
+}
+
+/// Helper for fallible [`windows`] APIs that have an optional pointer
+/// out-param and an optional pointer err-param.
+///
+/// The err-param is only returned if the API filled it: failures such as
+/// running out of memory don't come with a blob.
+///
+/// # Example
+///
+/// ```
+/// let blob: ID3DBlob = util::try_out_opt_err_blob(|v, err_blob| {
+///     D3D12SerializeRootSignature(
+///         &root_signature_desc,
+///         D3D_ROOT_SIGNATURE_VERSION_1_0,
+///         v,
+///         Some(err_blob),
+///     )
+/// })
+/// .map_err(print_opt_error_blob("Serializing root signature"))?;
+/// ```
+pub fn try_out_opt_err_blob<T1, T2, F, E, O>(mut f: F) -> Result<T1, (E, Option<T2>)>
 where
     F: FnMut(&mut Option<T1>, &mut Option<T2>) -> Result<O, E>,
 {
@@ -100,7 +134,7 @@ where
     let mut t2: Option<T2> = None;
     match f(&mut t1, &mut t2) {
         Ok(_) => Ok(t1.unwrap()),
-        Err(e) => Err((e, t2.unwrap())),
+        Err(e) => Err((e, t2)),
     }
 }
 
@@ -123,14 +157,31 @@ where
 /// Use together with [`try_out_err_blob`] for printing Direct3D error blobs.
 pub fn print_error_blob<D: Display, E>(msg: D) -> impl Fn((E, ID3DBlob)) -> E {
     move |(e, err_blob): (E, ID3DBlob)| {
-        let buf_ptr = unsafe { err_blob.GetBufferPointer() } as *mut u8;
-        let buf_size = unsafe { err_blob.GetBufferSize() };
-        let s = unsafe { String::from_raw_parts(buf_ptr, buf_size, buf_size + 1) };
-        error!("{msg}: {s}");
+        error!("{msg}: {}", error_blob_message(&err_blob));
+        e
+    }
+}
+
+/// Use together with [`try_out_opt_err_blob`] for printing Direct3D error
+/// blobs, or the error itself if there is no blob.
+pub fn print_opt_error_blob<D: Display, E: Debug>(msg: D) -> impl Fn((E, Option<ID3DBlob>)) -> E {
+    move |(e, err_blob): (E, Option<ID3DBlob>)| {
+        match err_blob {
+            Some(err_blob) => error!("{msg}: {}", error_blob_message(&err_blob)),
+            None => error!("{msg}: {e:?}"),
+        }
         e
     }
 }
 
+fn error_blob_message(err_blob: &ID3DBlob) -> String {
+    // The blob owns its buffer, so only borrow it.
+    let buf = unsafe {
+        slice::from_raw_parts(err_blob.GetBufferPointer() as *const u8, err_blob.GetBufferSize())
+    };
+    String::from_utf8_lossy(buf).trim_end_matches('\0').to_string()
+}
+
 /// Enables the Direct3D12 debug interface.
 ///
 /// It will not panic if the interface is not available. Call this from your
@@ -175,6 +226,36 @@ pub fn print_dxgi_debug_messages() {
     unsafe { diq.ClearStoredMessages(DXGI_DEBUG_ALL) };
 }
 
+/// Prints the messages of a Direct3D 12 info queue on the trace, by severity.
+/// The info queue is available from devices created after
+/// [`enable_debug_interface`] has been called.
+pub fn print_d3d12_debug_messages(info_queue: &ID3D12InfoQueue) {
+    let n = unsafe { info_queue.GetNumStoredMessages() };
+    for i in 0..n {
+        let mut msg_len: usize = 0;
+        if unsafe { info_queue.GetMessage(i, None, &mut msg_len) }.is_err() {
+            continue;
+        }
+        let msg = vec![0u64; msg_len.div_ceil(size_of::<u64>())];
+        let pmsg = msg.as_ptr() as *mut D3D12_MESSAGE;
+        if unsafe { info_queue.GetMessage(i, Some(pmsg), &mut msg_len) }.is_err() {
+            continue;
+        }
+        let msg = unsafe { pmsg.as_ref().unwrap() };
+        let description = String::from_utf8_lossy(unsafe {
+            std::slice::from_raw_parts(msg.pDescription, msg.DescriptionByteLength - 1)
+        });
+        match msg.Severity {
+            D3D12_MESSAGE_SEVERITY_CORRUPTION | D3D12_MESSAGE_SEVERITY_ERROR => {
+                error!("[D3D12] {description}")
+            },
+            D3D12_MESSAGE_SEVERITY_WARNING => warn!("[D3D12] {description}"),
+            _ => debug!("[D3D12] {description}"),
+        }
+    }
+    unsafe { info_queue.ClearStoredMessages() };
+}
+
 /// Helper that returns width and height of a given
 /// [`windows::Win32::Foundation::HWND`].
 pub fn win_size(hwnd: HWND) -> (i32, i32) {
@@ -241,6 +322,10 @@ pub fn drop_barrier(barrier: D3D12_RESOURCE_BARRIER) {
     let _ = ManuallyDrop::into_inner(transition.pResource);
 }
 
+// Longest time a fence is spun on with `FenceStrategy::Hybrid` before
+// blocking.
+const FENCE_SPIN_LIMIT: Duration = Duration::from_micros(50);
+
 /// Wrapper around [`windows::Win32::Graphics::Direct3D12::ID3D12Fence`].
 pub struct Fence {
     fence: ID3D12Fence,
@@ -273,9 +358,32 @@ impl Fence {
         self.value.fetch_add(1, Ordering::SeqCst);
     }
 
+    /// Retrieve the value of the last signal the GPU has completed.
+    pub fn completed_value(&self) -> u64 {
+        unsafe { self.fence.GetCompletedValue() }
+    }
+
     /// Wait for completion of the fence.
     pub fn wait(&self) -> windows::core::Result<()> {
-        let value = self.value();
+        self.wait_for(self.value())
+    }
+
+    /// Wait for completion of the signal with the given value, as set via
+    /// [`HudhookBuilder::with_fence_strategy`](crate::HudhookBuilder::with_fence_strategy).
+    pub fn wait_for(&self, value: u64) -> windows::core::Result<()> {
+        let strategy = options::get().fence_strategy;
+        if strategy != FenceStrategy::Block {
+            // The completed value is `u64::MAX` if the device is removed, so
+            // this always ends.
+            let start = Instant::now();
+            while self.completed_value() < value {
+                if strategy == FenceStrategy::Hybrid && start.elapsed() >= FENCE_SPIN_LIMIT {
+                    break;
+                }
+                hint::spin_loop();
+            }
+        }
+
         unsafe {
             if self.fence.GetCompletedValue() < value {
                 self.fence.SetEventOnCompletion(value, self.event)?;
@@ -397,10 +505,53 @@ impl Default for HookEjectionBarrier {
 
 #[cfg(test)]
 mod tests {
+    use std::ptr;
+
+    use windows::Win32::Foundation::{E_FAIL, E_OUTOFMEMORY};
+    use windows::Win32::Graphics::Direct3D::Fxc::D3DCreateBlob;
     use windows::Win32::System::Memory::{VirtualAlloc, VirtualProtect, MEM_COMMIT, PAGE_NOACCESS};
 
     use super::*;
 
+    #[test]
+    fn test_try_out_opt_err_blob_without_blob() {
+        let result: Result<ID3DBlob, _> = try_out_opt_err_blob(|_, _: &mut Option<ID3DBlob>| {
+            Err::<(), _>(windows::core::Error::from(E_OUTOFMEMORY))
+        });
+
+        let e = result.map_err(print_opt_error_blob("Compiling shader")).unwrap_err();
+        assert_eq!(e.code(), E_OUTOFMEMORY);
+    }
+
+    #[test]
+    fn test_try_out_opt_err_blob_with_blob() -> windows::core::Result<()> {
+        const MESSAGE: &[u8] = b"error X3000: syntax error\0";
+
+        let blob = unsafe { D3DCreateBlob(MESSAGE.len()) }?;
+        unsafe {
+            ptr::copy_nonoverlapping(
+                MESSAGE.as_ptr(),
+                blob.GetBufferPointer() as *mut u8,
+                MESSAGE.len(),
+            )
+        };
+
+        let result: Result<ID3DBlob, _> = try_out_opt_err_blob(|_, err_blob| {
+            *err_blob = Some(blob.clone());
+            Err::<(), _>(E_FAIL)
+        });
+        let (e, err_blob) = result.unwrap_err();
+        let err_blob = err_blob.unwrap();
+        assert_eq!(error_blob_message(&err_blob), "error X3000: syntax error");
+
+        // The buffer is only borrowed: the blob is still usable afterwards.
+        assert_eq!(print_opt_error_blob("Compiling shader")((e, Some(err_blob.clone()))), E_FAIL);
+        assert_eq!(print_error_blob("Compiling shader")((E_FAIL, err_blob.clone())), E_FAIL);
+        assert_eq!(unsafe { err_blob.GetBufferSize() }, MESSAGE.len());
+
+        Ok(())
+    }
+
     #[test]
     fn test_readable_region() -> windows::core::Result<()> {
         const PAGE_SIZE: usize = 0x1000;
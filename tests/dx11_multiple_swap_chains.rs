@@ -0,0 +1,50 @@
+mod harness;
+mod hook;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use harness::dx11::Dx11Harness;
+use hook::{FrameCounter, SwapChainFrameCounter};
+use hudhook::hooks::dx11::ImguiDx11Hooks;
+use hudhook::*;
+
+#[test]
+fn test_imgui_dx11_multiple_swap_chains() {
+    hook::setup_tracing();
+
+    let frames = Arc::new(AtomicUsize::new(0));
+    let swap_chain_frames = Arc::new(Mutex::new(HashMap::new()));
+    let dx11_harness_a = Dx11Harness::new("DX11 swap chain A");
+    let dx11_harness_b = Dx11Harness::new("DX11 swap chain B");
+    thread::sleep(Duration::from_millis(500));
+
+    Hudhook::builder()
+        .with::<ImguiDx11Hooks>(SwapChainFrameCounter(
+            FrameCounter(Arc::clone(&frames)),
+            Arc::clone(&swap_chain_frames),
+        ))
+        .build()
+        .apply()
+        .unwrap_or_else(|e| panic!("Couldn't apply hooks: {e:?}"));
+
+    thread::sleep(Duration::from_millis(1000));
+    assert!(frames.load(Ordering::SeqCst) > 0, "the overlay didn't render");
+
+    // The overlay renders into one of the swap chains only.
+    let rendered = hook::swap_chains_rendered_over(&swap_chain_frames, Duration::from_millis(1000));
+    assert_eq!(rendered.len(), 1, "the overlay rendered into {rendered:x?}");
+
+    // Whichever swap chain the overlay rendered into, it must render into the
+    // other one once the first is released, after it stopped presenting for
+    // a while.
+    drop(dx11_harness_a);
+    thread::sleep(Duration::from_millis(3000));
+    let rendered = hook::swap_chains_rendered_over(&swap_chain_frames, Duration::from_millis(1000));
+    assert_eq!(rendered.len(), 1, "the overlay rendered into {rendered:x?} with one swap chain");
+
+    drop(dx11_harness_b);
+}
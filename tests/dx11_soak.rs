@@ -0,0 +1,97 @@
+mod harness;
+mod hook;
+
+use std::time::{Duration, Instant};
+use std::{mem, thread};
+
+use harness::dx11::Dx11Harness;
+use hook::HookExample;
+use hudhook::hooks::dx11::ImguiDx11Hooks;
+use hudhook::*;
+use tracing::trace;
+use windows::Win32::System::ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+use windows::Win32::System::Threading::{GetCurrentProcess, GetProcessHandleCount};
+
+const CYCLES: usize = 20;
+// Cycles after which the process is expected to have settled, e.g. once the
+// lazily initialized statics and the driver's own caches are in place.
+const WARMUP_CYCLES: usize = 2;
+// Growth tolerated over the measured cycles. Leaking a single handle per
+// cycle, e.g. a fence event, exceeds it.
+const HANDLE_SLACK: u32 = CYCLES as u32 / 2;
+const MEMORY_SLACK: usize = 32 << 20;
+
+fn handle_count() -> u32 {
+    let mut count = 0;
+    unsafe { GetProcessHandleCount(GetCurrentProcess(), &mut count) }.unwrap();
+    count
+}
+
+fn private_bytes() -> usize {
+    let mut counters = PROCESS_MEMORY_COUNTERS::default();
+    unsafe {
+        GetProcessMemoryInfo(
+            GetCurrentProcess(),
+            &mut counters,
+            mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32,
+        )
+    }
+    .unwrap();
+    counters.PagefileUsage
+}
+
+// Wait for the present hook to pick up the ejection request and remove the
+// hooks.
+fn wait_for_unhook() {
+    let start = Instant::now();
+    while !installed_hooks().is_empty() {
+        assert!(start.elapsed() < Duration::from_secs(5), "hooks weren't removed");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // Let the removal complete after the hooks are released.
+    thread::sleep(Duration::from_millis(250));
+}
+
+#[test]
+fn test_imgui_dx11_soak() {
+    hook::setup_tracing();
+
+    let dx11_harness = Dx11Harness::new("DX11 soak test");
+    thread::sleep(Duration::from_millis(500));
+
+    let mut baseline = None;
+    for cycle in 0..CYCLES {
+        Hudhook::builder()
+            .with::<ImguiDx11Hooks>(HookExample::new())
+            .build()
+            .apply()
+            .unwrap_or_else(|e| panic!("Couldn't apply hooks in cycle {cycle}: {e:?}"));
+
+        // Render a few frames, so that the pipeline is created and used.
+        thread::sleep(Duration::from_millis(500));
+        assert!(!installed_hooks().is_empty());
+
+        eject();
+        wait_for_unhook();
+
+        let (handles, memory) = (handle_count(), private_bytes());
+        trace!("Cycle {cycle}: {handles} handles, {} KiB", memory >> 10);
+        if cycle + 1 == WARMUP_CYCLES {
+            baseline = Some((handles, memory));
+        }
+    }
+
+    let (baseline_handles, baseline_memory) = baseline.unwrap();
+    let (handles, memory) = (handle_count(), private_bytes());
+    assert!(
+        handles <= baseline_handles + HANDLE_SLACK,
+        "handle count grew from {baseline_handles} to {handles}"
+    );
+    assert!(
+        memory <= baseline_memory + MEMORY_SLACK,
+        "private bytes grew from {baseline_memory} to {memory}"
+    );
+
+    drop(dx11_harness);
+}
@@ -0,0 +1,38 @@
+mod harness;
+mod hook;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use harness::dx12::Dx12Harness;
+use hook::FrameCounter;
+use hudhook::hooks::dx12::ImguiDx12Hooks;
+use hudhook::*;
+
+#[test]
+fn test_imgui_dx12_buffer_count() {
+    hook::setup_tracing();
+
+    // The probe swap chain has 2 buffers: the overlay should render on a swap
+    // chain with the maximum of 16 as well, also across `ResizeBuffers` calls.
+    let frames = Arc::new(AtomicUsize::new(0));
+    let dx12_harness = Dx12Harness::with_buffer_count(16);
+    thread::sleep(Duration::from_millis(1000));
+
+    Hudhook::builder()
+        .with::<ImguiDx12Hooks>(FrameCounter(Arc::clone(&frames)))
+        .build()
+        .apply()
+        .unwrap_or_else(|e| panic!("Couldn't apply hooks: {e:?}"));
+
+    thread::sleep(Duration::from_millis(5000));
+    let rendered = frames.load(Ordering::SeqCst);
+    assert!(rendered > 0, "the overlay didn't render on 16 buffers");
+    dx12_harness.resize(1024, 768);
+    thread::sleep(Duration::from_millis(5000));
+    assert!(frames.load(Ordering::SeqCst) > rendered, "the overlay stopped rendering after resize");
+
+    drop(dx12_harness);
+}
@@ -0,0 +1,46 @@
+mod harness;
+mod hook;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use harness::dx12::Dx12Harness;
+use hook::{FrameCounter, SwapChainFrameCounter};
+use hudhook::hooks::dx12::ImguiDx12Hooks;
+use hudhook::*;
+
+#[test]
+fn test_imgui_dx12_multiple_swap_chains() {
+    hook::setup_tracing();
+
+    // The overlay should only appear in one of the two windows, and keep
+    // rendering into it rather than thrashing between them.
+    let frames = Arc::new(AtomicUsize::new(0));
+    let swap_chain_frames = Arc::new(Mutex::new(HashMap::new()));
+    let dx12_harness_a = Dx12Harness::new();
+    let dx12_harness_b = Dx12Harness::new();
+    thread::sleep(Duration::from_millis(1000));
+
+    Hudhook::builder()
+        .with::<ImguiDx12Hooks>(SwapChainFrameCounter(
+            FrameCounter(Arc::clone(&frames)),
+            Arc::clone(&swap_chain_frames),
+        ))
+        .build()
+        .apply()
+        .unwrap_or_else(|e| panic!("Couldn't apply hooks: {e:?}"));
+
+    thread::sleep(Duration::from_millis(5000));
+    assert!(frames.load(Ordering::SeqCst) > 0, "the overlay didn't render");
+
+    let rendered = hook::swap_chains_rendered_over(&swap_chain_frames, Duration::from_millis(1000));
+    assert_eq!(rendered.len(), 1, "the overlay rendered into {rendered:x?}");
+    let swap_chains = swap_chain_frames.lock().unwrap().len();
+    assert_eq!(swap_chains, 1, "the overlay moved across {swap_chains} swap chains");
+
+    drop(dx12_harness_b);
+    drop(dx12_harness_a);
+}
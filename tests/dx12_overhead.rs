@@ -0,0 +1,137 @@
+// Overhead of the DirectX 12 overlay with and without the options meant to
+// reduce it. Not run by default, as the numbers only mean something on real
+// hardware and with nothing else running:
+//
+//     cargo test --test dx12_overhead -- --ignored --nocapture
+
+mod harness;
+mod hook;
+
+use std::thread;
+use std::time::Duration;
+
+use harness::dx12::Dx12Harness;
+use hook::HookExample;
+use hudhook::hooks::dx12::ImguiDx12Hooks;
+use hudhook::*;
+use imgui::Ui;
+
+// Presents aggregated by `present_hook_timings`, the most it keeps.
+const TIMING_WINDOW: usize = 4096;
+
+// Draws that many filled rectangles on the background, i.e. 4 vertices and 6
+// indices each, like a table with as many cells would.
+struct Rects(usize);
+
+impl ImguiRenderLoop for Rects {
+    fn render(&mut self, ui: &mut Ui) {
+        let draw_list = ui.get_background_draw_list();
+        for i in 0..self.0 {
+            let [x, y] = [(i % 200) as f32 * 4., (i / 200) as f32 * 4.];
+            draw_list.add_rect([x, y], [x + 3., y + 3.], [1., 1., 1., 0.5]).filled(true).build();
+        }
+    }
+}
+
+// Draws nothing, e.g. an auto-hiding overlay with every window closed, and
+// tells whether the renderer may skip the frame.
+struct Idle {
+    skip_empty_frames: bool,
+}
+
+impl ImguiRenderLoop for Idle {
+    fn render(&mut self, _ui: &mut Ui) {}
+
+    fn render_ui(&mut self, _ui: &mut Ui) -> bool {
+        !self.skip_empty_frames
+    }
+}
+
+// Apply the hooks with the options set by `configure`, let the harness present
+// for `duration`, and print the time spent in the present hook. The hooks are
+// ejected before returning.
+fn measure<T>(
+    name: &str,
+    render_loop: T,
+    duration: Duration,
+    configure: impl FnOnce(HudhookBuilder) -> HudhookBuilder,
+) where
+    T: ImguiRenderLoop + Send + Sync + 'static,
+{
+    let builder = Hudhook::builder()
+        .with::<ImguiDx12Hooks>(render_loop)
+        .with_present_timing_window(TIMING_WINDOW);
+    configure(builder).build().apply().unwrap_or_else(|e| panic!("{name}: {e:?}"));
+
+    thread::sleep(duration);
+    let timings = present_hook_timings().unwrap_or_else(|| panic!("{name}: no presents"));
+    println!(
+        "{name:<40} {:>6} presents  p50 {:>9.1?}  p95 {:>9.1?}  p99 {:>9.1?}",
+        timings.samples, timings.p50, timings.p95, timings.p99
+    );
+
+    eject();
+    while !installed_hooks().is_empty() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    thread::sleep(Duration::from_millis(250));
+}
+
+#[test]
+#[ignore]
+fn bench_dx12_overhead() {
+    hook::setup_tracing();
+
+    let dx12_harness = Dx12Harness::new();
+    thread::sleep(Duration::from_millis(500));
+
+    let steady_state = Duration::from_secs(5);
+    measure("example overlay", HookExample::new(), steady_state, |builder| builder);
+
+    // Buffer growth: the first frames of a heavy overlay reallocate the
+    // vertex and index buffers until they fit, unless they're preallocated.
+    // 20000 rectangles take 80000 vertices and 120000 indices.
+    let first_frames = Duration::from_millis(500);
+    measure("20000 rects, default buffers", Rects(20000), first_frames, |builder| builder);
+    measure("20000 rects, preallocated buffers", Rects(20000), first_frames, |builder| {
+        builder.with_vertex_buffer_capacity(80000).with_index_buffer_capacity(120000)
+    });
+
+    // Command list ring: recording a frame no longer waits for the GPU to be
+    // done with the previous overlay frame. The harness waits for the GPU
+    // every frame, which hides most of the stall: this is the lower bound.
+    measure("20000 rects, single command list", Rects(20000), steady_state, |builder| builder);
+    measure("20000 rects, command list ring", Rects(20000), steady_state, |builder| {
+        builder.with_command_list_ring(true)
+    });
+
+    // Idle overlay: an empty frame still records and submits the barriers
+    // and the command list, unless the render loop lets the renderer skip it.
+    measure(
+        "idle, empty frames drawn",
+        Idle { skip_empty_frames: false },
+        steady_state,
+        |builder| builder,
+    );
+    measure(
+        "idle, empty frames skipped",
+        Idle { skip_empty_frames: true },
+        steady_state,
+        |builder| builder,
+    );
+
+    drop(dx12_harness);
+
+    // Waitable swap chain running up to 3 frames ahead of the GPU: without
+    // honoring its frame latency, the overlay waits for the GPU inside the
+    // present hook, after the game sampled input.
+    let dx12_harness = Dx12Harness::with_waitable_object(4, 3);
+    thread::sleep(Duration::from_millis(500));
+
+    measure("waitable, frame latency ignored", Rects(20000), steady_state, |builder| builder);
+    measure("waitable, frame latency honored", Rects(20000), steady_state, |builder| {
+        builder.with_swapchain_waitable_object(true)
+    });
+
+    drop(dx12_harness);
+}
@@ -0,0 +1,42 @@
+mod harness;
+mod hook;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use harness::dx12::Dx12Harness;
+use hook::FrameCounter;
+use hudhook::hooks::dx12::ImguiDx12Hooks;
+use hudhook::*;
+
+#[test]
+fn test_imgui_dx12_resize() {
+    hook::setup_tracing();
+
+    let frames = Arc::new(AtomicUsize::new(0));
+    let dx12_harness = Dx12Harness::new();
+    thread::sleep(Duration::from_millis(1000));
+
+    Hudhook::builder()
+        .with::<ImguiDx12Hooks>(FrameCounter(Arc::clone(&frames)))
+        .build()
+        .apply()
+        .unwrap_or_else(|e| panic!("Couldn't apply hooks: {e:?}"));
+
+    // The overlay should keep rendering at the right size, without the
+    // harness or the renderer failing, across `ResizeBuffers` calls.
+    thread::sleep(Duration::from_millis(5000));
+    let mut rendered = frames.load(Ordering::SeqCst);
+    assert!(rendered > 0, "the overlay didn't render");
+    for (width, height) in [(1024, 768), (640, 480), (800, 600)] {
+        dx12_harness.resize(width, height);
+        thread::sleep(Duration::from_millis(5000));
+        let frames = frames.load(Ordering::SeqCst);
+        assert!(frames > rendered, "the overlay stopped rendering at {width}x{height}");
+        rendered = frames;
+    }
+
+    drop(dx12_harness);
+}
@@ -0,0 +1,79 @@
+mod harness;
+mod hook;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use harness::dx12::Dx12Harness;
+use hook::FrameCounter;
+use hudhook::hooks::dx12::ImguiDx12Hooks;
+use hudhook::*;
+use imgui::{Context, Image, TextureId};
+
+// Well past the 8 descriptors the texture heap starts with, so that it is
+// grown several times.
+const TEXTURE_COUNT: usize = 64;
+const TEXTURE_SIZE: u32 = 16;
+
+// Loads a texture per frame while drawing all the ones loaded so far, so that
+// the heap is grown while earlier frames referencing it are in flight.
+struct TextureLoader {
+    frame_counter: FrameCounter,
+    textures: Vec<TextureId>,
+}
+
+impl ImguiRenderLoop for TextureLoader {
+    fn before_render<'a>(
+        &'a mut self,
+        _ctx: &mut Context,
+        render_context: &'a mut dyn RenderContext,
+    ) {
+        if self.textures.len() < TEXTURE_COUNT {
+            let shade = (self.textures.len() * 255 / TEXTURE_COUNT) as u8;
+            let data = [shade, 255 - shade, 0, 255].repeat((TEXTURE_SIZE * TEXTURE_SIZE) as usize);
+            let texture_id = render_context
+                .load_texture(&data, TEXTURE_SIZE, TEXTURE_SIZE)
+                .unwrap_or_else(|e| panic!("Couldn't load texture: {e:?}"));
+            self.textures.push(texture_id);
+        }
+    }
+
+    fn render(&mut self, ui: &mut imgui::Ui) {
+        self.frame_counter.render(ui);
+        ui.window("Textures").build(|| {
+            for texture_id in &self.textures {
+                Image::new(*texture_id, [TEXTURE_SIZE as f32; 2]).build(ui);
+                ui.same_line();
+            }
+        });
+    }
+}
+
+#[test]
+fn test_imgui_dx12_texture_heap_growth() {
+    hook::setup_tracing();
+
+    let frames = Arc::new(AtomicUsize::new(0));
+    let dx12_harness = Dx12Harness::with_buffer_count(3);
+    thread::sleep(Duration::from_millis(1000));
+
+    Hudhook::builder()
+        .with::<ImguiDx12Hooks>(TextureLoader {
+            frame_counter: FrameCounter(Arc::clone(&frames)),
+            textures: Vec::new(),
+        })
+        .with_command_list_ring(true)
+        .build()
+        .apply()
+        .unwrap_or_else(|e| panic!("Couldn't apply hooks: {e:?}"));
+
+    thread::sleep(Duration::from_millis(5000));
+    let rendered = frames.load(Ordering::SeqCst);
+    assert!(rendered > TEXTURE_COUNT, "the overlay stopped rendering after {rendered} frames");
+    thread::sleep(Duration::from_millis(1000));
+    assert!(frames.load(Ordering::SeqCst) > rendered, "the overlay stopped rendering");
+
+    drop(dx12_harness);
+}
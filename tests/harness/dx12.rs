@@ -1,5 +1,5 @@
 use std::ptr::null;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
@@ -7,7 +7,7 @@ use std::thread::{self, JoinHandle};
 use hudhook::util;
 use once_cell::sync::OnceCell;
 use tracing::{error, trace};
-use windows::core::{w, Interface, Result, PCSTR, PCWSTR};
+use windows::core::{w, Interface, Result, HSTRING, PCSTR, PCWSTR};
 use windows::Win32::Foundation::*;
 use windows::Win32::Graphics::Direct3D::D3D_FEATURE_LEVEL_11_0;
 use windows::Win32::Graphics::Direct3D12::*;
@@ -24,28 +24,87 @@ static TX: OnceCell<Arc<Sender<Msg>>> = OnceCell::new();
 pub struct Dx12Harness {
     child: Option<JoinHandle<()>>,
     done: Arc<AtomicBool>,
+    hwnd: Arc<AtomicIsize>,
 }
 
 impl Dx12Harness {
     #[allow(unused)]
     pub fn new() -> Self {
+        Self::with_buffer_count(2)
+    }
+
+    /// Like [`Dx12Harness::new`], with `buffer_count` back buffers in the swap
+    /// chain.
+    #[allow(unused)]
+    pub fn with_buffer_count(buffer_count: u32) -> Self {
+        Self::with_resize_buffer_count(buffer_count, buffer_count)
+    }
+
+    /// Like [`Dx12Harness::with_buffer_count`], passing `resize_buffer_count`
+    /// to `ResizeBuffers`, e.g. 0 to preserve the count.
+    #[allow(unused)]
+    pub fn with_resize_buffer_count(buffer_count: u32, resize_buffer_count: u32) -> Self {
+        Self::spawn(buffer_count, resize_buffer_count, None)
+    }
+
+    /// Like [`Dx12Harness::with_buffer_count`], with a swap chain created with
+    /// `DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT`. The harness waits
+    /// on the waitable object before each frame, as games do, instead of
+    /// waiting for the GPU, so it runs up to `max_frame_latency` frames ahead.
+    #[allow(unused)]
+    pub fn with_waitable_object(buffer_count: u32, max_frame_latency: u32) -> Self {
+        Self::spawn(buffer_count, buffer_count, Some(max_frame_latency))
+    }
+
+    fn spawn(buffer_count: u32, resize_buffer_count: u32, max_frame_latency: Option<u32>) -> Self {
         let done = Arc::new(AtomicBool::new(false));
+        let hwnd = Arc::new(AtomicIsize::new(0));
 
         let child = Some(thread::spawn({
             let done = Arc::clone(&done);
+            let hwnd = Arc::clone(&hwnd);
 
             let (tx, rx) = mpsc::channel();
             TX.get_or_init(move || Arc::new(tx));
 
             move || unsafe {
-                if let Err(e) = run_harness(done, rx) {
+                if let Err(e) = run_harness(
+                    done,
+                    hwnd,
+                    rx,
+                    buffer_count,
+                    resize_buffer_count,
+                    max_frame_latency,
+                ) {
                     util::print_dxgi_debug_messages();
                     error!("{e:?}");
                 }
             }
         }));
 
-        Self { child, done }
+        Self { child, done, hwnd }
+    }
+
+    /// Resize the client area of the window, which makes the harness resize
+    /// its swap chain buffers.
+    #[allow(unused)]
+    pub fn resize(&self, width: i32, height: i32) {
+        let hwnd = HWND(self.hwnd.load(Ordering::SeqCst));
+        let mut rect = RECT { left: 0, top: 0, right: width, bottom: height };
+        unsafe {
+            AdjustWindowRect(&mut rect, WS_OVERLAPPEDWINDOW | WS_VISIBLE, BOOL::from(false))
+                .unwrap();
+            SetWindowPos(
+                hwnd,
+                HWND::default(),
+                0,
+                0,
+                rect.right - rect.left,
+                rect.bottom - rect.top,
+                SWP_NOMOVE | SWP_NOZORDER,
+            )
+            .unwrap();
+        }
     }
 }
 
@@ -58,7 +117,11 @@ impl Drop for Dx12Harness {
 
 unsafe fn run_harness(
     done: Arc<AtomicBool>,
+    hwnd_out: Arc<AtomicIsize>,
     rx: Receiver<(HWND, u32, WPARAM, LPARAM)>,
+    buffer_count: u32,
+    resize_buffer_count: u32,
+    max_frame_latency: Option<u32>,
 ) -> Result<()> {
     trace!("Creating window");
     let hinstance = GetModuleHandleA(PCSTR(null())).unwrap();
@@ -94,6 +157,7 @@ unsafe fn run_harness(
         hinstance,
         None,
     );
+    hwnd_out.store(hwnd.0, Ordering::SeqCst);
 
     trace!("Enabling debug");
     util::enable_debug_interface();
@@ -112,18 +176,32 @@ unsafe fn run_harness(
             NodeMask: 0,
         })?;
 
-    let command_allocator: ID3D12CommandAllocator =
-        device.CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT)?;
+    // One allocator per back buffer, reused once the GPU is done with the
+    // frame that last rendered to that buffer.
+    let command_allocators = (0..buffer_count)
+        .map(|_| device.CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT))
+        .collect::<Result<Vec<ID3D12CommandAllocator>>>()?;
 
-    let command_list: ID3D12GraphicsCommandList =
-        device.CreateCommandList(0, D3D12_COMMAND_LIST_TYPE_DIRECT, &command_allocator, None)?;
+    let command_list: ID3D12GraphicsCommandList = device.CreateCommandList(
+        0,
+        D3D12_COMMAND_LIST_TYPE_DIRECT,
+        &command_allocators[0],
+        None,
+    )?;
 
     command_list.Close()?;
 
     command_queue.SetName(w!("Harness Command Queue"))?;
-    command_allocator.SetName(w!("Harness Command Allocator"))?;
+    for (i, command_allocator) in command_allocators.iter().enumerate() {
+        command_allocator.SetName(&HSTRING::from(format!("Harness Command Allocator {i}")))?;
+    }
     command_list.SetName(w!("Harness Command List"))?;
 
+    let swap_chain_flags = match max_frame_latency {
+        Some(_) => DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT.0 as u32,
+        None => 0,
+    };
+
     let swap_chain: IDXGISwapChain3 = factory
         .CreateSwapChainForHwnd(
             &command_queue,
@@ -131,9 +209,9 @@ unsafe fn run_harness(
             &DXGI_SWAP_CHAIN_DESC1 {
                 SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
                 BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
-                BufferCount: 2,
+                BufferCount: buffer_count,
                 SwapEffect: DXGI_SWAP_EFFECT_FLIP_DISCARD,
-                Flags: 0,
+                Flags: swap_chain_flags,
                 Width: 800,
                 Height: 600,
                 Format: DXGI_FORMAT_B8G8R8A8_UNORM,
@@ -149,41 +227,53 @@ unsafe fn run_harness(
     drop(adapter);
     drop(factory);
 
+    let waitable_object = match max_frame_latency {
+        Some(max_frame_latency) => {
+            let swap_chain: IDXGISwapChain2 = swap_chain.cast()?;
+            swap_chain.SetMaximumFrameLatency(max_frame_latency)?;
+            Some(swap_chain.GetFrameLatencyWaitableObject())
+        },
+        None => None,
+    };
+
     let rtv_heap: ID3D12DescriptorHeap =
         device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
             Type: D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
-            NumDescriptors: 2,
+            NumDescriptors: buffer_count,
             Flags: D3D12_DESCRIPTOR_HEAP_FLAG_NONE,
             NodeMask: 0,
         })?;
 
-    let rtv_desc0 = rtv_heap.GetCPUDescriptorHandleForHeapStart();
-    let rtv_desc1 = D3D12_CPU_DESCRIPTOR_HANDLE {
-        ptr: rtv_desc0.ptr
-            + device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_RTV) as usize,
-    };
-
-    {
-        let buf: ID3D12Resource = swap_chain.GetBuffer(0).unwrap();
-        buf.SetName(w!("Harness back buffer 0"))?;
-        device.CreateRenderTargetView(&buf, None, rtv_desc0);
-        drop(buf);
-        let buf: ID3D12Resource = swap_chain.GetBuffer(1).unwrap();
-        buf.SetName(w!("Harness back buffer 1"))?;
-        device.CreateRenderTargetView(&buf, None, rtv_desc1);
-        drop(buf);
-    }
+    let rtv_heap_start = rtv_heap.GetCPUDescriptorHandleForHeapStart();
+    let rtv_increment =
+        device.GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_RTV) as usize;
+    let rtv: Vec<_> = (0..buffer_count as usize)
+        .map(|i| D3D12_CPU_DESCRIPTOR_HANDLE { ptr: rtv_heap_start.ptr + i * rtv_increment })
+        .collect();
 
-    let rtv = [rtv_desc0, rtv_desc1];
+    create_render_target_views(&device, &swap_chain, &rtv)?;
 
     let fence: ID3D12Fence = device.CreateFence(0, D3D12_FENCE_FLAG_NONE)?;
     let mut fence_val = 0u64;
+    let mut buffer_fence_vals = vec![0u64; buffer_count as usize];
     let fence_event = CreateEventExW(None, None, CREATE_EVENT(0), 0x1F0003)?;
+    let wait_for_fence = |fence_val: u64| -> Result<()> {
+        if fence.GetCompletedValue() < fence_val {
+            fence.SetEventOnCompletion(fence_val, fence_event)?;
+            WaitForSingleObject(fence_event, INFINITE);
+        }
+        Ok(())
+    };
 
     loop {
         util::print_dxgi_debug_messages();
-        let rtv = rtv[swap_chain.GetCurrentBackBufferIndex() as usize];
-        let back_buffer = swap_chain.GetBuffer(swap_chain.GetCurrentBackBufferIndex())?;
+        if let Some(waitable_object) = waitable_object {
+            WaitForSingleObjectEx(waitable_object, 1000, BOOL::from(true));
+        }
+
+        let buffer_index = swap_chain.GetCurrentBackBufferIndex() as usize;
+        let rtv = rtv[buffer_index];
+        let back_buffer = swap_chain.GetBuffer(buffer_index as u32)?;
 
         let rtv_barrier = [util::create_barrier(
             &back_buffer,
@@ -199,20 +289,27 @@ unsafe fn run_harness(
 
         drop(back_buffer);
 
+        let command_allocator = &command_allocators[buffer_index];
+        wait_for_fence(buffer_fence_vals[buffer_index])?;
         command_allocator.Reset()?;
-        command_list.Reset(&command_allocator, None)?;
+        command_list.Reset(command_allocator, None)?;
         command_list.ResourceBarrier(&rtv_barrier);
         command_list.ClearRenderTargetView(rtv, &[0.3, 0.8, 0.3, 0.8], None);
         command_list.ResourceBarrier(&present_barrier);
         command_list.Close()?;
         command_queue.ExecuteCommandLists(&[Some(command_list.cast()?)]);
+
+        // The fence starts at 0: signal a value it hasn't reached yet, or the
+        // wait returns before the GPU is done with the back buffer.
+        fence_val += 1;
         command_queue.Signal(&fence, fence_val)?;
+        buffer_fence_vals[buffer_index] = fence_val;
 
-        if fence.GetCompletedValue() < fence_val {
-            fence.SetEventOnCompletion(fence_val, fence_event)?;
-            WaitForSingleObject(fence_event, INFINITE);
+        // With a waitable swap chain, the wait on the waitable object paces
+        // the frames instead.
+        if waitable_object.is_none() {
+            wait_for_fence(fence_val)?;
         }
-        fence_val += 1;
 
         rtv_barrier.into_iter().for_each(util::drop_barrier);
         present_barrier.into_iter().for_each(util::drop_barrier);
@@ -238,19 +335,23 @@ unsafe fn run_harness(
                     let height = hiword(lparam.0 as u32) as u32;
                     trace!("Resizing {width}x{height}");
 
-                    // TODO look deeper into this crash.
-                    // swap_chain.ResizeBuffers(2, width, height, DXGI_FORMAT_B8G8R8A8_UNORM, 0)?;
+                    // The back buffers must be idle, and no reference to them
+                    // is held here.
+                    wait_for_fence(fence_val)?;
+                    swap_chain.ResizeBuffers(
+                        resize_buffer_count,
+                        width,
+                        height,
+                        DXGI_FORMAT_B8G8R8A8_UNORM,
+                        swap_chain_flags,
+                    )?;
                     trace!("Resized");
 
-                    let buf: ID3D12Resource = swap_chain.GetBuffer(0).unwrap();
-                    buf.SetName(w!("Harness back buffer 0"))?;
-                    device.CreateRenderTargetView(&buf, None, rtv_desc0);
-                    drop(buf);
+                    // Resizing with 0 buffers preserves the count.
+                    let desc = swap_chain.GetDesc1()?;
+                    assert_eq!(desc.BufferCount, buffer_count, "Buffer count changed by resize");
 
-                    let buf: ID3D12Resource = swap_chain.GetBuffer(1).unwrap();
-                    buf.SetName(w!("Harness back buffer 1"))?;
-                    device.CreateRenderTargetView(&buf, None, rtv_desc1);
-                    drop(buf);
+                    create_render_target_views(&device, &swap_chain, &rtv)?;
                 },
                 _ => {},
             }
@@ -264,6 +365,19 @@ unsafe fn run_harness(
     Ok(())
 }
 
+unsafe fn create_render_target_views(
+    device: &ID3D12Device,
+    swap_chain: &IDXGISwapChain3,
+    rtv: &[D3D12_CPU_DESCRIPTOR_HANDLE],
+) -> Result<()> {
+    for (i, &rtv) in rtv.iter().enumerate() {
+        let buf: ID3D12Resource = swap_chain.GetBuffer(i as u32)?;
+        buf.SetName(&HSTRING::from(format!("Harness back buffer {i}")))?;
+        device.CreateRenderTargetView(&buf, None, rtv);
+    }
+    Ok(())
+}
+
 // Replication of the Win32 HIWORD macro.
 #[inline]
 pub fn hiword(l: u32) -> u16 {
@@ -1,6 +1,8 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Cursor;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use hudhook::{ImguiRenderLoop, MessageFilter, RenderContext};
@@ -49,6 +51,7 @@ pub fn setup_tracing() {
 
 const IMAGE_COUNT: usize = 16;
 
+#[allow(unused)]
 pub struct HookExample {
     frame_times: Vec<Duration>,
     first_time: Option<Instant>,
@@ -62,6 +65,7 @@ pub struct HookExample {
     main_window_movable: bool,
 }
 
+#[allow(unused)]
 impl HookExample {
     pub fn new() -> Self {
         println!("Initializing");
@@ -275,3 +279,54 @@ impl ImguiRenderLoop for HookExample {
         }
     }
 }
+
+/// Render loop counting the frames it rendered, for tests asserting that the
+/// overlay keeps rendering.
+#[allow(unused)]
+pub struct FrameCounter(pub Arc<AtomicUsize>);
+
+impl ImguiRenderLoop for FrameCounter {
+    fn render(&mut self, ui: &mut imgui::Ui) {
+        let frames = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+        ui.window("Frame counter").build(|| ui.text(format!("{frames} frames")));
+    }
+}
+
+/// Render loop counting the frames it rendered per swap chain, keyed by the
+/// raw pointer of [`RenderContext::swap_chain_raw`], for tests asserting which
+/// swap chains receive the overlay.
+#[allow(unused)]
+pub struct SwapChainFrameCounter(pub FrameCounter, pub Arc<Mutex<HashMap<usize, usize>>>);
+
+/// Swap chains that the overlay rendered into over `duration`, according to
+/// the counts of a [`SwapChainFrameCounter`].
+#[allow(unused)]
+pub fn swap_chains_rendered_over(
+    frames: &Mutex<HashMap<usize, usize>>,
+    duration: Duration,
+) -> Vec<usize> {
+    let before = frames.lock().unwrap().clone();
+    std::thread::sleep(duration);
+    let after = frames.lock().unwrap().clone();
+    after
+        .into_iter()
+        .filter(|&(swap_chain, frames)| before.get(&swap_chain).copied().unwrap_or(0) < frames)
+        .map(|(swap_chain, _)| swap_chain)
+        .collect()
+}
+
+impl ImguiRenderLoop for SwapChainFrameCounter {
+    fn before_render<'a>(
+        &'a mut self,
+        _ctx: &mut Context,
+        render_context: &'a mut dyn RenderContext,
+    ) {
+        let swap_chain = unsafe { render_context.swap_chain_raw() }
+            .expect("the renderer doesn't report its swap chain") as usize;
+        *self.1.lock().unwrap().entry(swap_chain).or_default() += 1;
+    }
+
+    fn render(&mut self, ui: &mut imgui::Ui) {
+        self.0.render(ui);
+    }
+}